@@ -0,0 +1,184 @@
+//compares evaluating property expressions by name against shader instances running them compiled to slots
+//
+//usage: cargo bench --bench expression_eval
+//every round assigns every property on each of a thousand instances and re-evaluates all of its expressions.
+//the instances go through the same set_property and eval_expressions calls a frame does, once by name and once by slot.
+//allocations are counted over the timed rounds, neither instance path should report any
+
+#[allow(dead_code, unused)]
+#[path = "../src/renderer/render_state/mod.rs"]
+mod render_state;
+
+extern crate pest;
+#[macro_use]
+extern crate pest_derive;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use render_state::properties::{Expression, PropertyGroup, Value};
+use render_state::texture::{Placement, Projection, ProjectionSpace, Shader, ShaderInstance};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8
+    {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        return System.alloc(layout);
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout)
+    {
+        System.dealloc(ptr, layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8
+    {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        return System.realloc(ptr, layout, new_size);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const INSTANCES: usize = 1000;
+const ROUNDS: usize = 200;
+
+//roughly what a shader's placements and color maps end up evaluating
+const EXPRESSIONS: [&str; 6] = 
+[
+    "color(hp / maxhp, 0.2, 0.2, 1)",
+    "mat4(vec3(offset_x, offset_y, 0), quat(vec3[0, 0, 1], angle), vec3(scale, scale, 1))",
+    "sin(time * 2 + phase) * 0.5 + 0.5",
+    "vec3(offset_x, offset_y, scale).zyx",
+    "normalize(cross(vec3(1, offset_x, 0), vec3[0, 0, 1]))",
+    "(hp / maxhp) ^ 2 * scale",
+];
+
+const PROPERTIES: [(&str, f32); 8] = [("hp", 75.0), ("maxhp", 100.0), ("offset_x", 0.5), ("offset_y", -0.25), ("angle", 0.3), ("scale", 2.0), ("time", 0.0), ("phase", 1.0)];
+
+fn default_properties() -> PropertyGroup
+{
+    let mut props = PropertyGroup::new();
+    for (name, val) in PROPERTIES
+    {
+        props.add_property(name, Value::Scalar(val));
+    }
+    return props;
+}
+
+fn parse_expressions() -> Vec<Expression>
+{
+    return EXPRESSIONS.iter().enumerate().map(|(id, text)| Expression::parse(id as u16, text).unwrap()).collect();
+}
+
+//the value each property gets for a round. time moves, the rest are set back to what they were
+fn property_value(property: usize, round: usize, instance: usize) -> Value
+{
+    let (name, val) = PROPERTIES[property];
+    return Value::Scalar(if name == "time" { (round + instance) as f32 * 0.016 } else { val });
+}
+
+//one round of both paths, returning the allocation count and how long it took
+fn measure(mut round: impl FnMut(usize)) -> (usize, Duration)
+{
+    //warm up so stacks and tables have already grown
+    round(0);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for i in 1..=ROUNDS
+    {
+        round(i);
+    }
+    let elapsed = start.elapsed();
+    return (ALLOCATIONS.load(Ordering::Relaxed) - allocations, elapsed);
+}
+
+fn report(name: &str, allocations: usize, elapsed: Duration)
+{
+    let evaluations = (ROUNDS * INSTANCES * EXPRESSIONS.len()) as f64;
+    println!
+    (
+        "{:<12} {:>10.2} ms total {:>8.1} ns/expression {:>10} allocations",
+        name, elapsed.as_secs_f64() * 1000.0, elapsed.as_secs_f64() * 1e9 / evaluations, allocations,
+    );
+}
+
+fn main()
+{
+    let expressions = parse_expressions();
+    let defaults = default_properties();
+    let mut groups = vec![defaults.clone(); INSTANCES];
+    let (by_name_allocations, by_name_time) = measure
+    (
+        |round| 
+        {
+            for (i, group) in groups.iter_mut().enumerate()
+            {
+                for property in 0..PROPERTIES.len()
+                {
+                    group.set_property(PROPERTIES[property].0, property_value(property, round, i)).unwrap();
+                }
+                for expr in &expressions
+                {
+                    black_box(expr.evaluate(group).unwrap());
+                }
+            }
+        }
+    );
+
+    //placements are the one part of a shader that can be put together outside the parser, so each expression goes in as a placement's transform
+    let placements = parse_expressions().into_iter().enumerate().map(|(i, expr)| Placement::new(i as u32, expr, Projection::Planar, ProjectionSpace::Object)).collect();
+    let shader = Rc::new(Shader::new("bench".to_string(), vec![], placements, defaults));
+    assert_eq!(shader.num_compiled_expressions(), EXPRESSIONS.len(), "every expression should compile");
+    let mut instances = (0..INSTANCES).map(|_| ShaderInstance::new(shader.clone(), vec![])).collect::<Vec<_>>();
+    let (instance_name_allocations, instance_name_time) = measure
+    (
+        |round| 
+        {
+            for (i, instance) in instances.iter_mut().enumerate()
+            {
+                for property in 0..PROPERTIES.len()
+                {
+                    instance.set_property(PROPERTIES[property].0, property_value(property, round, i)).unwrap();
+                }
+                instance.eval_expressions();
+            }
+        }
+    );
+
+    let slots = PROPERTIES.map(|(name, _)| shader.schema().slot(name).unwrap());
+    let (instance_slot_allocations, instance_slot_time) = measure
+    (
+        |round| 
+        {
+            for (i, instance) in instances.iter_mut().enumerate()
+            {
+                for property in 0..PROPERTIES.len()
+                {
+                    instance.set_slot(slots[property], property_value(property, round, i)).unwrap();
+                }
+                instance.eval_expressions();
+            }
+        }
+    );
+    assert!(instances.iter().all(|instance| instance.diagnostics().pending().next().is_none()), "every expression should evaluate");
+
+    println!("{} instances, {} expressions each, {} rounds", INSTANCES, EXPRESSIONS.len(), ROUNDS);
+    report("by name", by_name_allocations, by_name_time);
+    report("instance", instance_name_allocations, instance_name_time);
+    report("by slot", instance_slot_allocations, instance_slot_time);
+    println!
+    (
+        "speedup {:.2}x instance, {:.2}x by slot", 
+        by_name_time.as_secs_f64() / instance_name_time.as_secs_f64(), by_name_time.as_secs_f64() / instance_slot_time.as_secs_f64(),
+    );
+}
@@ -0,0 +1,145 @@
+//compares finding keyframes by scanning the keys, the way channels used to, against binary search with and without a cursor
+//
+//usage: cargo bench --bench keyframe_lookup
+//every bundled clip gets a looping playhead stepped along at 60 fps, and every linear channel in it is sampled each step.
+//the three paths have to come out bit for bit the same before any of them are timed
+
+#[allow(dead_code, unused)]
+#[path = "../src/logger.rs"]
+mod logger;
+#[allow(dead_code, unused)]
+#[path = "../src/pibald/mod.rs"]
+mod pibald;
+#[allow(dead_code, unused)]
+#[path = "../src/renderer/render_state/mod.rs"]
+mod render_state;
+#[allow(dead_code, unused)]
+#[path = "../src/renderer/data/mod.rs"]
+mod data;
+
+extern crate pest;
+#[macro_use]
+extern crate pest_derive;
+
+use std::hint::black_box;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use data::InMemoryModelRepository;
+use render_state::animation::{AnimationClip, ClipChannel, ClipCursor, KeyInterpolation, PlaybackType};
+use render_state::common::NormalizedFloat;
+
+//clip_0_aPose is left out, it predates the current clip layout and doesn't load
+const CLIPS: [&str; 5] = ["clip_idle", "clip_jump", "clip_run", "clip_test_action", "clip_walk"];
+const STEPS: usize = 2000;
+const STEP_SECONDS: f32 = 1.0 / 60.0;
+//what read_animation plays clips back at
+const CLIP_FPS: f32 = 30.0;
+
+//the lookup channels did before, a scan back for the start key and a scan forward for the end key. only linear keys are handled
+fn linear_scan(channel: &ClipChannel<f32>, min_frame: u16, max_frame: u16, t: NormalizedFloat, playback_type: PlaybackType) -> f32
+{
+    let (frames, values) = (channel.frames(), channel.values());
+    let f_range = (max_frame - min_frame) as f32;
+    let sample_frame = min_frame as f32 + t.get_val() * f_range;
+    let start = match frames.iter().rposition(|frame| (*frame as f32) <= sample_frame)
+    {
+        Some(i) => i,
+        None => match playback_type
+        {
+            PlaybackType::Sequential => 0,
+            PlaybackType::Looping => frames.len() - 1,
+        },
+    };
+    let end = match frames.iter().position(|frame| (*frame as f32) >= sample_frame)
+    {
+        Some(i) => i,
+        None => match playback_type
+        {
+            PlaybackType::Sequential => frames.len() - 1,
+            PlaybackType::Looping => 0,
+        },
+    };
+    let (f_start, f_end) = (frames[start] as f32, frames[end] as f32);
+    let (v_start, v_end) = (values[start], values[end]);
+    if frames[end] < frames[start]
+    {
+        let loop_sample = if sample_frame < f_start { sample_frame + f_range } else { sample_frame };
+        let phantom_end = f_end + f_range;
+        return v_start + (v_end - v_start) * ((loop_sample - f_start) / (phantom_end - f_start));
+    }
+    else if frames[start] == frames[end]
+    {
+        return v_start;
+    }
+    return v_start + (v_end - v_start) * ((sample_frame - f_start) / (f_end - f_start));
+}
+
+//steps every clip's playhead along and hands each sample time to sample, collecting what comes back
+fn measure(clips: &[(Rc<AnimationClip>, Vec<&ClipChannel<f32>>)], mut sample: impl FnMut(usize, &ClipChannel<f32>, u16, u16, NormalizedFloat) -> f32) -> (Vec<f32>, Duration)
+{
+    let mut out = Vec::with_capacity(STEPS * clips.iter().map(|(_, channels)| channels.len()).sum::<usize>());
+    let start = Instant::now();
+    for step in 0..STEPS
+    {
+        for (clip_index, (clip, channels)) in clips.iter().enumerate()
+        {
+            let (min_frame, max_frame) = clip.frame_range();
+            let span = (max_frame - min_frame).max(1) as f32;
+            let time = NormalizedFloat::wrapped((step as f32 * STEP_SECONDS * CLIP_FPS / span).fract());
+            for channel in channels
+            {
+                out.push(black_box(sample(clip_index, channel, min_frame, max_frame, time)));
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+    return (out, elapsed);
+}
+
+fn report(name: &str, samples: usize, elapsed: Duration)
+{
+    println!("{:<10} {:>10.2} ms total {:>8.1} ns/sample", name, elapsed.as_secs_f64() * 1000.0, elapsed.as_secs_f64() * 1e9 / samples as f64);
+}
+
+fn main()
+{
+    let mut repo = InMemoryModelRepository::new();
+    let arma_key = "rho_armature".to_string();
+    repo.load_armature(arma_key.clone(), Path::new("resources/rho.pibs"));
+    let clips = CLIPS.iter().map
+    (
+        |name|
+        {
+            repo.load_animation(name.to_string(), Path::new(&format!("resources/{}.piba", name)), &arma_key);
+            return repo.get_animation(&name.to_string()).expect("bundled clip should load");
+        }
+    ).collect::<Vec<_>>();
+    let channels = clips.iter().map
+    (
+        |clip|
+        {
+            let channels = clip.channels().filter(|channel| channel.interpolation().iter().all(|mode| *mode == KeyInterpolation::Linear)).collect::<Vec<_>>();
+            return (clip.clone(), channels);
+        }
+    ).collect::<Vec<_>>();
+    let keys = channels.iter().flat_map(|(_, channels)| channels.iter().map(|channel| channel.frames().len())).sum::<usize>();
+    let channel_count = channels.iter().map(|(_, channels)| channels.len()).sum::<usize>();
+
+    let (scanned, scan_time) = measure(&channels, |_, channel, min, max, time| linear_scan(channel, min, max, time, PlaybackType::Looping));
+    let (searched, search_time) = measure(&channels, |_, channel, min, max, time| channel.sample(min, max, time, PlaybackType::Looping, None));
+    //one cursor per playhead
+    let cursors = clips.iter().map(|_| ClipCursor::new()).collect::<Vec<_>>();
+    let (cached, cached_time) = measure(&channels, |clip, channel, min, max, time| channel.sample(min, max, time, PlaybackType::Looping, Some(&cursors[clip])));
+
+    let same = |a: &Vec<f32>, b: &Vec<f32>| a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.to_bits() == b.to_bits());
+    assert!(same(&scanned, &searched), "binary search should sample the same values as scanning");
+    assert!(same(&scanned, &cached), "sampling with a cursor should give the same values as scanning");
+
+    println!("{} clips, {} channels, {} keys, {} steps", clips.len(), channel_count, keys, STEPS);
+    report("scan", scanned.len(), scan_time);
+    report("search", searched.len(), search_time);
+    report("cursor", cached.len(), cached_time);
+    println!("speedup {:.2}x search, {:.2}x cursor", scan_time.as_secs_f64() / search_time.as_secs_f64(), scan_time.as_secs_f64() / cached_time.as_secs_f64());
+}
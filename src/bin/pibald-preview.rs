@@ -0,0 +1,246 @@
+//renders a .pib shader file to a png on the cpu so shaders can be checked without spinning up the renderer
+//
+//usage: pibald-preview <shader.pib> <output.png> [options]
+//  --size <width>x<height>            size of a single image, default 256x256
+//  --extent <val>                     width of the plane that gets rendered, centered on the origin. default 1.0
+//  --surface <plane|sphere>           surface to sample, either the z = 0 plane or a sphere filling the extent. default plane
+//  --param <name>=<val>               set a shader parameter. can be used more than once
+//  --sweep <name>=<start>:<end>       render a contact sheet sweeping a parameter from start to end
+//  --frames <count>                   number of frames in the sweep, default 9
+//  --columns <count>                  number of columns in the sweep, default fits the frames into a square
+
+#[path = "../pibald/mod.rs"]
+mod pibald;
+
+extern crate pest;
+#[macro_use]
+extern crate pest_derive;
+
+use std::{collections::HashMap, fs::File, io::BufWriter};
+use anyhow::{anyhow, Context};
+use glam::{Vec3, Vec4};
+use pibald::{PibaldEvaluator, ShapeShaderClass};
+
+struct PreviewArgs
+{
+    shader_path: String,
+    output_path: String,
+    width: u32,
+    height: u32,
+    extent: f32,
+    surface: Surface,
+    params: HashMap<String, f32>,
+    sweep: Option<Sweep>,
+}
+
+#[derive(Clone, Copy)]
+enum Surface
+{
+    Plane,
+    Sphere,
+}
+
+struct Sweep
+{
+    param: String,
+    start: f32,
+    end: f32,
+    frames: u32,
+    columns: Option<u32>,
+}
+
+pub fn main() -> anyhow::Result<()>
+{
+    let args = parse_args(std::env::args().skip(1).collect())?;
+    let source = std::fs::read_to_string(&args.shader_path).with_context(|| format!("Could not read {}", args.shader_path))?;
+    let shader = ShapeShaderClass::parse(&source)?;
+
+    let (image_width, image_height, pixels) = match &args.sweep
+    {
+        None =>
+        {
+            image_bytes(args.width, args.height)?;
+            let evaluator = PibaldEvaluator::new(&shader, &borrow_params(&args.params))?;
+            (args.width, args.height, render(&evaluator, args.width, args.height, args.extent, args.surface))
+        },
+        Some(sweep) =>
+        {
+            let columns = match sweep.columns
+            {
+                Some(columns) => columns,
+                None => (sweep.frames as f32).sqrt().ceil() as u32,
+            };
+            let rows = sweep.frames.div_ceil(columns);
+            let sheet_width = columns.checked_mul(args.width).ok_or_else(|| anyhow!("{} columns of {} pixels is too wide", columns, args.width))?;
+            let sheet_height = rows.checked_mul(args.height).ok_or_else(|| anyhow!("{} rows of {} pixels is too tall", rows, args.height))?;
+            let mut sheet = vec![0u8; image_bytes(sheet_width, sheet_height)?];
+            let mut params = args.params.clone();
+            for frame in 0..sweep.frames
+            {
+                let t = if sweep.frames > 1 { frame as f32 / (sweep.frames - 1) as f32 } else { 0.0 };
+                let val = sweep.start + (sweep.end - sweep.start) * t;
+                params.insert(sweep.param.clone(), val);
+                let evaluator = PibaldEvaluator::new(&shader, &borrow_params(&params)).with_context(|| format!("Failed on frame {} ({} = {})", frame, sweep.param, val))?;
+                let cell = render(&evaluator, args.width, args.height, args.extent, args.surface);
+                let cell_x = (frame % columns) as usize * args.width as usize;
+                let cell_y = (frame / columns) as usize * args.height as usize;
+                let row_len = args.width as usize * 4;
+                for row in 0..args.height as usize
+                {
+                    let src_start = row * row_len;
+                    let dst_start = ((cell_y + row) * sheet_width as usize + cell_x) * 4;
+                    sheet[dst_start..dst_start + row_len].copy_from_slice(&cell[src_start..src_start + row_len]);
+                }
+                println!("frame {}: {} = {}", frame, sweep.param, val);
+            }
+            (sheet_width, sheet_height, sheet)
+        },
+    };
+
+    write_png(&args.output_path, image_width, image_height, &pixels)?;
+    println!("wrote {}x{} image to {}", image_width, image_height, args.output_path);
+    return Ok(());
+}
+
+//bytes in an rgba image. checked, so sizes too big to hold fail instead of wrapping round
+fn image_bytes(width: u32, height: u32) -> anyhow::Result<usize>
+{
+    return (width as usize).checked_mul(height as usize).and_then(|pixels| pixels.checked_mul(4)).ok_or_else(|| anyhow!("A {}x{} image is too large", width, height));
+}
+
+fn borrow_params(params: &HashMap<String, f32>) -> HashMap<&str, f32>
+{
+    return params.iter().map(|(name, val)| (name.as_str(), *val)).collect();
+}
+
+//looks down -z with +y pointing up
+fn render(evaluator: &PibaldEvaluator, width: u32, height: u32, extent: f32, surface: Surface) -> Vec<u8>
+{
+    let radius = 0.5 * extent;
+    let mut pixels: Vec<u8> = Vec::with_capacity(width as usize * height as usize * 4);
+    let aspect = width as f32 / height as f32;
+    for y in 0..height
+    {
+        for x in 0..width
+        {
+            let u = ((x as f32 + 0.5) / width as f32 - 0.5) * extent;
+            let v = (0.5 - (y as f32 + 0.5) / height as f32) * extent / aspect;
+            let color = match surface
+            {
+                Surface::Plane => evaluator.sample(Vec3::new(u, v, 0.0), Vec3::Z),
+                Surface::Sphere =>
+                {
+                    let depth_sq = radius * radius - u * u - v * v;
+                    if depth_sq < 0.0
+                    {
+                        Vec4::ZERO
+                    }
+                    else
+                    {
+                        let point = Vec3::new(u, v, depth_sq.sqrt());
+                        evaluator.sample(point, point / radius)
+                    }
+                },
+            };
+            let bytes = (color.clamp(Vec4::ZERO, Vec4::ONE) * 255.0).round();
+            pixels.extend_from_slice(&[bytes.x as u8, bytes.y as u8, bytes.z as u8, bytes.w as u8]);
+        }
+    }
+    return pixels;
+}
+
+fn write_png(path: &str, width: u32, height: u32, pixels: &[u8]) -> anyhow::Result<()>
+{
+    let file = File::create(path).with_context(|| format!("Could not create {}", path))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+    return Ok(());
+}
+
+fn parse_args(args: Vec<String>) -> anyhow::Result<PreviewArgs>
+{
+    let mut positional: Vec<String> = vec![];
+    let mut width = 256;
+    let mut height = 256;
+    let mut extent = 1.0;
+    let mut surface = Surface::Plane;
+    let mut params: HashMap<String, f32> = HashMap::new();
+    let mut sweep: Option<(String, f32, f32)> = None;
+    let mut frames = 9;
+    let mut columns: Option<u32> = None;
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next()
+    {
+        match arg.as_str()
+        {
+            "--size" | "--extent" | "--surface" | "--param" | "--sweep" | "--frames" | "--columns" =>
+            {
+                let val = iter.next().ok_or_else(|| anyhow!("Missing value for {}", arg))?;
+                match arg.as_str()
+                {
+                    "--size" =>
+                    {
+                        let (w, h) = val.split_once('x').ok_or_else(|| anyhow!("Size should look like 256x256"))?;
+                        width = w.parse()?;
+                        height = h.parse()?;
+                    },
+                    "--extent" => extent = val.parse()?,
+                    "--surface" =>
+                    {
+                        surface = match val.as_str()
+                        {
+                            "plane" => Surface::Plane,
+                            "sphere" => Surface::Sphere,
+                            _ => return Err(anyhow!("Surface should be plane or sphere")),
+                        };
+                    },
+                    "--param" =>
+                    {
+                        let (name, param_val) = val.split_once('=').ok_or_else(|| anyhow!("Param should look like name=value"))?;
+                        params.insert(name.to_string(), param_val.parse()?);
+                    },
+                    "--sweep" =>
+                    {
+                        let (name, range) = val.split_once('=').ok_or_else(|| anyhow!("Sweep should look like name=start:end"))?;
+                        let (start, end) = range.split_once(':').ok_or_else(|| anyhow!("Sweep should look like name=start:end"))?;
+                        sweep = Some((name.to_string(), start.parse()?, end.parse()?));
+                    },
+                    "--frames" => frames = val.parse()?,
+                    _ => columns = Some(val.parse()?),
+                }
+            },
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() != 2
+    {
+        return Err(anyhow!("usage: pibald-preview <shader.pib> <output.png> [--size WxH] [--extent val] [--surface plane|sphere] [--param name=val] [--sweep name=start:end] [--frames count] [--columns count]"));
+    }
+    if width == 0 || height == 0 || frames == 0 || columns == Some(0)
+    {
+        return Err(anyhow!("Sizes and counts need to be greater than zero"));
+    }
+    let output_path = positional.pop().unwrap();
+    let shader_path = positional.pop().unwrap();
+    return Ok
+    (
+        PreviewArgs
+        {
+            shader_path: shader_path,
+            output_path: output_path,
+            width: width,
+            height: height,
+            extent: extent,
+            surface: surface,
+            params: params,
+            sweep: match sweep
+            {
+                Some((param, start, end)) => Some(Sweep { param: param, start: start, end: end, frames: frames, columns: columns }),
+                None => None,
+            },
+        }
+    );
+}
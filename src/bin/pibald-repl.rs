@@ -0,0 +1,216 @@
+//interactive prompt for poking at property expressions and pibald shaders without writing a throwaway test
+//
+//usage: pibald-repl
+//  <expr>               evaluate an expression, e.g. normalize(vec3(1, 2, 3)) or color(hp / maxhp, 0, 0, 1)
+//  let <name> = <expr>  evaluate and bind the result as a property other expressions can use
+//  :rpn <expr>          evaluate a postfix expression term by term, e.g. 1 2 3 vec3 normalize
+//  :props               list bound properties
+//  :load <shader.pib>   parse a shader for sampling. scalar properties are passed in as its parameters
+//  :sample <x> <y>      sample the loaded shader at (x, y, 0) facing +z
+//  :help                show this again
+//  :quit                exit
+
+#[path = "../pibald/mod.rs"]
+mod pibald;
+#[path = "../renderer/render_state/properties.rs"]
+mod properties;
+
+extern crate pest;
+#[macro_use]
+extern crate pest_derive;
+
+use std::{collections::HashMap, io::{self, BufRead, Write}};
+use anyhow::{anyhow, Context};
+use glam::Vec3;
+use pibald::{PibaldEvaluator, ShapeShaderClass};
+use properties::{EvaluationError, Expression, PropertyGroup, Value};
+
+const HELP: &str = "\
+<expr>               evaluate an expression, e.g. normalize(vec3(1, 2, 3))
+let <name> = <expr>  evaluate and bind the result as a property
+:rpn <expr>          evaluate a postfix expression, e.g. 1 2 3 vec3 normalize
+:props               list bound properties
+:load <shader.pib>   parse a shader for sampling
+:sample <x> <y>      sample the loaded shader at (x, y, 0)
+:help                show this again
+:quit                exit
+
+operators: + - * / % ^ == != < <= > >= && || and unary - !
+functions: vec2 vec3 vec4 color quat(axis, angle) mat3/mat4(translation, rotation, scale)
+           row col entry swizzle dot cross sin cos tan log normalize inverse transpose
+           select(condition, a, b) int float
+transform: trs(translation, rotation, scale) look_at(eye, target, up) slerp/nlerp(from, to, t)
+           transform_point/transform_vector(transform, v) translation_of rotation_of scale_of
+           quat_axis_angle(axis, angle) quat_euler(vec3) euler(quat)
+literals:  1.5 PI EULER true vec3[1, 0, 0] color[1, 0, 0, 1] #ff8800 mat4[16 values, column order]
+access:    v.x v.zyx c.rgb";
+
+struct Repl
+{
+    properties: PropertyGroup,
+    shader: Option<ShapeShaderClass>,
+}
+
+pub fn main() -> anyhow::Result<()>
+{
+    let mut repl = Repl { properties: PropertyGroup::new(), shader: None };
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    println!("pibald repl. :help for commands");
+    loop
+    {
+        print!("> ");
+        io::stdout().flush()?;
+        let line = match lines.next()
+        {
+            Some(line) => line?,
+            None => break,
+        };
+        let line = line.trim();
+        if line.is_empty()
+        {
+            continue;
+        }
+        if line == ":quit" || line == ":q"
+        {
+            break;
+        }
+        //nothing typed at the prompt should be able to end the session
+        if let Err(err) = repl.run_line(line)
+        {
+            println!("{:#}", err);
+        }
+    }
+    return Ok(());
+}
+
+impl Repl
+{
+    fn run_line(&mut self, line: &str) -> anyhow::Result<()>
+    {
+        if let Some(command) = line.strip_prefix(':')
+        {
+            let (name, arg) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+            return match name
+            {
+                "help" => 
+                {
+                    println!("{}", HELP);
+                    Ok(())
+                },
+                "props" => 
+                {
+                    let mut names = self.properties.get_properties().collect::<Vec<_>>();
+                    names.sort_by(|a, b| a.0.cmp(b.0));
+                    for (name, val) in names
+                    {
+                        println!("{}: {} = {}", name, val.type_name(), val);
+                    }
+                    Ok(())
+                },
+                "rpn" => 
+                {
+                    let val = self.evaluate(Expression::from_postfix(0, arg)?)?;
+                    println!("{} = {}", val.type_name(), val);
+                    Ok(())
+                },
+                "load" => self.load(arg.trim()),
+                "sample" => self.sample(arg),
+                _ => Err(anyhow!("Unknown command :{}. Try :help", name)),
+            };
+        }
+        if let Some(binding) = line.strip_prefix("let ")
+        {
+            let (name, expr) = binding.split_once('=').ok_or_else(|| anyhow!("Bindings should look like let name = expr"))?;
+            let name = name.trim();
+            if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_')
+            {
+                return Err(anyhow!("\"{}\" can't be used as a property name", name));
+            }
+            let val = self.evaluate(parse_with_caret(expr, line.len() - expr.len())?)?;
+            self.properties.add_property(name, val);
+            println!("{}: {} = {}", name, val.type_name(), val);
+            return Ok(());
+        }
+        let val = self.evaluate(parse_with_caret(line, 0)?)?;
+        println!("{} = {}", val.type_name(), val);
+        return Ok(());
+    }
+
+    fn evaluate(&self, expr: Expression) -> anyhow::Result<Value>
+    {
+        return expr.evaluate(&self.properties).map_err(|err| anyhow!("{}: {}", error_kind(&err), err));
+    }
+
+    fn load(&mut self, path: &str) -> anyhow::Result<()>
+    {
+        if path.is_empty()
+        {
+            return Err(anyhow!("usage: :load <shader.pib>"));
+        }
+        let source = std::fs::read_to_string(path).with_context(|| format!("Could not read {}", path))?;
+        self.shader = Some(ShapeShaderClass::parse(&source)?);
+        println!("loaded {}", path);
+        return Ok(());
+    }
+
+    fn sample(&self, args: &str) -> anyhow::Result<()>
+    {
+        let shader = self.shader.as_ref().ok_or_else(|| anyhow!("No shader loaded. Use :load <shader.pib> first"))?;
+        let coords = args.split_whitespace().map(|arg| arg.parse::<f32>()).collect::<Result<Vec<_>, _>>()?;
+        if coords.len() != 2
+        {
+            return Err(anyhow!("usage: :sample <x> <y>"));
+        }
+        //pibald parameters are all scalars, so ints get converted and anything else bound is left out
+        let params = self.properties.get_properties().filter_map
+        (
+            |(name, val)| match val
+            {
+                Value::Scalar(s_val) => Some((name.as_str(), *s_val)),
+                Value::Int(i_val) => Some((name.as_str(), *i_val as f32)),
+                _ => None,
+            }
+        ).collect::<HashMap<&str, f32>>();
+        let evaluator = PibaldEvaluator::new(shader, &params)?;
+        let color = evaluator.sample(Vec3::new(coords[0], coords[1], 0.0), Vec3::Z);
+        println!("{}", Value::Color(color));
+        return Ok(());
+    }
+}
+
+//offset is where the expression starts in the typed line, so the caret lands under the prompt text
+fn parse_with_caret(input: &str, offset: usize) -> anyhow::Result<Expression>
+{
+    return Expression::parse(0, input).map_err
+    (
+        |err| 
+        {
+            match err.span()
+            {
+                Some(span) => 
+                {
+                    let start = offset + input[..span.start].chars().count();
+                    let len = input[span.start..span.end].chars().count().max(1);
+                    anyhow!("  {}{}\n{}", " ".repeat(start), "^".repeat(len), err)
+                },
+                None => anyhow!("{}", err),
+            }
+        }
+    );
+}
+
+fn error_kind(err: &EvaluationError) -> &str
+{
+    return match err
+    {
+        EvaluationError::InvalidIdentifier { .. } => "unbound identifier",
+        EvaluationError::DivideByZeroError => "divide by zero",
+        EvaluationError::TypeMismatchError { .. } => "type mismatch",
+        EvaluationError::ValueUnderflowError { .. } => "stack underflow",
+        EvaluationError::DegenerateMatrixError => "degenerate matrix",
+        EvaluationError::DegenerateDirectionError { .. } => "degenerate direction",
+        EvaluationError::IndexOutOfBoundsError { .. } => "index out of bounds",
+        EvaluationError::InvalidOutputSize { .. } => "invalid output size",
+    };
+}
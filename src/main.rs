@@ -9,13 +9,13 @@ extern crate pest_derive;
 
 extern crate sdl2; 
 use sdl2::{event::Event, keyboard::Keycode};
-use std::{time::Duration, rc::Rc, f32::consts::PI, ops::Add, str::FromStr, path::{Path, PathBuf}};
+use std::{time::{Duration, Instant}, rc::Rc, f32::consts::PI, ops::Add, str::FromStr, path::{Path, PathBuf}};
 use logger::{ConsoleLogger, Logger};
 
 use glam::{Vec3, Mat4, Vec4, Quat};
 
 
-use crate::{window::SDLWindow, renderer::{render_state::{render_state::RenderState, view::CameraDescriptor, light::{PointLightDescriptor}, animation::{AnimationState, AnimationStateBuilder, MixerBuilder, SimpleMixerBuilder, PlaybackType}}, gpu::{renderer::Renderer, gpu_store::GPUStore}, data::InMemoryModelRepository}};
+use crate::{window::SDLWindow, renderer::{render_state::{render_state::RenderState, view::CameraDescriptor, light::{PointLightDescriptor}, common::NormalizedFloat, animation::{AnimationState, AnimationStateBuilder, AnimationParameter, ArmatureMask, IkConstraint, LayerBuilder, MixerBuilder, SimpleMixerBuilder, SpringBone, PlaybackType, StateMachineBuilder, StateTransition, TransitionCondition}}, gpu::{renderer::Renderer, gpu_store::GPUStore}, data::{InMemoryModelRepository, ReloadedAsset}}};
 
 /*impl render::RenderWindow for SDLWindow
 {
@@ -93,6 +93,8 @@ pub fn main()
 
     //get a test model
     let mut repo = InMemoryModelRepository::new();
+    let hot_reload = std::env::args().any(|arg| arg == "--hot-reload");
+    repo.set_hot_reload(hot_reload);
     let mut path_buf = PathBuf::new();
     path_buf.push("resources");
     path_buf.push("axes.pibm");
@@ -109,15 +111,61 @@ pub fn main()
     path_buf.pop();
     path_buf.push("clip_walk.piba");
     repo.load_animation("walk".to_string(), path_buf.as_path(), &"rho_armature".to_string());
+    path_buf.pop();
+    path_buf.push("clip_run.piba");
+    repo.load_animation("run".to_string(), path_buf.as_path(), &"rho_armature".to_string());
+    path_buf.pop();
+    path_buf.push("clip_test_action.piba");
+    repo.load_animation("wave".to_string(), path_buf.as_path(), &"rho_armature".to_string());
+    let mut wave_layer = LayerBuilder::create_builder("wave");
+    wave_layer.add_mixer(MixerBuilder::Simple(SimpleMixerBuilder::new("wave", repo.get_animation(&"wave".to_string()).unwrap()).playback_type(PlaybackType::Looping)))
+        .starting_mixer("wave")
+        .init_weight(NormalizedFloat::zero())
+        .mask(ArmatureMask::new().subtree("DEF-upper_arm.r", NormalizedFloat::one()));
     let anim_state = AnimationStateBuilder::create_builder
     (
         repo.get_armature(&"rho_armature".to_string()).unwrap(), 
         vec!
         [
             MixerBuilder::Simple(SimpleMixerBuilder::new("idle", repo.get_animation(&"idle".to_string()).unwrap()).playback_type(PlaybackType::Looping)),
-            MixerBuilder::Simple(SimpleMixerBuilder::new("walk", repo.get_animation(&"walk".to_string()).unwrap()).playback_type(PlaybackType::Looping)),
+            //the walk's feet come down on frames 9 and 25 of 1 to 32
+            MixerBuilder::Simple
+            (
+                SimpleMixerBuilder::new("walk", repo.get_animation(&"walk".to_string()).unwrap()).playback_type(PlaybackType::Looping)
+                    .add_event("right_foot_down", NormalizedFloat::clamped(8.0 / 31.0))
+                    .add_event("left_foot_down", NormalizedFloat::clamped(24.0 / 31.0))
+            ),
+            MixerBuilder::Simple(SimpleMixerBuilder::new("run", repo.get_animation(&"run".to_string()).unwrap()).playback_type(PlaybackType::Looping)),
         ]
-    ).base_starting_animation("idle").build();
+    )
+    .add_parameter("walking", AnimationParameter::Bool(false))
+    .add_parameter("running", AnimationParameter::Bool(false))
+    .base_state_machine
+    (
+        StateMachineBuilder::new("idle")
+            .add_state("idle", "idle")
+            .add_state("walk", "walk")
+            .add_state("run", "run")
+            .add_transition(StateTransition::new("idle", "walk").when(TransitionCondition::IsTrue("walking".to_string())).blend_duration(Duration::from_millis(300)))
+            .add_transition(StateTransition::new("walk", "idle").when(TransitionCondition::IsFalse("walking".to_string())).blend_duration(Duration::from_millis(300)))
+            .add_transition(StateTransition::new("walk", "run").when(TransitionCondition::IsTrue("running".to_string())).blend_duration(Duration::from_millis(250)).sync_phase())
+            .add_transition(StateTransition::new("run", "idle").when(TransitionCondition::IsFalse("walking".to_string())).blend_duration(Duration::from_millis(300)))
+            .add_transition(StateTransition::new("run", "walk").when(TransitionCondition::IsFalse("running".to_string())).blend_duration(Duration::from_millis(250)).sync_phase())
+    )
+    .add_layer(wave_layer)
+    //rho keeps an eye on the camera, within what a neck can manage
+    .add_ik(IkConstraint::aim("look", "DEF-head", -Vec3::Z).weight(NormalizedFloat::clamped(0.8)).limit("DEF-head", 60.0 * PI / 180.0))
+    //hair and ears trail the head a little, the antlers only barely, and the tail swings from the hips
+    .add_spring(SpringBone::new("DEF-hair_lock_front.l").stiffness(60.0).gravity(-9.8 * Vec3::Y))
+    .add_spring(SpringBone::new("DEF-hair_lock_front.r").stiffness(60.0).gravity(-9.8 * Vec3::Y))
+    .add_spring(SpringBone::new("DEF-hair_lock_back.l").stiffness(60.0).gravity(-9.8 * Vec3::Y))
+    .add_spring(SpringBone::new("DEF-hair_lock_back.r").stiffness(60.0).gravity(-9.8 * Vec3::Y))
+    .add_spring(SpringBone::new("DEF-ear.l").stiffness(150.0).max_angle(20.0 * PI / 180.0))
+    .add_spring(SpringBone::new("DEF-ear.r").stiffness(150.0).max_angle(20.0 * PI / 180.0))
+    .add_spring(SpringBone::new("DEF-antler.l").stiffness(400.0).max_angle(5.0 * PI / 180.0))
+    .add_spring(SpringBone::new("DEF-antler.r").stiffness(400.0).max_angle(5.0 * PI / 180.0))
+    .add_spring(SpringBone::subtree("DEF-tail_upper").stiffness(80.0).gravity(-9.8 * Vec3::Y))
+    .build();
     let anim_model = repo.get_animated_model(&"rho_anim".to_string()).unwrap();
     let model = repo.get_static_model(&"axes".to_string()).unwrap();
     let mut gpu_store = GPUStore::new();
@@ -147,14 +195,14 @@ pub fn main()
     let armature = repo.get_armature(&"rho_armature".to_string()).unwrap();
     {
         let mut arma_tfs = vec![];
-        for i in 0..armature.bones.len()
+        for i in 0..armature.bones().len()
         {
-            let arma_parent = match armature.bones[i].parent 
+            let arma_parent = match armature.bones()[i].parent 
             {
                 Some(parent) => arma_tfs[parent],
                 None => Mat4::IDENTITY,
             };
-            arma_tfs.push(arma_parent * armature.bones[i].local_tf);
+            arma_tfs.push(arma_parent * armature.bones()[i].local_tf);
             arma_ids.push(state.add_static_model(group_id, model, arma_tfs[i] * Mat4::from_scale(Vec3::ONE * 0.2)).unwrap());
         }
     }*/
@@ -171,9 +219,36 @@ pub fn main()
     let mut back = false;
     let mut left = false;
     let mut right = false;
+    let logger = ConsoleLogger;
+    let mut last_reload_poll = Instant::now();
 
     'running: loop
     {
+        if hot_reload && last_reload_poll.elapsed() >= Duration::from_secs(1)
+        {
+            last_reload_poll = Instant::now();
+            for asset in repo.poll_hot_reload(&logger)
+            {
+                match asset
+                {
+                    ReloadedAsset::StaticModel(key) =>
+                    {
+                        let model = repo.get_static_model(&key).unwrap();
+                        gpu_store.reload_static_model(model, &device);
+                        state.reload_static_model(model);
+                    },
+                    ReloadedAsset::AnimatedModel(key) =>
+                    {
+                        let model = repo.get_animated_model(&key).unwrap();
+                        gpu_store.reload_skinned_model(model, &device);
+                        state.reload_animated_model(model);
+                    },
+                    ReloadedAsset::Armature { old, new } => state.replace_armature(&old, &new),
+                    ReloadedAsset::Animation { old, new } => state.replace_animation_clip(&old, &new),
+                    ReloadedAsset::Shader { old, new } => state.replace_shader(&old, &new),
+                }
+            }
+        }
         let output = surface.get_current_texture().unwrap();
         let target = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
         renderer.set_output_view(cam_id, target);
@@ -229,11 +304,27 @@ pub fn main()
                 },
                 Event::KeyDown { keycode: Some(Keycode::E), .. } =>
                 {
-                    state.get_group_mut(group_id).unwrap().get_animated_model_mut(anim_mod).unwrap().anim_state_mut().base_layer.queue_clip_mixer("walk", Duration::from_millis(300));
+                    state.get_group_mut(group_id).unwrap().get_animated_model_mut(anim_mod).unwrap().anim_state_mut().set_bool("walking", true).unwrap();
                 }
                 Event::KeyDown { keycode: Some(Keycode::R), .. } =>
                 {
-                    state.get_group_mut(group_id).unwrap().get_animated_model_mut(anim_mod).unwrap().anim_state_mut().base_layer.queue_clip_mixer("idle", Duration::from_millis(300));
+                    state.get_group_mut(group_id).unwrap().get_animated_model_mut(anim_mod).unwrap().anim_state_mut().set_bool("walking", false).unwrap();
+                }
+                Event::KeyDown { keycode: Some(Keycode::LShift), .. } =>
+                {
+                    state.get_group_mut(group_id).unwrap().get_animated_model_mut(anim_mod).unwrap().anim_state_mut().set_bool("running", true).unwrap();
+                }
+                Event::KeyUp { keycode: Some(Keycode::LShift), .. } =>
+                {
+                    state.get_group_mut(group_id).unwrap().get_animated_model_mut(anim_mod).unwrap().anim_state_mut().set_bool("running", false).unwrap();
+                }
+                Event::KeyDown { keycode: Some(Keycode::Q), repeat: false, .. } =>
+                {
+                    state.get_group_mut(group_id).unwrap().get_animated_model_mut(anim_mod).unwrap().anim_state_mut().fade_layer_weight("wave", NormalizedFloat::one(), Duration::from_millis(250));
+                }
+                Event::KeyUp { keycode: Some(Keycode::Q), .. } =>
+                {
+                    state.get_group_mut(group_id).unwrap().get_animated_model_mut(anim_mod).unwrap().anim_state_mut().fade_layer_weight("wave", NormalizedFloat::zero(), Duration::from_millis(250));
                 }
                 Event::MouseMotion { xrel, yrel, .. } =>
                 {
@@ -265,7 +356,29 @@ pub fn main()
         
         test_tf = test_tf * Mat4::from_quat(Quat::from_axis_angle(Vec3::Y, 0.05));
         //state.get_group_mut(group_id).unwrap().get_static_model_mut(stat_mod).unwrap().set_transform(test_tf);
-        state.get_group_mut(group_id).unwrap().get_animated_model_mut(anim_mod).unwrap().update(1.0/60.0);
+        {
+            let group = state.get_group_mut(group_id).unwrap();
+            let eye = group.get_camera(cam_id).unwrap().location();
+            let rho = group.get_animated_model_mut(anim_mod).unwrap();
+            let look_target = rho.transform().inverse().transform_point3(eye);
+            rho.anim_state_mut().set_ik_target("look", look_target);
+        }
+        if let Err(err) = state.get_group_mut(group_id).unwrap().get_animated_model_mut(anim_mod).unwrap().update(1.0/60.0)
+        {
+            logger.log_error(&err.to_string());
+        }
+        for static_model in state.get_group_mut(group_id).unwrap().get_static_models_mut()
+        {
+            if let Err(err) = static_model.update(1.0/60.0)
+            {
+                logger.log_error(&err.to_string());
+            }
+        }
+        state.get_group_mut(group_id).unwrap().get_animated_model_mut(anim_mod).unwrap().apply_root_motion();
+        for event in state.get_group_mut(group_id).unwrap().get_animated_model_mut(anim_mod).unwrap().anim_state_mut().drain_events()
+        {
+            logger.log_debug(&format!("{} from {} at {:.2}", event.name, event.mixer, event.weight));
+        }
         renderer.push_buffer_updates(&state, &device, &queue);
         state.clear_dirty_state();
         renderer.render(&state, &gpu_store, &device, &queue);
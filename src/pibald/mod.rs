@@ -1,6 +1,8 @@
 mod test;
 use std::collections::HashMap;
+use std::f32::consts::PI;
 use std::fmt;
+use glam::{Vec2, Vec3, Vec4, Mat3, Mat4, Quat, Vec4Swizzles};
 use pest::Parser;
 use pest::iterators::Pair;
 
@@ -9,13 +11,14 @@ use pest::iterators::Pair;
 struct PibaldParser;
 
 #[derive(Debug)]
-enum PibaldError
+pub enum PibaldError
 {
-    ParseError,
+    ParseError(String),
     UnknownIdentifierError,
     MissingArgumentError(String),
     DivideByZeroError,
     InvalidExpressionError(String),
+    DegenerateTransformError,
 }
 
 impl std::error::Error for PibaldError {}
@@ -26,11 +29,12 @@ impl fmt::Display for PibaldError
     {
         match self 
         {
-            PibaldError::ParseError => write!(f, "ParseError!"),
+            PibaldError::ParseError(desc) => write!(f, "ParseError!\n{}", desc),
             PibaldError::UnknownIdentifierError => write!(f, "Undefined identifier."),
             PibaldError::DivideByZeroError => write!(f, "Division by zero"),
             PibaldError::MissingArgumentError(param) => write!(f, "No argumant found for parameter \"{}\"", param),
-            PibaldError::InvalidExpressionError(desc) => write!(f, "Invalid scalar expression: {}", desc)
+            PibaldError::InvalidExpressionError(desc) => write!(f, "Invalid scalar expression: {}", desc),
+            PibaldError::DegenerateTransformError => write!(f, "Transform could not be inverted."),
         }
     }
 }
@@ -42,15 +46,7 @@ impl PibaldParser
         let mut pairs = match PibaldParser::parse(Rule::pibald, input) 
         {
             Ok(res) => res,
-            //todo - actually bubble this error message up correctly
-            Err(err) => 
-            {
-                println!
-                (
-                    "{}", err
-                );
-                return Err(PibaldError::ParseError)
-            },
+            Err(err) => return Err(PibaldError::ParseError(err.to_string())),
         };
         let mut maps : Vec<ColorMap> = vec![];
         let shader_pairs = pairs.next().unwrap().into_inner();
@@ -111,7 +107,7 @@ impl PibaldParser
                                             grad_pairs.next(); //L_PAREN
                                             let distance = ScalarExpression::new(PibaldParser::parse_scalar_expr(grad_pairs.next().unwrap())); //distance
                                             grad_pairs.next(); //COMMA
-                                            let extrapolation_type = match grad_pairs.next().unwrap().as_rule()
+                                            let extrapolation_type = match grad_pairs.next().unwrap().into_inner().next().unwrap().as_rule()
                                             {
                                                 Rule::LAST_COLOR => GradientExtrapolation::LastColor(),
                                                 Rule::REPEAT => GradientExtrapolation::Repeat(),
@@ -132,7 +128,7 @@ impl PibaldParser
                                                         color_point_pairs.next();//COMMA
                                                         let point_dist = ScalarExpression::new(PibaldParser::parse_scalar_expr(color_point_pairs.next().unwrap()));
                                                         color_point_pairs.next();//COMMA
-                                                        let interpolation = match color_point_pairs.next().unwrap().as_rule()
+                                                        let interpolation = match color_point_pairs.next().unwrap().into_inner().next().unwrap().as_rule()
                                                         {
                                                             Rule::STEP => GradientInterpolation::Step(),
                                                             Rule::LINEAR => GradientInterpolation::Linear(),
@@ -151,6 +147,7 @@ impl PibaldParser
                                             else
                                             {
                                                 grad = gradient;
+                                                has_first_gradient = true;
                                             }
                                         },
                                         Rule::val_map =>
@@ -183,20 +180,42 @@ impl PibaldParser
                     placement_pairs.next();//L_PAREN
                     let mat = PibaldParser::parse_matrix(placement_pairs.next().unwrap());
                     placement_pairs.next(); //COMMA
-                    let index = placement_pairs.next().unwrap().as_str().trim().parse::<u32>().unwrap();
-                    match det_pair.as_rule()
+                    //the index is a run of DIGIT pairs, so gather all of them before moving on to the optional offset
+                    let mut index_str = String::new();
+                    let mut offset: Option<Vector> = None;
+                    let mut projection = Projection::Planar();
+                    let mut space = ProjectionSpace::Object();
+                    let mut bone: Option<String> = None;
+                    for arg_pair in placement_pairs
                     {
-                        Rule::SINGULAR => 
-                        {
-                            placements.push(Placement { index: index, tf: mat, variant: PlacementVariant::Singular() });
-                        },
-                        Rule::TILE_PATTERN => 
+                        match arg_pair.as_rule()
                         {
-                            let offset = PibaldParser::parse_vector3(placement_pairs.next().unwrap());
-                            placements.push(Placement { index: index, tf: mat, variant: PlacementVariant::TilePattern(offset)})
-                        },
-                        _ => (),
+                            Rule::DIGIT => index_str.push_str(arg_pair.as_str().trim()),
+                            Rule::vec3 => offset = Some(PibaldParser::parse_vector3(arg_pair)),
+                            Rule::placement_option => 
+                            {
+                                let option_pair = arg_pair.into_inner().next().unwrap();
+                                match option_pair.as_rule()
+                                {
+                                    Rule::projection => (projection, space) = PibaldParser::parse_projection(option_pair),
+                                    Rule::bone => 
+                                    {
+                                        let name_pair = option_pair.into_inner().find(|p| p.as_rule() == Rule::STRING).unwrap();
+                                        bone = Some(name_pair.as_str().trim_matches('"').to_string());
+                                    },
+                                    _ => (),
+                                }
+                            },
+                            _ => (),
+                        }
                     }
+                    let index = index_str.parse::<u32>().unwrap();
+                    let variant = match det_pair.as_rule()
+                    {
+                        Rule::TILE_PATTERN => PlacementVariant::TilePattern(offset.unwrap()),
+                        _ => PlacementVariant::Singular(),
+                    };
+                    placements.push(Placement { index: index, tf: mat, variant: variant, projection: projection, space: space, bone: bone });
                 },
                 (_) => (),
             }
@@ -364,28 +383,34 @@ impl PibaldParser
     fn parse_matrix(pair: Pair<Rule>) -> Matrix
     {
         let mut loc: Option<Vector> = None;
-        let mut rot: Option<Vector> = None;
+        let mut rot: Vec<ParamQuat> = vec![];
         let mut scale: Option<Vector> = None;
         let mut shear: Option<Vector> = None;
+        //the keyword that precedes each bracketed argument decides where the argument goes
+        let mut arg_rule: Option<Rule> = None;
         for mat_pair in pair.into_inner()
         {
             match mat_pair.as_rule() 
             {
-                /*Rule::tf_arg =>
+                Rule::TRANSLATION | Rule::ROTATION | Rule::SCALE | Rule::SHEAR =>
                 {
-                    let mut param_pair = mat_pair.into_inner();
-                    let param = param_pair.next().unwrap(); //param_name
-                    param_pair.next(); //LPAREN
-                    let vec = PibaldParser::parse_vector3(param_pair.next().unwrap());
-                    match param.as_rule()
+                    arg_rule = Some(mat_pair.as_rule());
+                },
+                Rule::vec3 =>
+                {
+                    let vec = PibaldParser::parse_vector3(mat_pair);
+                    match arg_rule
                     {
-                        Rule::TRANSLATION => loc = Some(vec),
-                        Rule::ROTATION => rot = Some(vec),
-                        Rule::SCALE => scale = Some(vec),
-                        Rule::SHEAR => shear = Some(vec),
+                        Some(Rule::TRANSLATION) => loc = Some(vec),
+                        Some(Rule::SCALE) => scale = Some(vec),
+                        Some(Rule::SHEAR) => shear = Some(vec),
                         _ => (),
                     }
-                },*/
+                },
+                Rule::quat =>
+                {
+                    rot = PibaldParser::parse_quat(mat_pair);
+                },
                 Rule::ID => 
                 {
                     return Matrix::IdMatrix(mat_pair.as_str().to_string());
@@ -402,15 +427,11 @@ impl PibaldParser
                     Some(vec) => vec, 
                     None => Vector::ParamVector(ParamVector::from_constant([0.0,0.0,0.0])),
                 }, 
-                rotation: match rot 
-                {
-                    Some(vec) => vec, 
-                    None => Vector::ParamVector(ParamVector::from_constant([0.0,0.0,0.0])),
-                },
+                rotation: rot,
                 scale: match scale
                 {
                     Some(vec) => vec, 
-                    None => Vector::ParamVector(ParamVector::from_constant([0.0,0.0,0.0])),
+                    None => Vector::ParamVector(ParamVector::from_constant([1.0,1.0,1.0])),
                 }, 
                 shear: match shear 
                 {
@@ -421,6 +442,50 @@ impl PibaldParser
         );
     }
 
+    fn parse_projection(pair: Pair<Rule>) -> (Projection, ProjectionSpace)
+    {
+        let mut proj_pairs = pair.into_inner();
+        let proj_rule = proj_pairs.next().unwrap().as_rule();
+        proj_pairs.next(); //L_PAREN
+        let space = match proj_pairs.next().unwrap().into_inner().next().unwrap().as_rule()
+        {
+            Rule::WORLD_SPACE => ProjectionSpace::World(),
+            _ => ProjectionSpace::Object(),
+        };
+        proj_pairs.next(); //COMMA
+        let projection = match proj_rule
+        {
+            Rule::PROJ_TRIPLANAR => Projection::Triplanar(ScalarExpression::new(PibaldParser::parse_scalar_expr(proj_pairs.next().unwrap()))),
+            Rule::PROJ_SPHERICAL => Projection::Spherical(),
+            Rule::PROJ_CYLINDRICAL => Projection::Cylindrical(PibaldParser::parse_vector3(proj_pairs.next().unwrap())),
+            _ => Projection::Planar(),
+        };
+        return (projection, space);
+    }
+
+    fn parse_quat(pair: Pair<Rule>) -> Vec<ParamQuat>
+    {
+        let mut quats: Vec<ParamQuat> = vec![];
+        for quat_pair in pair.into_inner()
+        {
+            match quat_pair.as_rule()
+            {
+                Rule::quat_primary =>
+                {
+                    let mut primary_pairs = quat_pair.into_inner();
+                    primary_pairs.next(); //QUAT
+                    primary_pairs.next(); //L_PAREN
+                    let axis = PibaldParser::parse_vector3(primary_pairs.next().unwrap());
+                    primary_pairs.next(); //COMMA
+                    let angle = ScalarExpression::new(PibaldParser::parse_scalar_expr(primary_pairs.next().unwrap()));
+                    quats.push(ParamQuat { axis: axis, angle: angle });
+                },
+                _ => (),
+            }
+        }
+        return quats;
+    }
+
     fn parse_vector3(pair: Pair<Rule>) -> Vector
     {
         let mut data: Vec<ScalarExpression> = vec![];
@@ -494,7 +559,7 @@ impl PibaldParser
         let mut sdf_stack: Vec<SDFTerm> = vec![];
         let mut expr_pairs = pair.into_inner();
         let expr_det_pair = expr_pairs.next().unwrap();
-        match expr_pairs.next().unwrap().as_rule()
+        match expr_det_pair.as_rule()
         {
             Rule::operator => 
             {
@@ -539,11 +604,19 @@ impl PibaldParser
                 expr_pairs.next();//L_PAREN
                 let mat = PibaldParser::parse_matrix(expr_pairs.next().unwrap());
                 expr_pairs.next();//COMMA
-                let height = ScalarExpression::new(PibaldParser::parse_scalar_expr(expr_pairs.next().unwrap()));
-                expr_pairs.next();//COMMA
                 let width = ScalarExpression::new(PibaldParser::parse_scalar_expr(expr_pairs.next().unwrap()));
+                expr_pairs.next();//COMMA
+                let height = ScalarExpression::new(PibaldParser::parse_scalar_expr(expr_pairs.next().unwrap()));
                 sdf_stack.push( SDFTerm::Operand( SDFOperand::Rectangle( mat, width, height ) ) );
             },
+            Rule::SD_SPHERE =>
+            {
+                expr_pairs.next();//L_PAREN
+                let mat = PibaldParser::parse_matrix(expr_pairs.next().unwrap());
+                expr_pairs.next();//COMMA
+                let radius = ScalarExpression::new(PibaldParser::parse_scalar_expr(expr_pairs.next().unwrap()));
+                sdf_stack.push( SDFTerm::Operand( SDFOperand::Sphere( mat, radius ) ) );
+            },
             Rule::SD_PLANE => 
             {
                 expr_pairs.next();//L_PAREN
@@ -554,14 +627,14 @@ impl PibaldParser
             {
                 expr_pairs.next();//L_PAREN
                 let mat = PibaldParser::parse_matrix(expr_pairs.next().unwrap());
-                let poly_points: Vec<ParamVector2> = vec![];
+                let mut poly_points: Vec<ParamVector2> = vec![];
                 for vec_pair in expr_pairs
                 {
                     match vec_pair.as_rule()
                     {
                         Rule::vec2 => 
                         {
-                            PibaldParser::parse_vector2(vec_pair);
+                            poly_points.push(PibaldParser::parse_vector2(vec_pair));
                         },
                         _ => ()
                     }
@@ -574,7 +647,9 @@ impl PibaldParser
                 let mat = PibaldParser::parse_matrix(expr_pairs.next().unwrap());
                 expr_pairs.next();//COMMA
                 let radius = ScalarExpression::new(PibaldParser::parse_scalar_expr(expr_pairs.next().unwrap()));
-                sdf_stack.push( SDFTerm::Operand( SDFOperand::RegularPolygon( mat, radius ) ) );
+                expr_pairs.next();//COMMA
+                let num_points = ScalarExpression::new(PibaldParser::parse_scalar_expr(expr_pairs.next().unwrap()));
+                sdf_stack.push( SDFTerm::Operand( SDFOperand::RegularPolygon( mat, radius, num_points ) ) );
                 
             },
             Rule::SD_POLYSTAR => 
@@ -585,7 +660,9 @@ impl PibaldParser
                 let outer_radius = ScalarExpression::new(PibaldParser::parse_scalar_expr(expr_pairs.next().unwrap()));
                 expr_pairs.next();//COMMA
                 let inner_radius = ScalarExpression::new(PibaldParser::parse_scalar_expr(expr_pairs.next().unwrap()));
-                sdf_stack.push( SDFTerm::Operand( SDFOperand::PolyStar( mat, outer_radius, inner_radius ) ) );
+                expr_pairs.next();//COMMA
+                let num_points = ScalarExpression::new(PibaldParser::parse_scalar_expr(expr_pairs.next().unwrap()));
+                sdf_stack.push( SDFTerm::Operand( SDFOperand::PolyStar( mat, outer_radius, inner_radius, num_points ) ) );
             },
             _ => ()
         }
@@ -595,12 +672,17 @@ impl PibaldParser
 
 pub struct ShapeShaderClass
 {
-    color_maps : Vec<ColorMap>,
-    placements : Vec<Placement>,
+    pub color_maps : Vec<ColorMap>,
+    pub placements : Vec<Placement>,
 }
 
 impl ShapeShaderClass
 {
+    pub fn parse(input: &str) -> Result<Self, PibaldError>
+    {
+        return PibaldParser::parse_shader_class(input);
+    }
+
     fn create_test()->Self
     {
         return ShapeShaderClass 
@@ -632,47 +714,57 @@ impl ShapeShaderClass
                         ParamMatrix 
                         { 
                             location: Vector::ParamVector(ParamVector::from_constant([0.0, 0.5, 0.0])),
-                            rotation: Vector::ParamVector(ParamVector::from_constant([0.0, 0.0, 0.0])),
+                            rotation: vec![],
                             scale: Vector::ParamVector(ParamVector::from_constant([1.0, 1.0, 1.0])) ,
                             shear: Vector::ParamVector(ParamVector::from_constant([0.0, 0.0, 0.0])) 
                         }
                     ), 
-                    variant: PlacementVariant::Singular() 
+                    variant: PlacementVariant::Singular(),
+                    projection: Projection::Planar(),
+                    space: ProjectionSpace::Object(),
+                    bone: None,
                 }
             ] 
         }
     }
 }
 
-struct ColorMap
+pub struct ColorMap
 {
-    variant : ColorMapVariant,
-    sdf_stack : Vec<SDFTerm>
+    pub variant : ColorMapVariant,
+    pub sdf_stack : Vec<SDFTerm>
 }
 
-enum ColorMapVariant
+pub enum ColorMapVariant
 {
     Gradient(GradientColorMap),
     Binary(BinaryColorMap),
 }
 
-struct BinaryColorMap
+pub struct BinaryColorMap
 {
-    color : Color,
+    pub color : Color,
 }
 
-enum Matrix
+pub enum Matrix
 {
     ParamMatrix(ParamMatrix),
     IdMatrix(String),
 }
 
-struct ParamMatrix
+pub struct ParamMatrix
+{
+    pub location : Vector,
+    pub rotation : Vec<ParamQuat>,
+    pub scale : Vector,
+    pub shear : Vector,
+}
+
+//axis-angle rotation. a list of these gets multiplied together in order
+pub struct ParamQuat
 {
-    location : Vector,
-    rotation : Vector,
-    scale : Vector,
-    shear : Vector,
+    pub axis : Vector,
+    pub angle : ScalarExpression,
 }
 
 impl ParamMatrix
@@ -682,22 +774,22 @@ impl ParamMatrix
         return ParamMatrix 
         { 
             location: Vector::ParamVector(ParamVector::from_constant([0.0, 0.0, 0.0])), 
-            rotation:  Vector::ParamVector(ParamVector::from_constant([0.0, 0.0, 0.0])), 
+            rotation: vec![], 
             scale:  Vector::ParamVector(ParamVector::from_constant([1.0, 1.0, 1.0])),
             shear:  Vector::ParamVector(ParamVector::from_constant([0.0, 0.0, 0.0])),
         };
     }
 }
 
-enum Vector
+pub enum Vector
 {
     ParamVector(ParamVector),
     IdVector(String)
 }
 
-struct ParamVector
+pub struct ParamVector
 {
-    data : [ScalarExpression;3],
+    pub data : [ScalarExpression;3],
 }
 
 impl ParamVector
@@ -716,15 +808,15 @@ impl ParamVector
     }
 }
 
-enum Color
+pub enum Color
 {
     ParamColor(ParamColor),
     IdColor(String),
 }
 
-struct ParamColor
+pub struct ParamColor
 {
-    data : [ScalarExpression;4],
+    pub data : [ScalarExpression;4],
 }
 
 impl ParamColor
@@ -744,64 +836,85 @@ impl ParamColor
     }
 }
 
-struct Placement
+pub struct Placement
 {
-    index : u32,
-    tf : Matrix,
-    variant : PlacementVariant,
+    pub index : u32,
+    pub tf : Matrix,
+    pub variant : PlacementVariant,
+    pub projection : Projection,
+    pub space : ProjectionSpace,
+    pub bone : Option<String>,
 }
 
-enum PlacementVariant
+pub enum PlacementVariant
 {
     Singular(),
     TilePattern(Vector),
 }
 
-struct ParamVector2
+pub enum Projection
 {
-    data : [ScalarExpression;2],
+    Planar(),
+    //blend sharpness
+    Triplanar(ScalarExpression),
+    Spherical(),
+    //axis
+    Cylindrical(Vector),
 }
 
-struct GradientColorMap
+pub enum ProjectionSpace
 {
-    inner_grad: ColorGradient,
-    outer_grad: Option<ColorGradient>,
+    Object(),
+    World(),
 }
 
-struct ColorGradient
+pub struct ParamVector2
 {
-    extrapolation : GradientExtrapolation,
-    color_points : Vec<ColorPoint>,
-    max_distance : ScalarExpression
+    pub data : [ScalarExpression;2],
 }
 
-struct ColorPoint
+pub struct GradientColorMap
 {
-    val : ScalarExpression,
-    color : Color,
-    interpolation_mode : GradientInterpolation
+    pub inner_grad: ColorGradient,
+    pub outer_grad: Option<ColorGradient>,
 }
 
-enum GradientInterpolation
+pub struct ColorGradient
+{
+    pub extrapolation : GradientExtrapolation,
+    pub color_points : Vec<ColorPoint>,
+    pub max_distance : ScalarExpression
+}
+
+pub struct ColorPoint
+{
+    pub val : ScalarExpression,
+    pub color : Color,
+    pub interpolation_mode : GradientInterpolation
+}
+
+#[derive(Clone, Copy)]
+pub enum GradientInterpolation
 {
     Linear(),
     Step(),
 }
 
-enum GradientExtrapolation
+#[derive(Clone, Copy)]
+pub enum GradientExtrapolation
 {
     LastColor(),
     Repeat(),
     RepeatReflect(),
 }
 
-enum SDFTerm
+pub enum SDFTerm
 {
     Operator(SDFOperator),
     Operand(SDFOperand),
 }
 
-enum SDFOperator
+pub enum SDFOperator
 {
     TilePattern,
     WavePattern,
@@ -810,18 +923,18 @@ enum SDFOperator
     Average(i32),
 }
 
-enum SDFOperand
+pub enum SDFOperand
 {
     Circle(Matrix, ScalarExpression),
     Rectangle(Matrix, ScalarExpression, ScalarExpression),
     Sphere(Matrix, ScalarExpression),
     Plane(Matrix),
     Polygon(Matrix, Vec<ParamVector2>),
-    RegularPolygon(Matrix, ScalarExpression),
-    PolyStar(Matrix, ScalarExpression, ScalarExpression),
+    RegularPolygon(Matrix, ScalarExpression, ScalarExpression),
+    PolyStar(Matrix, ScalarExpression, ScalarExpression, ScalarExpression),
 }
 
-struct ScalarExpression
+pub struct ScalarExpression
 {
     expr : Vec<ScalarTerm>
 }
@@ -916,6 +1029,46 @@ impl ScalarExpression
         return ScalarExpression { expr: vec![ScalarTerm::Value(ScalarOperand::Constant(constant))] };
     }
 
+    //whitespace separated postfix terms, the form property expressions read in with Expression::from_postfix
+    pub fn to_postfix(&self) -> String
+    {
+        return self.expr.iter().map
+        (
+            |term| match term
+            {
+                ScalarTerm::Value(ScalarOperand::Constant(val)) => val.to_string(),
+                ScalarTerm::Value(ScalarOperand::Variable(name)) => name.clone(),
+                ScalarTerm::Operator(op) => match op
+                {
+                    ScalarOperator::Add => "+",
+                    ScalarOperator::Subtract => "-",
+                    ScalarOperator::Multiply => "*",
+                    ScalarOperator::Divide => "/",
+                    ScalarOperator::Modulo => "%",
+                    ScalarOperator::Exponent => "^",
+                    ScalarOperator::Negate => "neg",
+                    ScalarOperator::Sine => "sin",
+                    ScalarOperator::Cosine => "cos",
+                    ScalarOperator::Tangent => "tan",
+                    ScalarOperator::Log => "log",
+                }.to_string(),
+            }
+        ).collect::<Vec<String>>().join(" ");
+    }
+
+    //the parameters the expression reads
+    pub fn variables(&self) -> impl Iterator<Item=&String>
+    {
+        return self.expr.iter().filter_map
+        (
+            |term| match term
+            {
+                ScalarTerm::Value(ScalarOperand::Variable(name)) => Some(name),
+                _ => None,
+            }
+        );
+    }
+
     fn evaluate(&self, args : &HashMap<&str, f32>) -> Result<f32, PibaldError>
     {
         //fast result if this doesn't use any variables
@@ -1067,9 +1220,640 @@ enum ScalarOperator
     Log,
 }
 
-struct PibaldEvaluator;
+//cpu side evaluation of a shader class. every expression gets resolved once up front against a set of args, 
+//then points can be sampled as many times as needed
+pub struct PibaldEvaluator
+{
+    color_maps : Vec<EvaluatedColorMap>,
+    placements : Vec<EvaluatedPlacement>,
+    //object to world, used by world space placements
+    object_tf : Mat4,
+    object_normal_tf : Mat3,
+}
+
+struct EvaluatedColorMap
+{
+    variant : EvaluatedColorMapVariant,
+    sdf_stack : Vec<EvaluatedSDFTerm>,
+}
+
+enum EvaluatedColorMapVariant
+{
+    Binary(Vec4),
+    Gradient(EvaluatedGradient, Option<EvaluatedGradient>),
+}
+
+struct EvaluatedGradient
+{
+    extrapolation : GradientExtrapolation,
+    max_distance : f32,
+    //sorted by value
+    color_points : Vec<(f32, Vec4, GradientInterpolation)>,
+}
+
+enum EvaluatedSDFTerm
+{
+    Operator(EvaluatedSDFOperator),
+    Operand(Mat4, EvaluatedSDFOperand),
+}
+
+enum EvaluatedSDFOperator
+{
+    Mask,
+    Minimum(usize),
+    Average(usize),
+}
+
+enum EvaluatedSDFOperand
+{
+    Circle(f32),
+    Rectangle(Vec2),
+    Sphere(f32),
+    Plane,
+    //regular polygons and stars get turned into their points here too
+    Polygon(Vec<Vec2>),
+}
+
+struct EvaluatedPlacement
+{
+    index : usize,
+    inverse_tf : Mat4,
+    normal_tf : Mat3,
+    tile_offset : Option<Vec3>,
+    projection : EvaluatedProjection,
+    world_space : bool,
+    bone : Option<String>,
+    //inverse of the bone's current pose transform, relative to the bind pose
+    inverse_bone_tf : Mat4,
+}
+
+enum EvaluatedProjection
+{
+    Planar,
+    Triplanar(f32),
+    Spherical,
+    //columns are side, axis, front
+    Cylindrical(Mat3),
+}
 
 impl PibaldEvaluator
 {
-    
-}
\ No newline at end of file
+    pub fn new(shader: &ShapeShaderClass, args: &HashMap<&str, f32>) -> Result<Self, PibaldError>
+    {
+        let mut color_maps: Vec<EvaluatedColorMap> = vec![];
+        for map in &shader.color_maps
+        {
+            color_maps.push(PibaldEvaluator::resolve_color_map(map, args)?);
+        }
+        let mut placements: Vec<EvaluatedPlacement> = vec![];
+        for placement in &shader.placements
+        {
+            let index = placement.index as usize;
+            if index >= color_maps.len()
+            {
+                return Err(PibaldError::InvalidExpressionError(format!("Placement refers to missing color map {}", index)));
+            }
+            let inverse_tf = PibaldEvaluator::resolve_inverse_matrix(&placement.tf, args)?;
+            placements.push
+            (
+                EvaluatedPlacement 
+                { 
+                    index: index, 
+                    inverse_tf: inverse_tf, 
+                    normal_tf: Mat3::from_mat4(inverse_tf.inverse().transpose()),
+                    tile_offset: match &placement.variant
+                    {
+                        PlacementVariant::Singular() => None,
+                        PlacementVariant::TilePattern(offset) => Some(PibaldEvaluator::resolve_vector(offset, args)?),
+                    },
+                    projection: PibaldEvaluator::resolve_projection(&placement.projection, args)?,
+                    world_space: matches!(placement.space, ProjectionSpace::World()),
+                    bone: placement.bone.clone(),
+                    inverse_bone_tf: Mat4::IDENTITY,
+                }
+            );
+        }
+        return Ok(PibaldEvaluator { color_maps: color_maps, placements: placements, object_tf: Mat4::IDENTITY, object_normal_tf: Mat3::IDENTITY });
+    }
+
+    //only matters for world space placements
+    pub fn set_object_transform(&mut self, tf: Mat4)
+    {
+        self.object_tf = tf;
+        self.object_normal_tf = Mat3::from_mat4(tf.inverse().transpose());
+    }
+
+    //tf is a skinning transform, bind pose to current pose, like the ones ArmaturePose writes out.
+    //placements following the bone get moved along with it
+    pub fn set_bone_transform(&mut self, bone: &str, tf: Mat4)
+    {
+        for placement in self.placements.iter_mut().filter(|p| p.bone.as_deref() == Some(bone))
+        {
+            placement.inverse_bone_tf = tf.inverse();
+        }
+    }
+
+    //point and normal are in object space. placements earlier in the file are drawn on top of later ones
+    pub fn sample(&self, point: Vec3, normal: Vec3) -> Vec4
+    {
+        let mut out_color = Vec4::ZERO;
+        for placement in &self.placements
+        {
+            let (point, normal) = if placement.world_space
+            {
+                (self.object_tf.transform_point3(point), self.object_normal_tf * normal)
+            }
+            else
+            {
+                (point, normal)
+            };
+            let (point, normal) = (placement.inverse_bone_tf.transform_point3(point), placement.inverse_bone_tf.transform_vector3(normal));
+            let local = placement.inverse_tf.transform_point3(point);
+            let map = &self.color_maps[placement.index];
+            let color = match &placement.projection
+            {
+                EvaluatedProjection::Planar => map.sample(placement.tile(local)),
+                EvaluatedProjection::Spherical => 
+                {
+                    let horizontal = Vec2::new(local.x, local.z).length();
+                    map.sample(placement.tile(Vec3::new(local.x.atan2(local.z), local.y.atan2(horizontal), local.length())))
+                },
+                EvaluatedProjection::Cylindrical(basis) => 
+                {
+                    let p = basis.transpose() * local;
+                    map.sample(placement.tile(Vec3::new(p.x.atan2(p.z), p.y, Vec2::new(p.x, p.z).length())))
+                },
+                EvaluatedProjection::Triplanar(sharpness) => 
+                {
+                    //each axis gets a planar projection, cycled so they all keep the same handedness
+                    let mut weights = (placement.normal_tf * normal).normalize_or_zero().abs().powf(*sharpness);
+                    let total = weights.x + weights.y + weights.z;
+                    if total > 0.0
+                    {
+                        weights /= total;
+                    }
+                    else 
+                    {
+                        weights = Vec3::Z;
+                    }
+                    let mut color = Vec4::ZERO;
+                    if weights.x > 0.0
+                    {
+                        color += weights.x * map.sample(placement.tile(Vec3::new(local.y, local.z, local.x)));
+                    }
+                    if weights.y > 0.0
+                    {
+                        color += weights.y * map.sample(placement.tile(Vec3::new(local.z, local.x, local.y)));
+                    }
+                    if weights.z > 0.0
+                    {
+                        color += weights.z * map.sample(placement.tile(local));
+                    }
+                    color
+                },
+            };
+            out_color = PibaldEvaluator::blend_colors(out_color, color);
+            if out_color.w >= 1.0
+            {
+                break;
+            }
+        }
+        return out_color;
+    }
+
+    fn resolve_projection(projection: &Projection, args: &HashMap<&str, f32>) -> Result<EvaluatedProjection, PibaldError>
+    {
+        match projection 
+        {
+            Projection::Planar() => return Ok(EvaluatedProjection::Planar),
+            Projection::Spherical() => return Ok(EvaluatedProjection::Spherical),
+            Projection::Triplanar(sharpness) => return Ok(EvaluatedProjection::Triplanar(sharpness.evaluate(args)?.max(0.0))),
+            Projection::Cylindrical(axis) => 
+            {
+                let axis = PibaldEvaluator::resolve_vector(axis, args)?.normalize_or_zero();
+                if axis == Vec3::ZERO
+                {
+                    return Err(PibaldError::DegenerateTransformError);
+                }
+                //the front of the cylinder is whatever is closest to z so it lines up with planar
+                let mut front = Vec3::Z - Vec3::Z.dot(axis) * axis;
+                if front.length_squared() <= f32::EPSILON
+                {
+                    front = Vec3::X - Vec3::X.dot(axis) * axis;
+                }
+                let front = front.normalize();
+                return Ok(EvaluatedProjection::Cylindrical(Mat3::from_cols(axis.cross(front), axis, front)));
+            },
+        }
+    }
+
+
+    fn blend_colors(top: Vec4, bottom: Vec4) -> Vec4
+    {
+        let alpha = top.w + bottom.w * (1.0 - top.w);
+        if alpha <= 0.0
+        {
+            return Vec4::ZERO;
+        }
+        let rgb = (top.xyz() * top.w + bottom.xyz() * bottom.w * (1.0 - top.w)) / alpha;
+        return Vec4::new(rgb.x, rgb.y, rgb.z, alpha);
+    }
+
+    fn resolve_color_map(map: &ColorMap, args: &HashMap<&str, f32>) -> Result<EvaluatedColorMap, PibaldError>
+    {
+        let variant = match &map.variant
+        {
+            ColorMapVariant::Binary(binary) => EvaluatedColorMapVariant::Binary(PibaldEvaluator::resolve_color(&binary.color, args)?),
+            ColorMapVariant::Gradient(gradient) => 
+            {
+                let outer = match &gradient.outer_grad
+                {
+                    Some(outer) => Some(PibaldEvaluator::resolve_gradient(outer, args)?),
+                    None => None,
+                };
+                EvaluatedColorMapVariant::Gradient(PibaldEvaluator::resolve_gradient(&gradient.inner_grad, args)?, outer)
+            },
+        };
+        let mut sdf_stack: Vec<EvaluatedSDFTerm> = vec![];
+        let mut depth: usize = 0;
+        for term in &map.sdf_stack
+        {
+            match term 
+            {
+                SDFTerm::Operator(op) => 
+                {
+                    let (evaluated_op, consumed) = match op 
+                    {
+                        SDFOperator::Mask => (EvaluatedSDFOperator::Mask, 2),
+                        SDFOperator::Minimum(count) => (EvaluatedSDFOperator::Minimum(*count as usize), *count as usize),
+                        SDFOperator::Average(count) => (EvaluatedSDFOperator::Average(*count as usize), *count as usize),
+                        SDFOperator::TilePattern | SDFOperator::WavePattern => 
+                        {
+                            return Err(PibaldError::InvalidExpressionError("Unsupported sdf operator".to_string()));
+                        },
+                    };
+                    if consumed == 0 || consumed > depth
+                    {
+                        return Err(PibaldError::InvalidExpressionError("Missing sdf operands".to_string()));
+                    }
+                    depth = depth - consumed + 1;
+                    sdf_stack.push(EvaluatedSDFTerm::Operator(evaluated_op));
+                },
+                SDFTerm::Operand(operand) => 
+                {
+                    sdf_stack.push(PibaldEvaluator::resolve_operand(operand, args)?);
+                    depth += 1;
+                },
+            }
+        }
+        if depth != 1
+        {
+            return Err(PibaldError::InvalidExpressionError("Color map must resolve to a single sdf".to_string()));
+        }
+        return Ok(EvaluatedColorMap { variant: variant, sdf_stack: sdf_stack });
+    }
+
+    fn resolve_gradient(gradient: &ColorGradient, args: &HashMap<&str, f32>) -> Result<EvaluatedGradient, PibaldError>
+    {
+        let mut color_points: Vec<(f32, Vec4, GradientInterpolation)> = vec![];
+        for point in &gradient.color_points
+        {
+            color_points.push
+            (
+                (
+                    point.val.evaluate(args)?, 
+                    PibaldEvaluator::resolve_color(&point.color, args)?, 
+                    point.interpolation_mode
+                )
+            );
+        }
+        color_points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        return Ok
+        (
+            EvaluatedGradient 
+            { 
+                extrapolation: gradient.extrapolation, 
+                max_distance: gradient.max_distance.evaluate(args)?, 
+                color_points: color_points 
+            }
+        );
+    }
+
+    fn resolve_operand(operand: &SDFOperand, args: &HashMap<&str, f32>) -> Result<EvaluatedSDFTerm, PibaldError>
+    {
+        let (tf, evaluated) = match operand 
+        {
+            SDFOperand::Circle(tf, radius) => (tf, EvaluatedSDFOperand::Circle(radius.evaluate(args)?)),
+            SDFOperand::Rectangle(tf, width, height) => 
+            {
+                (tf, EvaluatedSDFOperand::Rectangle(Vec2::new(width.evaluate(args)?, height.evaluate(args)?) * 0.5))
+            },
+            SDFOperand::Sphere(tf, radius) => (tf, EvaluatedSDFOperand::Sphere(radius.evaluate(args)?)),
+            SDFOperand::Plane(tf) => (tf, EvaluatedSDFOperand::Plane),
+            SDFOperand::Polygon(tf, points) => 
+            {
+                let mut poly_points: Vec<Vec2> = vec![];
+                for point in points
+                {
+                    poly_points.push(Vec2::new(point.data[0].evaluate(args)?, point.data[1].evaluate(args)?));
+                }
+                if poly_points.len() < 3
+                {
+                    return Err(PibaldError::InvalidExpressionError("Polygon needs at least 3 points".to_string()));
+                }
+                (tf, EvaluatedSDFOperand::Polygon(poly_points))
+            },
+            SDFOperand::RegularPolygon(tf, radius, num_points) => 
+            {
+                let radius = radius.evaluate(args)?;
+                let num_points = (num_points.evaluate(args)?.floor() as usize).max(3);
+                let mut poly_points: Vec<Vec2> = vec![];
+                for i in 0..num_points
+                {
+                    let angle = 0.5 * PI + 2.0 * PI * (i as f32) / (num_points as f32);
+                    poly_points.push(radius * Vec2::new(angle.cos(), angle.sin()));
+                }
+                (tf, EvaluatedSDFOperand::Polygon(poly_points))
+            },
+            SDFOperand::PolyStar(tf, outer_radius, inner_radius, num_points) => 
+            {
+                let outer_radius = outer_radius.evaluate(args)?;
+                let inner_radius = inner_radius.evaluate(args)?;
+                let num_points = (num_points.evaluate(args)?.floor() as usize).max(2);
+                let mut poly_points: Vec<Vec2> = vec![];
+                for i in 0..(2 * num_points)
+                {
+                    let angle = 0.5 * PI + PI * (i as f32) / (num_points as f32);
+                    let radius = if i % 2 == 0 { outer_radius } else { inner_radius };
+                    poly_points.push(radius * Vec2::new(angle.cos(), angle.sin()));
+                }
+                (tf, EvaluatedSDFOperand::Polygon(poly_points))
+            },
+        };
+        return Ok(EvaluatedSDFTerm::Operand(PibaldEvaluator::resolve_inverse_matrix(tf, args)?, evaluated));
+    }
+
+    fn resolve_inverse_matrix(mat: &Matrix, args: &HashMap<&str, f32>) -> Result<Mat4, PibaldError>
+    {
+        let tf = PibaldEvaluator::resolve_matrix(mat, args)?;
+        if tf.determinant().abs() <= f32::EPSILON
+        {
+            return Err(PibaldError::DegenerateTransformError);
+        }
+        return Ok(tf.inverse());
+    }
+
+    fn resolve_matrix(mat: &Matrix, args: &HashMap<&str, f32>) -> Result<Mat4, PibaldError>
+    {
+        match mat 
+        {
+            Matrix::IdMatrix(_) => return Err(PibaldError::UnknownIdentifierError),
+            Matrix::ParamMatrix(param) => 
+            {
+                let mut rotation = Quat::IDENTITY;
+                for quat in &param.rotation
+                {
+                    let axis = PibaldEvaluator::resolve_vector(&quat.axis, args)?.normalize_or_zero();
+                    if axis == Vec3::ZERO
+                    {
+                        continue;
+                    }
+                    rotation = rotation * Quat::from_axis_angle(axis, quat.angle.evaluate(args)?);
+                }
+                let shear = PibaldEvaluator::resolve_vector(&param.shear, args)?;
+                //shear is xy, xz, yz
+                let shear_mat = Mat4::from_cols
+                (
+                    Vec4::new(1.0, 0.0, 0.0, 0.0), 
+                    Vec4::new(shear.x, 1.0, 0.0, 0.0), 
+                    Vec4::new(shear.y, shear.z, 1.0, 0.0), 
+                    Vec4::new(0.0, 0.0, 0.0, 1.0)
+                );
+                return Ok
+                (
+                    Mat4::from_translation(PibaldEvaluator::resolve_vector(&param.location, args)?) * 
+                    Mat4::from_quat(rotation) * 
+                    shear_mat * 
+                    Mat4::from_scale(PibaldEvaluator::resolve_vector(&param.scale, args)?)
+                );
+            },
+        }
+    }
+
+    fn resolve_vector(vec: &Vector, args: &HashMap<&str, f32>) -> Result<Vec3, PibaldError>
+    {
+        match vec 
+        {
+            Vector::IdVector(_) => return Err(PibaldError::UnknownIdentifierError),
+            Vector::ParamVector(param) => 
+            {
+                return Ok(Vec3::new(param.data[0].evaluate(args)?, param.data[1].evaluate(args)?, param.data[2].evaluate(args)?));
+            },
+        }
+    }
+
+    fn resolve_color(color: &Color, args: &HashMap<&str, f32>) -> Result<Vec4, PibaldError>
+    {
+        match color 
+        {
+            Color::IdColor(_) => return Err(PibaldError::UnknownIdentifierError),
+            Color::ParamColor(param) => 
+            {
+                return Ok
+                (
+                    Vec4::new
+                    (
+                        param.data[0].evaluate(args)?, 
+                        param.data[1].evaluate(args)?, 
+                        param.data[2].evaluate(args)?, 
+                        param.data[3].evaluate(args)?
+                    ).clamp(Vec4::ZERO, Vec4::ONE)
+                );
+            },
+        }
+    }
+}
+
+impl EvaluatedPlacement
+{
+    fn tile(&self, point: Vec3) -> Vec3
+    {
+        match self.tile_offset 
+        {
+            Some(offset) => return Vec3::new
+            (
+                EvaluatedPlacement::wrap_tile(point.x, offset.x),
+                EvaluatedPlacement::wrap_tile(point.y, offset.y),
+                EvaluatedPlacement::wrap_tile(point.z, offset.z),
+            ),
+            None => return point,
+        }
+    }
+
+    fn wrap_tile(val: f32, offset: f32) -> f32
+    {
+        if offset.abs() <= f32::EPSILON
+        {
+            return val;
+        }
+        return (val + 0.5 * offset).rem_euclid(offset) - 0.5 * offset;
+    }
+}
+
+impl EvaluatedColorMap
+{
+    fn sample(&self, point: Vec3) -> Vec4
+    {
+        let dist = self.distance(point);
+        match &self.variant 
+        {
+            EvaluatedColorMapVariant::Binary(color) => 
+            {
+                if dist <= 0.0 
+                {
+                    return *color;
+                }
+                return Vec4::ZERO;
+            },
+            EvaluatedColorMapVariant::Gradient(inner, outer) => 
+            {
+                if dist <= 0.0
+                {
+                    return inner.sample(-dist);
+                }
+                match outer 
+                {
+                    Some(outer) => return outer.sample(dist),
+                    None => return Vec4::ZERO,
+                }
+            },
+        }
+    }
+
+    fn distance(&self, point: Vec3) -> f32
+    {
+        let mut dist_stack: Vec<f32> = vec![];
+        for term in &self.sdf_stack
+        {
+            match term 
+            {
+                EvaluatedSDFTerm::Operand(inverse_tf, operand) => 
+                {
+                    dist_stack.push(operand.distance(inverse_tf.transform_point3(point)));
+                },
+                EvaluatedSDFTerm::Operator(op) => 
+                {
+                    match op 
+                    {
+                        EvaluatedSDFOperator::Mask => 
+                        {
+                            let mask = dist_stack.pop().unwrap();
+                            let dist = dist_stack.pop().unwrap();
+                            dist_stack.push(dist.max(mask));
+                        },
+                        EvaluatedSDFOperator::Minimum(count) => 
+                        {
+                            let operands = dist_stack.split_off(dist_stack.len() - count);
+                            dist_stack.push(operands.into_iter().fold(f32::MAX, f32::min));
+                        },
+                        EvaluatedSDFOperator::Average(count) => 
+                        {
+                            let operands = dist_stack.split_off(dist_stack.len() - count);
+                            dist_stack.push(operands.iter().sum::<f32>() / (*count as f32));
+                        },
+                    }
+                },
+            }
+        }
+        return dist_stack.pop().unwrap();
+    }
+}
+
+impl EvaluatedSDFOperand
+{
+    //2d shapes are treated as cylinders down the z axis
+    fn distance(&self, point: Vec3) -> f32
+    {
+        match self 
+        {
+            EvaluatedSDFOperand::Circle(radius) => return point.truncate().length() - radius,
+            EvaluatedSDFOperand::Rectangle(half_extents) => 
+            {
+                let d = point.truncate().abs() - *half_extents;
+                return d.max(Vec2::ZERO).length() + d.x.max(d.y).min(0.0);
+            },
+            EvaluatedSDFOperand::Sphere(radius) => return point.length() - radius,
+            EvaluatedSDFOperand::Plane => return point.z,
+            EvaluatedSDFOperand::Polygon(points) => 
+            {
+                let p = point.truncate();
+                let mut d = (p - points[0]).length_squared();
+                let mut sign = 1.0;
+                let mut j = points.len() - 1;
+                for i in 0..points.len()
+                {
+                    let edge = points[j] - points[i];
+                    let w = p - points[i];
+                    let b = w - edge * (w.dot(edge) / edge.length_squared()).clamp(0.0, 1.0);
+                    d = d.min(b.length_squared());
+                    let c = [p.y >= points[i].y, p.y < points[j].y, edge.x * w.y > edge.y * w.x];
+                    if c.iter().all(|x| *x) || c.iter().all(|x| !*x)
+                    {
+                        sign = -sign;
+                    }
+                    j = i;
+                }
+                return sign * d.sqrt();
+            },
+        }
+    }
+}
+
+impl EvaluatedGradient
+{
+    fn sample(&self, dist: f32) -> Vec4
+    {
+        if self.color_points.is_empty()
+        {
+            return Vec4::ZERO;
+        }
+        let mut val = if self.max_distance > 0.0 { dist / self.max_distance } else { 0.0 };
+        val = match self.extrapolation 
+        {
+            GradientExtrapolation::LastColor() => val.min(1.0),
+            GradientExtrapolation::Repeat() => val.rem_euclid(1.0),
+            GradientExtrapolation::RepeatReflect() => 
+            {
+                let reflected = val.rem_euclid(2.0);
+                if reflected > 1.0 { 2.0 - reflected } else { reflected }
+            },
+        };
+        let first = &self.color_points[0];
+        if val <= first.0
+        {
+            return first.1;
+        }
+        for i in 1..self.color_points.len()
+        {
+            let (start_val, start_color, interpolation) = self.color_points[i - 1];
+            let (end_val, end_color, _) = self.color_points[i];
+            if val <= end_val
+            {
+                match interpolation 
+                {
+                    GradientInterpolation::Step() => return start_color,
+                    GradientInterpolation::Linear() => 
+                    {
+                        let span = end_val - start_val;
+                        if span <= 0.0
+                        {
+                            return end_color;
+                        }
+                        return start_color.lerp(end_color, (val - start_val) / span);
+                    },
+                }
+            }
+        }
+        return self.color_points[self.color_points.len() - 1].1;
+    }
+}
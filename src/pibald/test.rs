@@ -5,7 +5,7 @@ use pest::Parser;
 
 use crate::pibald::{ScalarOperator, ColorMap, BinaryColorMap, ColorMapVariant, ScalarExpression};
 
-use super::{PibaldParser, Rule, ScalarTerm, ScalarOperand, PibaldEvaluator};
+use super::{PibaldParser, Rule, ScalarTerm, ScalarOperand, PibaldEvaluator, ShapeShaderClass};
 
 #[test]
 fn test_scalar_parse()
@@ -69,3 +69,114 @@ fn test_shader_parse()
     );
 }
 
+
+
+#[test]
+fn test_evaluator_sample()
+{
+    let test_str = 
+    "SOLID
+    (
+        color(1.0, 0.0, 0.0, 1.0), 
+        SD_CIRCLE(mat4(translation[vec3(offset, 0.0, 0.0)]), 0.25) 
+    )
+    SINGULAR(mat4(scale[vec3(2.0, 2.0, 2.0)]), 0)
+    ";
+    let shader_class = ShapeShaderClass::parse(test_str).unwrap();
+    let mut arg_map: HashMap<&str, f32> = HashMap::new();
+    arg_map.insert("offset", 0.1);
+    let evaluator = PibaldEvaluator::new(&shader_class, &arg_map).unwrap();
+    assert_eq!(evaluator.sample(glam::Vec3::new(0.6, 0.0, 0.0), glam::Vec3::Z), glam::Vec4::new(1.0, 0.0, 0.0, 1.0), "Point should be inside translated and scaled circle");
+    assert_eq!(evaluator.sample(glam::Vec3::new(-0.6, 0.0, 0.0), glam::Vec3::Z).w, 0.0, "Point should be outside translated and scaled circle");
+    assert!(PibaldEvaluator::new(&shader_class, &HashMap::new()).is_err(), "Missing argument should fail to evaluate");
+}
+
+#[test]
+fn test_evaluator_gradient()
+{
+    let test_str = 
+    "GRAD_MAP
+    (
+        colorGradient(1.0, LAST_COLOR, gradPoint(color(0.0, 0.0, 0.0, 1.0), 0.0, LINEAR), gradPoint(color(1.0, 1.0, 1.0, 1.0), 1.0, LINEAR)),
+        OP_MIN(SD_BOX_CYLINDER(mat4(), 4.0, 2.0), SD_SPHERE(mat4(), 0.5))
+    )
+    SINGULAR(mat4(), 0)
+    ";
+    let shader_class = ShapeShaderClass::parse(test_str).unwrap();
+    let evaluator = PibaldEvaluator::new(&shader_class, &HashMap::new()).unwrap();
+    let color = evaluator.sample(glam::Vec3::new(1.5, 0.0, 0.0), glam::Vec3::Z);
+    assert!((color.x - 0.5).abs() < EPSILON, "Gradient should be halfway at half the max distance");
+    assert_eq!(evaluator.sample(glam::Vec3::new(0.0, 3.0, 0.0), glam::Vec3::Z).w, 0.0, "No outer gradient should leave the outside empty");
+}
+
+#[test]
+fn test_evaluator_projection()
+{
+    let test_str = 
+    "SOLID(color(1.0, 1.0, 1.0, 1.0), SD_CIRCLE(mat4(), 0.5))
+    SINGULAR(mat4(), 0, CYLINDRICAL(WORLD, vec3(0.0, 1.0, 0.0)))
+    ";
+    let shader_class = ShapeShaderClass::parse(test_str).unwrap();
+    let mut evaluator = PibaldEvaluator::new(&shader_class, &HashMap::new()).unwrap();
+    //0.3 radians around a cylinder of radius 3 would be well outside the circle with a planar projection
+    let point = glam::Vec3::new(3.0 * 0.3_f32.sin(), 0.0, 3.0 * 0.3_f32.cos());
+    assert_eq!(evaluator.sample(point, point.normalize()).w, 1.0, "Cylindrical projection should wrap the circle around the axis");
+    evaluator.set_object_transform(glam::Mat4::from_rotation_y(1.0));
+    assert_eq!(evaluator.sample(point, point.normalize()).w, 0.0, "World space projection should follow the object transform");
+}
+
+#[test]
+fn test_evaluator_bone_placement()
+{
+    let test_str = 
+    "SOLID(color(1.0, 1.0, 1.0, 1.0), SD_CIRCLE(mat4(), 0.25))
+    SINGULAR(mat4(), 0, BONE(\"DEF-head\"), PLANAR(OBJECT))
+    ";
+    let shader_class = ShapeShaderClass::parse(test_str).unwrap();
+    let mut evaluator = PibaldEvaluator::new(&shader_class, &HashMap::new()).unwrap();
+    evaluator.set_bone_transform("DEF-jaw", glam::Mat4::from_translation(glam::Vec3::new(0.0, 1.0, 0.0)));
+    assert_eq!(evaluator.sample(glam::Vec3::ZERO, glam::Vec3::Z).w, 1.0, "Placement should ignore other bones");
+    evaluator.set_bone_transform("DEF-head", glam::Mat4::from_translation(glam::Vec3::new(1.0, 0.0, 0.0)));
+    assert_eq!(evaluator.sample(glam::Vec3::new(1.0, 0.0, 0.0), glam::Vec3::Z).w, 1.0, "Placement should follow its bone");
+    assert_eq!(evaluator.sample(glam::Vec3::ZERO, glam::Vec3::Z).w, 0.0, "Placement should have left the bind pose");
+}
+
+#[test]
+fn test_evaluator_image()
+{
+    let test_str = 
+    "SOLID(color(1.0, 0.5, 0.0, 1.0), SD_CIRCLE(mat4(translation[vec3(0.125, 0.125, 0.0)]), 0.25))
+    SINGULAR(mat4(), 0)
+    ";
+    let shader_class = ShapeShaderClass::parse(test_str).unwrap();
+    let evaluator = PibaldEvaluator::new(&shader_class, &HashMap::new()).unwrap();
+    //pixel centers across the unit square, +y up and the top row first, the same way pibald-preview lays out its images
+    let image = (0..8).map
+    (
+        |y| (0..8).map
+        (
+            |x|
+            {
+                let point = glam::Vec3::new((x as f32 + 0.5) / 8.0 - 0.5, 0.5 - (y as f32 + 0.5) / 8.0, 0.0);
+                return match evaluator.sample(point, glam::Vec3::Z)
+                {
+                    color if color == glam::Vec4::new(1.0, 0.5, 0.0, 1.0) => '#',
+                    color if color == glam::Vec4::ZERO => '.',
+                    _ => '?',
+                };
+            }
+        ).collect::<String>()
+    ).collect::<Vec<_>>();
+    let expected =
+    [
+        "........",
+        "....##..",
+        "...####.",
+        "...####.",
+        "....##..",
+        "........",
+        "........",
+        "........",
+    ];
+    assert_eq!(image, expected, "Circle should cover the pixels up and to the right of the center in its own color and leave the rest empty");
+}
@@ -1,24 +1,60 @@
 mod test;
+mod shader;
 
-use std::{collections::{HashMap, vec_deque, VecDeque}, fs::{self, File}, io::{BufReader, Read, Seek}, rc::Rc, path::Path};
+use std::{collections::{HashMap, vec_deque, VecDeque}, error::Error, fs::{self, File}, io::{self, BufReader, Read, Seek}, rc::Rc, path::{Path, PathBuf}, time::SystemTime};
 
-use glam::{Vec4, Vec3, Vec4Swizzles, Quat, Mat4};
+use glam::{Vec2, Vec4, Vec3, Vec4Swizzles, Quat, Mat4};
 
-use super::{render_state::model::StaticModel, render_state::{model::{Model, StaticVertex, Triangle, ColorPalette, Polygon, ArmatureWeight, AnimatedModel, AnimatedVertex}, common::{Color, NormalizedFloat}, animation::{Armature, AnimationClip, Bone, ClipChannel, AnimationKey}}};
+use crate::logger::Logger;
+use super::{render_state::model::StaticModel, render_state::{texture::Shader, model::{Model, StaticVertex, Triangle, ColorPalette, Polygon, ArmatureWeight, AnimatedModel, AnimatedVertex}, common::{Color, NormalizedFloat}, animation::{Armature, AnimationClip, Bone, ClipChannel, AnimationKey, KeyInterpolation, SyncMarker}}};
 use byteorder::{ReadBytesExt, BigEndian, LittleEndian};
 
+pub use shader::{read_shader, ShaderLoadError};
+
 struct CurveFrame
 {
     frame: u32,
     value: f32,
 }
 
+//what changed during a hot reload poll. armatures, clips and shaders hand back the old Rc so live animation states can be repointed
+pub enum ReloadedAsset
+{
+    StaticModel(String),
+    AnimatedModel(String),
+    Armature{ old: Rc<Armature>, new: Rc<Armature> },
+    Animation{ old: Rc<AnimationClip>, new: Rc<AnimationClip> },
+    Shader{ old: Rc<Shader>, new: Rc<Shader> },
+}
+
+#[derive(Clone, PartialEq)]
+enum AssetKind
+{
+    StaticModel,
+    AnimatedModel,
+    Armature,
+    //armature key
+    Animation(String),
+    Shader,
+}
+
+struct WatchedAsset
+{
+    key: String,
+    kind: AssetKind,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
 pub struct InMemoryModelRepository
 {
     static_models: HashMap<String, StaticModel>,
     animated_models: HashMap<String, AnimatedModel>,
     armatures: HashMap<String, Rc<Armature>>,
-    animations: HashMap<String, Rc<AnimationClip>>
+    animations: HashMap<String, Rc<AnimationClip>>,
+    shaders: HashMap<String, Rc<Shader>>,
+    watched: Vec<WatchedAsset>,
+    hot_reload: bool,
 }
 
 impl InMemoryModelRepository
@@ -31,6 +67,9 @@ impl InMemoryModelRepository
             animated_models: HashMap::new(),
             armatures: HashMap::new(),
             animations: HashMap::new(),
+            shaders: HashMap::new(),
+            watched: vec![],
+            hot_reload: false,
         };
     }
 
@@ -38,7 +77,8 @@ impl InMemoryModelRepository
     {
         //let model_str = fs::read_to_string(path).expect("Failed to read model file");
         let file = File::open(path).unwrap();
-        let val = read_static_model(file, key.clone());
+        let val = read_static_model(file, key.clone()).unwrap();
+        self.watch(&key, AssetKind::StaticModel, path);
         self.static_models.insert(key, val);
     }
 
@@ -46,14 +86,16 @@ impl InMemoryModelRepository
     {
         //let model_str = fs::read_to_string(path).expect("Failed to read model file");
         let file = File::open(path).unwrap();
-        let val = read_animated_model(file, key.clone());
+        let val = read_animated_model(file, key.clone()).unwrap();
+        self.watch(&key, AssetKind::AnimatedModel, path);
         self.animated_models.insert(key, val);
     }
 
     pub fn load_armature(&mut self, key: String, path: &Path)
     {
         let file = File::open(path).unwrap();
-        let val = read_armature(key.clone(), file);
+        let val = read_armature(key.clone(), file).unwrap();
+        self.watch(&key, AssetKind::Armature, path);
         self.armatures.insert(key, Rc::new(val));
     }
 
@@ -61,18 +103,29 @@ impl InMemoryModelRepository
     {
         let arma = self.armatures.get(arma_key).unwrap();
         let file = File::open(path).unwrap();
-        let val = read_animation(file, arma);
+        let val = read_animation(file, arma).unwrap();
+        self.watch(&anim_id, AssetKind::Animation(arma_key.clone()), path);
         self.animations.insert(anim_id, Rc::new(val));
     }
 
+    pub fn load_shader(&mut self, key: String, path: &Path)
+    {
+        let source = fs::read_to_string(path).unwrap();
+        let val = read_shader(key.clone(), &source).unwrap();
+        self.watch(&key, AssetKind::Shader, path);
+        self.shaders.insert(key, Rc::new(val));
+    }
+
     pub fn unload_static_model(&mut self, id: &String)
     {
         self.static_models.remove(id);
+        self.watched.retain(|asset| !(asset.kind == AssetKind::StaticModel && asset.key == *id));
     }
 
     pub fn unload_animated_model(&mut self, id: &String)
     {
         self.animated_models.remove(id);
+        self.watched.retain(|asset| !(asset.kind == AssetKind::AnimatedModel && asset.key == *id));
     }
 
     pub fn get_static_model(&self, id: &String) -> Option<&StaticModel>
@@ -94,22 +147,116 @@ impl InMemoryModelRepository
     {
         return self.animations.get(id).cloned();
     }
+
+    pub fn get_shader(&self, id: &String) -> Option<Rc<Shader>>
+    {
+        return self.shaders.get(id).cloned();
+    }
+
+    //dev only. off by default so release builds never stat the asset files
+    pub fn set_hot_reload(&mut self, enabled: bool)
+    {
+        self.hot_reload = enabled;
+    }
+
+    //checks the modified time of every loaded file and reloads the ones that changed.
+    //a file that fails to load keeps its old asset around and the error gets logged
+    pub fn poll_hot_reload(&mut self, logger: &impl Logger) -> Vec<ReloadedAsset>
+    {
+        let mut reloaded = vec![];
+        if !self.hot_reload
+        {
+            return reloaded;
+        }
+        for i in 0..self.watched.len()
+        {
+            let modified = fs::metadata(&self.watched[i].path).and_then(|meta| meta.modified()).ok();
+            if modified.is_none() || modified == self.watched[i].modified
+            {
+                continue;
+            }
+            //updated even on failure so a broken file only gets reported once per save
+            self.watched[i].modified = modified;
+            let key = self.watched[i].key.clone();
+            let kind = self.watched[i].kind.clone();
+            let path = self.watched[i].path.clone();
+            match self.reload(&key, &kind, &path)
+            {
+                Ok(asset) => 
+                {
+                    logger.log_message(&format!("Reloaded {}", path.display()));
+                    reloaded.push(asset);
+                },
+                Err(err) => logger.log_error(&format!("Failed to reload {}, keeping the previous version: {}", path.display(), err)),
+            }
+        }
+        return reloaded;
+    }
+
+    fn reload(&mut self, key: &String, kind: &AssetKind, path: &Path) -> Result<ReloadedAsset, Box<dyn Error>>
+    {
+        match kind
+        {
+            AssetKind::StaticModel => 
+            {
+                let val = read_static_model(BufReader::new(File::open(path)?), key.clone())?;
+                self.static_models.insert(key.clone(), val);
+                return Ok(ReloadedAsset::StaticModel(key.clone()));
+            },
+            AssetKind::AnimatedModel => 
+            {
+                let val = read_animated_model(BufReader::new(File::open(path)?), key.clone())?;
+                self.animated_models.insert(key.clone(), val);
+                return Ok(ReloadedAsset::AnimatedModel(key.clone()));
+            },
+            AssetKind::Armature => 
+            {
+                let new = Rc::new(read_armature(key.clone(), BufReader::new(File::open(path)?))?);
+                let old = self.armatures.insert(key.clone(), new.clone()).ok_or_else(|| invalid_data("armature was unloaded"))?;
+                return Ok(ReloadedAsset::Armature{ old: old, new: new });
+            },
+            AssetKind::Animation(arma_key) => 
+            {
+                let arma = self.armatures.get(arma_key).ok_or_else(|| invalid_data("clip's armature was unloaded"))?;
+                let new = Rc::new(read_animation(BufReader::new(File::open(path)?), arma)?);
+                let old = self.animations.insert(key.clone(), new.clone()).ok_or_else(|| invalid_data("clip was unloaded"))?;
+                return Ok(ReloadedAsset::Animation{ old: old, new: new });
+            },
+            //model slots get repointed here, live instances keep the old Rc until they're handed the new one
+            AssetKind::Shader => 
+            {
+                let new = Rc::new(read_shader(key.clone(), &fs::read_to_string(path)?)?);
+                let old = self.shaders.insert(key.clone(), new.clone()).ok_or_else(|| invalid_data("shader was unloaded"))?;
+                let static_slots = self.static_models.values_mut().flat_map(|model| return model.model_data.shader_slots.values_mut());
+                let animated_slots = self.animated_models.values_mut().flat_map(|model| return model.model_data.shader_slots.values_mut());
+                static_slots.chain(animated_slots).for_each(|slot| slot.replace_shader(&old, &new));
+                return Ok(ReloadedAsset::Shader{ old: old, new: new });
+            },
+        }
+    }
+
+    fn watch(&mut self, key: &String, kind: AssetKind, path: &Path)
+    {
+        let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        self.watched.retain(|asset| !(asset.kind == kind && asset.key == *key));
+        self.watched.push(WatchedAsset { key: key.clone(), kind: kind, path: path.to_path_buf(), modified: modified });
+    }
 }
 
-fn read_flags(byte_rdr: &mut impl Read,) -> (bool, bool, bool)
+fn read_flags(byte_rdr: &mut impl Read,) -> io::Result<(bool, bool, bool)>
 {
-    let flags = byte_rdr.read_u8().unwrap();
+    let flags = byte_rdr.read_u8()?;
     let has_normals = (flags & 0b00000001) > 0;
     let has_colors = (flags & 0b00000010) > 0;
     let has_weights = (flags & 0b00000100) > 0;
-    return (has_normals, has_colors, has_weights);
+    return Ok((has_normals, has_colors, has_weights));
 }
 
-fn read_colors(byte_rdr: &mut impl Read,) -> Vec<ColorPalette>
+fn read_colors(byte_rdr: &mut impl Read,) -> io::Result<Vec<ColorPalette>>
 {
     let colors = 
     {
-        let num_colors = byte_rdr.read_u16::<LittleEndian>().unwrap() as usize;
+        let num_colors = byte_rdr.read_u16::<LittleEndian>()? as usize;
         let mut temp = vec![];
         for _ in 0..num_colors
         {
@@ -119,9 +266,9 @@ fn read_colors(byte_rdr: &mut impl Read,) -> Vec<ColorPalette>
                 { 
                     data: Vec4::new
                     (
-                        byte_rdr.read_f32::<LittleEndian>().unwrap(),
-                        byte_rdr.read_f32::<LittleEndian>().unwrap(),
-                        byte_rdr.read_f32::<LittleEndian>().unwrap(),
+                        byte_rdr.read_f32::<LittleEndian>()?,
+                        byte_rdr.read_f32::<LittleEndian>()?,
+                        byte_rdr.read_f32::<LittleEndian>()?,
                         1.0
                     ) 
                 }
@@ -130,22 +277,22 @@ fn read_colors(byte_rdr: &mut impl Read,) -> Vec<ColorPalette>
         temp
     };
     let mut palettes = vec![];
-    let num_palettes = byte_rdr.read_u16::<LittleEndian>().unwrap() as usize;
-    let palette_size = byte_rdr.read_u16::<LittleEndian>().unwrap() as usize;
+    let num_palettes = byte_rdr.read_u16::<LittleEndian>()? as usize;
+    let palette_size = byte_rdr.read_u16::<LittleEndian>()? as usize;
     for _ in 0..num_palettes
     {
         let mut palette = vec![];
         for _ in 0..palette_size
         {
-            let color_index = byte_rdr.read_u16::<LittleEndian>().unwrap() as usize;
-            palette.push(colors[color_index]);
+            let color_index = byte_rdr.read_u16::<LittleEndian>()? as usize;
+            palette.push(*colors.get(color_index).ok_or_else(|| invalid_data("palette color index out of range"))?);
         }
         palettes.push(ColorPalette{colors: palette});
     }
-    return palettes;
+    return Ok(palettes);
 }
 
-fn read_vec3_array(num_points: u32, byte_rdr: &mut impl Read,) -> Vec<Vec3>
+fn read_vec3_array(num_points: u32, byte_rdr: &mut impl Read,) -> io::Result<Vec<Vec3>>
 {
     let mut points = vec![];
     for _ in 0..num_points
@@ -154,57 +301,58 @@ fn read_vec3_array(num_points: u32, byte_rdr: &mut impl Read,) -> Vec<Vec3>
         (
             Vec3::new
             (
-                byte_rdr.read_f32::<LittleEndian>().unwrap(),
-                byte_rdr.read_f32::<LittleEndian>().unwrap(),
-                byte_rdr.read_f32::<LittleEndian>().unwrap(),
+                byte_rdr.read_f32::<LittleEndian>()?,
+                byte_rdr.read_f32::<LittleEndian>()?,
+                byte_rdr.read_f32::<LittleEndian>()?,
             )
         );
     }
-    return points;
+    return Ok(points);
 }
 
-fn skip_armature_weights(num_points: u32, byte_rdr: &mut (impl Read + Seek),)
+fn skip_armature_weights(num_points: u32, byte_rdr: &mut (impl Read + Seek),) -> io::Result<()>
 {
-    let armature_id_len = byte_rdr.read_u8().unwrap();
-    let _ = byte_rdr.seek(std::io::SeekFrom::Current(armature_id_len as i64));
+    let armature_id_len = byte_rdr.read_u8()?;
+    byte_rdr.seek(std::io::SeekFrom::Current(armature_id_len as i64))?;
     for _ in 0..num_points
     {
-        let num_weights = byte_rdr.read_u8().unwrap();
+        let num_weights = byte_rdr.read_u8()?;
         //I'm not gonna bother using sizeof here. doesn't save any grief if you think about it
-        let _ = byte_rdr.seek(std::io::SeekFrom::Current((num_weights as i64) * 6));
+        byte_rdr.seek(std::io::SeekFrom::Current((num_weights as i64) * 6))?;
     }
+    return Ok(());
 }
 
-fn read_armature_weights(num_points: u32, byte_rdr: &mut impl Read) -> (String, Vec<Vec<ArmatureWeight>>)
+fn read_armature_weights(num_points: u32, byte_rdr: &mut impl Read) -> io::Result<(String, Vec<Vec<ArmatureWeight>>)>
 {
-    let armature_id_len = byte_rdr.read_u8().unwrap() as usize;
+    let armature_id_len = byte_rdr.read_u8()? as usize;
     let mut buf: Vec<u8> = vec![0; armature_id_len];
-    byte_rdr.read_exact(&mut buf).expect("failed to read armature id");
-    let arma_id = String::from_utf8(buf).expect("failed to convert armature id");
+    byte_rdr.read_exact(&mut buf)?;
+    let arma_id = String::from_utf8(buf).map_err(|_| invalid_data("armature id is not valid utf8"))?;
     let mut points = vec![];
     for _ in 0..num_points
     {
         let mut v_weights = vec![];
-        let num_weights = byte_rdr.read_u8().unwrap();
+        let num_weights = byte_rdr.read_u8()?;
         for _ in 0..num_weights
         {
             v_weights.push
             (
                 ArmatureWeight
                 {
-                    index: byte_rdr.read_u16::<LittleEndian>().unwrap() as usize,
-                    weight: NormalizedFloat::clamped(byte_rdr.read_f32::<LittleEndian>().unwrap()),
+                    index: byte_rdr.read_u16::<LittleEndian>()? as usize,
+                    weight: NormalizedFloat::clamped(byte_rdr.read_f32::<LittleEndian>()?),
                 }
             );
         }
         points.push(v_weights);
     }
-    return (arma_id, points);
+    return Ok((arma_id, points));
 }
 
-fn read_geometry(points: &Vec<Vec3>, normals: &Vec<Vec3>, has_colors: bool, has_normals: bool, byte_rdr: &mut impl Read) -> (Vec<StaticVertex>, Vec<Polygon>, HashMap::<VertexKey, usize>)
+fn read_geometry(points: &Vec<Vec3>, normals: &Vec<Vec3>, has_colors: bool, has_normals: bool, byte_rdr: &mut impl Read) -> io::Result<(Vec<StaticVertex>, Vec<Polygon>, HashMap::<VertexKey, usize>)>
 {
-    let num_tris = byte_rdr.read_u32::<LittleEndian>().unwrap() as usize;
+    let num_tris = byte_rdr.read_u32::<LittleEndian>()? as usize;
     let mut tris = VecDeque::new();
     //todo - coalesce vertices with the same data to save space and have smaller buffer writes. 
     //This won't do anything for the usual type of model because due to the flat shading requiring vertices to have per-face normals  
@@ -216,18 +364,18 @@ fn read_geometry(points: &Vec<Vec3>, normals: &Vec<Vec3>, has_colors: bool, has_
         //todo - have another for loop up here for calculating the normal from the polygon's given postions for the case where the normals were not given/precalculated
         for t in 0..3
         {
-            let loc_dex = byte_rdr.read_u32::<LittleEndian>().unwrap();
+            let loc_dex = byte_rdr.read_u32::<LittleEndian>()?;
             let mut norm = Vec3::ZERO;
             let mut norm_dex = 0;
             if has_normals
             {
-                norm_dex = byte_rdr.read_u32::<LittleEndian>().unwrap();
-                norm = normals[norm_dex as usize];
+                norm_dex = byte_rdr.read_u32::<LittleEndian>()?;
+                norm = *normals.get(norm_dex as usize).ok_or_else(|| invalid_data("normal index out of range"))?;
             }
             let mut col = 0;
             if has_colors
             {
-                col = byte_rdr.read_u16::<LittleEndian>().unwrap();
+                col = byte_rdr.read_u16::<LittleEndian>()?;
             }
             let key = VertexKey{ loc: loc_dex, color: col, norm: norm_dex };
             if verts_map.contains_key(&key)
@@ -240,7 +388,7 @@ fn read_geometry(points: &Vec<Vec3>, normals: &Vec<Vec3>, has_colors: bool, has_
                 verts_map.insert(key, verts.len());
                 verts.push
                 (
-                    StaticVertex{ loc: points[loc_dex as usize], col: col as usize, norm }
+                    StaticVertex{ loc: *points.get(loc_dex as usize).ok_or_else(|| invalid_data("point index out of range"))?, col: col as usize, norm }
                 );
                 
             }
@@ -255,63 +403,63 @@ fn read_geometry(points: &Vec<Vec3>, normals: &Vec<Vec3>, has_colors: bool, has_
         }
         tris.push_back(Triangle{ indices: tri })
     }
-    let num_polys = byte_rdr.read_u32::<LittleEndian>().unwrap() as usize;
+    let num_polys = byte_rdr.read_u32::<LittleEndian>()? as usize;
     let mut polys = vec![];
     for _ in 0..num_polys
     {
         
         let mut poly_tris = vec![];
-        let poly_tri_count = byte_rdr.read_u16::<LittleEndian>().unwrap() as usize;
+        let poly_tri_count = byte_rdr.read_u16::<LittleEndian>()? as usize;
         for _ in 0..poly_tri_count
         {
-            poly_tris.push(tris.pop_front().unwrap())
+            poly_tris.push(tris.pop_front().ok_or_else(|| invalid_data("polygon refers to more triangles than were given"))?)
         }
         polys.push(Polygon{tris:poly_tris});
     }
-    return (verts, polys, verts_map);
+    return Ok((verts, polys, verts_map));
 }
 
-fn read_bbox(byte_rdr: &mut impl Read) -> (Vec3, Vec3)
+fn read_bbox(byte_rdr: &mut impl Read) -> io::Result<(Vec3, Vec3)>
 {
     let min_bound = Vec3::new
     (
-        byte_rdr.read_f32::<LittleEndian>().unwrap(),
-        byte_rdr.read_f32::<LittleEndian>().unwrap(),
-        byte_rdr.read_f32::<LittleEndian>().unwrap(),
+        byte_rdr.read_f32::<LittleEndian>()?,
+        byte_rdr.read_f32::<LittleEndian>()?,
+        byte_rdr.read_f32::<LittleEndian>()?,
     );
     let max_bound = Vec3::new
     (
-        byte_rdr.read_f32::<LittleEndian>().unwrap(),
-        byte_rdr.read_f32::<LittleEndian>().unwrap(),
-        byte_rdr.read_f32::<LittleEndian>().unwrap(),
+        byte_rdr.read_f32::<LittleEndian>()?,
+        byte_rdr.read_f32::<LittleEndian>()?,
+        byte_rdr.read_f32::<LittleEndian>()?,
     );
-    return (min_bound, max_bound);
+    return Ok((min_bound, max_bound));
 }
 
-fn read_static_model(mut byte_rdr: impl Read + Seek, id: String) -> StaticModel
+fn read_static_model(mut byte_rdr: impl Read + Seek, id: String) -> io::Result<StaticModel>
 {
-    let (has_normals, has_colors, has_weights) = read_flags(&mut byte_rdr);
-    let palettes = if has_colors { read_colors(&mut byte_rdr) } else { vec![ColorPalette{colors: vec![Color{data: Vec4::new(0.5, 0.5, 0.5, 1.0)}]}] };
-    let num_points = byte_rdr.read_u32::<LittleEndian>().unwrap();
+    let (has_normals, has_colors, has_weights) = read_flags(&mut byte_rdr)?;
+    let palettes = if has_colors { read_colors(&mut byte_rdr)? } else { vec![ColorPalette{colors: vec![Color{data: Vec4::new(0.5, 0.5, 0.5, 1.0)}]}] };
+    let num_points = byte_rdr.read_u32::<LittleEndian>()?;
     let points =
     {
-        read_vec3_array(num_points, &mut byte_rdr)
+        read_vec3_array(num_points, &mut byte_rdr)?
     };
     if has_weights
     {
-        skip_armature_weights(num_points, &mut byte_rdr);
+        skip_armature_weights(num_points, &mut byte_rdr)?;
     }
     let normals = if has_normals
     {
-        let num_norms = byte_rdr.read_u32::<LittleEndian>().unwrap();
-        read_vec3_array(num_norms, &mut byte_rdr)
+        let num_norms = byte_rdr.read_u32::<LittleEndian>()?;
+        read_vec3_array(num_norms, &mut byte_rdr)?
     }
     else
     {
         vec![]
     };
-    let (verts, polys, _) = read_geometry(&points, &normals, has_colors, has_normals, &mut byte_rdr);
-    let (min_bound, max_bound) = read_bbox(&mut byte_rdr);
+    let (verts, polys, _) = read_geometry(&points, &normals, has_colors, has_normals, &mut byte_rdr)?;
+    let (min_bound, max_bound) = read_bbox(&mut byte_rdr)?;
 
     let model = Model 
     {
@@ -323,40 +471,43 @@ fn read_static_model(mut byte_rdr: impl Read + Seek, id: String) -> StaticModel
         max_bound: max_bound 
     };
 
-    return StaticModel
-    {
-        id: id,
-        vertices: verts,
-        model_data: model
-    };
+    return Ok
+    (
+        StaticModel
+        {
+            id: id,
+            vertices: verts,
+            model_data: model
+        }
+    );
 }
 
-fn read_animated_model(mut byte_rdr: impl Read + Seek, id: String) -> AnimatedModel
+fn read_animated_model(mut byte_rdr: impl Read + Seek, id: String) -> io::Result<AnimatedModel>
 {
-    let (has_normals, has_colors, has_weights) = read_flags(&mut byte_rdr);
-    let palettes = if has_colors { read_colors(&mut byte_rdr) } else { vec![ColorPalette{colors: vec![Color{data: Vec4::new(0.5, 0.5, 0.5, 1.0)}]}] };
-    let num_points = byte_rdr.read_u32::<LittleEndian>().unwrap();
+    let (has_normals, has_colors, has_weights) = read_flags(&mut byte_rdr)?;
+    let palettes = if has_colors { read_colors(&mut byte_rdr)? } else { vec![ColorPalette{colors: vec![Color{data: Vec4::new(0.5, 0.5, 0.5, 1.0)}]}] };
+    let num_points = byte_rdr.read_u32::<LittleEndian>()?;
     let points =
     {
-        read_vec3_array(num_points, &mut byte_rdr)
+        read_vec3_array(num_points, &mut byte_rdr)?
     };
-    let (arma_id, weights) = if has_weights{ read_armature_weights(num_points, &mut byte_rdr) } else { ("".to_string(), vec![]) };
+    let (arma_id, weights) = if has_weights{ read_armature_weights(num_points, &mut byte_rdr)? } else { ("".to_string(), vec![]) };
     let normals = if has_normals
     {
-        let num_norms = byte_rdr.read_u32::<LittleEndian>().unwrap();
-        read_vec3_array(num_norms, &mut byte_rdr)
+        let num_norms = byte_rdr.read_u32::<LittleEndian>()?;
+        read_vec3_array(num_norms, &mut byte_rdr)?
     } 
     else
     {
         vec![]
     };
-    let (verts, polys, vert_map) = read_geometry(&points, &normals, has_colors, has_normals, &mut byte_rdr);
+    let (verts, polys, vert_map) = read_geometry(&points, &normals, has_colors, has_normals, &mut byte_rdr)?;
     let mut anim_verts = verts.into_iter().map(|s| AnimatedVertex{vert: s, weights: vec![]}).collect::<Vec<_>>();
     for (key, index) in vert_map
     {
-        anim_verts[index].weights = weights[key.loc as usize].clone();
+        anim_verts[index].weights = weights.get(key.loc as usize).ok_or_else(|| invalid_data("vertex has no weights"))?.clone();
     }
-    let (min_bound, max_bound) = read_bbox(&mut byte_rdr);
+    let (min_bound, max_bound) = read_bbox(&mut byte_rdr)?;
 
     let model = Model 
     {
@@ -368,49 +519,73 @@ fn read_animated_model(mut byte_rdr: impl Read + Seek, id: String) -> AnimatedMo
         max_bound: max_bound 
     };
 
-    return AnimatedModel
-    {
-        id: id,
-        vertices: anim_verts,
-        model_data: model,
-        armature_id: arma_id,
-    };
+    return Ok
+    (
+        AnimatedModel
+        {
+            id: id,
+            vertices: anim_verts,
+            model_data: model,
+            armature_id: arma_id,
+        }
+    );
 }
 
-fn read_armature( id: String, mut byte_rdr: impl Read ) -> Armature
+fn read_armature( id: String, mut byte_rdr: impl Read ) -> io::Result<Armature>
 {
-    let num_bones = byte_rdr.read_u16::<LittleEndian>().unwrap();
+    let num_bones = byte_rdr.read_u16::<LittleEndian>()?;
     let mut bones = vec![];
     for b in 0..num_bones
     {
-        let bone_id_len = byte_rdr.read_u8().unwrap() as usize;
+        let bone_id_len = byte_rdr.read_u8()? as usize;
         let mut buf: Vec<u8> = vec![0; bone_id_len];
-        byte_rdr.read_exact(&mut buf).unwrap();
-        let bone_id = String::from_utf8(buf).unwrap();
+        byte_rdr.read_exact(&mut buf)?;
+        let bone_id = String::from_utf8(buf).map_err(|_| invalid_data("bone name is not valid utf8"))?;
         let loc = Vec3::new
         (
-            byte_rdr.read_f32::<LittleEndian>().unwrap(),
-            byte_rdr.read_f32::<LittleEndian>().unwrap(),
-            byte_rdr.read_f32::<LittleEndian>().unwrap(),
+            byte_rdr.read_f32::<LittleEndian>()?,
+            byte_rdr.read_f32::<LittleEndian>()?,
+            byte_rdr.read_f32::<LittleEndian>()?,
         );
         let rot = Quat::from_vec4
         (
             Vec4::new
             (
-                byte_rdr.read_f32::<LittleEndian>().unwrap(),
-                byte_rdr.read_f32::<LittleEndian>().unwrap(),
-                byte_rdr.read_f32::<LittleEndian>().unwrap(),
-                byte_rdr.read_f32::<LittleEndian>().unwrap()
+                byte_rdr.read_f32::<LittleEndian>()?,
+                byte_rdr.read_f32::<LittleEndian>()?,
+                byte_rdr.read_f32::<LittleEndian>()?,
+                byte_rdr.read_f32::<LittleEndian>()?
             )
         );
-        let p_index = byte_rdr.read_u16::<LittleEndian>().unwrap() as usize;
+        let p_index = byte_rdr.read_u16::<LittleEndian>()? as usize;
         let parent = if b == 0 { Option::None } else { Option::Some(p_index) };
-        bones.push(Bone::new(parent, loc, rot));
+        bones.push(Bone::new(bone_id, parent, loc, rot));
+    }
+    let control_names = read_control_names(&mut byte_rdr)?;
+    return Ok(Armature::new(id, bones).with_controls(control_names));
+}
+
+//u16 count, then for each control a u8 name length and the name. armatures exported before controls existed just end before this
+fn read_control_names(byte_rdr: &mut impl Read) -> io::Result<Vec<String>>
+{
+    let num_controls = match byte_rdr.read_u16::<LittleEndian>()
+    {
+        Ok(num_controls) => num_controls,
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+    let mut names = vec![];
+    for _ in 0..num_controls
+    {
+        let name_len = byte_rdr.read_u8()? as usize;
+        let mut buf: Vec<u8> = vec![0; name_len];
+        byte_rdr.read_exact(&mut buf)?;
+        names.push(String::from_utf8(buf).map_err(|_| invalid_data("control name is not valid utf8"))?);
     }
-    return Armature::new(id, bones);
+    return Ok(names);
 }
 
-fn read_animation(mut byte_rdr: impl Read, arma: &Armature) -> AnimationClip
+fn read_animation(mut byte_rdr: impl Read, arma: &Armature) -> io::Result<AnimationClip>
 {
     let mut x_location_channels = HashMap::new(); 
     let mut y_location_channels = HashMap::new(); 
@@ -419,47 +594,52 @@ fn read_animation(mut byte_rdr: impl Read, arma: &Armature) -> AnimationClip
     let mut x_scale_channels = HashMap::new();
     let mut y_scale_channels = HashMap::new();
     let mut z_scale_channels = HashMap::new();
-    let anim_bone_count = byte_rdr.read_u16::<LittleEndian>().unwrap() as usize;
-    let min_frame = byte_rdr.read_u16::<LittleEndian>().unwrap();
-    let max_frame = byte_rdr.read_u16::<LittleEndian>().unwrap();
+    let anim_bone_count = byte_rdr.read_u16::<LittleEndian>()? as usize;
+    let min_frame = byte_rdr.read_u16::<LittleEndian>()?;
+    let max_frame = byte_rdr.read_u16::<LittleEndian>()?;
     for bone in 0..anim_bone_count
     {
         if bone  == 79
         {
             print!("");
         }
-        let bone_id = byte_rdr.read_u16::<LittleEndian>().unwrap() as usize;
-        let num_tracks = byte_rdr.read_u8().unwrap() as usize;
-        let mut curves = (0..10).map(|_| vec![]).collect::<Vec<_>>();
+        let bone_id = byte_rdr.read_u16::<LittleEndian>()? as usize;
+        let num_tracks = byte_rdr.read_u8()? as usize;
+        //keys and their interpolation modes
+        let mut curves = (0..10).map(|_| (vec![], vec![])).collect::<Vec<_>>();
         for _ in 0..num_tracks
         {
-            let track_id = byte_rdr.read_u8().unwrap() as usize;
-            let num_keyframes = byte_rdr.read_u16::<LittleEndian>().unwrap();
+            //the high bit marks tracks whose keys each carry an interpolation mode. the rest are linear
+            let track_byte = byte_rdr.read_u8()?;
+            let (track_id, curved) = ((track_byte & 0x7f) as usize, track_byte & 0x80 != 0);
+            let num_keyframes = byte_rdr.read_u16::<LittleEndian>()?;
+            let (keys, modes) = curves.get_mut(track_id).ok_or_else(|| invalid_data("unknown animation track"))?;
             for _ in 0..num_keyframes
             {
-                let frame = byte_rdr.read_u16::<LittleEndian>().unwrap();
-                curves[track_id].push
+                let frame = byte_rdr.read_u16::<LittleEndian>()?;
+                keys.push
                 (
                     AnimationKey
                     {
-                        data: byte_rdr.read_f32::<LittleEndian>().unwrap(),
+                        data: byte_rdr.read_f32::<LittleEndian>()?,
                         frame: frame
                     }
                 );
+                modes.push(if curved { read_key_interpolation(&mut byte_rdr)? } else { KeyInterpolation::Linear });
             }
         }
 
-        if let Option::Some(z_scale) = curves.pop()
+        if let Option::Some((z_scale, modes)) = curves.pop()
         { 
-            if z_scale.len() > 0 {z_scale_channels.insert(bone, ClipChannel::new(z_scale));}
+            if z_scale.len() > 0 {z_scale_channels.insert(bone, ClipChannel::new(z_scale).with_interpolation(modes));}
         }
-        if let Option::Some(y_scale) = curves.pop()
+        if let Option::Some((y_scale, modes)) = curves.pop()
         { 
-            if y_scale.len() > 0 {y_scale_channels.insert(bone, ClipChannel::new(y_scale));}
+            if y_scale.len() > 0 {y_scale_channels.insert(bone, ClipChannel::new(y_scale).with_interpolation(modes));}
         }
-        if let Option::Some(x_scale) = curves.pop()
+        if let Option::Some((x_scale, modes)) = curves.pop()
         { 
-            if x_scale.len() > 0 {x_scale_channels.insert(bone, ClipChannel::new(x_scale));}
+            if x_scale.len() > 0 {x_scale_channels.insert(bone, ClipChannel::new(x_scale).with_interpolation(modes));}
         }
 
         {
@@ -467,48 +647,144 @@ fn read_animation(mut byte_rdr: impl Read, arma: &Armature) -> AnimationClip
             let z_channel = curves.pop().unwrap();
             let y_channel = curves.pop().unwrap();
             let x_channel = curves.pop().unwrap();
-            let orientation_data_empty = w_channel.is_empty();
-            if [&z_channel, &y_channel, &x_channel].iter().any(|data| data.is_empty() != orientation_data_empty)
+            let orientation_data_empty = w_channel.0.is_empty();
+            if [&z_channel, &y_channel, &x_channel].iter().any(|(data, _)| data.is_empty() != orientation_data_empty)
             {
                 //actually riot if you gave me a quaternion that's straight up missing a component
-                panic!("Not all orientation component curves contain keyframe data, or an orientation track meant to be empty erroneously contains data in at least one curve.");
+                return Err(invalid_data("Not all orientation component curves contain keyframe data, or an orientation track meant to be empty erroneously contains data in at least one curve."));
             }
             if !orientation_data_empty
             {
-                orientation_channels.insert(bone, [x_channel, y_channel, z_channel, w_channel].map(|c| ClipChannel::new(c)));
+                orientation_channels.insert(bone, [x_channel, y_channel, z_channel, w_channel].map(|(keys, modes)| ClipChannel::new(keys).with_interpolation(modes)));
             }
         }
 
-        if let Option::Some(z_loc) = curves.pop()
+        if let Option::Some((z_loc, modes)) = curves.pop()
         {
-            if z_loc.len() > 0 {z_location_channels.insert(bone, ClipChannel::new(z_loc));}
+            if z_loc.len() > 0 {z_location_channels.insert(bone, ClipChannel::new(z_loc).with_interpolation(modes));}
         }
-        if let Option::Some(y_loc) = curves.pop()
+        if let Option::Some((y_loc, modes)) = curves.pop()
         {
-            if y_loc.len() > 0 {y_location_channels.insert(bone, ClipChannel::new(y_loc));}
+            if y_loc.len() > 0 {y_location_channels.insert(bone, ClipChannel::new(y_loc).with_interpolation(modes));}
         }
-        if let Option::Some(x_loc) = curves.pop()
+        if let Option::Some((x_loc, modes)) = curves.pop()
         {
-            if x_loc.len() > 0 {x_location_channels.insert(bone, ClipChannel::new(x_loc));}
+            if x_loc.len() > 0 {x_location_channels.insert(bone, ClipChannel::new(x_loc).with_interpolation(modes));}
         }
     }
-    return AnimationClip::new
+    let sync_markers = read_sync_markers(&mut byte_rdr)?;
+    let control_tracks = read_control_tracks(&mut byte_rdr, arma.num_controls(), min_frame)?;
+    return Ok
     (
-        min_frame,
-        max_frame,
-        30,
-        x_location_channels,
-        y_location_channels,
-        z_location_channels,
-        orientation_channels,
-        x_scale_channels,
-        y_scale_channels,
-        z_scale_channels,
-        vec![]
+        AnimationClip::new
+        (
+            min_frame,
+            max_frame,
+            30,
+            x_location_channels,
+            y_location_channels,
+            z_location_channels,
+            orientation_channels,
+            x_scale_channels,
+            y_scale_channels,
+            z_scale_channels,
+            control_tracks
+        ).with_sync_markers(sync_markers)
     );
     
 }
 
+//a u8 mode after the key's value. 0 is constant, 1 linear, 2 bezier followed by the in and out handles as four f32s (frames then value for each),
+//3 hermite followed by the in and out slopes as two f32s, and 4 catmull-rom
+fn read_key_interpolation(byte_rdr: &mut impl Read) -> io::Result<KeyInterpolation>
+{
+    return match byte_rdr.read_u8()?
+    {
+        0 => Ok(KeyInterpolation::Constant),
+        1 => Ok(KeyInterpolation::Linear),
+        2 => 
+        {
+            let in_handle = Vec2::new(byte_rdr.read_f32::<LittleEndian>()?, byte_rdr.read_f32::<LittleEndian>()?);
+            let out_handle = Vec2::new(byte_rdr.read_f32::<LittleEndian>()?, byte_rdr.read_f32::<LittleEndian>()?);
+            Ok(KeyInterpolation::Bezier { in_handle: in_handle, out_handle: out_handle })
+        },
+        3 => 
+        {
+            let in_slope = byte_rdr.read_f32::<LittleEndian>()?;
+            Ok(KeyInterpolation::Hermite { in_slope: in_slope, out_slope: byte_rdr.read_f32::<LittleEndian>()? })
+        },
+        4 => Ok(KeyInterpolation::CatmullRom),
+        _ => Err(invalid_data("unknown keyframe interpolation mode")),
+    };
+}
+
+//u16 count, then for each marker a u8 name length, the name, and a u16 frame. clips exported before markers existed just end before this
+fn read_sync_markers(byte_rdr: &mut impl Read) -> io::Result<Vec<SyncMarker>>
+{
+    let num_markers = match byte_rdr.read_u16::<LittleEndian>()
+    {
+        Ok(num_markers) => num_markers,
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+    let mut markers = vec![];
+    for _ in 0..num_markers
+    {
+        let name_len = byte_rdr.read_u8()? as usize;
+        let mut buf: Vec<u8> = vec![0; name_len];
+        byte_rdr.read_exact(&mut buf)?;
+        let name = String::from_utf8(buf).map_err(|_| invalid_data("sync marker name is not valid utf8"))?;
+        markers.push(SyncMarker { name: name, frame: byte_rdr.read_u16::<LittleEndian>()? });
+    }
+    return Ok(markers);
+}
+
+//after the sync markers, a u16 count, then for each track the u16 index of the armature control it drives and the track laid out
+//like a bone track: the 0x80 flag byte, a u16 key count and the keys. clips without control tracks just end before this.
+//controls the clip doesn't key are held at 0 so the tracks stay lined up with the armature's controls
+fn read_control_tracks(byte_rdr: &mut impl Read, num_controls: usize, min_frame: u16) -> io::Result<Vec<ClipChannel<f32>>>
+{
+    let num_tracks = match byte_rdr.read_u16::<LittleEndian>()
+    {
+        Ok(num_tracks) => num_tracks,
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+    let mut curves = (0..num_controls).map(|_| (vec![], vec![])).collect::<Vec<_>>();
+    for _ in 0..num_tracks
+    {
+        let control = byte_rdr.read_u16::<LittleEndian>()? as usize;
+        let curved = byte_rdr.read_u8()? & 0x80 != 0;
+        let num_keyframes = byte_rdr.read_u16::<LittleEndian>()?;
+        let (keys, modes) = curves.get_mut(control).ok_or_else(|| invalid_data("control track for a control the armature doesn't have"))?;
+        for _ in 0..num_keyframes
+        {
+            let frame = byte_rdr.read_u16::<LittleEndian>()?;
+            keys.push(AnimationKey { data: byte_rdr.read_f32::<LittleEndian>()?, frame: frame });
+            modes.push(if curved { read_key_interpolation(byte_rdr)? } else { KeyInterpolation::Linear });
+        }
+    }
+    return Ok
+    (
+        curves.into_iter().map
+        (
+            |(keys, modes)| 
+            {
+                if keys.is_empty()
+                {
+                    return ClipChannel::new(vec![AnimationKey { data: 0.0, frame: min_frame }]);
+                }
+                return ClipChannel::new(keys).with_interpolation(modes);
+            }
+        ).collect()
+    );
+}
+
+fn invalid_data(msg: &str) -> io::Error
+{
+    return io::Error::new(io::ErrorKind::InvalidData, msg);
+}
+
 #[derive(Hash, PartialEq, Eq)]
 struct VertexKey
 {
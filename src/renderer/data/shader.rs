@@ -0,0 +1,311 @@
+use std::{error::Error, fmt};
+
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::pibald::{self, PibaldError, ShapeShaderClass};
+use super::super::render_state::{properties::{Expression, ExpressionParseError, PropertyGroup, Value}, texture::{ColorGradient, ColorMap, GradientExtrapolation, GradientInterpolation, Placement, Projection, ProjectionSpace, SDFOperand, SDFOperator, SDFTerm, Shader}};
+
+#[derive(Debug)]
+pub enum ShaderLoadError
+{
+    ParseError(PibaldError),
+    //parts of the pibald language the render side has no counterpart for
+    Unsupported(String),
+    ExpressionError(ExpressionParseError),
+}
+
+impl Error for ShaderLoadError {}
+
+impl fmt::Display for ShaderLoadError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            ShaderLoadError::ParseError(err) => write!(f, "{}", err),
+            ShaderLoadError::Unsupported(what) => write!(f, "Shader uses {}, which can't be rendered", what),
+            ShaderLoadError::ExpressionError(err) => write!(f, "Shader expression didn't lower: {:?}", err),
+        }
+    }
+}
+
+//parses a .pib source and turns it into render side expressions. every name the source reads becomes a default arg
+pub fn read_shader(id: String, source: &str) -> Result<Shader, ShaderLoadError>
+{
+    let class = ShapeShaderClass::parse(source).map_err(ShaderLoadError::ParseError)?;
+    let mut lowering = ShaderLowering { next_id: 0, args: PropertyGroup::new() };
+    let mut color_maps = vec![];
+    for map in &class.color_maps
+    {
+        color_maps.push(lowering.color_map(map)?);
+    }
+    let mut placements = vec![];
+    for placement in &class.placements
+    {
+        placements.push(lowering.placement(placement)?);
+    }
+    return Ok(Shader::new(id, color_maps, placements, lowering.args));
+}
+
+struct ShaderLowering
+{
+    //expression ids only need to be unique within the shader
+    next_id: u16,
+    args: PropertyGroup,
+}
+
+impl ShaderLowering
+{
+    fn expression(&mut self, postfix: &str) -> Result<Expression, ShaderLoadError>
+    {
+        let expr = Expression::from_postfix(self.next_id, postfix).map_err(ShaderLoadError::ExpressionError)?;
+        self.next_id += 1;
+        return Ok(expr);
+    }
+
+    //the first use of a name decides its type
+    fn declare(&mut self, name: &str, default: Value)
+    {
+        if self.args.get_property(name).is_none()
+        {
+            self.args.add_property(name, default);
+        }
+    }
+
+    fn scalar(&mut self, expr: &pibald::ScalarExpression) -> String
+    {
+        for name in expr.variables()
+        {
+            self.declare(name, Value::Scalar(0.0));
+        }
+        return expr.to_postfix();
+    }
+
+    fn vector(&mut self, vector: &pibald::Vector) -> String
+    {
+        return match vector
+        {
+            pibald::Vector::ParamVector(param) => format!("{} {} {} vec3", self.scalar(&param.data[0]), self.scalar(&param.data[1]), self.scalar(&param.data[2])),
+            pibald::Vector::IdVector(name) =>
+            {
+                self.declare(name, Value::Vector3(Vec3::ZERO));
+                name.clone()
+            },
+        };
+    }
+
+    fn color(&mut self, color: &pibald::Color) -> String
+    {
+        return match color
+        {
+            pibald::Color::ParamColor(param) =>
+            {
+                let channels: Vec<String> = param.data.iter().map(|channel| return self.scalar(channel)).collect();
+                format!("{} color", channels.join(" "))
+            },
+            pibald::Color::IdColor(name) =>
+            {
+                self.declare(name, Value::Color(Vec4::new(0.0, 0.0, 0.0, 1.0)));
+                name.clone()
+            },
+        };
+    }
+
+    //rotations multiply together in order, same as the pibald evaluator
+    fn matrix(&mut self, matrix: &pibald::Matrix) -> Result<String, ShaderLoadError>
+    {
+        let param = match matrix
+        {
+            pibald::Matrix::ParamMatrix(param) => param,
+            pibald::Matrix::IdMatrix(name) =>
+            {
+                self.declare(name, Value::Matrix4(Mat4::IDENTITY));
+                return Ok(name.clone());
+            },
+        };
+        let sheared = match &param.shear
+        {
+            pibald::Vector::ParamVector(shear) => shear.data.iter().any(|val| return val.to_postfix().parse::<f32>() != Ok(0.0)),
+            pibald::Vector::IdVector(_) => true,
+        };
+        if sheared
+        {
+            return Err(ShaderLoadError::Unsupported("shear".to_string()));
+        }
+        let mut rotation = String::new();
+        for (i, quat) in param.rotation.iter().enumerate()
+        {
+            let axis = self.vector(&quat.axis);
+            let angle = self.scalar(&quat.angle);
+            rotation = match i
+            {
+                0 => format!("{} {} quat", axis, angle),
+                _ => format!("{} {} {} quat *", rotation, axis, angle),
+            };
+        }
+        if rotation.is_empty()
+        {
+            rotation = "0 0 1 vec3 0 quat".to_string();
+        }
+        let location = self.vector(&param.location);
+        let scale = self.vector(&param.scale);
+        return Ok(format!("{} {} {} trs", location, rotation, scale));
+    }
+
+    fn color_map(&mut self, map: &pibald::ColorMap) -> Result<ColorMap, ShaderLoadError>
+    {
+        let mut sdf_stack = vec![];
+        for term in &map.sdf_stack
+        {
+            sdf_stack.push(self.sdf_term(term)?);
+        }
+        return match &map.variant
+        {
+            pibald::ColorMapVariant::Binary(binary) =>
+            {
+                let color = self.color(&binary.color);
+                Ok(ColorMap::binary(self.expression(&color)?, sdf_stack))
+            },
+            pibald::ColorMapVariant::Gradient(gradient) =>
+            {
+                let inner_grad = self.gradient(&gradient.inner_grad)?;
+                let outer_grad = match &gradient.outer_grad
+                {
+                    Some(outer) => Some(self.gradient(outer)?),
+                    None => None,
+                };
+                Ok(ColorMap::gradient(inner_grad, outer_grad, sdf_stack))
+            },
+        };
+    }
+
+    fn gradient(&mut self, gradient: &pibald::ColorGradient) -> Result<ColorGradient, ShaderLoadError>
+    {
+        let extrapolation = match gradient.extrapolation
+        {
+            pibald::GradientExtrapolation::LastColor() => GradientExtrapolation::LastColor,
+            pibald::GradientExtrapolation::Repeat() => GradientExtrapolation::Repeat,
+            pibald::GradientExtrapolation::RepeatReflect() => GradientExtrapolation::RepeatReflect,
+        };
+        let max_distance = self.scalar(&gradient.max_distance);
+        let mut lowered = ColorGradient::new(self.expression(&max_distance)?, extrapolation);
+        for point in &gradient.color_points
+        {
+            let color = self.color(&point.color);
+            let val = self.scalar(&point.val);
+            let interpolation_mode = match point.interpolation_mode
+            {
+                pibald::GradientInterpolation::Linear() => GradientInterpolation::Linear,
+                pibald::GradientInterpolation::Step() => GradientInterpolation::Step,
+            };
+            lowered = lowered.with_point(self.expression(&color)?, self.expression(&val)?, interpolation_mode);
+        }
+        return Ok(lowered);
+    }
+
+    fn sdf_term(&mut self, term: &pibald::SDFTerm) -> Result<SDFTerm, ShaderLoadError>
+    {
+        let operand = match term
+        {
+            pibald::SDFTerm::Operator(op) =>
+            {
+                return match op
+                {
+                    pibald::SDFOperator::Mask => Ok(SDFTerm::Operator(SDFOperator::Mask)),
+                    pibald::SDFOperator::Minimum(count) => Ok(SDFTerm::Operator(SDFOperator::Minimum(*count as usize))),
+                    pibald::SDFOperator::Average(count) => Ok(SDFTerm::Operator(SDFOperator::Average(*count as usize))),
+                    pibald::SDFOperator::TilePattern => Err(ShaderLoadError::Unsupported("the tile pattern operator".to_string())),
+                    pibald::SDFOperator::WavePattern => Err(ShaderLoadError::Unsupported("the wave pattern operator".to_string())),
+                };
+            },
+            pibald::SDFTerm::Operand(operand) => operand,
+        };
+        let lowered = match operand
+        {
+            pibald::SDFOperand::Circle(tf, radius) =>
+            {
+                let (tf, radius) = (self.matrix(tf)?, self.scalar(radius));
+                SDFOperand::Circle { tf: self.expression(&tf)?, radius: self.expression(&radius)? }
+            },
+            pibald::SDFOperand::Rectangle(tf, width, height) =>
+            {
+                let (tf, width, height) = (self.matrix(tf)?, self.scalar(width), self.scalar(height));
+                SDFOperand::Rectangle { tf: self.expression(&tf)?, width: self.expression(&width)?, height: self.expression(&height)? }
+            },
+            pibald::SDFOperand::Sphere(tf, radius) =>
+            {
+                let (tf, radius) = (self.matrix(tf)?, self.scalar(radius));
+                SDFOperand::Sphere { tf: self.expression(&tf)?, radius: self.expression(&radius)? }
+            },
+            pibald::SDFOperand::Plane(tf) =>
+            {
+                let tf = self.matrix(tf)?;
+                SDFOperand::Plane { tf: self.expression(&tf)? }
+            },
+            pibald::SDFOperand::Polygon(tf, points) =>
+            {
+                let tf = self.matrix(tf)?;
+                let mut lowered_points = vec![];
+                for point in points
+                {
+                    let point = format!("{} {} vec2", self.scalar(&point.data[0]), self.scalar(&point.data[1]));
+                    lowered_points.push(self.expression(&point)?);
+                }
+                SDFOperand::Polygon { tf: self.expression(&tf)?, points: lowered_points }
+            },
+            pibald::SDFOperand::RegularPolygon(tf, radius, num_points) =>
+            {
+                let (tf, radius, num_points) = (self.matrix(tf)?, self.scalar(radius), self.scalar(num_points));
+                SDFOperand::RegularPolygon { tf: self.expression(&tf)?, num_points: self.expression(&num_points)?, radius: self.expression(&radius)? }
+            },
+            pibald::SDFOperand::PolyStar(tf, outer_radius, inner_radius, num_points) =>
+            {
+                let (tf, outer_radius, inner_radius, num_points) = (self.matrix(tf)?, self.scalar(outer_radius), self.scalar(inner_radius), self.scalar(num_points));
+                SDFOperand::PolyStar
+                {
+                    tf: self.expression(&tf)?,
+                    numpoints: self.expression(&num_points)?,
+                    inner_radius: self.expression(&inner_radius)?,
+                    outer_radius: self.expression(&outer_radius)?,
+                }
+            },
+        };
+        return Ok(SDFTerm::Operand(lowered));
+    }
+
+    fn placement(&mut self, placement: &pibald::Placement) -> Result<Placement, ShaderLoadError>
+    {
+        let tf = self.matrix(&placement.tf)?;
+        let projection = match &placement.projection
+        {
+            pibald::Projection::Planar() => Projection::Planar,
+            pibald::Projection::Triplanar(sharpness) =>
+            {
+                let sharpness = self.scalar(sharpness);
+                Projection::Triplanar { sharpness: self.expression(&sharpness)? }
+            },
+            pibald::Projection::Spherical() => Projection::Spherical,
+            pibald::Projection::Cylindrical(axis) =>
+            {
+                let axis = self.vector(axis);
+                Projection::Cylindrical { axis: self.expression(&axis)? }
+            },
+        };
+        let space = match placement.space
+        {
+            pibald::ProjectionSpace::Object() => ProjectionSpace::Object,
+            pibald::ProjectionSpace::World() => ProjectionSpace::World,
+        };
+        let mut lowered = Placement::new(placement.index, self.expression(&tf)?, projection, space);
+        if let pibald::PlacementVariant::TilePattern(offset) = &placement.variant
+        {
+            let offset = self.vector(offset);
+            lowered = lowered.with_tile(self.expression(&offset)?);
+        }
+        if let Some(bone) = &placement.bone
+        {
+            lowered = lowered.with_bone(bone);
+        }
+        return Ok(lowered);
+    }
+}
@@ -36,4 +36,211 @@ fn test_animation_read()
     let arma_key = "rho_armature".to_string();
     repo.load_armature(arma_key.clone(), String::from_str("C:\\Users\\fobja\\3D Objects\\export_test\\rho.pibs").ok().unwrap());
     repo.load_animation(String::from_str("jump").ok().unwrap(), String::from_str("C:\\Users\\fobja\\3D Objects\\export_test\\clip_jump.piba").ok().unwrap(), &arma_key);*/
-}
\ No newline at end of file
+}
+#[test]
+fn test_hot_reload_armature()
+{
+    use std::{fs::File, io::Write, time::{Duration, SystemTime}};
+    use byteorder::{WriteBytesExt, LittleEndian};
+    use crate::logger::ConsoleLogger;
+    use super::ReloadedAsset;
+
+    fn write_armature(path: &std::path::Path, bone_names: &[&str], modified: SystemTime)
+    {
+        let mut bytes: Vec<u8> = vec![];
+        bytes.write_u16::<LittleEndian>(bone_names.len() as u16).unwrap();
+        for name in bone_names
+        {
+            bytes.write_u8(name.len() as u8).unwrap();
+            bytes.extend_from_slice(name.as_bytes());
+            for val in [0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0]
+            {
+                bytes.write_f32::<LittleEndian>(val).unwrap();
+            }
+            bytes.write_u16::<LittleEndian>(0).unwrap();
+        }
+        let mut file = File::create(path).unwrap();
+        file.write_all(&bytes).unwrap();
+        file.set_modified(modified).unwrap();
+    }
+
+    let path = std::env::temp_dir().join(format!("hot_reload_{}.pibs", std::process::id()));
+    let start = SystemTime::now() - Duration::from_secs(60);
+    write_armature(&path, &["root"], start);
+    let mut repo = InMemoryModelRepository::new();
+    let key = "arma".to_string();
+    repo.load_armature(key.clone(), &path);
+    repo.set_hot_reload(true);
+    assert!(repo.poll_hot_reload(&ConsoleLogger).is_empty(), "Nothing changed so nothing should reload");
+
+    //cut off partway through the bone
+    let mut file = File::create(&path).unwrap();
+    file.write_all(&[1, 0, 4, b'r']).unwrap();
+    file.set_modified(start + Duration::from_secs(10)).unwrap();
+    drop(file);
+    assert!(repo.poll_hot_reload(&ConsoleLogger).is_empty(), "Broken file should not reload");
+    assert_eq!(repo.get_armature(&key).unwrap().num_bones(), 1, "Broken file should keep the old armature");
+
+    write_armature(&path, &["root", "head"], start + Duration::from_secs(20));
+    let old = repo.get_armature(&key).unwrap();
+    let reloaded = repo.poll_hot_reload(&ConsoleLogger);
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(reloaded.len(), 1, "Fixed file should reload");
+    assert!(matches!(&reloaded[0], ReloadedAsset::Armature{ old: prev, new } if std::rc::Rc::ptr_eq(prev, &old) && new.num_bones() == 2), "Reload should hand back both armatures");
+    assert_eq!(repo.get_armature(&key).unwrap().bone_index("head"), Some(1), "Repository should hold the new armature");
+}
+
+#[test]
+fn test_sync_marker_read()
+{
+    use std::path::Path;
+    use crate::renderer::render_state::{animation::SyncMarker, common::NormalizedFloat};
+
+    let mut repo = InMemoryModelRepository::new();
+    let arma_key = "rho_armature".to_string();
+    repo.load_armature(arma_key.clone(), Path::new("resources/rho.pibs"));
+    repo.load_animation("walk".to_string(), Path::new("resources/clip_walk.piba"), &arma_key);
+    repo.load_animation("run".to_string(), Path::new("resources/clip_run.piba"), &arma_key);
+    repo.load_animation("idle".to_string(), Path::new("resources/clip_idle.piba"), &arma_key);
+    let walk = repo.get_animation(&"walk".to_string()).unwrap();
+    let run = repo.get_animation(&"run".to_string()).unwrap();
+    assert_eq!(walk.sync_markers(), &[SyncMarker { name: "right_foot_down".to_string(), frame: 9 }, SyncMarker { name: "left_foot_down".to_string(), frame: 25 }]);
+    assert!(repo.get_animation(&"idle".to_string()).unwrap().sync_markers().is_empty(), "Clips exported without markers should still load");
+    //walk frame 17 is halfway from right_foot_down to left_foot_down. run goes from frame 1 to 12, so its right foot at 7 is five frames from its left at 1 and halfway is 9.5
+    let matched = run.match_phase(walk.as_ref(), NormalizedFloat::clamped(16.0 / 31.0)).unwrap();
+    assert!((matched.get_val() - 8.5 / 11.0).abs() < 0.0001, "Run should be halfway between its own markers, got {}", matched.get_val());
+}
+
+#[test]
+fn test_curved_track_read()
+{
+    use std::rc::Rc;
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use crate::renderer::render_state::animation::{AnimationStateBuilder, Armature, Bone, MixerBuilder, SimpleMixerBuilder};
+
+    let rig = || Armature::new("rig".to_string(), vec![Bone::new("root".to_string(), None, Vec3::ZERO, glam::Quat::IDENTITY)]);
+    //one bone with a curved x track, a constant key then a linear one, and a plain y track
+    let clip_bytes = |mode: u8| 
+    {
+        let mut bytes = vec![];
+        for value in [1u16, 0, 10, 0] { bytes.write_u16::<LittleEndian>(value).unwrap(); }
+        bytes.write_u8(2).unwrap();
+        bytes.write_u8(0x80).unwrap();
+        bytes.write_u16::<LittleEndian>(2).unwrap();
+        bytes.write_u16::<LittleEndian>(0).unwrap();
+        bytes.write_f32::<LittleEndian>(0.0).unwrap();
+        bytes.write_u8(mode).unwrap();
+        if mode == 3
+        {
+            bytes.write_f32::<LittleEndian>(0.0).unwrap();
+            bytes.write_f32::<LittleEndian>(0.0).unwrap();
+        }
+        bytes.write_u16::<LittleEndian>(10).unwrap();
+        bytes.write_f32::<LittleEndian>(1.0).unwrap();
+        bytes.write_u8(1).unwrap();
+        bytes.write_u8(1).unwrap();
+        bytes.write_u16::<LittleEndian>(1).unwrap();
+        bytes.write_u16::<LittleEndian>(0).unwrap();
+        bytes.write_f32::<LittleEndian>(2.0).unwrap();
+        return bytes;
+    };
+    let sample_x = |mode: u8| 
+    {
+        let clip = super::read_animation(clip_bytes(mode).as_slice(), &rig()).unwrap();
+        let mut anim_state = AnimationStateBuilder::create_builder(Rc::new(rig()), vec![MixerBuilder::Simple(SimpleMixerBuilder::new("clip", Rc::new(clip)))])
+            .base_starting_animation("clip")
+            .build();
+        //clips read at 30 frames a second, so this is halfway through
+        anim_state.update(1.0 / 6.0);
+        return anim_state.pose().joint(0).unwrap().loc_rot_scale().0;
+    };
+    assert_eq!(sample_x(0), Vec3::new(0.0, 2.0, 0.0), "Constant keys should read from curved tracks, with the plain track after them still lined up");
+    //flat out of the first key and into the linear second key along the segment's own slope
+    assert!((sample_x(3).x - 0.375).abs() < 0.0001, "Hermite keys should read their slopes, got {}", sample_x(3).x);
+    assert!(super::read_animation(clip_bytes(9).as_slice(), &rig()).is_err(), "Unknown interpolation modes should fail to read");
+}
+
+#[test]
+fn test_control_track_read()
+{
+    use std::rc::Rc;
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use crate::renderer::render_state::animation::{AnimationStateBuilder, MixerBuilder, SimpleMixerBuilder};
+
+    //one root bone, then the controls
+    let mut arma_bytes = vec![];
+    arma_bytes.write_u16::<LittleEndian>(1).unwrap();
+    arma_bytes.write_u8(4).unwrap();
+    arma_bytes.extend_from_slice(b"root");
+    for val in [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0]
+    {
+        arma_bytes.write_f32::<LittleEndian>(val).unwrap();
+    }
+    arma_bytes.write_u16::<LittleEndian>(0).unwrap();
+    arma_bytes.write_u16::<LittleEndian>(2).unwrap();
+    for name in ["blink", "smile"]
+    {
+        arma_bytes.write_u8(name.len() as u8).unwrap();
+        arma_bytes.extend_from_slice(name.as_bytes());
+    }
+    let armature = super::read_armature("rig".to_string(), arma_bytes.as_slice()).unwrap();
+    assert_eq!(armature.control_index("smile"), Some(1), "Control names should be read after the bones");
+
+    //no bone tracks or sync markers, and smile keyed from 0 to 1 over ten frames
+    let mut clip_bytes = vec![];
+    for value in [0u16, 0, 10, 0, 1, 1] { clip_bytes.write_u16::<LittleEndian>(value).unwrap(); }
+    clip_bytes.write_u8(0).unwrap();
+    clip_bytes.write_u16::<LittleEndian>(2).unwrap();
+    clip_bytes.write_u16::<LittleEndian>(0).unwrap();
+    clip_bytes.write_f32::<LittleEndian>(0.0).unwrap();
+    clip_bytes.write_u16::<LittleEndian>(10).unwrap();
+    clip_bytes.write_f32::<LittleEndian>(1.0).unwrap();
+    let clip = super::read_animation(clip_bytes.as_slice(), &armature).unwrap();
+    let mut anim_state = AnimationStateBuilder::create_builder(Rc::new(armature), vec![MixerBuilder::Simple(SimpleMixerBuilder::new("clip", Rc::new(clip)))])
+        .base_starting_animation("clip")
+        .build();
+    anim_state.update(1.0 / 6.0);
+    assert_eq!(anim_state.pose().control_values(), &[0.0, 0.5], "Keyed controls should play back and unkeyed ones hold at 0");
+}
+
+#[test]
+fn test_hot_reload_shader()
+{
+    use std::{fs, rc::Rc, time::{Duration, SystemTime}};
+    use crate::logger::ConsoleLogger;
+    use crate::renderer::render_state::{properties::Value, texture::ShaderInstance};
+    use super::ReloadedAsset;
+
+    let source = "SOLID(color(1.0, 0.0, 0.0, 1.0), SD_CIRCLE(mat4(), 0.5))
+    SINGULAR(mat4(translation[vec3(offset, 0.0, 0.0)]), 0)
+    ";
+    let path = std::env::temp_dir().join(format!("hot_reload_{}.pib", std::process::id()));
+    let start = SystemTime::now() - Duration::from_secs(60);
+    fs::write(&path, source).unwrap();
+    fs::File::options().write(true).open(&path).unwrap().set_modified(start).unwrap();
+    let mut repo = InMemoryModelRepository::new();
+    let key = "spot".to_string();
+    repo.load_shader(key.clone(), &path);
+    repo.set_hot_reload(true);
+    let mut instance = ShaderInstance::new(repo.get_shader(&key).unwrap(), vec![]);
+    instance.set_property("offset", Value::Scalar(2.0)).unwrap();
+
+    //a second placement reading the same property
+    fs::write(&path, format!("{}SINGULAR(mat4(translation[vec3(0.0, offset, 0.0)]), 0)\n", source)).unwrap();
+    fs::File::options().write(true).open(&path).unwrap().set_modified(start + Duration::from_secs(10)).unwrap();
+    let reloaded = repo.poll_hot_reload(&ConsoleLogger);
+    let _ = fs::remove_file(&path);
+    assert_eq!(reloaded.len(), 1, "Changed source should reload");
+    let (old, new) = match &reloaded[0]
+    {
+        ReloadedAsset::Shader { old, new } => (old.clone(), new.clone()),
+        _ => panic!("Reload should hand back both shaders"),
+    };
+    assert!(Rc::ptr_eq(&new, &repo.get_shader(&key).unwrap()), "Repository should hold the new shader");
+    assert!(instance.replace_shader(&old, &new), "Instance made from the old shader should be rebuilt");
+    assert!(!instance.replace_shader(&old, &new), "Rebuilt instance shouldn't match the old shader anymore");
+    instance.eval_expressions();
+    assert_eq!(instance.placement_transforms().len(), 2, "Instance should pick up the new placement");
+    assert_eq!(instance.get_property("offset"), Some(&Value::Scalar(2.0)), "Property values should carry over");
+    assert_eq!(instance.placement_transforms()[1].w_axis.y, -2.0, "New placement should read the carried over value");
+}
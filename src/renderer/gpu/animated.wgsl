@@ -5,6 +5,7 @@ const MAXBONES = 128u;
 const NUMBONESPERVERT = 8u;
 
 const MODELCOLORCOUNT = 32u;
+const PLACEMENTCOUNT = 128u;
 
 struct SpotLight
 {
@@ -65,6 +66,8 @@ var<uniform> model: mat4x4<f32>;
 var<uniform> colors: array<vec4<f32>, MODELCOLORCOUNT>;
 @group(2) @binding(2)
 var<uniform> anim_transforms: array<mat4x4<f32>, MAXBONES>;
+@group(2) @binding(3)
+var<storage> placements: PlacementStore;
 
 struct AnimVertexInput
 {
@@ -81,6 +84,10 @@ struct VertexOutput
     @location(0) world_position: vec4<f32>,
 	@location(1) color: vec4<f32>,
 	@location(2) normal: vec3<f32>,
+	//posed object space. placement transforms already carry any bone they follow,
+	//so object space placements get evaluated with these
+	@location(3) object_position: vec3<f32>,
+	@location(4) object_normal: vec3<f32>,
 }
 
 @vertex
@@ -111,6 +118,8 @@ fn vs_animated(v_in: AnimVertexInput,) -> VertexOutput
 	w_4567.w * (anim_transforms[i_4567.w] * vec4<f32>(v_in.normal, 0.0));
 	out.normal = (model * vec4<f32>(anim_normal.xyz, 0.0)).xyz;
     out.world_position = model * vec4<f32>(anim_pos.xyz, 1.0);
+    out.object_position = anim_pos.xyz;
+    out.object_normal = anim_normal.xyz;
     out.clip_position = view_proj * out.world_position;
     out.color = colors[v_in.color];
     return out;
@@ -119,15 +128,19 @@ fn vs_animated(v_in: AnimVertexInput,) -> VertexOutput
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> 
 {
+	if(placements.errored != 0u)
+	{
+		return vec4<f32>(fallback_pattern(in.object_position, in.object_normal), 1.0);
+	}
     //let eval_stack = array<f32, 32>();
     //var out_color = vec4<f32>(0.0, 0.0, 0.0, 0.0);
-	//for(var i = 0; i < 1; i++)
+	//for(var i = 0u; i < placements.count; i++)
 	//{	
-	//	let index = command_buffer.placements[i];
+	//	let index = placements.placements[i];
 	//	let color_map = command_buffer.maps[index.map_index];
 	//	for(var j = color_map.starting_instruction; j < color_map.ending_instruction + 1u; j++)
 	//	{
-    //        let map_pos = index.transform * in.world_position;
+    //        let map_pos = project_placement(index.projection, index.basis, (index.transform * vec4<f32>(in.object_position, 1.0)).xyz);
 	//		out_color = blend_colors(out_color, get_color(eval_sdf2(command_buffer.sdf_stack[j], eval_stack, map_pos.xy), color_map));
     //        if(out_color.a == 1.0)
     //        {
@@ -95,7 +95,7 @@ impl GPUState
         {
             if stat_mod.dirty() 
             {
-                if let Some(gpu_mod) = self.static_model_instances.get(&stat_mod.id())
+                if let Some(gpu_mod) = self.static_model_instances.get_mut(&stat_mod.id())
                 {
                     gpu_mod.update_static_model_instance(stat_mod, queue);
                 }
@@ -1,12 +1,13 @@
-use glam::{Quat, Mat4};
+use glam::{Quat, Mat3, Mat4, Vec3};
 use wgpu::{util::DeviceExt, BindGroupDescriptor, BindGroupEntry};
 
-use crate::renderer::render_state::{model::{AnimatedModelInstance, StaticModelInstance, Model}, common::Color};
+use crate::renderer::render_state::{model::{AnimatedModelInstance, StaticModelInstance, Model}, common::Color, properties::Value, texture::{Projection, ProjectionSpace, ShaderInstance}};
 
 const NUM_BONES : usize = 256;
 const NUM_MORPH_TARGETS : usize = 128;
 const NUM_SDFS_PER_VERT : usize = 8;
 const NUM_COLORS : usize = 128;
+const NUM_PLACEMENTS : usize = 128;
 
 
 //Model bind groups last as long as the scene they belong to is loaded, or the entire runtime if they're a common asset.
@@ -41,6 +42,19 @@ pub static STATIC_LAYOUT : wgpu::BindGroupLayoutDescriptor = wgpu::BindGroupLayo
             },
             count: None,
         },
+        //Placements - 2
+        wgpu::BindGroupLayoutEntry
+        {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer 
+            {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
         //pibald instructions - 1
         /*wgpu::BindGroupLayoutEntry
         {
@@ -100,6 +114,19 @@ pub static SKINNED_LAYOUT : wgpu::BindGroupLayoutDescriptor = wgpu::BindGroupLay
             },
             count: None,
         },
+        //Placements
+        wgpu::BindGroupLayoutEntry
+        {
+            binding: 3,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer 
+            {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
     ],
 };
 
@@ -110,6 +137,146 @@ struct GPUColor
     rgba : [f32;4],
 }
 
+//the start of PlacementStore in placement.wgsl, padded out to the placement array's alignment
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GPUPlacementHeader
+{
+    count : u32,
+    //set when any shader instance on the model is errored, the model gets drawn with the fallback pattern
+    errored : u32,
+    padding : [u32; 2],
+}
+
+const PLACEMENT_HEADER_SIZE : usize = std::mem::size_of::<GPUPlacementHeader>();
+
+//matches Placement in placement.wgsl, mat3x3 columns are padded out to four floats
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GPUPlacement
+{
+    map_index : u32,
+    projection : u32,
+    space : u32,
+    sharpness : f32,
+    transform : [[f32; 4]; 4],
+    basis : [[f32; 4]; 3],
+}
+
+//the PROJ constants in static.wgsl, with the sharpness and basis the projection needs.
+//triplanar blends by the normal in placement space, so its basis is tf's normal matrix
+fn resolve_projection(projection: &Projection, shader: &ShaderInstance, tf: &Mat4) -> (u32, f32, Mat3)
+{
+    return match projection
+    {
+        Projection::Planar => (0, 0.0, Mat3::IDENTITY),
+        Projection::Triplanar { sharpness } => 
+        {
+            let normal_tf = Mat3::from_mat4(*tf).inverse().transpose();
+            match shader.expression_value(sharpness.get_id())
+            {
+                Some(Value::Scalar(sharpness)) => (1, sharpness.max(0.0), normal_tf),
+                _ => (1, 1.0, normal_tf),
+            }
+        },
+        Projection::Spherical => (2, 0.0, Mat3::IDENTITY),
+        Projection::Cylindrical { axis } => match shader.expression_value(axis.get_id())
+        {
+            Some(Value::Vector3(axis)) => (3, 0.0, cylinder_basis(*axis)),
+            _ => (3, 0.0, Mat3::IDENTITY),
+        },
+    };
+}
+
+//columns are side, axis, front. the front is whatever is closest to z so it lines up with planar
+fn cylinder_basis(axis: Vec3) -> Mat3
+{
+    let axis = axis.normalize_or_zero();
+    if axis == Vec3::ZERO
+    {
+        return Mat3::IDENTITY;
+    }
+    let mut front = Vec3::Z - Vec3::Z.dot(axis) * axis;
+    if front.length_squared() <= f32::EPSILON
+    {
+        front = Vec3::X - Vec3::X.dot(axis) * axis;
+    }
+    let front = front.normalize();
+    return Mat3::from_cols(axis.cross(front), axis, front);
+}
+
+//every placement of every shader on the model into placements, which is cleared first. anything past NUM_PLACEMENTS is dropped
+fn collect_placements<'a>(shaders: impl Iterator<Item=&'a ShaderInstance>, placements: &mut Vec<GPUPlacement>) -> GPUPlacementHeader
+{
+    placements.clear();
+    let mut errored = false;
+    for shader in shaders
+    {
+        errored |= shader.is_errored();
+        for (placement, tf) in shader.shader().placements.iter().zip(shader.placement_transforms())
+        {
+            let (projection, sharpness, basis) = resolve_projection(placement.projection(), shader, tf);
+            placements.push
+            (
+                GPUPlacement
+                {
+                    map_index: placement.map_index(),
+                    projection: projection,
+                    space: match placement.space()
+                    {
+                        ProjectionSpace::Object => 0,
+                        ProjectionSpace::World => 1,
+                    },
+                    sharpness: sharpness,
+                    //world space placements already have the model transform folded in
+                    transform: tf.to_cols_array_2d(),
+                    basis: [basis.x_axis.extend(0.0).to_array(), basis.y_axis.extend(0.0).to_array(), basis.z_axis.extend(0.0).to_array()],
+                }
+            );
+        }
+    }
+    placements.truncate(NUM_PLACEMENTS);
+    return GPUPlacementHeader { count: placements.len() as u32, errored: errored as u32, padding: [0; 2] };
+}
+
+fn init_placement_storage_buffer(header: &GPUPlacementHeader, placements: &[GPUPlacement], device: &wgpu::Device) -> wgpu::Buffer
+{
+    let mut contents = vec![0u8; PLACEMENT_HEADER_SIZE + NUM_PLACEMENTS * std::mem::size_of::<GPUPlacement>()];
+    contents[0..PLACEMENT_HEADER_SIZE].copy_from_slice(bytemuck::bytes_of(header));
+    contents[PLACEMENT_HEADER_SIZE..PLACEMENT_HEADER_SIZE + placements.len() * std::mem::size_of::<GPUPlacement>()].copy_from_slice(bytemuck::cast_slice(placements));
+    return device.create_buffer_init
+    (
+        &wgpu::util::BufferInitDescriptor 
+        {
+            label: Some( "Placement Storage" ),
+            contents: &contents,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        }
+    );
+}
+
+fn write_placements(buffer: &wgpu::Buffer, header: &GPUPlacementHeader, placements: &[GPUPlacement], queue: &wgpu::Queue)
+{
+    queue.write_buffer(buffer, 0, bytemuck::bytes_of(header));
+    if !placements.is_empty()
+    {
+        queue.write_buffer(buffer, PLACEMENT_HEADER_SIZE as wgpu::BufferAddress, bytemuck::cast_slice(placements));
+    }
+}
+
+fn placement_binding(buffer: &wgpu::Buffer) -> wgpu::BindingResource
+{
+    return wgpu::BindingResource::Buffer
+    (
+        wgpu::BufferBinding
+        {
+            buffer: buffer,
+            offset: 0,
+            size: wgpu::BufferSize::new((PLACEMENT_HEADER_SIZE + NUM_PLACEMENTS * std::mem::size_of::<GPUPlacement>()) as u64),
+        }
+    );
+}
+
 fn init_tf_uniform_buffer(tf: &Mat4, device: &wgpu::Device) -> wgpu::Buffer
 {
     return device.create_buffer_init
@@ -153,6 +320,9 @@ pub struct GPUStaticModelInstance
     model_id: String,
     tf_uniform : wgpu::Buffer,
     color_store: wgpu::Buffer,
+    placement_store: wgpu::Buffer,
+    //refilled every update rather than collected into a new vec
+    placements: Vec<GPUPlacement>,
     pub static_bind_group : wgpu::BindGroup,
 }
 
@@ -162,6 +332,9 @@ impl GPUStaticModelInstance
     {
         let tf_uniform = init_tf_uniform_buffer(&model.transform(), device);
         let color_uniform = init_color_uniform_buffer(&model.colors(), device);
+        let mut placements = Vec::with_capacity(NUM_PLACEMENTS);
+        let header = collect_placements(model.shader_instances(), &mut placements);
+        let placement_store = init_placement_storage_buffer(&header, &placements, device);
         let bind_group = device.create_bind_group
         (
             &wgpu::BindGroupDescriptor
@@ -195,7 +368,12 @@ impl GPUStaticModelInstance
                                 size: wgpu::BufferSize::new(std::mem::size_of::<[[f32;4]; NUM_COLORS]>() as u64),
                             },
                         ),
-                    }
+                    },
+                    BindGroupEntry
+                    {
+                        binding: 2,
+                        resource: placement_binding(&placement_store),
+                    },
                 ]
             }
         );
@@ -204,11 +382,13 @@ impl GPUStaticModelInstance
             model_id: model.model_id().clone(), 
             tf_uniform: tf_uniform,
             color_store: color_uniform,
+            placement_store: placement_store,
+            placements: placements,
             static_bind_group: bind_group
         };
     }
 
-    pub fn update_static_model_instance(&self, instance: &StaticModelInstance,  queue: &wgpu::Queue)
+    pub fn update_static_model_instance(&mut self, instance: &StaticModelInstance,  queue: &wgpu::Queue)
     {
         queue.write_buffer
         (
@@ -216,11 +396,14 @@ impl GPUStaticModelInstance
             0,
             &bytemuck::bytes_of( &instance.transform().to_cols_array() )
         );
+        let header = collect_placements(instance.shader_instances(), &mut self.placements);
+        write_placements(&self.placement_store, &header, &self.placements, queue);
     }
 
     pub fn destroy(self)
     {
         self.tf_uniform.destroy();
+        self.placement_store.destroy();
     }
 }
 
@@ -232,6 +415,8 @@ pub struct GPUAnimatedModelInstance
     pose_buffer: [Mat4; NUM_BONES],
     inverse_pose_buffer: [Mat4; NUM_BONES],
     animation_state_uniform : wgpu::Buffer,
+    placement_store: wgpu::Buffer,
+    placements: Vec<GPUPlacement>,
     pub animated_bind_group : wgpu::BindGroup,
 }
 
@@ -241,6 +426,9 @@ impl GPUAnimatedModelInstance
     {
         let tf_uniform = init_tf_uniform_buffer(&instance.transform(), device);
         let color_store = init_color_uniform_buffer(&instance.colors(), device);
+        let mut placements = Vec::with_capacity(NUM_PLACEMENTS);
+        let header = collect_placements(instance.shader_instances(), &mut placements);
+        let placement_store = init_placement_storage_buffer(&header, &placements, device);
         let mut pose_buf = [Mat4::IDENTITY; NUM_BONES];
         let mut inv_buf = [Mat4::IDENTITY; NUM_BONES];
         instance.anim_state().write_current_pose_transforms(&mut pose_buf, &mut inv_buf);
@@ -300,6 +488,11 @@ impl GPUAnimatedModelInstance
                             }
                         ),
                     },
+                    BindGroupEntry
+                    {
+                        binding: 3,
+                        resource: placement_binding(&placement_store),
+                    },
                 ]
             }
         );
@@ -310,6 +503,8 @@ impl GPUAnimatedModelInstance
             inverse_pose_buffer: inv_buf,
             tf_uniform: tf_uniform,
             animation_state_uniform: anim_buf,
+            placement_store: placement_store,
+            placements: placements,
             animated_bind_group : animated_bind_group,
             color_store: color_store,
         };
@@ -330,12 +525,16 @@ impl GPUAnimatedModelInstance
             0,
             &bytemuck::cast_slice(&self.pose_buffer.iter().map(|m|m.to_cols_array()).flatten().collect::<Vec<f32>>()[0..(instance.anim_state().armature.num_bones()*16)]),
         );
+        //placements following a bone move with the pose every frame
+        let header = collect_placements(instance.shader_instances(), &mut self.placements);
+        write_placements(&self.placement_store, &header, &self.placements, queue);
     }
 
     pub fn destroy(self)
     {
         self.tf_uniform.destroy();
         self.animation_state_uniform.destroy();
+        self.placement_store.destroy();
     }
 }
 
@@ -299,10 +299,27 @@ impl GPUStore
         );
     }
 
+    //drops the old buffers first so material slots that went away in the new file don't linger
+    pub fn reload_static_model(&mut self, model: &StaticModel, device: &wgpu::Device)
+    {
+        self.unload_model(&model.id);
+        self.load_static_model(model, device);
+    }
+
+    pub fn reload_skinned_model(&mut self, model: &AnimatedModel, device: &wgpu::Device)
+    {
+        self.unload_model(&model.id);
+        self.load_skinned_model(model, device);
+    }
+
     pub fn unload_model(&mut self, handle : &String)
     {
-        self.mat_index_buffers.iter().for_each(|pair| pair.1.buf.destroy());
+        self.mat_index_buffers.iter().filter(|pair| pair.0.model_id.eq(handle)).for_each(|pair| pair.1.buf.destroy());
         self.mat_index_buffers.retain(|k, _v| !k.model_id.eq(handle) );
+        if let Some(index_buf) = self.index_buffers.remove(handle)
+        {
+            index_buf.buf.destroy();
+        }
         if let Some(buf) = self.anim_vert_buffers.remove(handle)
         {
             buf.destroy();
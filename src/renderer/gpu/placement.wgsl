@@ -0,0 +1,87 @@
+//placement code shared by static.wgsl and animated.wgsl. renderer.rs puts this in front of both,
+//and each of them declares PLACEMENTCOUNT and the placements binding
+
+//matches GPUPlacement in gpu_model.rs
+struct Placement
+{
+	map_index : u32,
+	projection : u32,
+	space : u32,
+	//triplanar only
+	sharpness : f32,
+	transform : mat4x4<f32>,
+	//cylindrical - side, axis, front. triplanar - takes object normals into placement space
+	basis : mat3x3<f32>,
+}
+
+//one per placement on the model, in the order the model instance lists its shaders
+struct PlacementStore
+{
+	count : u32,
+	//nonzero when one of the model's shader instances is errored
+	errored : u32,
+	placements : array<Placement, PLACEMENTCOUNT>,
+}
+
+//maps a point in placement space to the coordinates the sdfs get evaluated at.
+//triplanar goes through sample_fallback instead, it needs the normal
+fn project_placement(projection : u32, basis : mat3x3<f32>, p : vec3<f32>) -> vec3<f32>
+{
+	switch projection
+	{
+		case 2u: //PROJSPHERICAL
+		{
+			return vec3<f32>(atan2(p.x, p.z), atan2(p.y, length(p.xz)), length(p));
+		}
+		case 3u: //PROJCYLINDRICAL
+		{
+			let c = transpose(basis) * p;
+			return vec3<f32>(atan2(c.x, c.z), c.y, length(c.xz));
+		}
+		default:
+		{
+			return p;
+		}
+	}
+}
+
+fn triplanar_weights(n : vec3<f32>, sharpness : f32) -> vec3<f32>
+{
+	let w = pow(abs(normalize(n)), vec3<f32>(sharpness));
+	let total = w.x + w.y + w.z;
+	if(total <= 0.0)
+	{
+		return vec3<f32>(0.0, 0.0, 1.0);
+	}
+	return w / total;
+}
+
+//1 on magenta squares, 0 on black ones, eight to a unit
+fn fallback_checks(p : vec2<f32>) -> f32
+{
+	let check = floor(p * 8.0);
+	return f32((i32(check.x + check.y) & 1) == 0);
+}
+
+//each axis gets a planar projection, cycled the same way as the pibald evaluator so they all keep the same handedness
+fn sample_fallback(placement : Placement, object_position : vec3<f32>, object_normal : vec3<f32>) -> f32
+{
+	let local = (placement.transform * vec4<f32>(object_position, 1.0)).xyz;
+	if(placement.projection == 1u) //PROJTRIPLANAR
+	{
+		let w = triplanar_weights(placement.basis * object_normal, placement.sharpness);
+		return w.x * fallback_checks(local.yz) + w.y * fallback_checks(local.zx) + w.z * fallback_checks(local.xy);
+	}
+	return fallback_checks(project_placement(placement.projection, placement.basis, local).xy);
+}
+
+//magenta and black checks in the first placement's space, for models with an errored shader instance
+fn fallback_pattern(object_position : vec3<f32>, object_normal : vec3<f32>) -> vec3<f32>
+{
+	var magenta = fallback_checks(object_position.xy);
+	if(placements.count > 0u)
+	{
+		magenta = sample_fallback(placements.placements[0], object_position, object_normal);
+	}
+	return vec3<f32>(magenta, 0.0, magenta);
+}
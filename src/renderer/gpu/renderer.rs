@@ -1,14 +1,27 @@
 use std::{rc::Rc, collections::HashMap, process::id};
 
-use wgpu::{Device, Queue, RenderPipeline, BindGroupLayout, VertexState, PrimitiveState, PrimitiveTopology, FrontFace, Face, PolygonMode, MultisampleState, include_wgsl, SurfaceConfiguration};
+use wgpu::{Device, Queue, RenderPipeline, BindGroupLayout, VertexState, PrimitiveState, PrimitiveTopology, FrontFace, Face, PolygonMode, MultisampleState, SurfaceConfiguration};
 
 use crate::renderer::render_state::{render_state::RenderState, common::Id};
 
 use super::{gpu::GPUState, gpu_store::GPUStore, gpu_camera::GPUCamera};
 
+//like include_wgsl, with placement.wgsl put in front since wgsl has no includes of its own
+macro_rules! include_placement_wgsl
+{
+    ($file:literal) => 
+    {
+        wgpu::ShaderModuleDescriptor
+        {
+            label: Some($file),
+            source: wgpu::ShaderSource::Wgsl(concat!(include_str!("placement.wgsl"), include_str!($file)).into()),
+        }
+    };
+}
+
 fn create_static_pipeline(bg_layouts: &[&BindGroupLayout], config: &wgpu::SurfaceConfiguration, device: &Device,) -> wgpu::RenderPipeline
 {
-    let static_shader = device.create_shader_module(include_wgsl!("static.wgsl") );
+    let static_shader = device.create_shader_module(include_placement_wgsl!("static.wgsl") );
 
     let static_pipeline_layout = device.create_pipeline_layout
     (
@@ -90,7 +103,7 @@ fn create_static_pipeline(bg_layouts: &[&BindGroupLayout], config: &wgpu::Surfac
 fn create_animated_pipeline(bg_layouts: &[&BindGroupLayout], config: &wgpu::SurfaceConfiguration, device: &Device,) -> wgpu::RenderPipeline
 {
     
-    let animated_shader = device.create_shader_module(include_wgsl!("animated.wgsl") );
+    let animated_shader = device.create_shader_module(include_placement_wgsl!("animated.wgsl") );
 
     let animated_pipeline_layout = device.create_pipeline_layout
     (
@@ -28,6 +28,16 @@ const LASTCOLOR = 0u;
 const REPEAT = 1u;
 const REPEATREFLECT = 2u;
 
+//enumeration of placement projections
+const PROJPLANAR = 0u;
+const PROJTRIPLANAR = 1u;
+const PROJSPHERICAL = 2u;
+const PROJCYLINDRICAL = 3u;
+
+//enumeration of placement spaces
+const SPACEOBJECT = 0u;
+const SPACEWORLD = 1u;
+
 const SPOTLIGHTCOUNT = 1024u;
 const POINTLIGHTCOUNT = 1024u;
 
@@ -62,12 +72,6 @@ struct ColorMap
 }
 
 
-struct Placement
-{
-	map_index : u32,
-	transform : mat4x4<f32>,
-}
-
 struct PibaldBuffer
 {
     maps : array<ColorMap, COLORMAPCOUNT>,
@@ -212,6 +216,8 @@ var<storage> spot_lights: SpotLightStore;
 var<uniform> model: mat4x4<f32>;
 @group(2) @binding(1)
 var<uniform> colors: array<vec4<f32>, MODELCOLORCOUNT>;
+@group(2) @binding(2)
+var<storage> placements: PlacementStore;
 //@group(1) @binding(1)
 //var<storage> command_buffer: PibaldBuffer;
 
@@ -228,6 +234,9 @@ struct VertexOutput
     @location(0) world_position: vec4<f32>,
 	@location(1) color: vec4<f32>,
 	@location(2) normal: vec3<f32>,
+	//placements get evaluated with these, world space ones carry the model transform in theirs
+	@location(3) object_position: vec3<f32>,
+	@location(4) object_normal: vec3<f32>,
 }
 
 
@@ -238,6 +247,8 @@ fn vs_static(v_in: VertexInput,) -> VertexOutput
     var out: VertexOutput;
 	out.normal = (model * vec4<f32>(v_in.normal, 0.0)).xyz;
     out.world_position = model * vec4<f32>(v_in.position, 1.0);
+    out.object_position = v_in.position;
+    out.object_normal = v_in.normal;
     out.clip_position = view_proj * out.world_position;
     out.color = colors[v_in.color];
     return out;
@@ -247,15 +258,19 @@ fn vs_static(v_in: VertexInput,) -> VertexOutput
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> 
 {
+	if(placements.errored != 0u)
+	{
+		return vec4<f32>(fallback_pattern(in.object_position, in.object_normal), 1.0);
+	}
     //let eval_stack = array<f32, 32>();
     //var out_color = vec4<f32>(0.0, 0.0, 0.0, 0.0);
-	//for(var i = 0; i < 1; i++)
+	//for(var i = 0u; i < placements.count; i++)
 	//{	
-	//	let index = command_buffer.placements[i];
+	//	let index = placements.placements[i];
 	//	let color_map = command_buffer.maps[index.map_index];
 	//	for(var j = color_map.starting_instruction; j < color_map.ending_instruction + 1u; j++)
 	//	{
-    //        let map_pos = index.transform * in.world_position;
+    //        let map_pos = project_placement(index.projection, index.basis, (index.transform * vec4<f32>(in.object_position, 1.0)).xyz);
 	//		out_color = blend_colors(out_color, get_color(eval_sdf2(command_buffer.sdf_stack[j], eval_stack, map_pos.xy), color_map));
     //        if(out_color.a == 1.0)
     //        {
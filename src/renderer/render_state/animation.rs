@@ -1,6 +1,6 @@
-use std::{rc::Rc, collections::HashMap, f32::consts::PI};
+use std::{rc::Rc, cell::RefCell, collections::HashMap, f32::consts::PI, fmt, time::Duration};
 
-use glam::{Vec3, Quat, Mat4};
+use glam::{Vec2, Vec3, Quat, Mat4};
 use super::common::NormalizedFloat;
 
 #[derive(Clone)]
@@ -38,114 +38,138 @@ impl ArmaturePose
         }
     }
 
+    //offset from the bone's rest transform
+    pub fn joint(&self, index: usize) -> Option<&PoseTransform>
+    {
+        return self.joints.get(index);
+    }
+
     pub fn control_values(&self) -> &Vec<f32>
     {
         return &self.control_values;
     }
 
-    fn add_clip(&mut self, other: &AnimationClip, other_t: NormalizedFloat, other_playback: PlaybackType, mask: &Option<ArmatureMask>, clip_weight : NormalizedFloat)
+    //each bone's model space transform, without the inverse rest transform skinning needs
+    fn model_transforms(&self, armature: &Armature, dest: &mut Vec<Mat4>)
     {
-        let multiplier = match mask 
+        dest.clear();
+        for i in 0..self.joints.len()
         {
-            Some(m) => m.multiplier.get_val(),
-            None => 1.0,
-        };
+            let parent_tf = armature.bones[i].parent.map_or(Mat4::IDENTITY, |p_dex| dest[p_dex]);
+            dest.push(parent_tf * armature.bones[i].local_tf * self.joints[i].to_matrix());
+        }
+    }
+
+    //after a joint changes, everything under it has moved too
+    fn refresh_model_transforms(&self, armature: &Armature, bone: usize, dest: &mut [Mat4])
+    {
+        for i in armature.subtree(bone)
+        {
+            let parent_tf = armature.bones[i].parent.map_or(Mat4::IDENTITY, |p_dex| dest[p_dex]);
+            dest[i] = parent_tf * armature.bones[i].local_tf * self.joints[i].to_matrix();
+        }
+    }
+
+    //turns a bone about its head by a model space rotation, then clamps the joint to max_angle away from rest
+    fn rotate_joint(&mut self, armature: &Armature, model_tfs: &mut [Mat4], bone: usize, rotation: Quat, max_angle: f32)
+    {
+        let parent_tf = armature.bones[bone].parent.map_or(Mat4::IDENTITY, |p_dex| model_tfs[p_dex]);
+        let (_, rest_rot, _) = (parent_tf * armature.bones[bone].local_tf).to_scale_rotation_translation();
+        let mut joint = (rest_rot.inverse() * rotation * rest_rot * self.joints[bone].orientation).normalize();
+        if joint.w < 0.0
+        {
+            joint = -joint;
+        }
+        let (axis, angle) = joint.to_axis_angle();
+        if angle > max_angle
+        {
+            joint = Quat::from_axis_angle(axis, max_angle);
+        }
+        self.joints[bone].orientation = joint;
+        self.refresh_model_transforms(armature, bone, model_tfs);
+    }
 
-        if multiplier > 0.0
+    //with a root space, the root's horizontal movement and yaw are left out so they can be applied to the instance instead
+    fn add_clip(&mut self, other: &AnimationClip, cursor: &ClipCursor, other_t: NormalizedFloat, other_playback: PlaybackType, clip_weight : NormalizedFloat, root: Option<&RootMotionSpace>)
+    {
+        let joint_weight = clip_weight.get_val();
+        if joint_weight > 0.0
         {
             for i in 0..self.joints.len()
             {
-                let joint_weight = match mask  
-                {
-                    Some(m) => m.bone_weights[i].get_val(), 
-                    None => 1.0 
-                } * clip_weight.get_val();
-                if joint_weight > 0.0
+                if let Some(space) = root.filter(|space| space.bone == i)
                 {
-                    let loc_track = other.sample_location_track(i, other_t, other_playback);
-                    if let Some(x) = loc_track[0] { self.joints[i].location.x = self.joints[i].location.x + x * joint_weight; }
-                    if let Some(y) = loc_track[1] { self.joints[i].location.y = self.joints[i].location.y + y * joint_weight; }
-                    if let Some(z) = loc_track[2] { self.joints[i].location.z = self.joints[i].location.z + z * joint_weight; }
-
-                    if let Some(other_rot) = other.sample_orientation_track(i, other_t, other_playback)
-                    {
-                        self.joints[i].orientation = self.joints[i].orientation * Quat::IDENTITY.slerp(other_rot, joint_weight);
-                    }
-
-                    let scale_track = other.sample_scale_track(i, other_t, other_playback);
+                    let (loc, rot) = space.strip(other.sample_root(i, Some(cursor), other_t, other_playback));
+                    self.joints[i].location = self.joints[i].location + loc * joint_weight;
+                    self.joints[i].orientation = self.joints[i].orientation * Quat::IDENTITY.slerp(rot, joint_weight);
+                    let scale_track = other.sample_scale_track(i, cursor, other_t, other_playback);
                     if let Some(x) = scale_track[0] { self.joints[i].scale.x = self.joints[i].scale.x * f32::interpolate(&1.0, &x, joint_weight); }
                     if let Some(y) = scale_track[1] { self.joints[i].scale.y = self.joints[i].scale.y * f32::interpolate(&1.0, &y, joint_weight); }
                     if let Some(z) = scale_track[2] { self.joints[i].scale.z = self.joints[i].scale.z * f32::interpolate(&1.0, &z, joint_weight); }
+                    continue;
+                }
+                let loc_track = other.sample_location_track(i, Some(cursor), other_t, other_playback);
+                if let Some(x) = loc_track[0] { self.joints[i].location.x = self.joints[i].location.x + x * joint_weight; }
+                if let Some(y) = loc_track[1] { self.joints[i].location.y = self.joints[i].location.y + y * joint_weight; }
+                if let Some(z) = loc_track[2] { self.joints[i].location.z = self.joints[i].location.z + z * joint_weight; }
 
+                if let Some(other_rot) = other.sample_orientation_track(i, Some(cursor), other_t, other_playback)
+                {
+                    self.joints[i].orientation = self.joints[i].orientation * Quat::IDENTITY.slerp(other_rot, joint_weight);
                 }
+
+                let scale_track = other.sample_scale_track(i, cursor, other_t, other_playback);
+                if let Some(x) = scale_track[0] { self.joints[i].scale.x = self.joints[i].scale.x * f32::interpolate(&1.0, &x, joint_weight); }
+                if let Some(y) = scale_track[1] { self.joints[i].scale.y = self.joints[i].scale.y * f32::interpolate(&1.0, &y, joint_weight); }
+                if let Some(z) = scale_track[2] { self.joints[i].scale.z = self.joints[i].scale.z * f32::interpolate(&1.0, &z, joint_weight); }
             }
             for i in 0..self.control_values.len()
             {
-                let control_weight = match mask 
-                { 
-                    Some(m) => m.control_weights[i].get_val(), 
-                    None => 1.0 
-                } * clip_weight.get_val();
-                if control_weight > 0.0
+                if let Some(other_control) = other.sample_control_track(i, cursor, other_t, other_playback)
                 {
-                    if let Some(other_control) = other.sample_control_track(i, other_t, other_playback)
-                    {
-                        self.control_values[i] = self.control_values[i] + other_control * control_weight;
-                    }
+                    self.control_values[i] = self.control_values[i] + other_control * joint_weight;
                 }
             }
         }
     }
 
-    fn mix_clip(&mut self, other: &AnimationClip, other_t: NormalizedFloat, other_playback: PlaybackType, mask: &Option<ArmatureMask>, clip_weight: NormalizedFloat)
+    //puts a layer's pose on top of this one, bone by bone at the layer's weight times the mask's weight for that bone.
+    //override pulls each joint toward the layer's, additive stacks the layer's offset from the bind pose on top
+    fn blend_layer(&mut self, layer: &ArmaturePose, mix: MixType, weight: NormalizedFloat, mask: Option<&ArmatureMask>)
     {
-        let multiplier = match mask
-        {
-            Some(m) => m.multiplier.get_val(),
-            None => 1.0,
-        };
-
-        if multiplier > 0.0
+        for i in 0..self.joints.len().min(layer.joints.len())
         {
-            for i in 0..self.joints.len()
+            let joint_weight = weight.get_val() * mask.map_or(1.0, |m| m.bone_weight(i));
+            if joint_weight > 0.0
             {
-                let joint_weight = match mask  
-                { 
-                    Some(m) => m.bone_weights[i].get_val(), 
-                    None => 1.0 
-                } * clip_weight.get_val();
-                if joint_weight > 0.0
+                let (joint, other) = (&mut self.joints[i], &layer.joints[i]);
+                match mix
                 {
-                    let loc_track = other.sample_location_track(i, other_t, other_playback);
-                    if let Some(x) = loc_track[0] { self.joints[i].location.x = self.joints[i].location.x.interpolate(&x, joint_weight) } else { self.joints[i].location.x; }
-                    if let Some(y) = loc_track[1] { self.joints[i].location.y = self.joints[i].location.y.interpolate(&y, joint_weight) } else { self.joints[i].location.y; } 
-                    if let Some(z) = loc_track[2] { self.joints[i].location.z = self.joints[i].location.z.interpolate(&z, joint_weight) } else { self.joints[i].location.z; }
-                    
-                    if let Some(other_rot) = other.sample_orientation_track(i, other_t, other_playback)
+                    MixType::Override =>
                     {
-                        self.joints[i].orientation = self.joints[i].orientation.lerp(other_rot, joint_weight);
-                    }
-
-                    let scale_track = other.sample_scale_track(i, other_t, other_playback);
-                    if let Some(x) = scale_track[0] { self.joints[i].scale.x = self.joints[i].scale.x.interpolate(&x, joint_weight) } else { self.joints[i].scale.x; }
-                    if let Some(y) = scale_track[1] { self.joints[i].scale.y = self.joints[i].scale.y.interpolate(&y, joint_weight) } else { self.joints[i].scale.y; } 
-                    if let Some(z) = scale_track[2] { self.joints[i].scale.z = self.joints[i].scale.z.interpolate(&z, joint_weight) } else { self.joints[i].scale.z; }                        
+                        joint.location = joint.location.lerp(other.location, joint_weight);
+                        joint.orientation = joint.orientation.slerp(other.orientation, joint_weight);
+                        joint.scale = joint.scale.lerp(other.scale, joint_weight);
+                    },
+                    MixType::Additive =>
+                    {
+                        joint.location = joint.location + other.location * joint_weight;
+                        joint.orientation = joint.orientation * Quat::IDENTITY.slerp(other.orientation, joint_weight);
+                        joint.scale = joint.scale * Vec3::ONE.lerp(other.scale, joint_weight);
+                    },
                 }
             }
-            for i in 0..self.control_values.len()
+        }
+        for i in 0..self.control_values.len().min(layer.control_values.len())
+        {
+            let control_weight = weight.get_val() * mask.map_or(1.0, |m| m.control_weight(i));
+            if control_weight > 0.0
             {
-                let control = match mask 
-                { 
-                    Some(m) => m.control_weights[i].get_val(), 
-                    None => 1.0 
-                } * clip_weight.get_val();
-                if control > 0.0
+                self.control_values[i] = match mix
                 {
-                    if let Some(other_control) = other.sample_control_track(i, other_t, other_playback)
-                    {
-                        self.control_values[i] = self.control_values[i].interpolate(&other_control, control);
-                    }
-                }
+                    MixType::Override => self.control_values[i].interpolate(&layer.control_values[i], control_weight),
+                    MixType::Additive => self.control_values[i] + layer.control_values[i] * control_weight,
+                };
             }
         }
     }
@@ -158,6 +182,10 @@ impl ArmaturePose
             joint.orientation = Quat::IDENTITY;
             joint.scale = Vec3::ONE;
         }
+        for control in &mut self.control_values
+        {
+            *control = 0.0;
+        }
     }
 }
 
@@ -165,21 +193,57 @@ struct AnimationTransition
 {
     mixer_id : String,
     duration : f32,
-    elapsed : f32
+    elapsed : f32,
+    //how far into the incoming mixer, kept apart from elapsed so a synced transition can start partway in
+    portion : NormalizedFloat,
+    //the incoming mixer follows the working mixer's sync marker phase until the blend finishes
+    sync : bool,
+    //whether the transition has been through an update, the first one reports events sitting right at its starting portion
+    started : bool,
 }
 
 impl AnimationTransition
 {
     fn new(mixer_id: &str, duration: std::time::Duration) -> Self
     {
-        return AnimationTransition { mixer_id: mixer_id.to_string(), duration: duration.as_secs_f32(), elapsed: 0.0 };
+        return AnimationTransition { mixer_id: mixer_id.to_string(), duration: duration.as_secs_f32(), elapsed: 0.0, portion: NormalizedFloat::zero(), sync: false, started: false };
+    }
+
+    //how far the blend has got, from zero as it starts to one once it's done
+    fn fade(&self) -> f32
+    {
+        return (self.elapsed / self.duration).min(1.0);
     }
 }
 
 struct MixerEvent
 {
+    name: String,
     trigger_time: NormalizedFloat,
-    callbacks: Vec<Box<dyn Fn()>>,
+}
+
+impl MixerEvent
+{
+    //in time order, so events passed in the same update are reported in the order they happen
+    fn from_builder(events: &HashMap<String, NormalizedFloat>) -> Vec<MixerEvent>
+    {
+        let mut events = events.iter().map(|(name, time)| MixerEvent { name: name.clone(), trigger_time: *time }).collect::<Vec<MixerEvent>>();
+        events.sort_by(|a, b| a.trigger_time.get_val().total_cmp(&b.trigger_time.get_val()).then_with(|| a.name.cmp(&b.name)));
+        return events;
+    }
+}
+
+//an event a mixer passed, queued on AnimationState until drained
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationEvent
+{
+    pub name: String,
+    pub layer: String,
+    pub mixer: String,
+    //where the event sits in the mixer
+    pub time: NormalizedFloat,
+    //the mixer's share of its layer once the update was done, times the layer's weight. so footsteps from a walk that's nearly faded out can be quiet
+    pub weight: f32,
 }
 
 //These should probably be considered value objects
@@ -190,18 +254,134 @@ pub struct ClipMixer
     playback_rate: f32,
     playback_type: PlaybackType,
     mixer_variant: ClipMixerVariant,
-    events: HashMap<String, MixerEvent>
+    events: Vec<MixerEvent>,
+    root_motion: bool,
 }
 
 impl ClipMixer
 {
-    fn mix_to_pose(&self, mix: MixType, time : NormalizedFloat, mask: &Option<ArmatureMask>, clip_weight: NormalizedFloat, destination: &mut ArmaturePose, )
+    fn add_to_pose(&self, time : NormalizedFloat, clip_weight: NormalizedFloat, destination: &mut ArmaturePose, root: Option<&RootMotionSpace>)
+    {
+        let root = root.filter(|_| self.root_motion);
+        self.mixer_variant.for_each_clip
+        (
+            time, 
+            |clip, cursor, clip_time, weight| destination.add_clip(clip, cursor, clip_time, self.playback_type, NormalizedFloat::clamped(clip_weight.get_val() * weight), root)
+        );
+    }
+
+    //the root's horizontal position and yaw, blended across the mixer's clips. sampled without the clips' cursors,
+    //root motion reads the start and end of a stride and a lap's ends, which would keep sending the pose's cursors back to a full search
+    fn sample_root_motion(&self, time: NormalizedFloat, root: &RootMotionSpace) -> (Vec3, f32)
+    {
+        let mut position = Vec3::ZERO;
+        let mut yaw = 0.0;
+        self.mixer_variant.for_each_clip
+        (
+            time, 
+            |clip, _, clip_time, weight| 
+            {
+                let (clip_position, clip_yaw) = root.extract(clip.sample_root(root.bone, None, clip_time, self.playback_type));
+                position += clip_position * weight;
+                yaw += clip_yaw * weight;
+            }
+        );
+        return (position, yaw);
+    }
+
+    //how the root moved going travel on from from, counted the same way as passed_events. a looping mixer that came back around
+    //is taken as running to the end, then a whole stride for every extra time round, and then on from the start
+    fn root_motion_between(&self, from: NormalizedFloat, travel: f32, root: &RootMotionSpace) -> RootMotion
+    {
+        if !self.root_motion
+        {
+            return RootMotion::default();
+        }
+        let segment = |a: NormalizedFloat, b: NormalizedFloat|
+        {
+            let (start, start_yaw) = self.sample_root_motion(a, root);
+            let (end, end_yaw) = self.sample_root_motion(b, root);
+            //the short way round, in case the yaw crossed over from pi to -pi
+            let yaw = (end_yaw - start_yaw + PI).rem_euclid(2.0 * PI) - PI;
+            RootMotion { translation: Quat::from_rotation_y(-start_yaw) * (end - start), yaw: yaw }
+        };
+        let end = from.get_val() + travel.max(0.0);
+        let laps = end.floor() as i64;
+        if matches!(self.playback_type, PlaybackType::Sequential) || laps == 0
+        {
+            return segment(from, NormalizedFloat::clamped(end));
+        }
+        let lap = segment(NormalizedFloat::zero(), NormalizedFloat::one());
+        let mut motion = segment(from, NormalizedFloat::one());
+        for _ in 1..laps
+        {
+            motion = motion.then(lap);
+        }
+        return motion.then(segment(NormalizedFloat::zero(), NormalizedFloat::wrapped(end)));
+    }
+
+    fn advance(&self, portion: NormalizedFloat, dt: f32) -> NormalizedFloat
+    {
+        let step = self.travel(dt);
+        return match self.playback_type 
+        {
+            PlaybackType::Sequential => NormalizedFloat::clamped(step + portion.get_val()),
+            PlaybackType::Looping => NormalizedFloat::wrapped(step + portion.get_val()),
+        };
+    }
+
+    //how far through the mixer dt takes it, before any wrapping or clamping. more than one for a loop that comes round more than once
+    fn travel(&self, dt: f32) -> f32
+    {
+        return (self.playback_rate * dt)/self.duration_seconds();
+    }
+
+    //the forward distance between two portions, for mixers that were moved rather than advanced.
+    //moving backwards counts as no travel. a loop that went back less than half way round is taken as having slipped back
+    //rather than come almost all the way round, nothing following a phase moves that far in one update
+    fn travel_between(&self, from: NormalizedFloat, to: NormalizedFloat) -> f32
+    {
+        return match self.playback_type
+        {
+            PlaybackType::Sequential => (to.get_val() - from.get_val()).max(0.0),
+            PlaybackType::Looping => 
+            {
+                let forward = (to.get_val() - from.get_val()).rem_euclid(1.0);
+                if forward > 0.5 { 0.0 } else { forward }
+            },
+        };
+    }
+
+    //the events passed going travel on from from, in the order they were passed. the end is included and the start is left out
+    //unless include_start is set, which it only is for a mixer's first update, so an event is never reported twice.
+    //loops report an event once for every time round. passed is cleared, then filled with where each event was passed and its index in events
+    fn passed_events(&self, from: NormalizedFloat, travel: f32, include_start: bool, passed: &mut Vec<(f32, usize)>)
     {
-        match mix 
+        let from = from.get_val();
+        passed.clear();
+        for (i, event) in self.events.iter().enumerate()
         {
-            MixType::Additive => self.mixer_variant.mix_to_pose(time, self.playback_type, mask, clip_weight, destination),
-            MixType::Override => self.mixer_variant.add_to_pose(time, self.playback_type, mask, clip_weight, destination),
+            let t = event.trigger_time.get_val();
+            match self.playback_type
+            {
+                PlaybackType::Sequential if (from < t || (include_start && from == t)) && t <= from + travel => passed.push((t, i)),
+                PlaybackType::Sequential => (),
+                PlaybackType::Looping => 
+                {
+                    let first = if include_start { (from - t).ceil() as i64 } else { (from - t).floor() as i64 + 1 };
+                    let last = (from + travel - t).floor() as i64;
+                    passed.extend((first..=last).map(|lap| (t + lap as f32, i)));
+                },
+            }
         }
+        //events passed at the same time stay in the order they were added
+        passed.sort_unstable_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    }
+
+    //where this mixer should be to match leader's sync marker phase. None if either has no markers or they share no marker names
+    fn match_phase(&self, leader: &ClipMixer, leader_portion: NormalizedFloat) -> Option<NormalizedFloat>
+    {
+        return self.mixer_variant.sync_clip().match_phase(leader.mixer_variant.sync_clip(), leader_portion);
     }
 
     fn duration_seconds(&self) -> f32
@@ -219,25 +399,64 @@ pub enum ClipMixerVariant
 {
     Single(SingleClipMixer),
     LinearBlended(LinearBlendMixer),
+    BlendSpace(BlendSpaceMixer),
 }
 
 impl ClipMixerVariant
 {
-    fn mix_to_pose(&self, time : NormalizedFloat, playback_type: PlaybackType, mask: &Option<ArmatureMask>, clip_weight: NormalizedFloat, destination: &mut ArmaturePose, )
+    fn replace_clip(&mut self, old: &Rc<AnimationClip>, new: &Rc<AnimationClip>)
     {
         match self 
         {
-            ClipMixerVariant::Single(single_clip) => single_clip.mix_to_pose(time, playback_type, mask, clip_weight, destination),
-            ClipMixerVariant::LinearBlended(blend_clip) => blend_clip.mix_to_pose(time, playback_type, mask, clip_weight, destination),
+            ClipMixerVariant::Single(single_clip) => 
+            {
+                if Rc::ptr_eq(&single_clip.clip, old)
+                {
+                    single_clip.clip = new.clone();
+                }
+            },
+            ClipMixerVariant::LinearBlended(blend_clip) => 
+            {
+                for point in &mut blend_clip.points
+                {
+                    if Rc::ptr_eq(&point.clip, old)
+                    {
+                        point.clip = new.clone();
+                    }
+                }
+            },
+            ClipMixerVariant::BlendSpace(blend_space) => 
+            {
+                for point in &mut blend_space.points
+                {
+                    if Rc::ptr_eq(&point.clip, old)
+                    {
+                        point.clip = new.clone();
+                    }
+                }
+            },
         };
     }
 
-    fn add_to_pose(&self, time : NormalizedFloat, playback_type: PlaybackType, mask: &Option<ArmatureMask>, clip_weight: NormalizedFloat, destination: &mut ArmaturePose, )
+    //calls f with each clip the mixer plays, the time to play it at and its share of the mixer
+    fn for_each_clip(&self, time : NormalizedFloat, f: impl FnMut(&AnimationClip, &ClipCursor, NormalizedFloat, f32))
     {
         match self 
         {
-            ClipMixerVariant::Single(single_clip) => single_clip.add_to_pose(time, playback_type, mask, clip_weight, destination),
-            ClipMixerVariant::LinearBlended(blend_clip) => blend_clip.add_to_pose(time, playback_type, mask, clip_weight, destination),
+            ClipMixerVariant::Single(single_clip) => single_clip.for_each_clip(time, f),
+            ClipMixerVariant::LinearBlended(blend_clip) => blend_clip.for_each_clip(time, f),
+            ClipMixerVariant::BlendSpace(blend_space) => blend_space.for_each_clip(time, f),
+        };
+    }
+
+    //the clip whose sync markers stand for the whole mixer
+    fn sync_clip(&self) -> &AnimationClip
+    {
+        return match self 
+        {
+            ClipMixerVariant::Single(single_clip) => single_clip.clip.as_ref(),
+            ClipMixerVariant::LinearBlended(blend_clip) => blend_clip.get_start_point().clip.as_ref(),
+            ClipMixerVariant::BlendSpace(blend_space) => blend_space.leader().clip.as_ref(),
         };
     }
 
@@ -247,6 +466,7 @@ impl ClipMixerVariant
         {
             ClipMixerVariant::Single(single_clip) => single_clip.clip.duration_seconds(),
             ClipMixerVariant::LinearBlended(blend_clip) => blend_clip.duration_seconds(),
+            ClipMixerVariant::BlendSpace(blend_space) => blend_space.duration_seconds(),
         };
     }
 
@@ -256,6 +476,7 @@ impl ClipMixerVariant
         {
             ClipMixerVariant::Single(single_clip) => single_clip.clip.duration_frames() as f32,
             ClipMixerVariant::LinearBlended(blend_clip) => blend_clip.duration_frames(),
+            ClipMixerVariant::BlendSpace(blend_space) => blend_space.duration_frames(),
         };
     }
 }
@@ -268,11 +489,21 @@ pub struct AnimationClip
     x_location_tracks : HashMap<usize, ClipChannel<f32>>,
     y_location_tracks : HashMap<usize, ClipChannel<f32>>,
     z_location_tracks : HashMap<usize, ClipChannel<f32>>,
-    orientation_tracks : HashMap<usize, [ClipChannel<f32>; 4]>,
+    orientation_tracks : HashMap<usize, ClipChannel<Quat>>,
     x_scale_tracks : HashMap<usize, ClipChannel<f32>>,
     y_scale_tracks : HashMap<usize, ClipChannel<f32>>,
     z_scale_tracks : HashMap<usize, ClipChannel<f32>>,
     control_tracks : Vec<ClipChannel<f32>>,
+    //sorted by frame
+    sync_markers : Vec<SyncMarker>,
+}
+
+//a named moment in a clip, like a foot touching down, used to line up clips with different timing
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyncMarker
+{
+    pub name: String,
+    pub frame: u16,
 }
 
 impl AnimationClip
@@ -292,7 +523,7 @@ impl AnimationClip
         control_tracks : Vec<ClipChannel<f32>>,
     ) -> Self
     {
-        return AnimationClip
+        let mut clip = AnimationClip
         { 
             start_frame: start, 
             end_frame: end, 
@@ -300,19 +531,100 @@ impl AnimationClip
             x_location_tracks, 
             y_location_tracks, 
             z_location_tracks, 
-            orientation_tracks,
+            //authored as one curve per component, but blended as whole rotations so they stay on the short arc
+            orientation_tracks: orientation_tracks.into_iter().map(|(index, components)| return (index, ClipChannel::from_components(components, start, end))).collect(),
             x_scale_tracks, 
             y_scale_tracks, 
             z_scale_tracks, 
-            control_tracks 
+            control_tracks,
+            sync_markers: vec![],
+        };
+        //every channel gets its own place in a cursor
+        let tracks = [&mut clip.x_location_tracks, &mut clip.y_location_tracks, &mut clip.z_location_tracks, &mut clip.x_scale_tracks, &mut clip.y_scale_tracks, &mut clip.z_scale_tracks];
+        let channels = tracks.into_iter().flat_map(|tracks| tracks.values_mut())
+            .chain(clip.control_tracks.iter_mut());
+        let mut slots = 0;
+        for (slot, channel) in channels.enumerate()
+        {
+            channel.slot = slot;
+            slots = slot + 1;
+        }
+        for (slot, channel) in clip.orientation_tracks.values_mut().enumerate()
+        {
+            channel.slot = slots + slot;
+        }
+        return clip;
+    }
+
+    //the first and last frame the clip plays between
+    pub fn frame_range(&self) -> (u16, u16)
+    {
+        return (self.start_frame, self.end_frame);
+    }
+
+    //every scalar channel in the clip, in no particular order. orientation channels aren't included
+    pub fn channels(&self) -> impl Iterator<Item = &ClipChannel<f32>>
+    {
+        let tracks = [&self.x_location_tracks, &self.y_location_tracks, &self.z_location_tracks, &self.x_scale_tracks, &self.y_scale_tracks, &self.z_scale_tracks];
+        return tracks.into_iter().flat_map(|tracks| tracks.values())
+            .chain(self.control_tracks.iter());
+    }
+
+    pub fn with_sync_markers(mut self, mut sync_markers: Vec<SyncMarker>) -> Self
+    {
+        sync_markers.sort_by_key(|marker| marker.frame);
+        self.sync_markers = sync_markers;
+        return self;
+    }
+
+    pub fn sync_markers(&self) -> &[SyncMarker]
+    {
+        return &self.sync_markers;
+    }
+
+    //the marker at or before time and how far it is to the next one. markers wrap around the end of the clip,
+    //so before the first marker counts as part of the segment from the last
+    fn marker_phase(&self, time: NormalizedFloat) -> Option<(usize, f32)>
+    {
+        let span = (self.end_frame - self.start_frame) as f32;
+        if self.sync_markers.is_empty() || span <= 0.0
+        {
+            return None;
         }
+        let frame = self.start_frame as f32 + time.get_val() * span;
+        let i = self.sync_markers.iter().rposition(|marker| marker.frame as f32 <= frame).unwrap_or(self.sync_markers.len() - 1);
+        let from = self.sync_markers[i].frame as f32;
+        return Some((i, (frame - from).rem_euclid(span) / self.segment_length(i)));
+    }
+
+    fn segment_length(&self, marker: usize) -> f32
+    {
+        let span = (self.end_frame - self.start_frame) as f32;
+        let next = (marker + 1) % self.sync_markers.len();
+        let length = (self.sync_markers[next].frame as f32 - self.sync_markers[marker].frame as f32).rem_euclid(span);
+        return if length == 0.0 { span } else { length };
     }
 
-    fn sample_vec_tracks_for_clip(tf_tracks: &HashMap<usize, ClipChannel<f32>>, bone_dex: usize, start_frame: u16, end_frame: u16, time: NormalizedFloat, playback_type: PlaybackType) -> Option<f32>
+    //the time in this clip at the same point between markers as leader is at leader_time. prefers a segment with the same
+    //marker at both ends, so left_foot_down to right_foot_down lines up with the same step rather than the other one
+    pub fn match_phase(&self, leader: &AnimationClip, leader_time: NormalizedFloat) -> Option<NormalizedFloat>
+    {
+        let (leader_marker, fraction) = leader.marker_phase(leader_time)?;
+        let name = &leader.sync_markers[leader_marker].name;
+        let next_name = &leader.sync_markers[(leader_marker + 1) % leader.sync_markers.len()].name;
+        let count = self.sync_markers.len();
+        let marker = (0..count).find(|i| &self.sync_markers[*i].name == name && &self.sync_markers[(i + 1) % count].name == next_name)
+            .or_else(|| (0..count).find(|i| &self.sync_markers[*i].name == name))?;
+        let span = (self.end_frame - self.start_frame) as f32;
+        let frame = self.sync_markers[marker].frame as f32 + fraction * self.segment_length(marker);
+        return Some(NormalizedFloat::wrapped(((frame - self.start_frame as f32) / span).rem_euclid(1.0)));
+    }
+
+    fn sample_vec_tracks_for_clip(tf_tracks: &HashMap<usize, ClipChannel<f32>>, bone_dex: usize, cursor: Option<&ClipCursor>, start_frame: u16, end_frame: u16, time: NormalizedFloat, playback_type: PlaybackType) -> Option<f32>
     {
         if let Option::Some(track) = tf_tracks.get(&bone_dex)
         {
-            return Option::Some(track.sample(start_frame, end_frame, time, playback_type));
+            return Option::Some(track.sample(start_frame, end_frame, time, playback_type, cursor));
         }
         else 
         { 
@@ -320,17 +632,24 @@ impl AnimationClip
         }
     }
 
-    fn sample_scale_track(&self, index: usize, time : NormalizedFloat, playback_type: PlaybackType,) -> [Option<f32>; 3]
+    fn sample_scale_track(&self, index: usize, cursor: &ClipCursor, time : NormalizedFloat, playback_type: PlaybackType,) -> [Option<f32>; 3]
     {
         return
         [
-            AnimationClip::sample_vec_tracks_for_clip(&self.x_scale_tracks, index, self.start_frame, self.end_frame, time, playback_type),
-            AnimationClip::sample_vec_tracks_for_clip(&self.y_scale_tracks, index, self.start_frame, self.end_frame, time, playback_type),
-            AnimationClip::sample_vec_tracks_for_clip(&self.z_scale_tracks, index, self.start_frame, self.end_frame, time, playback_type),
+            AnimationClip::sample_vec_tracks_for_clip(&self.x_scale_tracks, index, Some(cursor), self.start_frame, self.end_frame, time, playback_type),
+            AnimationClip::sample_vec_tracks_for_clip(&self.y_scale_tracks, index, Some(cursor), self.start_frame, self.end_frame, time, playback_type),
+            AnimationClip::sample_vec_tracks_for_clip(&self.z_scale_tracks, index, Some(cursor), self.start_frame, self.end_frame, time, playback_type),
         ];
     }
 
-    fn sample_location_track(&self, index: usize, time : NormalizedFloat, playback_type: PlaybackType,) -> [Option<f32>; 3]
+    //missing tracks count as the rest pose. root motion samples without a cursor, see ClipMixer::sample_root_motion
+    fn sample_root(&self, index: usize, cursor: Option<&ClipCursor>, time : NormalizedFloat, playback_type: PlaybackType) -> (Vec3, Quat)
+    {
+        let loc = self.sample_location_track(index, cursor, time, playback_type).map(|component| component.unwrap_or(0.0));
+        return (Vec3::from_array(loc), self.sample_orientation_track(index, cursor, time, playback_type).unwrap_or(Quat::IDENTITY));
+    }
+
+    fn sample_location_track(&self, index: usize, cursor: Option<&ClipCursor>, time : NormalizedFloat, playback_type: PlaybackType,) -> [Option<f32>; 3]
     {
         if index == 79
         {
@@ -338,39 +657,26 @@ impl AnimationClip
         }
         return
         [
-            AnimationClip::sample_vec_tracks_for_clip(&self.x_location_tracks, index, self.start_frame, self.end_frame, time, playback_type),
-            AnimationClip::sample_vec_tracks_for_clip(&self.y_location_tracks, index, self.start_frame, self.end_frame, time, playback_type),
-            AnimationClip::sample_vec_tracks_for_clip(&self.z_location_tracks, index, self.start_frame, self.end_frame, time, playback_type),
+            AnimationClip::sample_vec_tracks_for_clip(&self.x_location_tracks, index, cursor, self.start_frame, self.end_frame, time, playback_type),
+            AnimationClip::sample_vec_tracks_for_clip(&self.y_location_tracks, index, cursor, self.start_frame, self.end_frame, time, playback_type),
+            AnimationClip::sample_vec_tracks_for_clip(&self.z_location_tracks, index, cursor, self.start_frame, self.end_frame, time, playback_type),
         ];
     }
 
-    fn sample_orientation_track(&self, index: usize, time : NormalizedFloat, playback_type: PlaybackType,) -> Option<Quat>
+    fn sample_orientation_track(&self, index: usize, cursor: Option<&ClipCursor>, time : NormalizedFloat, playback_type: PlaybackType,) -> Option<Quat>
     {
-        if let Option::Some(quat_curves) = self.orientation_tracks.get(&index)
+        if let Option::Some(channel) = self.orientation_tracks.get(&index)
         {
-            if index == 79
-            {
-                print!("");
-            }
-            return Option::Some
-            (
-                Quat::from_xyzw
-                (
-                    quat_curves[0].sample(self.start_frame, self.end_frame, time, playback_type), 
-                    quat_curves[1].sample(self.start_frame, self.end_frame, time, playback_type), 
-                    quat_curves[2].sample(self.start_frame, self.end_frame, time, playback_type), 
-                    quat_curves[3].sample(self.start_frame, self.end_frame, time, playback_type),
-                )
-            );
+            return Option::Some(channel.sample(self.start_frame, self.end_frame, time, playback_type, cursor));
         }
         return Option::None;
     }
 
-    fn sample_control_track(&self, index: usize, time: NormalizedFloat, playback_type: PlaybackType,) -> Option<f32>
+    fn sample_control_track(&self, index: usize, cursor: &ClipCursor, time: NormalizedFloat, playback_type: PlaybackType,) -> Option<f32>
     {
         if let Option::Some(control_channel) = self.control_tracks.get(index)
         {
-            return Option::Some( control_channel.sample(self.start_frame, self.end_frame, time, playback_type) );
+            return Option::Some( control_channel.sample(self.start_frame, self.end_frame, time, playback_type, Some(cursor)) );
         }
         return Option::None;
     }
@@ -388,24 +694,20 @@ impl AnimationClip
 
 pub struct SingleClipMixer
 {
-    clip: Rc<AnimationClip>
+    clip: Rc<AnimationClip>,
+    cursor: ClipCursor,
 }
 
 impl SingleClipMixer
 {
-    fn mix_to_pose(&self, time : NormalizedFloat, playback_type: PlaybackType, mask: &Option<ArmatureMask>, clip_weight: NormalizedFloat, destination: &mut ArmaturePose, )
-    {
-        destination.mix_clip(self.clip.as_ref(), time, playback_type, mask, clip_weight);
-    }
-
-    fn add_to_pose(&self, time : NormalizedFloat, playback_type: PlaybackType, mask: &Option<ArmatureMask>, clip_weight: NormalizedFloat, destination: &mut ArmaturePose, )
+    fn for_each_clip(&self, time : NormalizedFloat, mut f: impl FnMut(&AnimationClip, &ClipCursor, NormalizedFloat, f32))
     {
-        destination.add_clip(self.clip.as_ref(), time, playback_type, mask, clip_weight);
+        f(self.clip.as_ref(), &self.cursor, time, 1.0);
     }
 
     pub fn new(clip: Rc<AnimationClip>) -> Self
     {
-       return SingleClipMixer{clip: clip};
+       return SingleClipMixer{clip: clip, cursor: ClipCursor::new()};
     }
 }
 
@@ -413,6 +715,7 @@ pub enum MixerBuilder
 {
     Simple(SimpleMixerBuilder),
     LinearBlend(BlendMixerBuilder),
+    BlendSpace(BlendSpaceMixerBuilder),
 }
 
 impl MixerBuilder
@@ -423,6 +726,7 @@ impl MixerBuilder
         {
             MixerBuilder::Simple(simple) => &simple.id,
             MixerBuilder::LinearBlend(linear) => &linear.id,
+            MixerBuilder::BlendSpace(blend_space) => &blend_space.id,
         };
     }
 
@@ -432,6 +736,7 @@ impl MixerBuilder
         {
             MixerBuilder::Simple(simple) => simple.build(),
             MixerBuilder::LinearBlend(builder) => builder.build(),
+            MixerBuilder::BlendSpace(builder) => builder.build(),
         }
     }
 }
@@ -442,14 +747,15 @@ pub struct SimpleMixerBuilder
     playback_rate: f32,
     playback_type: PlaybackType,
     clip: Rc<AnimationClip>,
-    events: HashMap<String, NormalizedFloat>
+    events: HashMap<String, NormalizedFloat>,
+    root_motion: bool,
 }
 
 impl SimpleMixerBuilder
 {
     pub fn new(id: &str, clip_name: Rc<AnimationClip>) -> Self
     {
-        return SimpleMixerBuilder { id: id.to_string(), playback_rate: 1.0, playback_type: PlaybackType::Sequential, clip: clip_name, events: HashMap::new() };
+        return SimpleMixerBuilder { id: id.to_string(), playback_rate: 1.0, playback_type: PlaybackType::Sequential, clip: clip_name, events: HashMap::new(), root_motion: false };
     }
 
     pub fn playback_rate(mut self, speed: f32) -> Self
@@ -464,6 +770,13 @@ impl SimpleMixerBuilder
         return self;
     }
 
+    //takes the root bone's horizontal movement and yaw out of the pose and reports them through AnimationState::root_motion instead
+    pub fn root_motion(mut self) -> Self
+    {
+        self.root_motion = true;
+        return self;
+    }
+
     pub fn add_event( mut self, name: &str, time: NormalizedFloat, ) -> Self
     {
         self.events.insert(name.to_string(), time);
@@ -478,7 +791,8 @@ impl SimpleMixerBuilder
             playback_rate: self.playback_rate, 
             playback_type: self.playback_type, 
             mixer_variant: ClipMixerVariant::Single(SingleClipMixer::new(self.clip.clone())),
-            events: self.events.iter().map(|item| (item.0.clone(), MixerEvent{trigger_time: *item.1, callbacks: vec![]})).collect(),
+            events: MixerEvent::from_builder(&self.events),
+            root_motion: self.root_motion,
         }
     }
 }
@@ -488,13 +802,14 @@ pub struct LinearBlendPoint
 {
     clip: Rc<AnimationClip>,
     pos: NormalizedFloat,
+    cursor: ClipCursor,
 }
 
 impl LinearBlendPoint
 {
     pub fn new(clip : Rc<AnimationClip>, pos : NormalizedFloat) -> Self
     {
-        return LinearBlendPoint { clip: clip, pos: pos };
+        return LinearBlendPoint { clip: clip, pos: pos, cursor: ClipCursor::new() };
     }
 }
 
@@ -502,13 +817,15 @@ pub struct LinearBlendMixer
 {
     points : Vec<LinearBlendPoint>,
     blend : NormalizedFloat,
+    //plays the end point at the start point's sync marker phase rather than the same normalized time
+    sync : bool,
 }
 
 impl LinearBlendMixer
 {
     pub fn new(clips: Vec<LinearBlendPoint>) -> Self
     {
-        return LinearBlendMixer { points: clips, blend: NormalizedFloat::zero() };
+        return LinearBlendMixer { points: clips, blend: NormalizedFloat::zero(), sync: false };
     }
 }
 
@@ -538,22 +855,14 @@ impl LinearBlendMixer
         };
     }
 
-    fn mix_to_pose(&self, time : NormalizedFloat, playback_type: PlaybackType, mask: &Option<ArmatureMask>, clip_weight: NormalizedFloat, destination: &mut ArmaturePose, )
-    {
-        let start_point = self.get_start_point();
-        let end_point = self.get_end_point();
-        let blend_weight = self.blend.get_val() - start_point.pos.get_val() / (end_point.pos.get_val() - start_point.pos.get_val());
-        destination.mix_clip(start_point.clip.as_ref(), time, playback_type, mask, NormalizedFloat::clamped(clip_weight.get_val() * ( 1.0 - blend_weight ) ) );
-        destination.mix_clip(end_point.clip.as_ref(), time, playback_type, mask, NormalizedFloat::clamped(clip_weight.get_val() * blend_weight ) );
-    }
-
-    fn add_to_pose(&self, time : NormalizedFloat, playback_type: PlaybackType, mask: &Option<ArmatureMask>, clip_weight: NormalizedFloat, destination: &mut ArmaturePose, )
+    fn for_each_clip(&self, time : NormalizedFloat, mut f: impl FnMut(&AnimationClip, &ClipCursor, NormalizedFloat, f32))
     {
         let start_point = self.get_start_point();
         let end_point = self.get_end_point();
-        let blend_weight = self.blend.get_val() - start_point.pos.get_val() / (end_point.pos.get_val() - start_point.pos.get_val());
-        destination.add_clip(start_point.clip.as_ref(), time, playback_type, mask, NormalizedFloat::clamped(clip_weight.get_val() * ( 1.0 - blend_weight ) ) );
-        destination.add_clip(end_point.clip.as_ref(), time, playback_type, mask, NormalizedFloat::clamped(clip_weight.get_val() * blend_weight ) );
+        let blend_weight = NormalizedFloat::clamped(self.blend.get_val() - start_point.pos.get_val() / (end_point.pos.get_val() - start_point.pos.get_val())).get_val();
+        let end_time = if self.sync { end_point.clip.match_phase(start_point.clip.as_ref(), time).unwrap_or(time) } else { time };
+        f(start_point.clip.as_ref(), &start_point.cursor, time, 1.0 - blend_weight);
+        f(end_point.clip.as_ref(), &end_point.cursor, end_time, blend_weight);
     }
 
     fn duration_seconds(&self) -> f32
@@ -579,7 +888,9 @@ pub struct BlendMixerBuilder
     playback_type: PlaybackType,
     points: Vec<LinearBlendPoint>,
     init_blend: NormalizedFloat,
+    sync: bool,
     events: HashMap<String, NormalizedFloat>,
+    root_motion: bool,
 }
 
 impl BlendMixerBuilder
@@ -591,9 +902,11 @@ impl BlendMixerBuilder
             id: id.to_string(),
             playback_rate: 1.0, 
             playback_type: PlaybackType::Sequential, 
-            points: vec![LinearBlendPoint{clip: init_clip, pos: init_blend}], 
+            points: vec![LinearBlendPoint::new(init_clip, init_blend)], 
             init_blend: NormalizedFloat::zero(),
+            sync: false,
             events: HashMap::new(),
+            root_motion: false,
         };
     }
 
@@ -609,16 +922,16 @@ impl BlendMixerBuilder
         {
             if blend_point.get_val() > self.points[i].pos.get_val()
             {
-                self.points.insert(i, LinearBlendPoint { clip: clip, pos: blend_point });
+                self.points.insert(i, LinearBlendPoint::new(clip, blend_point));
                 return self;
             }
             else if blend_point.get_val() == self.points[i].pos.get_val()
             {
-                self.points[i] = LinearBlendPoint { clip: clip, pos: blend_point };
+                self.points[i] = LinearBlendPoint::new(clip, blend_point);
                 return self;
             }
         }
-        self.points.push(LinearBlendPoint { clip: clip, pos: blend_point });
+        self.points.push(LinearBlendPoint::new(clip, blend_point));
         return self;
     }
 
@@ -628,12 +941,26 @@ impl BlendMixerBuilder
         return self;
     }
 
+    //matches the points' sync markers instead of playing them all at the same normalized time
+    pub fn sync_phase(mut self) -> Self
+    {
+        self.sync = true;
+        return self;
+    }
+
     pub fn playback_type(mut self, playback_type: PlaybackType) -> Self
     {
         self.playback_type = playback_type;
         return self;
     }
 
+    //takes the root bone's horizontal movement and yaw out of the pose and reports them through AnimationState::root_motion instead
+    pub fn root_motion(mut self) -> Self
+    {
+        self.root_motion = true;
+        return self;
+    }
+
     pub fn add_event( mut self, name: &str, time: NormalizedFloat, ) -> Self
     {
         self.events.insert(name.to_string(), time);
@@ -653,159 +980,1581 @@ impl BlendMixerBuilder
                 {
                     points: self.points.clone(),
                     blend: self.init_blend,
+                    sync: self.sync,
                 }
             ),
-            events: self.events.iter().map(|item| (item.0.clone(), MixerEvent{trigger_time: *item.1, callbacks: vec![]})).collect(),
+            events: MixerEvent::from_builder(&self.events),
+            root_motion: self.root_motion,
         }
     }
 }
 
-pub struct ClipChannel<T : Interpolate>
+#[derive(Clone)]
+pub struct BlendSpacePoint
 {
-    data : Vec<AnimationKey<T>>,
+    clip: Rc<AnimationClip>,
+    pos: Vec2,
+    cursor: ClipCursor,
 }
 
-#[derive(Clone, Copy)]
-pub struct AnimationKey<T : Interpolate>
+impl BlendSpacePoint
 {
-    pub data : T,
-    pub frame : u16,
+    pub fn new(clip : Rc<AnimationClip>, pos : Vec2) -> Self
+    {
+        return BlendSpacePoint { clip: clip, pos: pos, cursor: ClipCursor::new() };
+    }
 }
 
-pub trait Interpolate : Copy
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendSpaceWeighting
 {
-    fn interpolate(&self, rhs: &Self, t: f32) -> Self;
+    //blends the three clips around the position. outside the clips it uses the nearest point on their outline
+    Barycentric,
+    //every clip gets weight by how far the position is toward it from each other clip, measured straight across the plane.
+    //weights change smoothly everywhere rather than jumping at triangle edges, but clips laid out in a circle still blend by
+    //distance, so a position between two directions takes some of the clips either side of them as well
+    GradientBand,
 }
 
-impl Interpolate for Quat
+//blends clips laid out on a plane, like strafe direction by speed. every clip plays at the same normalized time so their cycles stay in step,
+//and the mixer's duration is the weighted duration of its clips
+pub struct BlendSpaceMixer
 {
-    fn interpolate(&self, rhs: &Self, t: f32) -> Self 
-    {
-        return self.slerp(rhs.clone(), t);
-    }
+    points: Vec<BlendSpacePoint>,
+    triangles: Vec<[usize; 3]>,
+    weighting: BlendSpaceWeighting,
+    blend: Vec2,
+    //one per point, worked out whenever blend changes
+    weights: Vec<f32>,
+    //plays every clip at the heaviest clip's sync marker phase rather than the same normalized time
+    sync: bool,
 }
 
-/*impl Interpolate for Vec3
+impl BlendSpaceMixer
 {
-    fn interpolate(&self, rhs: &Self, t: f32) -> Self 
+    pub fn new(points: Vec<BlendSpacePoint>, weighting: BlendSpaceWeighting) -> Self
     {
-        return self.lerp(rhs.clone(), t);
+        let mut mixer = BlendSpaceMixer 
+        { 
+            triangles: triangulate(&points.iter().map(|point| point.pos).collect::<Vec<_>>()), 
+            weights: vec![0.0; points.len()], 
+            points: points, 
+            weighting: weighting, 
+            blend: Vec2::ZERO,
+            sync: false,
+        };
+        mixer.set_blend(Vec2::ZERO);
+        return mixer;
     }
-}*/
 
-impl Interpolate for f32
-{
-    fn interpolate(&self, rhs: &Self, t: f32) -> Self 
+    pub fn blend(&self) -> Vec2
     {
-        return self + (rhs - self) * t;
+        return self.blend;
     }
-}
 
-impl<T:Interpolate> ClipChannel<T>
-{
-    pub fn new(data : Vec<AnimationKey<T>>) -> ClipChannel<T>
+    pub fn set_blend(&mut self, blend: Vec2)
     {
-        if data.is_empty()
+        self.blend = blend;
+        self.weights.fill(0.0);
+        //with fewer than three clips, or all of them in a line, there's nothing to triangulate
+        if self.weighting == BlendSpaceWeighting::GradientBand || self.triangles.is_empty()
         {
-            panic!("Attempted to create a clip channel with no data. If this channel is not supposed to have keyframe data, then it should instead be excluded from whatever container you're trying to put it in.");
+            self.gradient_band_weights();
+        }
+        else
+        {
+            self.barycentric_weights();
         }
-        return ClipChannel { data: data };
     }
 
-    fn sample(&self, min_frame : u16, max_frame: u16, t: NormalizedFloat, extrapolation_mode : PlaybackType) -> T
+    pub fn weights(&self) -> &[f32]
     {
-        let f_range = (max_frame - min_frame) as f32;
-        let sample_frame = min_frame as f32 + t.get_val() * f_range;
-        let start_key = match self.data.iter().rev().find
+        return &self.weights;
+    }
+
+    pub fn set_sync(&mut self, sync: bool)
+    {
+        self.sync = sync;
+    }
+
+    fn leader(&self) -> &BlendSpacePoint
+    {
+        let heaviest = (0..self.points.len()).max_by(|a, b| self.weights[*a].total_cmp(&self.weights[*b])).unwrap_or(0);
+        return &self.points[heaviest];
+    }
+
+    fn barycentric_weights(&mut self)
+    {
+        let positions = |tri: &[usize; 3]| [self.points[tri[0]].pos, self.points[tri[1]].pos, self.points[tri[2]].pos];
+        let containing = self.triangles.iter().find
         (
-            |key| (key.frame as f32) <= sample_frame 
-        )
+            |tri| barycentric(positions(tri), self.blend).iter().all(|w| *w >= -1e-5)
+        );
+        let (tri, target) = match containing
         {
-            Some(key) => key,
-            None => match extrapolation_mode
+            Some(tri) => (tri, self.blend),
+            None => 
             {
-                PlaybackType::Sequential => self.data.first().unwrap(),
-                PlaybackType::Looping => self.data.last().unwrap(),
+                //the nearest point on any triangle's edges is on the outline of the whole space
+                let mut nearest = (&self.triangles[0], self.points[self.triangles[0][0]].pos);
+                let mut nearest_dist = f32::MAX;
+                for tri in &self.triangles
+                {
+                    let corners = positions(tri);
+                    for i in 0..3
+                    {
+                        let (a, b) = (corners[i], corners[(i + 1) % 3]);
+                        let along = (self.blend - a).dot(b - a) / (b - a).length_squared().max(f32::EPSILON);
+                        let candidate = a + (b - a) * along.clamp(0.0, 1.0);
+                        let dist = candidate.distance_squared(self.blend);
+                        if dist < nearest_dist
+                        {
+                            nearest_dist = dist;
+                            nearest = (tri, candidate);
+                        }
+                    }
+                }
+                nearest
             },
         };
-        let f_start = start_key.frame as f32;
-        let end_key = match self.data.iter().find
-        (
-            |key| (key.frame as f32) >= sample_frame
-        )
+        let tri_weights = barycentric(positions(tri), target).map(|w| w.max(0.0));
+        let total = tri_weights.iter().sum::<f32>().max(f32::EPSILON);
+        for i in 0..3
         {
-            Some(key) => key,
-            None => match extrapolation_mode
+            self.weights[tri[i]] += tri_weights[i] / total;
+        }
+    }
+
+    fn gradient_band_weights(&mut self)
+    {
+        for i in 0..self.points.len()
+        {
+            let from = self.points[i].pos;
+            let mut weight: f32 = 1.0;
+            for j in 0..self.points.len()
             {
-                PlaybackType::Sequential => self.data.last().unwrap(),
-                PlaybackType::Looping => self.data.first().unwrap(),
-            },
-        };
-        let f_end = end_key.frame as f32;
-        if end_key.frame < start_key.frame
+                let edge = self.points[j].pos - from;
+                if i != j && edge.length_squared() > 0.0
+                {
+                    weight = weight.min(1.0 - (self.blend - from).dot(edge) / edge.length_squared());
+                }
+            }
+            self.weights[i] = weight.max(0.0);
+        }
+        let total = self.weights.iter().sum::<f32>();
+        if total > 0.0
         {
-            let loop_sample = if sample_frame < f_start { sample_frame + f_range } else { sample_frame };
-            let phantom_end = f_end + f_range;
-            return start_key.data.interpolate(&end_key.data, (loop_sample - f_start)/(phantom_end - f_start));
+            self.weights.iter_mut().for_each(|w| *w /= total);
         }
-        else if start_key.frame == end_key.frame
+        else if let Some(nearest) = (0..self.points.len()).min_by(|a, b| self.points[*a].pos.distance_squared(self.blend).total_cmp(&self.points[*b].pos.distance_squared(self.blend)))
         {
-            return start_key.data;
+            self.weights[nearest] = 1.0;
         }
-        return start_key.data.interpolate(&end_key.data, (sample_frame - f_start)/(f_end - f_start) );
-
     }
-}
 
-#[derive(Clone, Copy)]
-pub enum PlaybackType
+    fn for_each_clip(&self, time : NormalizedFloat, mut f: impl FnMut(&AnimationClip, &ClipCursor, NormalizedFloat, f32))
+    {
+        let leader = self.leader().clip.as_ref();
+        for (point, weight) in self.points.iter().zip(&self.weights)
+        {
+            if *weight > 0.0
+            {
+                let point_time = if self.sync { point.clip.match_phase(leader, time).unwrap_or(time) } else { time };
+                f(point.clip.as_ref(), &point.cursor, point_time, *weight);
+            }
+        }
+    }
+
+    fn duration_seconds(&self) -> f32
+    {
+        return self.points.iter().zip(&self.weights).map(|(point, weight)| point.clip.duration_seconds() * weight).sum();
+    }
+
+    fn duration_frames(&self) -> f32
+    {
+        return self.points.iter().zip(&self.weights).map(|(point, weight)| point.clip.duration_frames() as f32 * weight).sum();
+    }
+}
+
+//weights of a, b and c for p. they go negative outside the triangle
+fn barycentric(corners: [Vec2; 3], p: Vec2) -> [f32; 3]
+{
+    let (v0, v1, v2) = (corners[1] - corners[0], corners[2] - corners[0], p - corners[0]);
+    let denom = v0.perp_dot(v1);
+    if denom.abs() <= f32::EPSILON
+    {
+        return [1.0, 0.0, 0.0];
+    }
+    let v = v2.perp_dot(v1) / denom;
+    let w = v0.perp_dot(v2) / denom;
+    return [1.0 - v - w, v, w];
+}
+
+//delaunay triangulation by bowyer-watson, so blend spaces don't get long thin triangles that blend clips far from the position
+fn triangulate(points: &[Vec2]) -> Vec<[usize; 3]>
+{
+    if points.len() < 3
+    {
+        return vec![];
+    }
+    let min = points.iter().fold(Vec2::splat(f32::MAX), |min, p| min.min(*p));
+    let max = points.iter().fold(Vec2::splat(f32::MIN), |max, p| max.max(*p));
+    let center = (min + max) * 0.5;
+    let size = (max - min).max_element().max(1.0) * 20.0;
+    //a triangle around everything to start from, removed at the end
+    let n = points.len();
+    let mut verts = points.to_vec();
+    verts.extend([center + Vec2::new(-size, -size), center + Vec2::new(size, -size), center + Vec2::new(0.0, size)]);
+    let mut triangles = vec![[n, n + 1, n + 2]];
+    for i in 0..n
+    {
+        let (bad, good): (Vec<[usize; 3]>, Vec<[usize; 3]>) = triangles.into_iter().partition
+        (
+            |tri| in_circumcircle([verts[tri[0]], verts[tri[1]], verts[tri[2]]], verts[i])
+        );
+        triangles = good;
+        //the outline of the hole the bad triangles leave gets fanned to the new point
+        for tri in &bad
+        {
+            for (a, b) in [(tri[0], tri[1]), (tri[1], tri[2]), (tri[2], tri[0])]
+            {
+                let shared = bad.iter().filter(|other| other.contains(&a) && other.contains(&b)).count() > 1;
+                if !shared
+                {
+                    triangles.push([a, b, i]);
+                }
+            }
+        }
+    }
+    triangles.retain
+    (
+        |tri| tri.iter().all(|v| *v < n) && (verts[tri[1]] - verts[tri[0]]).perp_dot(verts[tri[2]] - verts[tri[0]]).abs() > f32::EPSILON
+    );
+    return triangles;
+}
+
+fn in_circumcircle(corners: [Vec2; 3], p: Vec2) -> bool
+{
+    let [a, b, c] = corners.map(|corner| corner - p);
+    let det = a.length_squared() * b.perp_dot(c) - b.length_squared() * a.perp_dot(c) + c.length_squared() * a.perp_dot(b);
+    //the determinant's sign flips with the triangle's winding
+    let winding = (corners[1] - corners[0]).perp_dot(corners[2] - corners[0]);
+    return det * winding.signum() > 0.0 && winding != 0.0;
+}
+
+pub struct BlendSpaceMixerBuilder
+{
+    id: String,
+    playback_rate: f32,
+    playback_type: PlaybackType,
+    points: Vec<BlendSpacePoint>,
+    weighting: BlendSpaceWeighting,
+    init_blend: Vec2,
+    sync: bool,
+    events: HashMap<String, NormalizedFloat>,
+    root_motion: bool,
+}
+
+impl BlendSpaceMixerBuilder
+{
+    pub fn create(id: &str, init_clip: Rc<AnimationClip>, pos: Vec2) -> Self
+    {
+        return BlendSpaceMixerBuilder 
+        {
+            id: id.to_string(),
+            playback_rate: 1.0, 
+            playback_type: PlaybackType::Sequential, 
+            points: vec![BlendSpacePoint::new(init_clip, pos)], 
+            weighting: BlendSpaceWeighting::Barycentric,
+            init_blend: pos,
+            sync: false,
+            events: HashMap::new(),
+            root_motion: false,
+        };
+    }
+
+    pub fn playback_rate(mut self, speed: f32) -> Self
+    {
+        self.playback_rate = speed;
+        return self;
+    }
+
+    //a clip at a position that's already taken replaces the one there
+    pub fn add_point(mut self, clip: Rc<AnimationClip>, pos: Vec2) -> Self
+    {
+        match self.points.iter_mut().find(|point| point.pos == pos)
+        {
+            Some(point) => point.clip = clip,
+            None => self.points.push(BlendSpacePoint::new(clip, pos)),
+        }
+        return self;
+    }
+
+    pub fn weighting(mut self, weighting: BlendSpaceWeighting) -> Self
+    {
+        self.weighting = weighting;
+        return self;
+    }
+
+    pub fn blend(mut self, blend: Vec2) -> Self
+    {
+        self.init_blend = blend;
+        return self;
+    }
+
+    //matches the clips' sync markers instead of playing them all at the same normalized time
+    pub fn sync_phase(mut self) -> Self
+    {
+        self.sync = true;
+        return self;
+    }
+
+    pub fn playback_type(mut self, playback_type: PlaybackType) -> Self
+    {
+        self.playback_type = playback_type;
+        return self;
+    }
+
+    //takes the root bone's horizontal movement and yaw out of the pose and reports them through AnimationState::root_motion instead
+    pub fn root_motion(mut self) -> Self
+    {
+        self.root_motion = true;
+        return self;
+    }
+
+    pub fn add_event( mut self, name: &str, time: NormalizedFloat, ) -> Self
+    {
+        self.events.insert(name.to_string(), time);
+        return self;
+    }
+
+    pub fn build(&self) -> ClipMixer
+    {
+        let mut blend_space = BlendSpaceMixer::new(self.points.clone(), self.weighting);
+        blend_space.set_blend(self.init_blend);
+        blend_space.set_sync(self.sync);
+        return ClipMixer
+        {
+            id: self.id.clone(),
+            playback_rate: self.playback_rate, 
+            playback_type: self.playback_type, 
+            mixer_variant: ClipMixerVariant::BlendSpace(blend_space),
+            events: MixerEvent::from_builder(&self.events),
+            root_motion: self.root_motion,
+        }
+    }
+}
+
+//frames and values are kept apart so looking a frame up only walks the frames
+pub struct ClipChannel<T : Interpolate>
+{
+    frames : Vec<u16>,
+    values : Vec<T>,
+    //one per key, for the curve from that key to the next
+    interpolation : Vec<KeyInterpolation>,
+    //where this channel keeps its key in a cursor, handed out by the clip
+    slot : usize,
+}
+
+//remembers the key each channel of a clip was last sampled at, so the next sample can start looking there.
+//playback mostly moves a key or less per frame, so that's usually the answer. give each playhead its own,
+//since two playheads at different times sharing one would keep sending each other back to a full search
+#[derive(Clone, Default)]
+pub struct ClipCursor
+{
+    keys : RefCell<Vec<u32>>,
+}
+
+impl ClipCursor
+{
+    pub fn new() -> Self
+    {
+        return ClipCursor { keys: RefCell::new(vec![]) };
+    }
+
+    fn key(&self, slot: usize) -> Option<usize>
+    {
+        return self.keys.borrow().get(slot).map(|key| *key as usize);
+    }
+
+    fn set_key(&self, slot: usize, key: usize)
+    {
+        let mut keys = self.keys.borrow_mut();
+        if keys.len() <= slot
+        {
+            keys.resize(slot + 1, 0);
+        }
+        keys[slot] = key as u32;
+    }
+}
+
+//how a channel gets from one key to the next. the key at the start of a segment picks its shape
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyInterpolation
+{
+    //holds the key's value until the next key
+    Constant,
+    Linear,
+    //handles are offsets from the key in frames and value, like the dcc's. the segment uses this key's out handle and the next key's in handle,
+    //or a third of the way back along the segment if the next key isn't a bezier. handles past the other end of the segment are pulled in to it
+    Bezier{ in_handle: Vec2, out_handle: Vec2 },
+    //slopes are in value per frame. the segment uses this key's out slope and the next key's in slope, or the segment's own slope if the next key isn't hermite
+    Hermite{ in_slope: f32, out_slope: f32 },
+    //a hermite curve with each key's slope taken from the keys either side
+    CatmullRom,
+}
+
+#[derive(Clone, Copy)]
+pub struct AnimationKey<T : Interpolate>
+{
+    pub data : T,
+    pub frame : u16,
+}
+
+pub trait Interpolate : Copy
+{
+    fn interpolate(&self, rhs: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for Quat
+{
+    fn interpolate(&self, rhs: &Self, t: f32) -> Self 
+    {
+        return self.slerp(rhs.clone(), t);
+    }
+}
+
+/*impl Interpolate for Vec3
+{
+    fn interpolate(&self, rhs: &Self, t: f32) -> Self 
+    {
+        return self.lerp(rhs.clone(), t);
+    }
+}*/
+
+impl Interpolate for f32
+{
+    fn interpolate(&self, rhs: &Self, t: f32) -> Self 
+    {
+        return self + (rhs - self) * t;
+    }
+}
+
+impl<T:Interpolate> ClipChannel<T>
+{
+    pub fn new(data : Vec<AnimationKey<T>>) -> ClipChannel<T>
+    {
+        if data.is_empty()
+        {
+            panic!("Attempted to create a clip channel with no data. If this channel is not supposed to have keyframe data, then it should instead be excluded from whatever container you're trying to put it in.");
+        }
+        return ClipChannel 
+        { 
+            frames: data.iter().map(|key| key.frame).collect(), 
+            values: data.iter().map(|key| key.data).collect(), 
+            interpolation: vec![KeyInterpolation::Linear; data.len()], 
+            slot: 0,
+        };
+    }
+
+    pub fn frames(&self) -> &[u16]
+    {
+        return &self.frames;
+    }
+
+    pub fn values(&self) -> &[T]
+    {
+        return &self.values;
+    }
+
+    pub fn interpolation(&self) -> &[KeyInterpolation]
+    {
+        return &self.interpolation;
+    }
+
+    //the last key at or before frame. tries the cursor's key and the one after it before searching
+    fn find_key(&self, frame: f32, cursor: Option<&ClipCursor>) -> Option<usize>
+    {
+        let at_or_before = |i: usize| (self.frames[i] as f32) <= frame && !self.frames.get(i + 1).is_some_and(|next| (*next as f32) <= frame);
+        if let Some((cursor, hint)) = cursor.and_then(|cursor| cursor.key(self.slot).map(|hint| (cursor, hint)))
+        {
+            if hint < self.frames.len() && at_or_before(hint)
+            {
+                return Some(hint);
+            }
+            if hint + 1 < self.frames.len() && at_or_before(hint + 1)
+            {
+                cursor.set_key(self.slot, hint + 1);
+                return Some(hint + 1);
+            }
+        }
+        let found = self.frames.partition_point(|key_frame| (*key_frame as f32) <= frame).checked_sub(1);
+        if let (Some(cursor), Some(i)) = (cursor, found)
+        {
+            cursor.set_key(self.slot, i);
+        }
+        return found;
+    }
+
+    //one mode per key, in key order. keys left without one stay linear
+    pub fn with_interpolation(mut self, interpolation: Vec<KeyInterpolation>) -> Self
+    {
+        for (mode, new_mode) in self.interpolation.iter_mut().zip(interpolation)
+        {
+            *mode = new_mode;
+        }
+        return self;
+    }
+
+    //the keys either side of frame and their frames. the end key's frame and the returned frame are shifted on by f_range
+    //when the segment wraps round a loop. both keys are the same when the frame sits on a key or past a sequential channel's ends
+    fn bracket(&self, frame: f32, f_range: f32, extrapolation_mode: PlaybackType, cursor: Option<&ClipCursor>) -> (usize, usize, f32, f32, f32)
+    {
+        let last = self.frames.len() - 1;
+        let found = self.find_key(frame, cursor);
+        let start = match found
+        {
+            Some(i) => i,
+            None => match extrapolation_mode
+            {
+                PlaybackType::Sequential => 0,
+                PlaybackType::Looping => last,
+            },
+        };
+        //the first key at or after the frame
+        let end = match found
+        {
+            Some(i) if self.frames[i] as f32 == frame => i,
+            Some(i) if i < last => i + 1,
+            Some(_) => match extrapolation_mode
+            {
+                PlaybackType::Sequential => last,
+                PlaybackType::Looping => 0,
+            },
+            None => 0,
+        };
+        let f_start = self.frames[start] as f32;
+        let f_end = self.frames[end] as f32;
+        if self.frames[end] < self.frames[start]
+        {
+            let loop_frame = if frame < f_start { frame + f_range } else { frame };
+            return (start, end, f_start, f_end + f_range, loop_frame);
+        }
+        return (start, end, f_start, f_end, frame);
+    }
+}
+
+impl ClipChannel<Quat>
+{
+    //builds one rotation channel out of per component curves. it keys every frame any component is keyed on, and
+    //every whole frame inside curved segments, so slerping between the baked keys stays close to the authored curves
+    pub fn from_components(components: [ClipChannel<f32>; 4], min_frame: u16, max_frame: u16) -> Self
+    {
+        let f_range = (max_frame - min_frame) as f32;
+        let mut frames = components.iter().flat_map(|component| component.frames.iter().copied()).collect::<Vec<_>>();
+        frames.sort();
+        frames.dedup();
+        let curved = |frame: u16| components.iter().any
+        (
+            |component| return !matches!(component.mode_at(frame as f32), KeyInterpolation::Constant | KeyInterpolation::Linear)
+        );
+        let mut baked = vec![];
+        for (i, frame) in frames.iter().enumerate()
+        {
+            baked.push(*frame);
+            if let Some(next) = frames.get(i + 1).filter(|_| curved(*frame))
+            {
+                baked.extend(frame + 1..*next);
+            }
+        }
+        let mut values: Vec<Quat> = vec![];
+        let mut interpolation = vec![];
+        for frame in &baked
+        {
+            let sample = |component: &ClipChannel<f32>| return component.sample_frame(*frame as f32, f_range, PlaybackType::Sequential, None);
+            let quat = Quat::from_xyzw(sample(&components[0]), sample(&components[1]), sample(&components[2]), sample(&components[3])).normalize();
+            //q and -q are the same rotation. keep neighbouring keys in the same hemisphere
+            let quat = match values.last()
+            {
+                Some(previous) if previous.dot(quat) < 0.0 => -quat,
+                _ => quat,
+            };
+            values.push(quat);
+            let constant = components.iter().all(|component| return component.mode_at(*frame as f32) == KeyInterpolation::Constant);
+            interpolation.push(if constant { KeyInterpolation::Constant } else { KeyInterpolation::Linear });
+        }
+        return ClipChannel { frames: baked, values: values, interpolation: interpolation, slot: 0 };
+    }
+
+    //slerps between keys, which always takes the shorter way round
+    pub fn sample(&self, min_frame : u16, max_frame: u16, t: NormalizedFloat, extrapolation_mode : PlaybackType, cursor: Option<&ClipCursor>) -> Quat
+    {
+        let f_range = (max_frame - min_frame) as f32;
+        let (start, end, f_start, f_end, frame) = self.bracket(min_frame as f32 + t.get_val() * f_range, f_range, extrapolation_mode, cursor);
+        if f_start == f_end || self.interpolation[start] == KeyInterpolation::Constant
+        {
+            return self.values[start];
+        }
+        return self.values[start].interpolate(&self.values[end], (frame - f_start) / (f_end - f_start));
+    }
+}
+
+impl ClipChannel<f32>
+{
+    //keys are expected in frame order, which is how they come out of the exporter
+    pub fn sample(&self, min_frame : u16, max_frame: u16, t: NormalizedFloat, extrapolation_mode : PlaybackType, cursor: Option<&ClipCursor>) -> f32
+    {
+        let f_range = (max_frame - min_frame) as f32;
+        return self.sample_frame(min_frame as f32 + t.get_val() * f_range, f_range, extrapolation_mode, cursor);
+    }
+
+    fn sample_frame(&self, frame: f32, f_range: f32, extrapolation_mode: PlaybackType, cursor: Option<&ClipCursor>) -> f32
+    {
+        let (start, end, f_start, f_end, frame) = self.bracket(frame, f_range, extrapolation_mode, cursor);
+        if f_start == f_end
+        {
+            return self.values[start];
+        }
+        return self.sample_segment(start, end, f_start, f_end, frame, f_range, extrapolation_mode);
+    }
+
+    //the mode of the segment frame falls in, linear before the first key
+    fn mode_at(&self, frame: f32) -> KeyInterpolation
+    {
+        return self.find_key(frame, None).map_or(KeyInterpolation::Linear, |i| self.interpolation[i]);
+    }
+
+    //frames are given separately since the end key's is shifted on by the range when the segment wraps round a loop
+    fn sample_segment(&self, start: usize, end: usize, f_start: f32, f_end: f32, frame: f32, f_range: f32, extrapolation_mode: PlaybackType) -> f32
+    {
+        let (v_start, v_end) = (self.values[start], self.values[end]);
+        let span = f_end - f_start;
+        let t = (frame - f_start) / span;
+        return match self.interpolation[start]
+        {
+            KeyInterpolation::Constant => v_start,
+            KeyInterpolation::Linear => v_start.interpolate(&v_end, t),
+            KeyInterpolation::Bezier { out_handle, .. } => 
+            {
+                let in_handle = match self.interpolation[end]
+                {
+                    KeyInterpolation::Bezier { in_handle, .. } => in_handle,
+                    _ => Vec2::new(-span, v_start - v_end) / 3.0,
+                };
+                let p1 = Vec2::new(f_start + out_handle.x.clamp(0.0, span), v_start + out_handle.y);
+                let p2 = Vec2::new(f_end + in_handle.x.clamp(-span, 0.0), v_end + in_handle.y);
+                let s = solve_bezier_x(f_start, p1.x, p2.x, f_end, frame);
+                cubic_bezier(v_start, p1.y, p2.y, v_end, s)
+            },
+            KeyInterpolation::Hermite { out_slope, .. } => 
+            {
+                let in_slope = match self.interpolation[end]
+                {
+                    KeyInterpolation::Hermite { in_slope, .. } => in_slope,
+                    _ => (v_end - v_start) / span,
+                };
+                hermite(v_start, out_slope * span, v_end, in_slope * span, t)
+            },
+            KeyInterpolation::CatmullRom => 
+            {
+                let looping = matches!(extrapolation_mode, PlaybackType::Looping);
+                hermite(v_start, self.catmull_rom_slope(start, f_range, looping) * span, v_end, self.catmull_rom_slope(end, f_range, looping) * span, t)
+            },
+        };
+    }
+
+    //the slope between the keys either side. loops look round the end for neighbours, skipping a key that sits on the same frame once wrapped,
+    //which is how a loop's first and last keys usually are. the ends of a sequential channel only have the one neighbour
+    fn catmull_rom_slope(&self, key: usize, f_range: f32, looping: bool) -> f32
+    {
+        let len = self.frames.len();
+        let frame = self.frames[key] as f32;
+        let neighbour = |step: isize| 
+        {
+            let mut i = key as isize;
+            let mut offset = 0.0;
+            for _ in 0..len
+            {
+                i += step;
+                if i < 0 || i >= len as isize
+                {
+                    if !looping
+                    {
+                        break;
+                    }
+                    offset += step as f32 * f_range;
+                    i = i.rem_euclid(len as isize);
+                }
+                let neighbour_frame = self.frames[i as usize] as f32 + offset;
+                if neighbour_frame != frame
+                {
+                    return (neighbour_frame, self.values[i as usize]);
+                }
+            }
+            return (frame, self.values[key]);
+        };
+        let ((f_prev, v_prev), (f_next, v_next)) = (neighbour(-1), neighbour(1));
+        return if f_next > f_prev { (v_next - v_prev) / (f_next - f_prev) } else { 0.0 };
+    }
+}
+
+fn cubic_bezier(p0: f32, p1: f32, p2: f32, p3: f32, s: f32) -> f32
+{
+    let r = 1.0 - s;
+    return r * r * r * p0 + 3.0 * r * r * s * p1 + 3.0 * r * s * s * p2 + s * s * s * p3;
+}
+
+//the curve parameter where the bezier's frame is x. the handles are kept inside the segment, so the frame only ever goes forward and bisection finds it
+fn solve_bezier_x(x0: f32, x1: f32, x2: f32, x3: f32, x: f32) -> f32
+{
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..24
+    {
+        let mid = (low + high) / 2.0;
+        if cubic_bezier(x0, x1, x2, x3, mid) < x
+        {
+            low = mid;
+        }
+        else 
+        {
+            high = mid;
+        }
+    }
+    return (low + high) / 2.0;
+}
+
+//tangents are scaled to the segment, so t runs from 0 to 1
+fn hermite(p0: f32, m0: f32, p1: f32, m1: f32, t: f32) -> f32
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    return (2.0 * t3 - 3.0 * t2 + 1.0) * p0 + (t3 - 2.0 * t2 + t) * m0 + (-2.0 * t3 + 3.0 * t2) * p1 + (t3 - t2) * m1;
+}
+
+#[derive(Clone, Copy)]
+pub enum PlaybackType
+{
+    Sequential,
+    Looping,
+}
+
+#[derive(Clone, Copy)]
+pub struct PoseTransform
+{
+    location: Vec3,
+    orientation: Quat,
+    scale: Vec3
+}
+
+impl PoseTransform
+{
+    fn empty() -> Self
+    {
+        return PoseTransform { location: Vec3::ZERO, orientation: Quat::IDENTITY, scale: Vec3::ONE };
+    }
+
+    fn to_matrix(&self) -> Mat4
+    {
+        return Mat4::from_scale_rotation_translation(self.scale, self.orientation, self.location);
+    }
+
+    pub fn loc_rot_scale(&self) -> (Vec3, Quat, Vec3)
+    {
+        return (self.location, self.orientation, self.scale);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum MixType
+{
+    Additive,
+    Override,
+}
+
+//how far the character moved during one update, in the space it was facing at the start of it. the model's up axis is y
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RootMotion
+{
+    pub translation: Vec3,
+    //radians about y
+    pub yaw: f32,
+}
+
+impl RootMotion
+{
+    //this motion followed by next
+    pub fn then(self, next: RootMotion) -> RootMotion
+    {
+        return RootMotion { translation: self.translation + Quat::from_rotation_y(self.yaw) * next.translation, yaw: self.yaw + next.yaw };
+    }
+
+    //for an instance transform, tf * to_matrix()
+    pub fn to_matrix(&self) -> Mat4
+    {
+        return Mat4::from_rotation_translation(Quat::from_rotation_y(self.yaw), self.translation);
+    }
+}
+
+//the armature's root bone and its rest rotation, for moving root motion between the bone's space and the model's
+#[derive(Clone, Copy)]
+struct RootMotionSpace
+{
+    bone: usize,
+    rest: Quat,
+}
+
+impl RootMotionSpace
+{
+    //the first bone without a parent
+    fn new(armature: &Armature) -> Option<Self>
+    {
+        let bone = armature.bones.iter().position(|bone| bone.parent.is_none())?;
+        let (_, rest, _) = armature.bones[bone].local_tf.to_scale_rotation_translation();
+        return Some(RootMotionSpace { bone: bone, rest: rest });
+    }
+
+    //yaw is the twist of the root's model space rotation about y
+    fn yaw(&self, rot: Quat) -> f32
+    {
+        let model_rot = self.rest * rot * self.rest.inverse();
+        return 2.0 * model_rot.y.atan2(model_rot.w);
+    }
+
+    fn extract(&self, (loc, rot): (Vec3, Quat)) -> (Vec3, f32)
+    {
+        let model_loc = self.rest * loc;
+        return (Vec3::new(model_loc.x, 0.0, model_loc.z), self.yaw(rot));
+    }
+
+    //what's left of the root's offset once extract's part is taken out
+    fn strip(&self, (loc, rot): (Vec3, Quat)) -> (Vec3, Quat)
+    {
+        let model_loc = self.rest * loc;
+        let model_rot = self.rest * rot * self.rest.inverse();
+        let swing = Quat::from_rotation_y(-self.yaw(rot)) * model_rot;
+        return (self.rest.inverse() * Vec3::new(0.0, model_loc.y, 0.0), self.rest.inverse() * swing * self.rest);
+    }
+}
+
+#[derive(Clone)]
+enum MaskEntry
+{
+    Subtree(String, NormalizedFloat),
+    Bone(String, NormalizedFloat),
+    Control(String, NormalizedFloat),
+    AllControls(NormalizedFloat),
+}
+
+//which bones and controls a layer reaches, and how strongly. described by name so it can be worked out again for a different armature.
+//entries apply in the order they were added and later ones win, so subtree("DEF-chest", 1).subtree("DEF-neck", 0.5) leaves the neck and head at half.
+//anything no entry covers is left out of the layer
+#[derive(Clone)]
+pub struct ArmatureMask
+{
+    entries : Vec<MaskEntry>,
+    bone_weights : Vec<NormalizedFloat>,
+    control_weights : Vec<NormalizedFloat>
+}
+
+impl ArmatureMask
+{
+    pub fn new() -> Self
+    {
+        return ArmatureMask { entries: vec![], bone_weights: vec![], control_weights: vec![] };
+    }
+
+    //the bone and everything under it
+    pub fn subtree(mut self, bone: &str, weight: NormalizedFloat) -> Self
+    {
+        self.entries.push(MaskEntry::Subtree(bone.to_string(), weight));
+        return self;
+    }
+
+    //just these bones, not their children
+    pub fn bones(mut self, bones: &[&str], weight: NormalizedFloat) -> Self
+    {
+        self.entries.extend(bones.iter().map(|bone| MaskEntry::Bone(bone.to_string(), weight)));
+        return self;
+    }
+
+    pub fn control(mut self, name: &str, weight: NormalizedFloat) -> Self
+    {
+        self.entries.push(MaskEntry::Control(name.to_string(), weight));
+        return self;
+    }
+
+    pub fn all_controls(mut self, weight: NormalizedFloat) -> Self
+    {
+        self.entries.push(MaskEntry::AllControls(weight));
+        return self;
+    }
+
+    //names the armature doesn't have are skipped
+    fn resolve(&mut self, armature: &Armature)
+    {
+        self.bone_weights = vec![NormalizedFloat::zero(); armature.num_bones()];
+        self.control_weights = vec![NormalizedFloat::zero(); armature.num_controls()];
+        for entry in &self.entries
+        {
+            match entry
+            {
+                MaskEntry::Subtree(bone, weight) => 
+                {
+                    for i in armature.bone_index(bone).map(|root| armature.subtree(root)).unwrap_or_default()
+                    {
+                        self.bone_weights[i] = *weight;
+                    }
+                },
+                MaskEntry::Bone(bone, weight) => 
+                {
+                    if let Some(i) = armature.bone_index(bone)
+                    {
+                        self.bone_weights[i] = *weight;
+                    }
+                },
+                MaskEntry::Control(name, weight) => 
+                {
+                    if let Some(i) = armature.control_index(name)
+                    {
+                        self.control_weights[i] = *weight;
+                    }
+                },
+                MaskEntry::AllControls(weight) => self.control_weights.fill(*weight),
+            }
+        }
+    }
+
+    fn bone_weight(&self, index: usize) -> f32
+    {
+        return self.bone_weights.get(index).map_or(0.0, |weight| weight.get_val());
+    }
+
+    fn control_weight(&self, index: usize) -> f32
+    {
+        return self.control_weights.get(index).map_or(0.0, |weight| weight.get_val());
+    }
+}
+
+//how a chain constraint gets solved. fabrik spreads the bend evenly down the chain, ccd curls the end first, which suits tails
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChainMethod
+{
+    Fabrik,
+    Ccd,
+}
+
+#[derive(Clone)]
+enum IkSolver
+{
+    TwoBone{ upper: String, lower: String, end: String },
+    Chain{ root: String, tip: String, method: ChainMethod },
+    //axis is the way the bone faces in the rest pose, in model space
+    Aim{ bone: String, axis: Vec3 },
+}
+
+//a correction run on the finished pose, after every layer has been blended. bones are named so it can be worked out again for a different armature.
+//targets and poles are in model space, so anything in world space needs the instance's inverse transform applied first
+#[derive(Clone)]
+pub struct IkConstraint
+{
+    name: String,
+    solver: IkSolver,
+    target: Vec3,
+    pole: Option<Vec3>,
+    weight: NormalizedFloat,
+    iterations: u32,
+    tolerance: f32,
+    limits: Vec<(String, f32)>,
+    //filled by resolve. empty when the armature is missing a bone, which leaves the constraint doing nothing
+    bones: Vec<usize>,
+    max_angles: Vec<f32>,
+    //the aim axis in the bone's own space
+    bone_axis: Vec3,
+}
+
+impl IkConstraint
+{
+    fn new(name: &str, solver: IkSolver) -> Self
+    {
+        return IkConstraint
+        {
+            name: name.to_string(),
+            solver: solver,
+            target: Vec3::ZERO,
+            pole: None,
+            weight: NormalizedFloat::one(),
+            iterations: 10,
+            tolerance: 0.001,
+            limits: vec![],
+            bones: vec![],
+            max_angles: vec![],
+            bone_axis: Vec3::Y,
+        };
+    }
+
+    //turns upper and lower so end's head lands on the target, solved exactly. each bone has to be the parent of the next,
+    //like thigh, calf and cannon for a leg. the bend goes towards the pole if there is one, otherwise it stays in the plane the limb is already bent in
+    pub fn two_bone(name: &str, upper: &str, lower: &str, end: &str) -> Self
+    {
+        return IkConstraint::new(name, IkSolver::TwoBone { upper: upper.to_string(), lower: lower.to_string(), end: end.to_string() });
+    }
+
+    //turns every bone from root down to tip's parent to bring tip's head to the target. root has to be an ancestor of tip
+    pub fn chain(name: &str, root: &str, tip: &str, method: ChainMethod) -> Self
+    {
+        return IkConstraint::new(name, IkSolver::Chain { root: root.to_string(), tip: tip.to_string(), method: method });
+    }
+
+    //turns the bone so the way it faces points at the target. axis is that facing in the rest pose, in model space, so -Z for a head looking down the model's forward
+    pub fn aim(name: &str, bone: &str, axis: Vec3) -> Self
+    {
+        return IkConstraint::new(name, IkSolver::Aim { bone: bone.to_string(), axis: axis.normalize_or_zero() });
+    }
+
+    pub fn target(mut self, target: Vec3) -> Self
+    {
+        self.target = target;
+        return self;
+    }
+
+    //only used by two bone constraints
+    pub fn pole(mut self, pole: Vec3) -> Self
+    {
+        self.pole = Some(pole);
+        return self;
+    }
+
+    //how far the pose is pulled towards the solve, per bone
+    pub fn weight(mut self, weight: NormalizedFloat) -> Self
+    {
+        self.weight = weight;
+        return self;
+    }
+
+    //only used by chain constraints, which stop early once the tip is within tolerance of the target
+    pub fn iterations(mut self, iterations: u32, tolerance: f32) -> Self
+    {
+        self.iterations = iterations;
+        self.tolerance = tolerance;
+        return self;
+    }
+
+    //the most the bone's joint can turn away from its rest orientation, in radians. bones without a limit can turn freely
+    pub fn limit(mut self, bone: &str, max_angle: f32) -> Self
+    {
+        self.limits.push((bone.to_string(), max_angle.max(0.0)));
+        return self;
+    }
+
+    pub fn name(&self) -> &str
+    {
+        return &self.name;
+    }
+
+    fn resolve(&mut self, armature: &Armature)
+    {
+        self.bones = match &self.solver
+        {
+            IkSolver::TwoBone { upper, lower, end } => 
+            {
+                match (armature.bone_index(upper), armature.bone_index(lower), armature.bone_index(end))
+                {
+                    (Some(u), Some(l), Some(e)) if armature.bones[l].parent == Some(u) && armature.bones[e].parent == Some(l) => vec![u, l, e],
+                    _ => vec![],
+                }
+            },
+            IkSolver::Chain { root, tip, .. } => 
+            {
+                let path = armature.bone_index(tip).map(|tip| armature.path_from_root(tip)).unwrap_or_default();
+                match armature.bone_index(root).and_then(|root| path.iter().position(|bone| *bone == root))
+                {
+                    Some(start) if start + 1 < path.len() => path[start..].to_vec(),
+                    _ => vec![],
+                }
+            },
+            IkSolver::Aim { bone, .. } => armature.bone_index(bone).into_iter().collect(),
+        };
+        //later limits on the same bone win
+        self.max_angles = self.bones.iter().map(|bone| 
+        {
+            return self.limits.iter().rev().find(|(name, _)| armature.bone_index(name) == Some(*bone)).map_or(PI, |(_, angle)| *angle);
+        }).collect();
+        if let (IkSolver::Aim { axis, .. }, Some(bone)) = (&self.solver, self.bones.first())
+        {
+            let rest = armature.path_from_root(*bone).iter().fold(Mat4::IDENTITY, |tf, i| tf * armature.bones[*i].local_tf);
+            self.bone_axis = rest.inverse().transform_vector3(*axis).normalize_or_zero();
+        }
+    }
+
+    //globals are the pose's model space bone transforms, kept up to date as bones are turned.
+    //before is scratch space for the chain's orientations ahead of solving, so partial weights can blend back to them
+    fn apply(&self, armature: &Armature, pose: &mut ArmaturePose, globals: &mut [Mat4], before: &mut Vec<Quat>)
+    {
+        if self.bones.is_empty() || self.weight.get_val() <= 0.0
+        {
+            return;
+        }
+        before.clear();
+        before.extend(self.bones.iter().map(|bone| pose.joints[*bone].orientation));
+        match &self.solver
+        {
+            IkSolver::TwoBone { .. } => self.solve_two_bone(armature, pose, globals),
+            IkSolver::Chain { method: ChainMethod::Fabrik, .. } => self.solve_fabrik(armature, pose, globals),
+            IkSolver::Chain { method: ChainMethod::Ccd, .. } => self.solve_ccd(armature, pose, globals),
+            IkSolver::Aim { .. } => 
+            {
+                let bone = self.bones[0];
+                let facing = globals[bone].transform_vector3(self.bone_axis);
+                pose.rotate_joint(armature, globals, bone, rotation_between(facing, self.target - bone_head(globals, bone)), self.max_angles[0]);
+            },
+        }
+        if self.weight.get_val() < 1.0
+        {
+            for (bone, from) in self.bones.iter().zip(before.iter())
+            {
+                pose.joints[*bone].orientation = from.slerp(pose.joints[*bone].orientation, self.weight.get_val());
+            }
+            pose.refresh_model_transforms(armature, self.bones[0], globals);
+        }
+    }
+
+    //law of cosines for the angles at the upper and lower joints, then the whole limb is swung onto the target and twisted towards the pole
+    fn solve_two_bone(&self, armature: &Armature, pose: &mut ArmaturePose, globals: &mut [Mat4])
+    {
+        const EPSILON: f32 = 0.0001;
+        let (upper, lower, end) = (self.bones[0], self.bones[1], self.bones[2]);
+        let (a, b, c) = (bone_head(globals, upper), bone_head(globals, lower), bone_head(globals, end));
+        let (ab, bc) = (a.distance(b), b.distance(c));
+        if ab < EPSILON || bc < EPSILON
+        {
+            return;
+        }
+        //out of reach targets get the limb pointed straight at them
+        let at = a.distance(self.target).max((ab - bc).abs() + EPSILON).min(ab + bc - EPSILON);
+        let upper_angle = (c - a).angle_between(b - a);
+        let lower_angle = (a - b).angle_between(c - b);
+        let wanted_upper = ((bc * bc - ab * ab - at * at) / (-2.0 * ab * at)).clamp(-1.0, 1.0).acos();
+        let wanted_lower = ((at * at - ab * ab - bc * bc) / (-2.0 * ab * bc)).clamp(-1.0, 1.0).acos();
+        let bend_axis = (c - a).cross(b - a).try_normalize()
+            .or_else(|| self.pole.and_then(|pole| (c - a).cross(pole - a).try_normalize()))
+            .unwrap_or_else(|| (c - a).any_orthonormal_vector());
+        //the lower joint goes first, while the upper bone is still where it was measured
+        pose.rotate_joint(armature, globals, lower, Quat::from_axis_angle(bend_axis, wanted_lower - lower_angle), self.max_angles[1]);
+        let swing = rotation_between(c - a, self.target - a);
+        pose.rotate_joint(armature, globals, upper, swing * Quat::from_axis_angle(bend_axis, wanted_upper - upper_angle), self.max_angles[0]);
+        if let Some(pole) = self.pole
+        {
+            if let Some(axis) = (bone_head(globals, end) - a).try_normalize()
+            {
+                let knee = (bone_head(globals, lower) - a).reject_from_normalized(axis);
+                let towards = (pole - a).reject_from_normalized(axis);
+                if knee.length_squared() > EPSILON && towards.length_squared() > EPSILON
+                {
+                    let twist = axis.dot(knee.cross(towards)).atan2(knee.dot(towards));
+                    pose.rotate_joint(armature, globals, upper, Quat::from_axis_angle(axis, twist), self.max_angles[0]);
+                }
+            }
+        }
+    }
+
+    fn solve_fabrik(&self, armature: &Armature, pose: &mut ArmaturePose, globals: &mut [Mat4])
+    {
+        let mut points = self.bones.iter().map(|bone| bone_head(globals, *bone)).collect::<Vec<Vec3>>();
+        let lengths = points.windows(2).map(|pair| pair[0].distance(pair[1])).collect::<Vec<f32>>();
+        let (base, last) = (points[0], points.len() - 1);
+        for _ in 0..self.iterations
+        {
+            if points[last].distance(self.target) <= self.tolerance
+            {
+                break;
+            }
+            points[last] = self.target;
+            for i in (0..last).rev()
+            {
+                points[i] = points[i + 1] + (points[i] - points[i + 1]).normalize_or_zero() * lengths[i];
+            }
+            points[0] = base;
+            for i in 0..last
+            {
+                points[i + 1] = points[i] + (points[i + 1] - points[i]).normalize_or_zero() * lengths[i];
+            }
+        }
+        //turn the bones onto the points from the top down, aiming from where each bone actually ended up in case a limit held the one above back
+        for i in 0..last
+        {
+            let head = bone_head(globals, self.bones[i]);
+            let rotation = rotation_between(bone_head(globals, self.bones[i + 1]) - head, points[i + 1] - head);
+            pose.rotate_joint(armature, globals, self.bones[i], rotation, self.max_angles[i]);
+        }
+    }
+
+    fn solve_ccd(&self, armature: &Armature, pose: &mut ArmaturePose, globals: &mut [Mat4])
+    {
+        let last = self.bones.len() - 1;
+        let tip = self.bones[last];
+        for _ in 0..self.iterations
+        {
+            if bone_head(globals, tip).distance(self.target) <= self.tolerance
+            {
+                break;
+            }
+            for i in (0..last).rev()
+            {
+                let head = bone_head(globals, self.bones[i]);
+                let rotation = rotation_between(bone_head(globals, tip) - head, self.target - head);
+                pose.rotate_joint(armature, globals, self.bones[i], rotation, self.max_angles[i]);
+            }
+        }
+    }
+}
+
+fn bone_head(globals: &[Mat4], bone: usize) -> Vec3
 {
-    Sequential,
-    Looping,
+    return globals[bone].w_axis.truncate();
+}
+
+//identity when either direction is too short to say which way it points
+fn rotation_between(from: Vec3, to: Vec3) -> Quat
+{
+    return match (from.try_normalize(), to.try_normalize())
+    {
+        (Some(from), Some(to)) => Quat::from_rotation_arc(from, to),
+        _ => Quat::IDENTITY,
+    };
 }
 
 #[derive(Clone, Copy)]
-pub struct PoseTransform
+struct SpringTail
 {
-    location: Vec3,
-    orientation: Quat,
-    scale: Vec3
+    position: Vec3,
+    previous: Vec3,
 }
 
-impl PoseTransform
+//secondary motion for dangling bones like hair, ears and tails. the tip of each bone is a verlet particle pulled back towards where the animation put it,
+//and the bone is turned to follow it. everything is worked out in model space, so it reacts to the pose moving but not to the instance being moved around
+#[derive(Clone)]
+pub struct SpringBone
 {
-    fn empty() -> Self
+    bone: String,
+    whole_subtree: bool,
+    stiffness: f32,
+    damping: f32,
+    gravity: Vec3,
+    max_angle: f32,
+    length: f32,
+    //filled by resolve, one of each per simulated bone, parents first
+    bones: Vec<usize>,
+    tail_offsets: Vec<Vec3>,
+    tails: Vec<Option<SpringTail>>,
+}
+
+impl SpringBone
+{
+    //just this bone
+    pub fn new(bone: &str) -> Self
     {
-        return PoseTransform { location: Vec3::ZERO, orientation: Quat::IDENTITY, scale: Vec3::ONE };
+        return SpringBone
+        {
+            bone: bone.to_string(),
+            whole_subtree: false,
+            stiffness: 100.0,
+            damping: 5.0,
+            gravity: Vec3::ZERO,
+            max_angle: PI / 4.0,
+            length: 0.25,
+            bones: vec![],
+            tail_offsets: vec![],
+            tails: vec![],
+        };
     }
 
-    fn to_matrix(&self) -> Mat4
+    //the bone and everything under it, simulated from the top down so each bone swings from wherever its parent ended up
+    pub fn subtree(bone: &str) -> Self
     {
-        return Mat4::from_scale_rotation_translation(self.scale, self.orientation, self.location);
+        return SpringBone { whole_subtree: true, ..SpringBone::new(bone) };
     }
 
-    pub fn loc_rot_scale(&self) -> (Vec3, Quat, Vec3)
+    //how hard the tip is pulled back towards the animated pose, in acceleration per unit of distance
+    pub fn stiffness(mut self, stiffness: f32) -> Self
     {
-        return (self.location, self.orientation, self.scale);
+        self.stiffness = stiffness.max(0.0);
+        return self;
+    }
+
+    //the share of the tip's velocity lost each second
+    pub fn damping(mut self, damping: f32) -> Self
+    {
+        self.damping = damping.max(0.0);
+        return self;
+    }
+
+    //in model space
+    pub fn gravity(mut self, gravity: Vec3) -> Self
+    {
+        self.gravity = gravity;
+        return self;
+    }
+
+    //the furthest a bone can swing away from its animated direction, in radians
+    pub fn max_angle(mut self, max_angle: f32) -> Self
+    {
+        self.max_angle = max_angle.max(0.0);
+        return self;
+    }
+
+    //bones with children reach to their first child's head. the rest are taken to be this long along their own y axis
+    pub fn length(mut self, length: f32) -> Self
+    {
+        self.length = length;
+        return self;
+    }
+
+    fn resolve(&mut self, armature: &Armature)
+    {
+        self.bones = match armature.bone_index(&self.bone)
+        {
+            Some(bone) if self.whole_subtree => armature.subtree(bone),
+            Some(bone) => vec![bone],
+            None => vec![],
+        };
+        self.tail_offsets = self.bones.iter().map(|bone| 
+        {
+            return armature.children(*bone).next().map_or(Vec3::Y * self.length, |child| armature.bones[child].local_tf.w_axis.truncate());
+        }).collect();
+        self.reset();
+    }
+
+    fn reset(&mut self)
+    {
+        self.tails = vec![None; self.bones.len()];
+    }
+
+    //turns the bones towards their tips. with a step the tips are moved first, without one the bones just follow where they already are
+    fn apply(&mut self, armature: &Armature, pose: &mut ArmaturePose, model_tfs: &mut [Mat4], step: Option<f32>)
+    {
+        for i in 0..self.bones.len()
+        {
+            let bone = self.bones[i];
+            let head = bone_head(model_tfs, bone);
+            let goal = model_tfs[bone].transform_point3(self.tail_offsets[i]);
+            let tail = self.tails[i].get_or_insert(SpringTail { position: goal, previous: goal });
+            if let Some(step) = step
+            {
+                let velocity = (tail.position - tail.previous) * (1.0 - self.damping * step).max(0.0);
+                let acceleration = (goal - tail.position) * self.stiffness + self.gravity;
+                tail.previous = tail.position;
+                tail.position += velocity + acceleration * step * step;
+            }
+            let animated = goal - head;
+            let mut direction = (tail.position - head).try_normalize().unwrap_or(animated.normalize_or_zero());
+            let (axis, angle) = rotation_between(animated, direction).to_axis_angle();
+            if angle > self.max_angle
+            {
+                direction = Quat::from_axis_angle(axis, self.max_angle) * animated.normalize_or_zero();
+            }
+            tail.position = head + direction * animated.length();
+            pose.rotate_joint(armature, model_tfs, bone, rotation_between(animated, direction), PI);
+        }
     }
 }
 
-#[derive(Clone, Copy)]
-pub enum MixType
+//typed inputs for layer state machines, declared on AnimationStateBuilder and set by game code
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnimationParameter
 {
-    Additive,
-    Override,
+    Float(f32),
+    Bool(bool),
+    //stays set until a transition that checks it fires
+    Trigger(bool),
 }
 
+#[derive(Debug)]
+pub enum ParameterError
+{
+    NoSuchParameter{ name: String },
+    TypeMismatch{ name: String, expected: AnimationParameter, given: AnimationParameter },
+}
+
+impl std::error::Error for ParameterError {}
+
+impl fmt::Display for ParameterError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        return match self
+        {
+            ParameterError::NoSuchParameter { name } => write!(f, "No animation parameter named \"{}\".", name),
+            ParameterError::TypeMismatch { name, expected, given } => write!(f, "Animation parameter \"{}\" is {:?}, can't set it to {:?}.", name, expected, given),
+        };
+    }
+}
+
+//parameters that don't exist or have a different type never hold
 #[derive(Clone)]
-struct ArmatureMask
+pub enum TransitionCondition
 {
-    multiplier : NormalizedFloat,
-    bone_weights : Vec<NormalizedFloat>,
-    control_weights : Vec<NormalizedFloat>
+    Greater(String, f32),
+    Less(String, f32),
+    IsTrue(String),
+    IsFalse(String),
+    Triggered(String),
+}
+
+impl TransitionCondition
+{
+    fn holds(&self, parameters: &HashMap<String, AnimationParameter>) -> bool
+    {
+        return match self
+        {
+            TransitionCondition::Greater(name, threshold) => matches!(parameters.get(name), Some(AnimationParameter::Float(val)) if val > threshold),
+            TransitionCondition::Less(name, threshold) => matches!(parameters.get(name), Some(AnimationParameter::Float(val)) if val < threshold),
+            TransitionCondition::IsTrue(name) => matches!(parameters.get(name), Some(AnimationParameter::Bool(true))),
+            TransitionCondition::IsFalse(name) => matches!(parameters.get(name), Some(AnimationParameter::Bool(false))),
+            TransitionCondition::Triggered(name) => matches!(parameters.get(name), Some(AnimationParameter::Trigger(true))),
+        };
+    }
+}
+
+#[derive(Clone)]
+pub struct StateTransition
+{
+    //None for transitions that can leave any state
+    from: Option<String>,
+    to: String,
+    conditions: Vec<TransitionCondition>,
+    exit_time: Option<NormalizedFloat>,
+    blend_duration: f32,
+    sync: bool,
+}
+
+impl StateTransition
+{
+    pub fn new(from: &str, to: &str) -> Self
+    {
+        return StateTransition { from: Some(from.to_string()), to: to.to_string(), conditions: vec![], exit_time: None, blend_duration: 0.0, sync: false };
+    }
+
+    //checked before the current state's own transitions, and never from the state it goes to
+    pub fn from_any_state(to: &str) -> Self
+    {
+        return StateTransition { from: None, to: to.to_string(), conditions: vec![], exit_time: None, blend_duration: 0.0, sync: false };
+    }
+
+    //every condition has to hold
+    pub fn when(mut self, condition: TransitionCondition) -> Self
+    {
+        self.conditions.push(condition);
+        return self;
+    }
+
+    //waits until the current state's mixer has played at least this far
+    pub fn exit_time(mut self, time: NormalizedFloat) -> Self
+    {
+        self.exit_time = Some(time);
+        return self;
+    }
+
+    pub fn blend_duration(mut self, duration: Duration) -> Self
+    {
+        self.blend_duration = duration.as_secs_f32();
+        return self;
+    }
+
+    //the state being entered follows the sync marker phase of the one being left for the length of the blend
+    pub fn sync_phase(mut self) -> Self
+    {
+        self.sync = true;
+        return self;
+    }
+}
+
+pub struct StateMachineBuilder
+{
+    starting_state: String,
+    //state name, mixer id
+    states: Vec<(String, String)>,
+    transitions: Vec<StateTransition>,
+}
+
+impl StateMachineBuilder
+{
+    pub fn new(starting_state: &str) -> Self
+    {
+        return StateMachineBuilder { starting_state: starting_state.to_string(), states: vec![], transitions: vec![] };
+    }
+
+    pub fn add_state(mut self, name: &str, mixer_id: &str) -> Self
+    {
+        self.states.push((name.to_string(), mixer_id.to_string()));
+        return self;
+    }
+
+    //transitions out of the same state are checked in the order they were added
+    pub fn add_transition(mut self, transition: StateTransition) -> Self
+    {
+        self.transitions.push(transition);
+        return self;
+    }
+
+    //states whose mixer isn't in the layer are left out, along with any transition that touches them
+    fn build(&self, mixer_ids: &[&String]) -> AnimationStateMachine
+    {
+        let states = self.states.iter().filter(|(_, mixer)| mixer_ids.contains(&mixer)).cloned().collect::<HashMap<_, _>>();
+        let mut transitions = self.transitions.iter().filter
+        (
+            |transition| states.contains_key(&transition.to) && transition.from.as_ref().map_or(true, |from| states.contains_key(from))
+        ).cloned().collect::<Vec<_>>();
+        //stable, so each group keeps its order
+        transitions.sort_by_key(|transition| transition.from.is_some());
+        return AnimationStateMachine { current: self.starting_state.clone(), states: states, transitions: transitions };
+    }
+}
+
+struct AnimationStateMachine
+{
+    states: HashMap<String, String>,
+    transitions: Vec<StateTransition>,
+    current: String,
+}
+
+impl AnimationStateMachine
+{
+    fn current_mixer(&self) -> Option<&String>
+    {
+        return self.states.get(&self.current);
+    }
+
+    //takes at most one transition, consuming the triggers it checked. returns the new state's mixer, how long to blend into it, and whether to sync them
+    fn step(&mut self, progress: NormalizedFloat, parameters: &mut HashMap<String, AnimationParameter>) -> Option<(String, f32, bool)>
+    {
+        let current = &self.current;
+        let transition = self.transitions.iter().find
+        (
+            |transition| 
+            {
+                let leaves_current = match &transition.from
+                {
+                    Some(from) => from == current,
+                    None => &transition.to != current,
+                };
+                leaves_current 
+                    && transition.exit_time.map_or(true, |exit| progress.get_val() >= exit.get_val())
+                    && transition.conditions.iter().all(|condition| condition.holds(parameters))
+            }
+        )?;
+        for condition in &transition.conditions
+        {
+            if let TransitionCondition::Triggered(name) = condition
+            {
+                parameters.insert(name.clone(), AnimationParameter::Trigger(false));
+            }
+        }
+        self.current = transition.to.clone();
+        return Some((self.states[&self.current].clone(), transition.blend_duration, transition.sync));
+    }
 }
 
-struct LayerBuilder
+pub struct LayerBuilder
 {
     name: String,
     starting_mixer: String,
@@ -813,11 +2562,12 @@ struct LayerBuilder
     mix: MixType,
     mask: Option<ArmatureMask>,
     mixers: Vec<MixerBuilder>,
+    state_machine: Option<StateMachineBuilder>,
 }
 
 impl LayerBuilder
 {
-    fn create_builder(id: &str) -> Self
+    pub fn create_builder(id: &str) -> Self
     {
         return LayerBuilder
         {
@@ -827,52 +2577,136 @@ impl LayerBuilder
             mask: None,
             starting_mixer: "".to_string(),
             mixers: vec![],
+            state_machine: None,
         };
     }
 
-    fn init_weight(&mut self, weight: NormalizedFloat) -> &mut Self
+    pub fn init_weight(&mut self, weight: NormalizedFloat) -> &mut Self
     {
         self.weight = weight;
         return self;
     }
 
-    fn mix(&mut self, mix: MixType) -> &mut Self
+    //layers other than the base are override unless set otherwise
+    pub fn mix(&mut self, mix: MixType) -> &mut Self
     {
         self.mix = mix;
         return self;
     }
 
-    fn mask(&mut self, mask: ArmatureMask) -> &mut Self
+    //without a mask the layer reaches every bone and control
+    pub fn mask(&mut self, mask: ArmatureMask) -> &mut Self
     {
         self.mask = Option::Some(mask);
         return self;
     }
 
-    fn add_mixer(&mut self, builder: MixerBuilder) -> &mut Self
+    pub fn add_mixer(&mut self, builder: MixerBuilder) -> &mut Self
     {
         self.mixers.push( builder );
         return self;
     }
 
-    fn starting_mixer(&mut self, id: &str) -> &mut Self
+    pub fn starting_mixer(&mut self, id: &str) -> &mut Self
     {
         self.starting_mixer = id.to_string();
         return self;
     }
 
-    fn build(&self) -> ArmatureLayer
+    //the layer starts in the machine's starting state rather than starting_mixer
+    pub fn state_machine(&mut self, machine: StateMachineBuilder) -> &mut Self
+    {
+        self.state_machine = Some(machine);
+        return self;
+    }
+
+    fn build(&self, armature: &Armature) -> ArmatureLayer
     {
+        let mut mask = self.mask.clone();
+        if let Some(mask) = &mut mask
+        {
+            mask.resolve(armature);
+        }
+        let mixer_ids = self.mixers.iter().map(|mix| mix.get_id()).collect::<Vec<_>>();
+        let state_machine = self.state_machine.as_ref().map(|machine| machine.build(&mixer_ids));
+        let starting_mixer = match &state_machine
+        {
+            Some(machine) => machine.current_mixer().cloned(),
+            None => if mixer_ids.contains(&&self.starting_mixer) { Option::Some(self.starting_mixer.clone()) } else { Option::None },
+        };
         return ArmatureLayer
         {
             name: self.name.clone(),
             mix: self.mix,
-            mask: self.mask.clone(),
+            mask: mask,
             weight: self.weight,
+            weight_fade: None,
             transition_queue: vec![],
-            working_mixer_opt: if self.mixers.iter().any(|mix| mix.get_id() == &self.starting_mixer) { Option::Some(self.starting_mixer.clone()) } else { Option::None },
+            working_mixer_opt: starting_mixer,
             portion_into_working_mixer: NormalizedFloat::zero(),
+            working_mixer_started: false,
+            mixer_events: vec![],
+            passed: vec![],
+            root_from: MixerScratch::new(),
+            travels: MixerScratch::new(),
             mixers: self.mixers.iter().map(|mix_builder| (mix_builder.get_id().clone(), mix_builder.build())).collect::<HashMap<_,_>>(),
+            state_machine: state_machine,
+        }
+    }
+}
+
+struct WeightFade
+{
+    from : f32,
+    to : f32,
+    duration : f32,
+    elapsed : f32
+}
+
+//values an update keeps per mixer. ids are written over in place rather than dropped when it's cleared,
+//so once the layer's mixers have all been through it the ids stop allocating
+struct MixerScratch<T>
+{
+    entries: Vec<(String, T)>,
+    //entries past len are left over from earlier updates
+    len: usize,
+}
+
+impl<T> MixerScratch<T>
+{
+    fn new() -> Self
+    {
+        return MixerScratch { entries: vec![], len: 0 };
+    }
+
+    fn clear(&mut self)
+    {
+        self.len = 0;
+    }
+
+    fn push(&mut self, mixer_id: &str, val: T)
+    {
+        match self.entries.get_mut(self.len)
+        {
+            Some(entry) =>
+            {
+                entry.0.clear();
+                entry.0.push_str(mixer_id);
+                entry.1 = val;
+            },
+            None => self.entries.push((mixer_id.to_string(), val)),
         }
+        self.len += 1;
+    }
+
+    fn iter(&self) -> impl Iterator<Item=&(String, T)>
+    {
+        return self.entries[..self.len].iter();
+    }
+
+    fn get(&self, mixer_id: &str) -> Option<&T>
+    {
+        return self.iter().find(|(id, _)| id == mixer_id).map(|(_, val)| val);
     }
 }
 
@@ -882,11 +2716,22 @@ pub struct ArmatureLayer
     mix : MixType,
     mask : Option<ArmatureMask>,
     weight : NormalizedFloat,
+    weight_fade : Option<WeightFade>,
     transition_queue : Vec<AnimationTransition>,
     working_mixer_opt : Option<String>,
     portion_into_working_mixer : NormalizedFloat,
+    //whether the working mixer has been through an update yet
+    working_mixer_started : bool,
+    //scratch space for update, kept so it doesn't allocate. the events one mixer passed, then the event name, mixer and where
+    //the event sits for every event the layer passed
+    mixer_events : Vec<(f32, usize)>,
+    passed : Vec<(String, String, NormalizedFloat)>,
+    //where each mixer was and its share of the layer as the update started, and how far each mixer that moved travelled
+    root_from : MixerScratch<(NormalizedFloat, f32)>,
+    travels : MixerScratch<f32>,
     //todo: change this to a hashmap of strings and mixers - it'll make it a lot more human-readable since these objects will be loaded as assets
     mixers: HashMap<String, ClipMixer>,
+    state_machine: Option<AnimationStateMachine>,
 }
 
 impl ArmatureLayer
@@ -923,17 +2768,47 @@ impl ArmatureLayer
         }
     }*/
 
-    fn update(&mut self, dt : f32)
+    //returns the root motion of the layer's root motion mixers over the update, weighted by how much each was playing at the start of it
+    //events passed by any of the layer's mixers are added to events
+    fn update(&mut self, dt : f32, parameters: &mut HashMap<String, AnimationParameter>, root: Option<&RootMotionSpace>, events: &mut Vec<AnimationEvent>) -> RootMotion
     {
-        if let Option::Some(working_mixer) = &self.working_mixer_opt 
+        if let Some(fade) = &mut self.weight_fade
         {
-            let init_clip = &self.mixers.get_mut(working_mixer).unwrap();
-            let step = (init_clip.playback_rate * dt)/init_clip.duration_seconds();
-            self.portion_into_working_mixer = match init_clip.playback_type 
+            fade.elapsed += dt;
+            let t = if fade.duration > 0.0 { (fade.elapsed / fade.duration).min(1.0) } else { 1.0 };
+            self.weight = NormalizedFloat::clamped(fade.from.interpolate(&fade.to, t));
+            if t >= 1.0
             {
-                PlaybackType::Sequential => NormalizedFloat::clamped(step + self.portion_into_working_mixer.get_val()),
-                PlaybackType::Looping => NormalizedFloat::wrapped(step + self.portion_into_working_mixer.get_val()),
-            };
+                self.weight_fade = None;
+            }
+        }
+        //taken out while it's filled since playing borrows the whole layer
+        let mut root_from = std::mem::replace(&mut self.root_from, MixerScratch::new());
+        root_from.clear();
+        if root.is_some()
+        {
+            for (mixer_id, portion, weight) in self.playing()
+            {
+                root_from.push(mixer_id, (portion, weight));
+            }
+        }
+        //how far each mixer that moved this update travelled, for counting root motion laps
+        self.travels.clear();
+        let progress = self.current_mixer_portion();
+        if let Some((mixer_id, blend_duration, sync)) = self.state_machine.as_mut().and_then(|machine| machine.step(progress, parameters))
+        {
+            self.transition_queue.push(AnimationTransition { sync: sync, ..AnimationTransition::new(&mixer_id, Duration::from_secs_f32(blend_duration)) });
+        }
+        self.passed.clear();
+        if let Option::Some(working_mixer) = &self.working_mixer_opt 
+        {
+            let init_clip = &self.mixers[working_mixer];
+            let from = self.portion_into_working_mixer;
+            self.portion_into_working_mixer = init_clip.advance(from, dt);
+            init_clip.passed_events(from, init_clip.travel(dt), !self.working_mixer_started, &mut self.mixer_events);
+            self.passed.extend(self.mixer_events.iter().map(|(_, i)| (init_clip.events[*i].name.clone(), working_mixer.clone(), init_clip.events[*i].trigger_time)));
+            self.travels.push(working_mixer, init_clip.travel(dt));
+            self.working_mixer_started = true;
             if self.transition_queue.len() > 0
             {
                 let mut i = 0;
@@ -941,85 +2816,186 @@ impl ArmatureLayer
                 {
                     let transition = &mut self.transition_queue[i];
                     let clip = &self.mixers[&transition.mixer_id];
-                    let prev_elapsed = transition.elapsed;
+                    let prev_portion = transition.portion;
                     transition.elapsed += dt;
-                    for event in clip.events.values()
+                    let synced = match transition.sync
                     {
-                        if event.trigger_time.get_val() > prev_elapsed/clip.duration_seconds() && event.trigger_time.get_val() <= transition.elapsed/clip.duration_seconds()
-                        {
-                            for callback in &event.callbacks
-                            {
-                                callback();
-                            }
-                        }
-                    }
+                        //the working mixer changes when an earlier transition finishes, so it's looked up each time
+                        true => self.working_mixer_opt.as_ref().and_then(|working| clip.match_phase(&self.mixers[working], self.portion_into_working_mixer)),
+                        false => None,
+                    };
+                    let (from, travel) = match synced
+                    {
+                        //jumping onto the working mixer's phase as the blend starts doesn't play through what it skipped
+                        Some(portion) if !transition.started => (portion, 0.0),
+                        Some(portion) => (prev_portion, clip.travel_between(prev_portion, portion)),
+                        None => (prev_portion, clip.travel(dt)),
+                    };
+                    transition.portion = synced.unwrap_or_else(|| clip.advance(prev_portion, dt));
+                    clip.passed_events(from, travel, !transition.started, &mut self.mixer_events);
+                    self.passed.extend(self.mixer_events.iter().map(|(_, i)| (clip.events[*i].name.clone(), transition.mixer_id.clone(), clip.events[*i].trigger_time)));
+                    self.travels.push(&transition.mixer_id, travel);
+                    transition.started = true;
                     //the transition's duration has been exceeded by the time it's been alive
                     if transition.duration <= transition.elapsed
                     {
-                        //first figure out how far we are into the transition's clip
-                        let portion = transition.elapsed/clip.duration_seconds();
+                        let portion = transition.portion;
                         //remove all transitions before this one. Even if they aren't finished, this one is, so it overlaps those before it
                         for _j in 0..i
                         {
                             self.transition_queue.remove(0);
                         }
                         let next_clip_id = self.transition_queue.remove(0).mixer_id;
-                        self.working_mixer_opt = Some(next_clip_id.clone());
-                        let working_clip = &self.mixers.get_mut(&next_clip_id).unwrap();
-                        self.portion_into_working_mixer = match working_clip.playback_type 
-                        {
-                            PlaybackType::Sequential => NormalizedFloat::clamped(portion),
-                            PlaybackType::Looping => NormalizedFloat::wrapped(portion),
-                        };
+                        self.working_mixer_opt = Some(next_clip_id);
+                        self.portion_into_working_mixer = portion;
                         i = 0;
                     }
                     i += 1;
                 }
             }
         }
+        //taken out for the loop since playing borrows the whole layer. taking leaves an empty vec behind, which doesn't allocate
+        let mut passed = std::mem::take(&mut self.passed);
+        for (name, mixer, time) in passed.drain(..)
+        {
+            let share = self.playing().filter(|(id, _, _)| **id == mixer).map(|(_, _, weight)| weight).sum::<f32>();
+            events.push(AnimationEvent { name: name, layer: self.name.clone(), mixer: mixer, time: time, weight: share * self.weight.get_val() });
+        }
+        self.passed = passed;
+        let mut motion = RootMotion::default();
+        if let Some(root) = root
+        {
+            for (mixer_id, (from, weight)) in root_from.iter()
+            {
+                let mixer = &self.mixers[mixer_id];
+                //mixers dropped by a finished transition are carried on as they would have played
+                let travel = self.travels.get(mixer_id).copied().unwrap_or_else(|| mixer.travel(dt));
+                let mixer_motion = mixer.root_motion_between(*from, travel, root);
+                motion.translation += mixer_motion.translation * *weight;
+                motion.yaw += mixer_motion.yaw * *weight;
+            }
+        }
+        self.root_from = root_from;
+        return motion;
     }
 
-    fn apply_to_pose(&self, destination: &mut ArmaturePose, current_weight: NormalizedFloat)
+    //how far into the mixer most recently transitioned to, or the working mixer if nothing is blending in
+    fn current_mixer_portion(&self) -> NormalizedFloat
     {
-        if let Option::Some(working_mixer) = &self.working_mixer_opt
+        return match self.transition_queue.last()
         {
-            let working_clip = &self.mixers[working_mixer];
-            let mut m_weights: Vec<f32> = Vec::with_capacity(self.transition_queue.len());
-            let mut base_weight = 0.0;
-            self.transition_queue.iter().rev().for_each
-            (
-                |active_transition|
-                {
-                    m_weights.push( (1.0 - m_weights.last().unwrap_or(&0.0)) * (active_transition.elapsed)/(active_transition.duration) );
-                    base_weight += m_weights.last().unwrap();
-                }
-            );
-            base_weight = 1.0 - base_weight;
-            working_clip.mix_to_pose(self.mix, self.portion_into_working_mixer, &self.mask, NormalizedFloat::clamped(base_weight*current_weight.get_val()), destination);
-            for i in 0..self.transition_queue.len()
-            {
-                let active_transition = &self.transition_queue[i];
-                let clip_portion = match self.mixers[&active_transition.mixer_id].playback_type 
-                {
-                    PlaybackType::Sequential => NormalizedFloat::clamped((active_transition.elapsed)/self.mixers[&active_transition.mixer_id].duration_seconds()),
-                    PlaybackType::Looping => NormalizedFloat::wrapped((active_transition.elapsed)/self.mixers[&active_transition.mixer_id].duration_seconds()),
-                };
-                self.mixers[&active_transition.mixer_id].mix_to_pose(self.mix, clip_portion, &self.mask, NormalizedFloat::clamped(m_weights.pop().unwrap() * current_weight.get_val()), destination);
-            }
+            Some(transition) => transition.portion,
+            None => self.portion_into_working_mixer,
+        };
+    }
+
+    //None for layers without a state machine
+    pub fn current_state(&self) -> Option<&str>
+    {
+        return self.state_machine.as_ref().map(|machine| machine.current.as_str());
+    }
+
+    //adds the layer's mixers onto a cleared pose. their weights sum to one, so this crossfades whatever is transitioning.
+    //returns false, leaving destination alone, when the layer has nothing playing
+    fn sample_to_pose(&self, destination: &mut ArmaturePose, root: Option<&RootMotionSpace>) -> bool
+    {
+        if self.working_mixer_opt.is_none()
+        {
+            return false;
+        }
+        for (mixer_id, portion, weight) in self.playing()
+        {
+            self.mixers[mixer_id].add_to_pose(portion, NormalizedFloat::clamped(weight), destination, root);
+        }
+        return true;
+    }
+
+    //each mixer playing, where it is, and its share of the layer. each transition takes its portion of what's under it,
+    //so the shares always sum to one
+    fn playing(&self) -> impl Iterator<Item=(&String, NormalizedFloat, f32)>
+    {
+        //what the transitions from i on leave for everything under them
+        let left_under = move |i: usize| self.transition_queue[i..].iter().map(|transition| 1.0 - transition.fade()).product::<f32>();
+        let working = self.working_mixer_opt.iter().map(move |mixer_id| (mixer_id, self.portion_into_working_mixer, left_under(0)));
+        return working.chain(self.transition_queue.iter().enumerate().map(move |(i, transition)| (&transition.mixer_id, transition.portion, transition.fade() * left_under(i + 1))));
+    }
+
+    fn replace_clip(&mut self, old: &Rc<AnimationClip>, new: &Rc<AnimationClip>)
+    {
+        for mixer in self.mixers.values_mut()
+        {
+            mixer.mixer_variant.replace_clip(old, new);
+        }
+    }
+
+    //masks are worked out again from their bone names, so they follow bones that moved in the new armature
+    fn fit_to_armature(&mut self, armature: &Armature)
+    {
+        if let Some(mask) = &mut self.mask
+        {
+            mask.resolve(armature);
+        }
+    }
+
+    pub fn weight(&self) -> NormalizedFloat
+    {
+        return self.weight;
+    }
+
+    //eases the weight toward target over duration, replacing any fade already running
+    pub fn fade_weight(&mut self, target: NormalizedFloat, duration: Duration)
+    {
+        self.weight_fade = Some(WeightFade { from: self.weight.get_val(), to: target.get_val(), duration: duration.as_secs_f32(), elapsed: 0.0 });
+    }
+
+    //stops any fade
+    pub fn set_weight(&mut self, weight: NormalizedFloat)
+    {
+        self.weight = weight;
+        self.weight_fade = None;
+    }
+
+    //moves a blend space mixer's position. does nothing for mixers that aren't blend spaces
+    pub fn set_blend_position(&mut self, mixer_id: &str, blend: Vec2)
+    {
+        if let Some(ClipMixerVariant::BlendSpace(blend_space)) = self.mixers.get_mut(mixer_id).map(|mixer| &mut mixer.mixer_variant)
+        {
+            blend_space.set_blend(blend);
         }
     }
 
+    pub fn blend_position(&self, mixer_id: &str) -> Option<Vec2>
+    {
+        return match self.mixers.get(mixer_id).map(|mixer| &mixer.mixer_variant)
+        {
+            Some(ClipMixerVariant::BlendSpace(blend_space)) => Some(blend_space.blend()),
+            _ => None,
+        };
+    }
+
     pub fn queue_clip_mixer(&mut self, mixer_id: &str, duration: std::time::Duration)
     {
         if self.mixers.contains_key(mixer_id)
         {
-            self.transition_queue.push(AnimationTransition { mixer_id: mixer_id.to_string(), duration: duration.as_secs_f32(), elapsed: 0.0 });
+            self.transition_queue.push(AnimationTransition::new(mixer_id, duration));
+        }
+    }
+
+    //like queue_clip_mixer, but the incoming mixer keeps to the working mixer's sync marker phase until the blend is done,
+    //so feet land together instead of sliding. falls back to an ordinary transition when the mixers share no markers
+    pub fn queue_synced_clip_mixer(&mut self, mixer_id: &str, duration: std::time::Duration)
+    {
+        if self.mixers.contains_key(mixer_id)
+        {
+            let portion = self.working_mixer_opt.as_ref().and_then(|working| self.mixers[mixer_id].match_phase(&self.mixers[working], self.portion_into_working_mixer));
+            self.transition_queue.push(AnimationTransition { portion: portion.unwrap_or(NormalizedFloat::zero()), sync: true, ..AnimationTransition::new(mixer_id, duration) });
         }
     }
 }
 
 pub struct Bone
 {
+    pub name: String,
     pub parent: Option<usize>,
     //todo make private again
     pub local_tf: Mat4,
@@ -1027,39 +3003,72 @@ pub struct Bone
 
 impl Bone
 {
-    pub fn new(parent: Option<usize>, loc: Vec3, rot: Quat) -> Bone
+    pub fn new(name: String, parent: Option<usize>, loc: Vec3, rot: Quat) -> Bone
     {
         return Bone
         {
+            name: name,
             parent: parent,
             local_tf: Mat4::from_rotation_translation(rot, loc),
         }
     }
 }
 
+//bones are expected to come after their parents, which is how the exporter writes them
 pub struct Armature
 {
     id: String,
-    //todo - make this private again
-    pub bones: Vec<Bone>,
-    num_controls: usize,
+    bones: Vec<Bone>,
+    //built from bones in new. with repeated names the first bone wins
+    bone_indices: HashMap<String, usize>,
+    children: Vec<Vec<usize>>,
+    //one per control track, in the order clips store them
+    control_names: Vec<String>,
 }
 
 impl Armature
 {
     pub fn new(id: String, bones: Vec<Bone>) -> Armature
     {  
+        let mut bone_indices = HashMap::new();
+        let mut children = vec![vec![]; bones.len()];
+        for (i, bone) in bones.iter().enumerate()
+        {
+            bone_indices.entry(bone.name.clone()).or_insert(i);
+            if let Some(parent) = bone.parent.filter(|parent| *parent < bones.len())
+            {
+                children[parent].push(i);
+            }
+        }
         return Armature 
         {
             id: id,
             bones: bones, 
-            num_controls: 0 
+            bone_indices: bone_indices,
+            children: children,
+            control_names: vec![],
         };
     }
 
+    pub fn id(&self) -> &str
+    {
+        return &self.id;
+    }
+
+    pub fn bones(&self) -> &[Bone]
+    {
+        return &self.bones;
+    }
+
+    pub fn with_controls(mut self, control_names: Vec<String>) -> Armature
+    {
+        self.control_names = control_names;
+        return self;
+    }
+
     fn create_empty_pose(&self) -> ArmaturePose
     {
-        return ArmaturePose{joints: vec![PoseTransform::empty(); self.bones.len()], control_values: vec![0.0; self.num_controls]};
+        return ArmaturePose{joints: vec![PoseTransform::empty(); self.bones.len()], control_values: vec![0.0; self.num_controls()]};
     }
 
     fn create_empty_transform_list(&self) -> Vec<Mat4>
@@ -1088,6 +3097,73 @@ impl Armature
     {
         return self.bones.len();
     }
+
+    pub fn bone_index(&self, name: &str) -> Option<usize>
+    {
+        return self.bone_indices.get(name).copied();
+    }
+
+    pub fn bone(&self, name: &str) -> Option<&Bone>
+    {
+        return self.bone_index(name).map(|i| &self.bones[i]);
+    }
+
+    pub fn bone_name(&self, index: usize) -> Option<&str>
+    {
+        return self.bones.get(index).map(|bone| bone.name.as_str());
+    }
+
+    //direct children only
+    pub fn children(&self, index: usize) -> impl Iterator<Item=usize> + '_
+    {
+        return self.children.get(index).into_iter().flatten().copied();
+    }
+
+    //the bone and everything under it, parents before children. empty if the index is out of range
+    pub fn subtree(&self, index: usize) -> Vec<usize>
+    {
+        let mut subtree = vec![];
+        if index < self.bones.len()
+        {
+            let mut open = vec![index];
+            while let Some(bone) = open.pop()
+            {
+                subtree.push(bone);
+                open.extend(self.children[bone].iter().rev());
+            }
+        }
+        return subtree;
+    }
+
+    //from the root down to and including the bone
+    pub fn path_from_root(&self, index: usize) -> Vec<usize>
+    {
+        let mut path = vec![];
+        let mut current = Some(index).filter(|i| *i < self.bones.len());
+        while let Some(bone) = current
+        {
+            //a malformed parent chain could loop, and no real path is longer than the bone count
+            if path.len() == self.bones.len()
+            {
+                break;
+            }
+            path.push(bone);
+            current = self.bones[bone].parent.filter(|parent| *parent < self.bones.len());
+        }
+        path.reverse();
+        return path;
+    }
+
+    pub fn num_controls(&self) -> usize
+    {
+        return self.control_names.len();
+    }
+
+    //index into ArmaturePose::control_values
+    pub fn control_index(&self, name: &str) -> Option<usize>
+    {
+        return self.control_names.iter().position(|c| c == name);
+    }
     
 }
 
@@ -1101,12 +3177,33 @@ pub struct AnimationState
 {
     pub armature: Rc<Armature>,
     current_pose: ArmaturePose,
+    //scratch space each layer samples into before it's blended onto current_pose
+    layer_pose: ArmaturePose,
     pub base_layer: ArmatureLayer,
-    layers: Vec<ArmatureLayer>
+    layers: Vec<ArmatureLayer>,
+    parameters: HashMap<String, AnimationParameter>,
+    //None for armatures without bones
+    root_space: Option<RootMotionSpace>,
+    root_motion: RootMotion,
+    //run in order on the blended pose
+    ik_constraints: Vec<IkConstraint>,
+    //scratch space for ik and springs, kept so updates don't allocate. the pose's model space transforms, and a chain's orientations before solving
+    model_tfs: Vec<Mat4>,
+    ik_orientations: Vec<Quat>,
+    spring_bones: Vec<SpringBone>,
+    //the springs' animated orientations, which each step starts again from
+    spring_orientations: Vec<Quat>,
+    spring_step: f32,
+    //mixer events since the last drain, oldest first
+    events: Vec<AnimationEvent>,
+    //time not yet simulated, always less than a step between updates
+    spring_time: f32,
 }
 
 impl AnimationState
 {
+    //a long hitch would otherwise run a burst of steps on the frame after it, making the next frame slow as well
+    const MAX_SPRING_STEPS : u32 = 8;
 
     pub fn pose(&self) -> &ArmaturePose
     {
@@ -1115,28 +3212,127 @@ impl AnimationState
 
     pub fn update(&mut self, dt : f32)
     {
-        self.base_layer.update(dt);
+        //only the base layer moves the character, the others just have their root motion mixers held in place
+        self.root_motion = self.base_layer.update(dt, &mut self.parameters, self.root_space.as_ref(), &mut self.events);
         for layer in &mut self.layers
         {
-            layer.update(dt);
+            layer.update(dt, &mut self.parameters, None, &mut self.events);
         }
-        let mut normalized_layer_weights: Vec<f32> = Vec::with_capacity(self.layers.len());
-        let mut base_weight = 0.0;
-
         self.current_pose.clear();
-        self.layers.iter().rev().for_each
-        (
-            |layer|
+        self.base_layer.sample_to_pose(&mut self.current_pose, self.root_space.as_ref());
+        for layer in &self.layers
+        {
+            if layer.weight.get_val() > 0.0
             {
-                normalized_layer_weights.push( (1.0 - normalized_layer_weights.last().unwrap_or(&0.0)) * layer.weight.get_val() );
-                base_weight += normalized_layer_weights.last().unwrap();
+                self.layer_pose.clear();
+                if layer.sample_to_pose(&mut self.layer_pose, self.root_space.as_ref())
+                {
+                    self.current_pose.blend_layer(&self.layer_pose, layer.mix, layer.weight, layer.mask.as_ref());
+                }
             }
-        );
-        self.base_layer.apply_to_pose(&mut self.current_pose, NormalizedFloat::clamped(1.0-base_weight));
-        for layer in &self.layers
+        }
+        if !self.ik_constraints.is_empty() || !self.spring_bones.is_empty()
+        {
+            self.current_pose.model_transforms(&self.armature, &mut self.model_tfs);
+            for constraint in &self.ik_constraints
+            {
+                constraint.apply(&self.armature, &mut self.current_pose, &mut self.model_tfs, &mut self.ik_orientations);
+            }
+            self.update_springs(dt);
+        }
+    }
+
+    //springs go after ik so hair and ears react to the head being turned. they're stepped at a fixed rate, carrying the leftover time to
+    //the next update, so how updates are split up doesn't change the result as long as none of them goes past the step limit.
+    //time past the limit is dropped, the springs fall behind rather than catching up
+    fn update_springs(&mut self, dt: f32)
+    {
+        if self.spring_bones.is_empty()
+        {
+            return;
+        }
+        self.spring_orientations.clear();
+        self.spring_orientations.extend(self.spring_bones.iter().flat_map(|spring| spring.bones.iter().map(|bone| self.current_pose.joints[*bone].orientation)));
+        self.spring_time += dt.max(0.0);
+        let steps = (self.spring_time / self.spring_step).floor() as u32;
+        self.spring_time -= steps as f32 * self.spring_step;
+        let steps = steps.min(Self::MAX_SPRING_STEPS);
+        if steps == 0
+        {
+            for spring in &mut self.spring_bones
+            {
+                spring.apply(&self.armature, &mut self.current_pose, &mut self.model_tfs, None);
+            }
+        }
+        for step in 0..steps
+        {
+            //each step starts from the animated pose again
+            if step > 0
+            {
+                let mut animated = self.spring_orientations.iter();
+                for spring in &self.spring_bones
+                {
+                    for (bone, orientation) in spring.bones.iter().zip(&mut animated)
+                    {
+                        self.current_pose.joints[*bone].orientation = *orientation;
+                    }
+                    if let Some(bone) = spring.bones.first()
+                    {
+                        self.current_pose.refresh_model_transforms(&self.armature, *bone, &mut self.model_tfs);
+                    }
+                }
+            }
+            for spring in &mut self.spring_bones
+            {
+                spring.apply(&self.armature, &mut self.current_pose, &mut self.model_tfs, Some(self.spring_step));
+            }
+        }
+    }
+
+    //every mixer event passed since the last drain, base layer first within each update. nothing is lost if an update goes by without draining
+    pub fn drain_events(&mut self) -> impl Iterator<Item=AnimationEvent> + '_
+    {
+        return self.events.drain(..);
+    }
+
+    //the root motion from the last update, for the caller to move the instance by
+    pub fn root_motion(&self) -> RootMotion
+    {
+        return self.root_motion;
+    }
+
+    //points any mixer playing old at new, used when a clip gets reloaded
+    pub fn replace_clip(&mut self, old: &Rc<AnimationClip>, new: &Rc<AnimationClip>)
+    {
+        self.base_layer.replace_clip(old, new);
+        for layer in &mut self.layers
+        {
+            layer.replace_clip(old, new);
+        }
+    }
+
+    pub fn set_armature(&mut self, armature: Rc<Armature>)
+    {
+        if armature.num_bones() != self.armature.num_bones() || armature.num_controls() != self.armature.num_controls()
+        {
+            self.current_pose = armature.create_empty_pose();
+            self.layer_pose = armature.create_empty_pose();
+        }
+        self.base_layer.fit_to_armature(&armature);
+        for layer in &mut self.layers
+        {
+            layer.fit_to_armature(&armature);
+        }
+        for constraint in &mut self.ik_constraints
         {
-            layer.apply_to_pose(&mut self.current_pose, NormalizedFloat::clamped(normalized_layer_weights.pop().unwrap()));
+            constraint.resolve(&armature);
         }
+        for spring in &mut self.spring_bones
+        {
+            spring.resolve(&armature);
+        }
+        self.root_space = RootMotionSpace::new(&armature);
+        self.armature = armature;
     }
 
     pub fn write_current_pose_transforms(&self, dest: &mut [Mat4], inverse_buffer: &mut [Mat4])
@@ -1148,11 +3344,100 @@ impl AnimationState
     {
         match self.layers.iter_mut().find(|layer|  layer.name == *layer_name )
         {
-            Some(layer) => layer.weight = weight,
+            Some(layer) => layer.set_weight(weight),
             None => (),
         }
     }
 
+    //for bringing a layer in or out smoothly, like fading a wave in over a walk and back out when it's done
+    pub fn fade_layer_weight(&mut self, layer_name: &str, target: NormalizedFloat, duration: Duration)
+    {
+        if let Some(layer) = self.get_layer_mut(layer_name)
+        {
+            layer.fade_weight(target, duration);
+        }
+    }
+
+    //targets are in model space
+    pub fn set_ik_target(&mut self, name: &str, target: Vec3)
+    {
+        if let Some(constraint) = self.get_ik_mut(name)
+        {
+            constraint.target = target;
+        }
+    }
+
+    pub fn set_ik_pole(&mut self, name: &str, pole: Vec3)
+    {
+        if let Some(constraint) = self.get_ik_mut(name)
+        {
+            constraint.pole = Some(pole);
+        }
+    }
+
+    pub fn set_ik_weight(&mut self, name: &str, weight: NormalizedFloat)
+    {
+        if let Some(constraint) = self.get_ik_mut(name)
+        {
+            constraint.weight = weight;
+        }
+    }
+
+    //drops the springs' momentum and lets them start again from the current pose, for after a teleport or a cut
+    pub fn reset_springs(&mut self)
+    {
+        for spring in &mut self.spring_bones
+        {
+            spring.reset();
+        }
+        self.spring_time = 0.0;
+    }
+
+    pub fn get_ik(&self, name: &str) -> Option<&IkConstraint>
+    {
+        return self.ik_constraints.iter().find(|constraint| constraint.name == name);
+    }
+
+    fn get_ik_mut(&mut self, name: &str) -> Option<&mut IkConstraint>
+    {
+        return self.ik_constraints.iter_mut().find(|constraint| constraint.name == name);
+    }
+
+    //parameters can only be set to values of the type they were declared with
+    pub fn set_parameter(&mut self, name: &str, value: AnimationParameter) -> Result<(), ParameterError>
+    {
+        return match self.parameters.get_mut(name)
+        {
+            Some(current) if std::mem::discriminant(current) == std::mem::discriminant(&value) => 
+            {
+                *current = value;
+                Ok(())
+            },
+            Some(current) => Err(ParameterError::TypeMismatch { name: name.to_string(), expected: *current, given: value }),
+            None => Err(ParameterError::NoSuchParameter { name: name.to_string() }),
+        };
+    }
+
+    pub fn set_float(&mut self, name: &str, value: f32) -> Result<(), ParameterError>
+    {
+        return self.set_parameter(name, AnimationParameter::Float(value));
+    }
+
+    pub fn set_bool(&mut self, name: &str, value: bool) -> Result<(), ParameterError>
+    {
+        return self.set_parameter(name, AnimationParameter::Bool(value));
+    }
+
+    pub fn set_trigger(&mut self, name: &str) -> Result<(), ParameterError>
+    {
+        return self.set_parameter(name, AnimationParameter::Trigger(true));
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<AnimationParameter>
+    {
+        return self.parameters.get(name).copied();
+    }
+
     pub fn get_layer(&self, layer_id: &str) -> Option<&ArmatureLayer>
     {
         return self.layers.iter().find(|l| l.name == layer_id);
@@ -1175,6 +3460,10 @@ pub struct AnimationStateBuilder
     default_pose: ArmaturePose,
     base_builder: LayerBuilder,
     layer_builders: Vec<LayerBuilder>,
+    parameters: HashMap<String, AnimationParameter>,
+    ik_constraints: Vec<IkConstraint>,
+    spring_bones: Vec<SpringBone>,
+    spring_step: Duration,
 }
 
 impl AnimationStateBuilder
@@ -1191,32 +3480,96 @@ impl AnimationStateBuilder
             default_pose: armature.create_empty_pose(),
             armature: armature,
             base_builder: builder, 
-            layer_builders: vec![]
+            layer_builders: vec![],
+            parameters: HashMap::new(),
+            ik_constraints: vec![],
+            spring_bones: vec![],
+            spring_step: Duration::from_secs_f64(1.0 / 120.0),
         }
     }
 
-    pub fn base_starting_animation(&mut self, id: &str) -> &Self
+    pub fn base_starting_animation(&mut self, id: &str) -> &mut Self
     {
         self.base_builder.starting_mixer(id);
         return self;
     }
 
-    pub fn add_layer(&mut self, layer : LayerBuilder) -> &Self
+    pub fn base_state_machine(&mut self, machine: StateMachineBuilder) -> &mut Self
+    {
+        self.base_builder.state_machine(machine);
+        return self;
+    }
+
+    //the value given is the parameter's type and starting value
+    pub fn add_parameter(&mut self, name: &str, value: AnimationParameter) -> &mut Self
+    {
+        self.parameters.insert(name.to_string(), value);
+        return self;
+    }
+
+    //layers go on top of the base in the order they're added
+    pub fn add_layer(&mut self, layer : LayerBuilder) -> &mut Self
     {
         self.layer_builders.push(layer);
         return self;
     }
 
+    //constraints run after all the layers, in the order they're added
+    pub fn add_ik(&mut self, constraint: IkConstraint) -> &mut Self
+    {
+        self.ik_constraints.push(constraint);
+        return self;
+    }
+
+    //springs run after ik, in the order they're added
+    pub fn add_spring(&mut self, spring: SpringBone) -> &mut Self
+    {
+        self.spring_bones.push(spring);
+        return self;
+    }
+
+    //how often springs are stepped. 120 times a second unless set
+    pub fn spring_step(&mut self, step: Duration) -> &mut Self
+    {
+        if !step.is_zero()
+        {
+            self.spring_step = step;
+        }
+        return self;
+    }
+
     pub fn build(&self) -> AnimationState
     {
-        let base_layer = self.base_builder.build();
+        let base_layer = self.base_builder.build(&self.armature);
         //base_layer.working_mixer_opt = Some(base_layer.mixers.keys().next().unwrap().clone());
         return AnimationState
         {
             current_pose: self.default_pose.clone(), 
+            layer_pose: self.default_pose.clone(),
             base_layer: base_layer,
-            layers: self.layer_builders.iter().map(|builder|builder.build()).collect::<Vec<ArmatureLayer>>(),
+            layers: self.layer_builders.iter().map(|builder|builder.build(&self.armature)).collect::<Vec<ArmatureLayer>>(),
             armature: self.armature.clone(),
+            parameters: self.parameters.clone(),
+            root_space: RootMotionSpace::new(&self.armature),
+            root_motion: RootMotion::default(),
+            ik_constraints: self.ik_constraints.iter().map(|constraint| 
+            {
+                let mut constraint = constraint.clone();
+                constraint.resolve(&self.armature);
+                return constraint;
+            }).collect(),
+            model_tfs: vec![],
+            ik_orientations: vec![],
+            spring_bones: self.spring_bones.iter().map(|spring| 
+            {
+                let mut spring = spring.clone();
+                spring.resolve(&self.armature);
+                return spring;
+            }).collect(),
+            spring_orientations: vec![],
+            spring_step: self.spring_step.as_secs_f32(),
+            spring_time: 0.0,
+            events: vec![],
         }
     }
 }
\ No newline at end of file
@@ -2,7 +2,7 @@ use std::collections::VecDeque;
 
 use glam::{Vec3, Vec4};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct NormalizedFloat
 {
     val: f32,
@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use super::properties::{EvaluationError, Value};
+
+//older entries are dropped past this, so an instance nobody drains can't grow forever
+const MAX_DIAGNOSTICS: usize = 64;
+
+//what a shader instance does with an expression that fails to evaluate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorPolicy
+{
+    //the expression keeps whatever it last evaluated to
+    KeepLastValue,
+    //the expression falls back to its value under the shader's default properties
+    SubstituteDefault,
+    //keeps the last value but reports the instance as errored until every failing expression evaluates again,
+    //and the renderer draws the model in magenta and black checks until then
+    MarkErrored,
+}
+
+#[derive(Debug)]
+pub struct EvaluationDiagnostic
+{
+    pub expression_id: u16,
+    pub error: EvaluationError,
+    pub frame: u64,
+    //the properties the expression reads, as they were when it failed
+    pub inputs: Vec<(String, Value)>,
+}
+
+impl fmt::Display for EvaluationDiagnostic
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "Frame {}: expression {} failed. {}", self.frame, self.expression_id, self.error)?;
+        for (i, (name, val)) in self.inputs.iter().enumerate()
+        {
+            write!(f, "{} {} = {}", if i == 0 { " Inputs:" } else { "," }, name, val)?;
+        }
+        return Ok(());
+    }
+}
+
+pub struct ShaderDiagnostics
+{
+    policy: ErrorPolicy,
+    frame: u64,
+    entries: VecDeque<EvaluationDiagnostic>,
+    dropped: usize,
+    //one per compiled expression, whether its last evaluation failed
+    failing: Vec<bool>,
+}
+
+impl ShaderDiagnostics
+{
+    pub fn new(num_expressions: usize) -> Self
+    {
+        return ShaderDiagnostics
+        {
+            policy: ErrorPolicy::KeepLastValue,
+            frame: 0,
+            entries: VecDeque::new(),
+            dropped: 0,
+            failing: vec![false; num_expressions],
+        };
+    }
+
+    pub fn with_policy(mut self, policy: ErrorPolicy) -> Self
+    {
+        self.policy = policy;
+        return self;
+    }
+
+    //for a rebuilt shader. keeps the policy, frame count and anything not yet drained
+    pub fn reset(&mut self, num_expressions: usize)
+    {
+        self.failing = vec![false; num_expressions];
+    }
+
+    pub fn policy(&self) -> ErrorPolicy
+    {
+        return self.policy;
+    }
+
+    pub fn set_policy(&mut self, policy: ErrorPolicy)
+    {
+        self.policy = policy;
+    }
+
+    pub fn frame(&self) -> u64
+    {
+        return self.frame;
+    }
+
+    pub fn advance_frame(&mut self)
+    {
+        self.frame += 1;
+    }
+
+    //index is the expression's position among the shader's compiled expressions, not its id.
+    //ShaderInstance tracks each placement's transform after those
+    pub fn record(&mut self, index: usize, expression_id: u16, error: EvaluationError, inputs: Vec<(String, Value)>)
+    {
+        if let Some(failing) = self.failing.get_mut(index)
+        {
+            *failing = true;
+        }
+        if self.entries.len() == MAX_DIAGNOSTICS
+        {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+        self.entries.push_back(EvaluationDiagnostic { expression_id: expression_id, error: error, frame: self.frame, inputs: inputs });
+    }
+
+    pub fn resolve(&mut self, index: usize)
+    {
+        if let Some(failing) = self.failing.get_mut(index)
+        {
+            *failing = false;
+        }
+    }
+
+    pub fn is_failing(&self, index: usize) -> bool
+    {
+        return self.failing.get(index).copied().unwrap_or(false);
+    }
+
+    pub fn has_failures(&self) -> bool
+    {
+        return self.failing.iter().any(|failing| *failing);
+    }
+
+    pub fn is_errored(&self) -> bool
+    {
+        return self.policy == ErrorPolicy::MarkErrored && self.has_failures();
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item=&EvaluationDiagnostic>
+    {
+        return self.entries.iter();
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item=EvaluationDiagnostic> + '_
+    {
+        return self.entries.drain(..);
+    }
+
+    //entries pushed out by newer ones before anyone drained them
+    pub fn dropped(&self) -> usize
+    {
+        return self.dropped;
+    }
+}
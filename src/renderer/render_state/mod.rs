@@ -1,7 +1,12 @@
+mod test;
+
 pub mod animation;
 pub mod render_state;
 pub mod properties;
 pub mod texture;
+pub mod tween;
+pub mod overrides;
+pub mod diagnostics;
 pub mod model;
 pub mod common;
 pub mod light;
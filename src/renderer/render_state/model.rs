@@ -5,7 +5,9 @@ use glam::{Mat4, Vec3};
 
 use super::animation::{Armature, AnimationState, ArmaturePose};
 use super::properties::{Value, AssignmentError};
-use super::texture::{Shader, ShaderInstance, ShaderValueLink};
+use super::overrides::{OverrideError, PropertyOverrides};
+use super::texture::{Shader, ShaderInstance, ShaderUpdateError, ShaderValueLink};
+use super::tween::{Tween, TweenId};
 use super::common::{Color, AABB, NormalizedFloat, Id};
 
 pub struct MorphTargetVertex
@@ -50,10 +52,24 @@ pub struct ShaderSlot
 
 impl ShaderSlot
 {
+    pub fn new(tris: Vec<usize>, shader: Rc<Shader>, links: Vec<ShaderValueLink>) -> Self
+    {
+        return ShaderSlot { tris: tris, shader: shader, links: links };
+    }
+
     pub fn get_shader(&self) -> &Shader
     {
         return self.shader.as_ref();
     }
+
+    //for hot reload, slots holding some other shader are left alone
+    pub fn replace_shader(&mut self, old: &Rc<Shader>, new: &Rc<Shader>)
+    {
+        if Rc::ptr_eq(&self.shader, old)
+        {
+            self.shader = new.clone();
+        }
+    }
 }
 
 pub struct AnimatedModel
@@ -113,10 +129,11 @@ impl ModelInstance
             shaders: model.shader_slots.iter().map
             (
                 |entry| 
-                (
-                    entry.0.clone(), 
-                    ShaderInstance::new(entry.1.shader.clone(), entry.1.links.to_owned()),
-                )
+                {
+                    let mut instance = ShaderInstance::new(entry.1.shader.clone(), entry.1.links.to_owned());
+                    instance.set_object_transform(tf);
+                    return (entry.0.clone(), instance);
+                }
             ).collect::<HashMap<String, ShaderInstance>>(),
             colors: model.palettes[model.default_palette].colors.to_owned(),
             bbox: AABB::new(model.min_bound, model.max_bound),
@@ -125,6 +142,46 @@ impl ModelInstance
     pub fn set_transform(&mut self, tf: Mat4)
     {
         self.tf = tf;
+        for shader in self.shaders.values_mut()
+        {
+            shader.set_object_transform(tf);
+        }
+    }
+
+    //picks up a reloaded model while keeping the transform, shader properties and, if the palette is the same size, the colors
+    fn rebuild(&mut self, model: &Model)
+    {
+        self.shaders.retain(|slot_id, _| model.shader_slots.contains_key(slot_id));
+        for (slot_id, slot) in &model.shader_slots
+        {
+            match self.shaders.get_mut(slot_id)
+            {
+                Some(instance) => instance.rebuild(slot.shader.clone(), slot.links.to_owned()),
+                None => 
+                {
+                    let mut instance = ShaderInstance::new(slot.shader.clone(), slot.links.to_owned());
+                    instance.set_object_transform(self.tf);
+                    self.shaders.insert(slot_id.clone(), instance);
+                },
+            }
+        }
+        let default_colors = &model.palettes[model.default_palette].colors;
+        if default_colors.len() != self.colors.len()
+        {
+            self.colors = default_colors.to_owned();
+        }
+        self.bbox = AABB::new(model.min_bound, model.max_bound);
+    }
+
+    //returns whether any of the instance's shaders were made from old
+    fn replace_shader(&mut self, old: &Rc<Shader>, new: &Rc<Shader>) -> bool
+    {
+        let mut replaced = false;
+        for shader in self.shaders.values_mut()
+        {
+            replaced |= shader.replace_shader(old, new);
+        }
+        return replaced;
     }
 
     pub fn set_color(&mut self, index: usize, color: Color)
@@ -146,11 +203,66 @@ impl ModelInstance
         }
     }
 
+    fn tween_shader_property(&mut self, shader_id: &str, tween: Tween) -> Result<TweenId, AssignmentError>
+    {
+        return match self.shaders.get_mut(shader_id)
+        {
+            Some(shader) => Ok(shader.tween_property(tween)),
+            None => Result::Err(AssignmentError::NoSuchPropertyGroupError { container_name: shader_id.to_string() }),
+        }
+    }
+
     pub fn set_palette(&mut self, palette: ColorPalette)
     {
         self.colors = palette.colors;
     }
 
+    //applies what it can and returns every override that didn't fit
+    fn apply_overrides(&mut self, overrides: &PropertyOverrides) -> Vec<OverrideError>
+    {
+        let mut errors = vec![];
+        for (slot, property_name, val) in overrides.entries()
+        {
+            if let Err(err) = self.set_shader_property(slot, property_name, *val)
+            {
+                errors.push(OverrideError::AssignmentError { slot: slot.clone(), err: err });
+            }
+        }
+        return errors;
+    }
+
+    fn collect_overrides(&self) -> PropertyOverrides
+    {
+        let mut overrides = PropertyOverrides::new();
+        for (slot, shader) in &self.shaders
+        {
+            for (property_name, val) in shader.overridden_properties()
+            {
+                overrides.set(slot, property_name, *val);
+            }
+        }
+        return overrides;
+    }
+
+    //returns whether any shader values changed, along with the first tween or link that failed to apply
+    fn update_shaders(&mut self, dt: f32, control_values: &[f32]) -> (bool, Result<(), ShaderUpdateError>)
+    {
+        let mut result = Ok(());
+        let mut changed = false;
+        for shader in self.shaders.values_mut()
+        {
+            let was_errored = shader.is_errored();
+            let applied = shader.update(dt, &self.colors, control_values);
+            //going in or out of the errored state swaps the fallback pattern on the gpu
+            changed |= shader.changed_expression_ids().next().is_some() || shader.is_errored() != was_errored;
+            if result.is_ok()
+            {
+                result = applied;
+            }
+        }
+        return (changed, result);
+    }
+
     pub fn get_bounding_box(&self) -> &AABB
     {
         return &self.bbox;
@@ -206,6 +318,23 @@ impl StaticModelInstance
         return self.model_instance.set_shader_property(shader_id, property_name, value);
     }
 
+    pub fn tween_shader_property(&mut self, shader_id: &str, tween: Tween) -> Result<TweenId, AssignmentError>
+    {
+        return self.model_instance.tween_shader_property(shader_id, tween);
+    }
+
+    pub fn apply_overrides(&mut self, overrides: &PropertyOverrides) -> Vec<OverrideError>
+    {
+        self.dirty = true;
+        return self.model_instance.apply_overrides(overrides);
+    }
+
+    //the shader properties that differ from their defaults, ready to be saved
+    pub fn overrides(&self) -> PropertyOverrides
+    {
+        return self.model_instance.collect_overrides();
+    }
+
     pub fn set_palette(&mut self, palette: ColorPalette)
     {
         self.model_instance.set_palette(palette);
@@ -218,9 +347,32 @@ impl StaticModelInstance
         self.dirty = true;
     }
 
+    //static models have no armature, so only color links can apply
+    pub fn update(&mut self, dt: f32) -> Result<(), ShaderUpdateError>
+    {
+        let (changed, result) = self.model_instance.update_shaders(dt, &[]);
+        self.dirty |= changed;
+        return result;
+    }
+
+    pub fn rebuild(&mut self, model: &StaticModel)
+    {
+        self.model_instance.rebuild(&model.model_data);
+        self.dirty = true;
+    }
+
+    pub fn replace_shader(&mut self, old: &Rc<Shader>, new: &Rc<Shader>)
+    {
+        self.dirty |= self.model_instance.replace_shader(old, new);
+    }
+
     pub fn clear_dirty_state(&mut self)
     {
         self.dirty = false;
+        for shader in self.model_instance.shaders.values_mut()
+        {
+            shader.clear_changed_expressions();
+        }
     }
 
     pub fn dirty(&self) -> bool
@@ -228,9 +380,10 @@ impl StaticModelInstance
         return self.dirty;
     }
 
-    pub fn shader_instance(&self, id: &str) -> Option<&ShaderInstance>
+    //in no particular order, but the same one until the model is rebuilt
+    pub fn shader_instances(&self) -> impl Iterator<Item=&ShaderInstance>
     {
-        return self.model_instance.shaders.get(id);
+        return self.model_instance.shaders.values();
     }
 
     pub fn bounding_box(&self) -> &AABB
@@ -255,6 +408,8 @@ pub struct AnimatedModelInstance
     animated_model_id: String,
     model_instance: ModelInstance,
     anim_state: AnimationState,
+    pose_buffer: Vec<Mat4>,
+    inverse_pose_buffer: Vec<Mat4>,
     dirty: bool,
 }
 
@@ -262,14 +417,19 @@ impl AnimatedModelInstance
 {
     pub fn new(id: Id, template: &AnimatedModel, tf: Mat4, state: AnimationState) -> Self
     {
-        return AnimatedModelInstance 
+        let num_bones = state.armature.num_bones();
+        let mut instance = AnimatedModelInstance 
         { 
             id: id,
             model_instance: ModelInstance::new(&template.model_data, tf),
             anim_state: state,
             animated_model_id: template.id.clone(),
+            pose_buffer: vec![Mat4::IDENTITY; num_bones],
+            inverse_pose_buffer: vec![Mat4::IDENTITY; num_bones],
             dirty: true,
         };
+        instance.bind_shaders();
+        return instance;
     }
 
     pub fn id(&self) -> Id { return self.id; }
@@ -290,6 +450,23 @@ impl AnimatedModelInstance
         return self.model_instance.set_shader_property(shader_id, property_name, value);
     }
 
+    pub fn tween_shader_property(&mut self, shader_id: &str, tween: Tween) -> Result<TweenId, AssignmentError>
+    {
+        return self.model_instance.tween_shader_property(shader_id, tween);
+    }
+
+    pub fn apply_overrides(&mut self, overrides: &PropertyOverrides) -> Vec<OverrideError>
+    {
+        self.dirty = true;
+        return self.model_instance.apply_overrides(overrides);
+    }
+
+    //the shader properties that differ from their defaults, ready to be saved
+    pub fn overrides(&self) -> PropertyOverrides
+    {
+        return self.model_instance.collect_overrides();
+    }
+
     pub fn set_color(&mut self, index: usize, color: Color)
     {
         self.model_instance.set_color(index, color);
@@ -302,9 +479,49 @@ impl AnimatedModelInstance
         self.dirty = true;
     }
 
+    pub fn rebuild(&mut self, model: &AnimatedModel)
+    {
+        self.model_instance.rebuild(&model.model_data);
+        self.bind_shaders();
+        self.dirty = true;
+    }
+
+    pub fn replace_shader(&mut self, old: &Rc<Shader>, new: &Rc<Shader>)
+    {
+        if self.model_instance.replace_shader(old, new)
+        {
+            self.bind_shaders();
+            self.dirty = true;
+        }
+    }
+
+    //for when the armature gets swapped out underneath the animation state
+    pub fn set_armature(&mut self, armature: Rc<Armature>)
+    {
+        let num_bones = armature.num_bones();
+        self.anim_state.set_armature(armature);
+        self.pose_buffer = vec![Mat4::IDENTITY; num_bones];
+        self.inverse_pose_buffer = vec![Mat4::IDENTITY; num_bones];
+        self.bind_shaders();
+        self.dirty = true;
+    }
+
+    fn bind_shaders(&mut self)
+    {
+        for shader in self.model_instance.shaders.values_mut()
+        {
+            shader.bind_armature(&self.anim_state.armature);
+        }
+        self.update_bone_placements();
+    }
+
     pub fn clear_dirty_state(&mut self)
     {
         self.dirty = false;
+        for shader in self.model_instance.shaders.values_mut()
+        {
+            shader.clear_changed_expressions();
+        }
     }
 
     pub fn dirty(&self) -> bool
@@ -312,10 +529,28 @@ impl AnimatedModelInstance
         return self.dirty;
     }
 
-    pub fn update(&mut self, dt: f32)
+    pub fn update(&mut self, dt: f32) -> Result<(), ShaderUpdateError>
     {
         self.anim_state.update(dt);
+        self.update_bone_placements();
         self.dirty = true;
+        let (_, result) = self.model_instance.update_shaders(dt, self.anim_state.pose().control_values());
+        return result;
+    }
+
+    fn update_bone_placements(&mut self)
+    {
+        self.anim_state.write_current_pose_transforms(&mut self.pose_buffer, &mut self.inverse_pose_buffer);
+        for shader in self.model_instance.shaders.values_mut()
+        {
+            shader.update_placement_transforms(&self.pose_buffer);
+        }
+    }
+
+    //in no particular order, but the same one until the model is rebuilt
+    pub fn shader_instances(&self) -> impl Iterator<Item=&ShaderInstance>
+    {
+        return self.model_instance.shaders.values();
     }
 
     pub fn bounding_box(&self) -> &AABB
@@ -342,5 +577,12 @@ impl AnimatedModelInstance
     {
         return &mut self.anim_state;
     }
+
+    //moves the instance by the root motion from the last update. call it after update for mixers set to root_motion
+    pub fn apply_root_motion(&mut self)
+    {
+        let tf = self.transform() * self.anim_state.root_motion().to_matrix();
+        self.set_transform(tf);
+    }
 }
 
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::model::Model;
+use super::properties::{AssignmentError, Expression, PropertyGroup, Value};
+
+//shader property overrides for one model instance, saved as text like
+//
+//  #comments start with a hash
+//  [hull]
+//  stripe_color = color[1, 0, 0.5, 1]
+//  stripe_count = int(4)
+//
+//each section is a shader slot. values are constant property expressions, so vec3(1, 2, 3) or 0.5 * PI work too
+#[derive(Clone, Default, Debug)]
+pub struct PropertyOverrides
+{
+    //ordered so saving the same overrides always writes the same file
+    slots: BTreeMap<String, BTreeMap<String, Value>>,
+}
+
+#[derive(Debug)]
+pub enum OverrideError
+{
+    SyntaxError{ line: usize, message: String },
+    InvalidValue{ line: usize, property_name: String, message: String },
+    AssignmentError{ slot: String, err: AssignmentError },
+}
+
+impl std::error::Error for OverrideError {}
+
+impl fmt::Display for OverrideError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        return match self
+        {
+            OverrideError::SyntaxError { line, message } => write!(f, "Line {}: {}", line, message),
+            OverrideError::InvalidValue { line, property_name, message } => write!(f, "Line {}: invalid value for \"{}\". {}", line, property_name, message),
+            OverrideError::AssignmentError { slot, err } => write!(f, "Shader slot \"{}\": {}", slot, err),
+        };
+    }
+}
+
+impl PropertyOverrides
+{
+    pub fn new() -> Self
+    {
+        return PropertyOverrides { slots: BTreeMap::new() };
+    }
+
+    pub fn set(&mut self, slot: &str, property_name: &str, value: Value)
+    {
+        self.slots.entry(slot.to_string()).or_default().insert(property_name.to_string(), value);
+    }
+
+    pub fn get(&self, slot: &str, property_name: &str) -> Option<&Value>
+    {
+        return self.slots.get(slot).and_then(|props| props.get(property_name));
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        return self.slots.values().all(|props| props.is_empty());
+    }
+
+    //(slot, property name, value), sorted by slot then property name
+    pub fn entries(&self) -> impl Iterator<Item=(&String, &String, &Value)>
+    {
+        return self.slots.iter().flat_map(|(slot, props)| props.iter().map(move |(name, val)| (slot, name, val)));
+    }
+
+    //reports every bad line rather than stopping at the first
+    pub fn parse(input: &str) -> Result<PropertyOverrides, Vec<OverrideError>>
+    {
+        let mut overrides = PropertyOverrides::new();
+        let mut errors = vec![];
+        let mut slot: Option<String> = None;
+        let no_properties = PropertyGroup::new();
+        for (i, raw_line) in input.lines().enumerate()
+        {
+            let line_num = i + 1;
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#')
+            {
+                continue;
+            }
+            if line.starts_with('[')
+            {
+                match line.strip_prefix('[').and_then(|l| l.strip_suffix(']')).map(str::trim)
+                {
+                    Some(name) if !name.is_empty() => slot = Some(name.to_string()),
+                    _ => errors.push(OverrideError::SyntaxError { line: line_num, message: format!("\"{}\" is not a valid slot header, expected [slot name]", line) }),
+                }
+                continue;
+            }
+            let (name, val_text) = match line.split_once('=')
+            {
+                Some((name, val_text)) if !name.trim().is_empty() => (name.trim(), val_text.trim()),
+                _ =>
+                {
+                    errors.push(OverrideError::SyntaxError { line: line_num, message: format!("expected property = value, found \"{}\"", line) });
+                    continue;
+                },
+            };
+            let slot_name = match &slot
+            {
+                Some(slot_name) => slot_name,
+                None =>
+                {
+                    errors.push(OverrideError::SyntaxError { line: line_num, message: format!("\"{}\" comes before any [slot] header", name) });
+                    continue;
+                },
+            };
+            if overrides.get(slot_name, name).is_some()
+            {
+                errors.push(OverrideError::SyntaxError { line: line_num, message: format!("\"{}\" is set more than once in [{}]", name, slot_name) });
+                continue;
+            }
+            let val = Expression::parse(0, val_text)
+                .map_err(|err| err.to_string())
+                .and_then(|expr| expr.evaluate(&no_properties).map_err(|err| err.to_string()));
+            match val
+            {
+                Ok(val) => overrides.set(slot_name, name, val),
+                Err(message) => errors.push(OverrideError::InvalidValue { line: line_num, property_name: name.to_string(), message: message }),
+            }
+        }
+        if !errors.is_empty()
+        {
+            return Err(errors);
+        }
+        return Ok(overrides);
+    }
+
+    //checks every override against the parameters the model's shaders declare
+    pub fn validate(&self, model: &Model) -> Vec<OverrideError>
+    {
+        let mut errors = vec![];
+        for (slot, props) in &self.slots
+        {
+            let defaults = match model.shader_slots.get(slot)
+            {
+                Some(shader_slot) => &shader_slot.get_shader().default_args,
+                None =>
+                {
+                    errors.push(OverrideError::AssignmentError { slot: slot.clone(), err: AssignmentError::NoSuchPropertyGroupError { container_name: slot.clone() } });
+                    continue;
+                },
+            };
+            for (name, val) in props
+            {
+                let err = match defaults.get_property(name)
+                {
+                    Some(default) if std::mem::discriminant(default) == std::mem::discriminant(val) => continue,
+                    Some(default) => AssignmentError::TypeMismatchError { property_name: name.clone(), expected: *default, given: *val },
+                    None => AssignmentError::NoSuchPropertyError { property_name: name.clone() },
+                };
+                errors.push(OverrideError::AssignmentError { slot: slot.clone(), err: err });
+            }
+        }
+        return errors;
+    }
+
+    pub fn load(input: &str, model: &Model) -> Result<PropertyOverrides, Vec<OverrideError>>
+    {
+        let overrides = PropertyOverrides::parse(input)?;
+        let errors = overrides.validate(model);
+        if !errors.is_empty()
+        {
+            return Err(errors);
+        }
+        return Ok(overrides);
+    }
+}
+
+//writes the format parse reads
+impl fmt::Display for PropertyOverrides
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        let mut first = true;
+        for (slot, props) in &self.slots
+        {
+            if !first
+            {
+                writeln!(f)?;
+            }
+            first = false;
+            writeln!(f, "[{}]", slot)?;
+            for (name, val) in props
+            {
+                writeln!(f, "{} = {}", name, literal(val))?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+//typed literal syntax, so matrices and quaternions come back as the exact components rather than a constructor call
+fn literal(val: &Value) -> String
+{
+    let list = |type_name: &str, components: &[f32]| format!("{}[{}]", type_name, components.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", "));
+    return match val
+    {
+        Value::Scalar(s_val) => s_val.to_string(),
+        Value::Int(i_val) => format!("int({})", i_val),
+        Value::Bool(b_val) => b_val.to_string(),
+        Value::Vector2(v2_val) => list("vec2", &v2_val.to_array()),
+        Value::Vector3(v3_val) => list("vec3", &v3_val.to_array()),
+        Value::Vector4(v4_val) => list("vec4", &v4_val.to_array()),
+        Value::Color(c_val) => list("color", &c_val.to_array()),
+        Value::Quaternion(q_val) => list("quat", &q_val.to_array()),
+        Value::Matrix3(m3_val) => list("mat3", &m3_val.to_cols_array()),
+        Value::Matrix4(m4_val) => list("mat4", &m4_val.to_cols_array()),
+    };
+}
@@ -1,16 +1,27 @@
 use std::collections::HashMap;
 use std::fmt;
+use std::rc::Rc;
 
-use glam::{Vec2, Mat4, Mat3, Vec4, Vec3, Quat};
+use glam::{Affine2, EulerRot, Vec2, Mat4, Mat3, Vec4, Vec3, Quat};
+use pest::Parser;
+use pest::error::InputLocation;
+use pest::iterators::Pair;
+use pest::pratt_parser::{Assoc, Op, PrattParser};
 
-#[derive(Debug)]
-enum EvaluationError
+#[derive(Parser)]
+#[grammar = "properties.pest"]
+struct ExpressionParser;
+
+#[derive(Clone, Debug)]
+pub enum EvaluationError
 {
     InvalidIdentifier{id : String},
     DivideByZeroError,
     TypeMismatchError{op: Operator},
     ValueUnderflowError{op: Operator},
     DegenerateMatrixError,
+    //zero length axes and quaternions, or a look_at with nowhere to look
+    DegenerateDirectionError{op: Operator},
     IndexOutOfBoundsError{op: Operator, index : usize},
     InvalidOutputSize{op: Operator, size: f32}
 }
@@ -28,12 +39,68 @@ impl fmt::Display for EvaluationError
             EvaluationError::TypeMismatchError { op, } => write!(f, "Type mismatch at operation \"{}.\"", op,),
             EvaluationError::ValueUnderflowError { op, } => write!(f, "Not enough values to perform operation \"{}.\"", op),
             EvaluationError::DegenerateMatrixError => write!(f, "Given matrix could not be inverted."),
+            EvaluationError::DegenerateDirectionError { op } => write!(f, "Zero length or parallel directions given to operation \"{}.\"", op),
             EvaluationError::IndexOutOfBoundsError { op, index } => write!(f, "Index {} was out of bounds for operation \"{}\"", index, op),
             EvaluationError::InvalidOutputSize { op, size } => write!(f, "Could create object with given output size {}", size),
         };
     }
 }
 
+//byte range into the parsed text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span
+{
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug)]
+pub enum ExpressionParseError
+{
+    EmptyExpression,
+    UnknownToken{ token: String },
+    SyntaxError{ span: Span, message: String },
+    UnknownFunction{ span: Span, name: String },
+    ArgumentCountError{ span: Span, name: String, expected: String, given: usize },
+    InvalidLiteral{ span: Span, message: String },
+    InvalidAccessor{ span: Span, accessor: String },
+}
+
+impl ExpressionParseError
+{
+    pub fn span(&self) -> Option<Span>
+    {
+        return match self 
+        {
+            ExpressionParseError::EmptyExpression | ExpressionParseError::UnknownToken { .. } => None,
+            ExpressionParseError::SyntaxError { span, .. } => Some(*span),
+            ExpressionParseError::UnknownFunction { span, .. } => Some(*span),
+            ExpressionParseError::ArgumentCountError { span, .. } => Some(*span),
+            ExpressionParseError::InvalidLiteral { span, .. } => Some(*span),
+            ExpressionParseError::InvalidAccessor { span, .. } => Some(*span),
+        };
+    }
+}
+
+impl std::error::Error for ExpressionParseError {}
+
+impl fmt::Display for ExpressionParseError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result 
+    {
+        return match self 
+        {
+            ExpressionParseError::EmptyExpression => write!(f, "Expression has no terms."),
+            ExpressionParseError::UnknownToken { token } => write!(f, "\"{}\" is not a number, identifier or operator.", token),
+            ExpressionParseError::SyntaxError { span, message } => write!(f, "Syntax error at {}: {}", span.start, message),
+            ExpressionParseError::UnknownFunction { span, name } => write!(f, "No function named \"{}\" at {}.", name, span.start),
+            ExpressionParseError::ArgumentCountError { span, name, expected, given } => write!(f, "\"{}\" at {} takes {} arguments but was given {}.", name, span.start, expected, given),
+            ExpressionParseError::InvalidLiteral { span, message } => write!(f, "Invalid literal at {}: {}", span.start, message),
+            ExpressionParseError::InvalidAccessor { span, accessor } => write!(f, "\"{}\" at {} should be 1 to 4 components from xyzw or rgba.", accessor, span.start),
+        };
+    }
+}
+
 #[derive(Debug)]
 pub enum AssignmentError
 {
@@ -63,21 +130,37 @@ impl fmt::Display for AssignmentError
 #[derive(Clone)]
 pub struct PropertyGroup
 {
-    values: HashMap<String, Value>
+    values: HashMap<String, Value>,
 }
 
 impl PropertyGroup
 {
+    pub fn new() -> Self
+    {
+        return PropertyGroup { values: HashMap::new() };
+    }
+
+    //unlike set_property this will create the property or change its type
+    pub fn add_property(&mut self, property_name: &str, value: Value)
+    {
+        self.values.insert(property_name.to_string(), value);
+    }
+
+    pub fn get_property(&self, property_name: &str) -> Option<&Value>
+    {
+        return self.values.get(property_name);
+    }
+
     pub fn set_property(&mut self, property_name: &str, in_value : Value) -> Result<(), AssignmentError>
     {
-        let val_opt = self.values.get(property_name);
+        let val_opt = self.values.get_mut(property_name);
         return match val_opt 
         {
             Some(val) => 
             {
                 if in_value.matches_type(val)
                 {
-                    self.values.insert(property_name.to_string(), in_value);
+                    *val = in_value;
                     Result::Ok(())
                 }
                 else
@@ -88,36 +171,309 @@ impl PropertyGroup
             None => Result::Err(AssignmentError::NoSuchPropertyError { property_name: property_name.to_string() }),
         };
     }
+
+    pub fn get_properties(&self) -> impl Iterator<Item=(&String, &Value)>
+    {
+        return self.values.iter();
+    }
+
+}
+
+//anything properties can be read from and assigned to by name, so tweens can run on a PropertyGroup or a shader instance
+pub trait PropertyStore
+{
+    fn get_property(&self, property_name: &str) -> Option<&Value>;
+    fn set_property(&mut self, property_name: &str, in_value: Value) -> Result<(), AssignmentError>;
 }
 
+impl PropertyStore for PropertyGroup
+{
+    fn get_property(&self, property_name: &str) -> Option<&Value>
+    {
+        return PropertyGroup::get_property(self, property_name);
+    }
+
+    fn set_property(&mut self, property_name: &str, in_value: Value) -> Result<(), AssignmentError>
+    {
+        return PropertyGroup::set_property(self, property_name, in_value);
+    }
+}
+
+//results indexed by expression id. kept dense so storing a result never hashes or, once every id has been seen, allocates
+#[derive(Clone)]
 pub struct EvalTable
 {
-    expression_table: HashMap<u16, Value>
+    expression_table: Vec<Option<Value>>,
+    //flags instead of a set so uploads can still walk ids in ascending order
+    changed: Vec<bool>,
 }
 
 impl EvalTable
 {
     pub fn new() -> Self
     {
-        return EvalTable { expression_table: HashMap::new() };
+        return EvalTable { expression_table: vec![], changed: vec![] };
+    }
+
+    //on failure the table keeps the expression's previous value, what to do about it is up to the caller
+    pub fn update_compiled(&mut self, expr: &CompiledExpression, slots: &[Value], stack: &mut EvalStack) -> Result<(), EvaluationError>
+    {
+        let val = expr.evaluate(slots, stack)?;
+        self.set_value(expr.get_id(), val);
+        return Ok(());
     }
 
-    pub fn update(&mut self, expr: &Expression, args: &PropertyGroup)
+    pub fn set_value(&mut self, id: u16, val: Value)
     {
-        if let Result::Ok(val) = expr.evaluate(args)
+        let index = id as usize;
+        if index >= self.expression_table.len()
         {
-            self.expression_table.insert(expr.get_id(), val);
+            self.expression_table.resize(index + 1, None);
+            self.changed.resize(index + 1, false);
         }
+        self.expression_table[index] = Some(val);
+        self.changed[index] = true;
     }
 
-    pub fn get_entries(&self,) -> impl Iterator<Item=(&u16, &Value)>
+    //ids written since the last clear_changed, in ascending order
+    pub fn changed_ids(&self) -> impl Iterator<Item=u16> + '_
     {
-        return self.expression_table.iter();
+        return self.changed.iter().enumerate().filter(|(_, changed)| **changed).map(|(id, _)| id as u16);
+    }
+
+    pub fn clear_changed(&mut self)
+    {
+        self.changed.fill(false);
+    }
+
+    pub fn get_entries(&self,) -> impl Iterator<Item=(u16, &Value)>
+    {
+        return self.expression_table.iter().enumerate().filter_map(|(id, val)| val.as_ref().map(|val| (id as u16, val)));
     }
 
     pub fn get_value(&self, id: u16) -> Option<&Value>
     {
-        return self.expression_table.get(&id);
+        return self.expression_table.get(id as usize).and_then(|val| val.as_ref());
+    }
+}
+
+//fixes every property of a shader to a slot index, so compiled expressions read a slice instead of hashing names
+pub struct PropertySchema
+{
+    names: Vec<String>,
+    slots: HashMap<String, usize>,
+    defaults: Vec<Value>,
+}
+
+impl PropertySchema
+{
+    //slots are assigned in name order so the same properties always give the same layout
+    pub fn new(properties: &PropertyGroup) -> Self
+    {
+        let mut names = properties.values.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        let slots = names.iter().enumerate().map(|(slot, name)| (name.clone(), slot)).collect();
+        let defaults = names.iter().map(|name| properties.values[name]).collect();
+        return PropertySchema { names: names, slots: slots, defaults: defaults };
+    }
+
+    pub fn slot(&self, property_name: &str) -> Option<usize>
+    {
+        return self.slots.get(property_name).copied();
+    }
+
+    pub fn names(&self) -> &[String]
+    {
+        return &self.names;
+    }
+
+    pub fn len(&self) -> usize
+    {
+        return self.names.len();
+    }
+
+    pub fn create_slots(&self) -> Vec<Value>
+    {
+        return self.defaults.clone();
+    }
+
+    //the values the schema was built from, one per slot
+    pub fn defaults(&self) -> &[Value]
+    {
+        return &self.defaults;
+    }
+}
+
+//property values laid out by a schema, with a flag per slot for whatever's been assigned since the last clear_changed.
+//assigning by slot never hashes or allocates, by name it only hashes the name to find the slot
+#[derive(Clone)]
+pub struct PropertySlots
+{
+    schema: Rc<PropertySchema>,
+    values: Vec<Value>,
+    changed: Vec<bool>,
+}
+
+impl PropertySlots
+{
+    //starts at the schema's defaults with nothing changed
+    pub fn new(schema: Rc<PropertySchema>) -> Self
+    {
+        let values = schema.create_slots();
+        let changed = vec![false; values.len()];
+        return PropertySlots { schema: schema, values: values, changed: changed };
+    }
+
+    pub fn schema(&self) -> &PropertySchema
+    {
+        return &self.schema;
+    }
+
+    pub fn values(&self) -> &[Value]
+    {
+        return &self.values;
+    }
+
+    pub fn get_slot(&self, slot: usize) -> Option<&Value>
+    {
+        return self.values.get(slot);
+    }
+
+    pub fn set_slot(&mut self, slot: usize, in_value: Value) -> Result<(), AssignmentError>
+    {
+        return match self.values.get_mut(slot)
+        {
+            Some(val) if in_value.matches_type(val) => 
+            {
+                *val = in_value;
+                self.changed[slot] = true;
+                Ok(())
+            },
+            Some(val) => Err(AssignmentError::TypeMismatchError { property_name: self.schema.names[slot].clone(), expected: *val, given: in_value }),
+            None => Err(AssignmentError::NoSuchPropertyError { property_name: format!("slot {}", slot) }),
+        };
+    }
+
+    //every property name with its current value, in slot order
+    pub fn get_properties(&self) -> impl Iterator<Item=(&String, &Value)>
+    {
+        return self.schema.names.iter().zip(&self.values);
+    }
+
+    //slots assigned since the last clear_changed, in ascending order
+    pub fn changed_slots(&self) -> impl Iterator<Item=usize> + '_
+    {
+        return self.changed.iter().enumerate().filter(|(_, changed)| **changed).map(|(slot, _)| slot);
+    }
+
+    pub fn clear_changed(&mut self)
+    {
+        self.changed.fill(false);
+    }
+}
+
+impl PropertyStore for PropertySlots
+{
+    fn get_property(&self, property_name: &str) -> Option<&Value>
+    {
+        return self.schema.slot(property_name).and_then(|slot| self.values.get(slot));
+    }
+
+    fn set_property(&mut self, property_name: &str, in_value: Value) -> Result<(), AssignmentError>
+    {
+        return match self.schema.slot(property_name)
+        {
+            Some(slot) => self.set_slot(slot, in_value),
+            None => Err(AssignmentError::NoSuchPropertyError { property_name: property_name.to_string() }),
+        };
+    }
+}
+
+//value stack for compiled expressions. reuse one across evaluations and it never reallocates once it has grown to the largest stack_size
+pub struct EvalStack
+{
+    values: Vec<Value>,
+}
+
+impl EvalStack
+{
+    pub fn with_capacity(capacity: usize) -> Self
+    {
+        return EvalStack { values: Vec::with_capacity(capacity) };
+    }
+
+    pub fn capacity(&self) -> usize
+    {
+        return self.values.capacity();
+    }
+}
+
+enum SlotTerm
+{
+    Literal(Value),
+    Slot(usize),
+    Operator(Operator),
+}
+
+//an expression with its variables resolved against a PropertySchema
+pub struct CompiledExpression
+{
+    id: u16,
+    terms: Vec<SlotTerm>,
+    slots: Vec<usize>,
+    stack_size: usize,
+}
+
+impl CompiledExpression
+{
+    pub fn get_id(&self) -> u16
+    {
+        return self.id;
+    }
+
+    //the most values the expression can have on the stack at once
+    pub fn stack_size(&self) -> usize
+    {
+        return self.stack_size;
+    }
+
+    //every slot the expression reads
+    pub fn slots(&self) -> &[usize]
+    {
+        return &self.slots;
+    }
+
+    pub fn evaluate(&self, slots: &[Value], stack: &mut EvalStack) -> Result<Value, EvaluationError>
+    {
+        let val_stack = &mut stack.values;
+        val_stack.clear();
+        //no-op unless the stack was made for a smaller expression
+        val_stack.reserve(self.stack_size);
+        for term in &self.terms
+        {
+            match term 
+            {
+                SlotTerm::Literal(lit) => val_stack.push(*lit),
+                SlotTerm::Slot(slot) => 
+                {
+                    match slots.get(*slot)
+                    {
+                        Some(val) => val_stack.push(*val),
+                        None => return Result::Err(EvaluationError::InvalidIdentifier { id: format!("slot {}", slot) }),
+                    }
+                },
+                SlotTerm::Operator(op) => 
+                {
+                    let result = op.evaluate(val_stack)?;
+                    val_stack.push(result);
+                },
+            }
+        }
+        return match val_stack.pop() 
+        {
+            Some(val) => Result::Ok(val),
+            None => Result::Err(EvaluationError::ValueUnderflowError { op: Operator::Entry }),
+        }
     }
 }
 
@@ -134,6 +490,170 @@ impl Expression
         return self.id;
     }
 
+    //every property name the expression reads
+    pub fn variables(&self) -> impl Iterator<Item=&String>
+    {
+        return self.terms.iter().filter_map
+        (
+            |term| match term
+            {
+                Term::Operand(Operand::Variable(name)) => Some(name),
+                _ => None,
+            }
+        );
+    }
+
+    //resolves variables to slots up front. fails on the first variable the schema doesn't have
+    pub fn compile(&self, schema: &PropertySchema) -> Result<CompiledExpression, EvaluationError>
+    {
+        let mut terms = Vec::with_capacity(self.terms.len());
+        let mut slots = vec![];
+        //operators that take a variable number of values are counted at their fewest, so this can only overestimate
+        let mut depth: usize = 0;
+        let mut stack_size: usize = 0;
+        for term in &self.terms
+        {
+            match term 
+            {
+                Term::Operand(Operand::Literal(lit)) => 
+                {
+                    terms.push(SlotTerm::Literal(*lit));
+                    depth += 1;
+                },
+                Term::Operand(Operand::Variable(var_name)) => 
+                {
+                    let slot = match schema.slot(var_name)
+                    {
+                        Some(slot) => slot,
+                        None => return Result::Err(EvaluationError::InvalidIdentifier { id: var_name.clone() }),
+                    };
+                    if !slots.contains(&slot)
+                    {
+                        slots.push(slot);
+                    }
+                    terms.push(SlotTerm::Slot(slot));
+                    depth += 1;
+                },
+                Term::Operator(op) => 
+                {
+                    terms.push(SlotTerm::Operator(*op));
+                    depth = depth.saturating_sub(op.min_operands()) + 1;
+                },
+            }
+            stack_size = stack_size.max(depth);
+        }
+        return Ok(CompiledExpression { id: self.id, terms: terms, slots: slots, stack_size: stack_size });
+    }
+
+    //infix text like "color(hp / maxhp, 0, 0, 1)". see properties.pest for the syntax
+    pub fn parse(id: u16, input: &str) -> Result<Expression, ExpressionParseError>
+    {
+        let mut pairs = match ExpressionParser::parse(Rule::expression, input)
+        {
+            Ok(pairs) => pairs,
+            Err(err) => 
+            {
+                let span = match err.location
+                {
+                    InputLocation::Pos(pos) => Span { start: pos, end: pos },
+                    InputLocation::Span((start, end)) => Span { start: start, end: end },
+                };
+                return Err(ExpressionParseError::SyntaxError { span: span, message: err.variant.message().to_string() });
+            },
+        };
+        let expr = pairs.next().unwrap().into_inner().next().unwrap();
+        let terms = parse_infix_expr(expr, &expression_pratt_parser())?;
+        return Ok(Expression { id: id, terms: terms });
+    }
+
+    //whitespace separated terms in the order they get pushed onto the stack, so "1 2 3 vec3 normalize"
+    pub fn from_postfix(id: u16, input: &str) -> Result<Expression, ExpressionParseError>
+    {
+        let mut terms = vec![];
+        for token in input.split_whitespace()
+        {
+            let term = match token
+            {
+                "+" => Term::Operator(Operator::BinaryOperator(BinaryOperator::Add)),
+                "-" => Term::Operator(Operator::BinaryOperator(BinaryOperator::Subtract)),
+                "*" => Term::Operator(Operator::BinaryOperator(BinaryOperator::Multiply)),
+                "/" => Term::Operator(Operator::BinaryOperator(BinaryOperator::Divide)),
+                "%" => Term::Operator(Operator::BinaryOperator(BinaryOperator::Modulo)),
+                "^" => Term::Operator(Operator::BinaryOperator(BinaryOperator::Exponent)),
+                "dot" => Term::Operator(Operator::BinaryOperator(BinaryOperator::Dot)),
+                "cross" => Term::Operator(Operator::BinaryOperator(BinaryOperator::Cross)),
+                "==" => Term::Operator(Operator::BinaryOperator(BinaryOperator::Equal)),
+                "!=" => Term::Operator(Operator::BinaryOperator(BinaryOperator::NotEqual)),
+                "<" => Term::Operator(Operator::BinaryOperator(BinaryOperator::Less)),
+                "<=" => Term::Operator(Operator::BinaryOperator(BinaryOperator::LessEqual)),
+                ">" => Term::Operator(Operator::BinaryOperator(BinaryOperator::Greater)),
+                ">=" => Term::Operator(Operator::BinaryOperator(BinaryOperator::GreaterEqual)),
+                "&&" => Term::Operator(Operator::BinaryOperator(BinaryOperator::And)),
+                "||" => Term::Operator(Operator::BinaryOperator(BinaryOperator::Or)),
+                "!" => Term::Operator(Operator::UnaryOperator(UnaryOperator::Not)),
+                "true" => Term::Operand(Operand::Literal(Value::Bool(true))),
+                "false" => Term::Operand(Operand::Literal(Value::Bool(false))),
+                "neg" => Term::Operator(Operator::UnaryOperator(UnaryOperator::Negate)),
+                "sin" => Term::Operator(Operator::UnaryOperator(UnaryOperator::Sine)),
+                "cos" => Term::Operator(Operator::UnaryOperator(UnaryOperator::Cosine)),
+                "tan" => Term::Operator(Operator::UnaryOperator(UnaryOperator::Tangent)),
+                "log" => Term::Operator(Operator::UnaryOperator(UnaryOperator::Log)),
+                "normalize" => Term::Operator(Operator::UnaryOperator(UnaryOperator::Normalize)),
+                "inverse" => Term::Operator(Operator::UnaryOperator(UnaryOperator::Inverse)),
+                "transpose" => Term::Operator(Operator::UnaryOperator(UnaryOperator::Transpose)),
+                "vec2" => Term::Operator(Operator::CreateVector2),
+                "vec3" => Term::Operator(Operator::CreateVector3),
+                "color" => Term::Operator(Operator::CreateColor),
+                "vec4" => Term::Operator(Operator::CreateVector4),
+                "select" => Term::Operator(Operator::Select),
+                "int" => Term::Operator(Operator::UnaryOperator(UnaryOperator::ToInt)),
+                "float" => Term::Operator(Operator::UnaryOperator(UnaryOperator::ToScalar)),
+                "quat" => Term::Operator(Operator::CreateQuaternion),
+                "mat3" => Term::Operator(Operator::CreateMatrix3),
+                "mat4" => Term::Operator(Operator::CreateMatrix4),
+                "row" => Term::Operator(Operator::Row),
+                "col" => Term::Operator(Operator::Column),
+                "entry" => Term::Operator(Operator::Entry),
+                "swizzle2" => Term::Operator(Operator::Swizzle2),
+                "swizzle3" => Term::Operator(Operator::Swizzle3),
+                "swizzle4" => Term::Operator(Operator::Swizzle4),
+                "quat_axis_angle" => Term::Operator(Operator::CreateQuaternion),
+                "quat_euler" => Term::Operator(Operator::UnaryOperator(UnaryOperator::FromEuler)),
+                "euler" => Term::Operator(Operator::UnaryOperator(UnaryOperator::ToEuler)),
+                "translation_of" => Term::Operator(Operator::UnaryOperator(UnaryOperator::Translation)),
+                "rotation_of" => Term::Operator(Operator::UnaryOperator(UnaryOperator::Rotation)),
+                "scale_of" => Term::Operator(Operator::UnaryOperator(UnaryOperator::Scale)),
+                "trs" => Term::Operator(Operator::ComposeTransform),
+                "look_at" => Term::Operator(Operator::LookAt),
+                "slerp" => Term::Operator(Operator::Slerp),
+                "nlerp" => Term::Operator(Operator::Nlerp),
+                "transform_point" => Term::Operator(Operator::TransformPoint),
+                "transform_vector" => Term::Operator(Operator::TransformVector),
+                _ => 
+                {
+                    if let Ok(num) = token.parse::<f32>()
+                    {
+                        Term::Operand(Operand::Literal(Value::Scalar(num)))
+                    }
+                    else if token.chars().next().map_or(false, |c| c.is_alphabetic() || c == '_') && token.chars().all(|c| c.is_alphanumeric() || c == '_')
+                    {
+                        Term::Operand(Operand::Variable(token.to_string()))
+                    }
+                    else
+                    {
+                        return Err(ExpressionParseError::UnknownToken { token: token.to_string() });
+                    }
+                },
+            };
+            terms.push(term);
+        }
+        if terms.is_empty()
+        {
+            return Err(ExpressionParseError::EmptyExpression);
+        }
+        return Ok(Expression { id: id, terms: terms });
+    }
+
     pub fn evaluate(&self, context: &PropertyGroup) -> Result<Value, EvaluationError>
     {
         let mut val_stack: Vec<Value> = vec![];
@@ -176,13 +696,273 @@ impl Expression
     }
 }
 
+//lowest precedence first. unary minus sits under ^ so -x^2 is -(x^2)
+fn expression_pratt_parser() -> PrattParser<Rule>
+{
+    return PrattParser::new()
+        .op(Op::infix(Rule::or, Assoc::Left))
+        .op(Op::infix(Rule::and, Assoc::Left))
+        .op(Op::infix(Rule::eq, Assoc::Left) | Op::infix(Rule::neq, Assoc::Left))
+        .op(Op::infix(Rule::lt, Assoc::Left) | Op::infix(Rule::lte, Assoc::Left) | Op::infix(Rule::gt, Assoc::Left) | Op::infix(Rule::gte, Assoc::Left))
+        .op(Op::infix(Rule::add, Assoc::Left) | Op::infix(Rule::sub, Assoc::Left))
+        .op(Op::infix(Rule::mul, Assoc::Left) | Op::infix(Rule::div, Assoc::Left) | Op::infix(Rule::modulo, Assoc::Left))
+        .op(Op::prefix(Rule::neg) | Op::prefix(Rule::not))
+        .op(Op::infix(Rule::pow, Assoc::Right))
+        .op(Op::postfix(Rule::accessor));
+}
+
+fn parse_infix_expr(pair: Pair<Rule>, pratt: &PrattParser<Rule>) -> Result<Vec<Term>, ExpressionParseError>
+{
+    return pratt
+        .map_primary(|primary| parse_primary(primary, pratt))
+        .map_prefix
+        (
+            |op, rhs| 
+            {
+                let uop = if op.as_rule() == Rule::not { UnaryOperator::Not } else { UnaryOperator::Negate };
+                let mut terms = rhs?;
+                terms.push(Term::Operator(Operator::UnaryOperator(uop)));
+                Ok(terms)
+            }
+        )
+        .map_postfix(|lhs, accessor| parse_accessor(lhs?, accessor))
+        .map_infix
+        (
+            |lhs, op, rhs| 
+            {
+                let bop = match op.as_rule()
+                {
+                    Rule::add => BinaryOperator::Add,
+                    Rule::sub => BinaryOperator::Subtract,
+                    Rule::mul => BinaryOperator::Multiply,
+                    Rule::div => BinaryOperator::Divide,
+                    Rule::modulo => BinaryOperator::Modulo,
+                    Rule::eq => BinaryOperator::Equal,
+                    Rule::neq => BinaryOperator::NotEqual,
+                    Rule::lt => BinaryOperator::Less,
+                    Rule::lte => BinaryOperator::LessEqual,
+                    Rule::gt => BinaryOperator::Greater,
+                    Rule::gte => BinaryOperator::GreaterEqual,
+                    Rule::and => BinaryOperator::And,
+                    Rule::or => BinaryOperator::Or,
+                    _ => BinaryOperator::Exponent,
+                };
+                let mut terms = lhs?;
+                terms.append(&mut rhs?);
+                terms.push(Term::Operator(Operator::BinaryOperator(bop)));
+                Ok(terms)
+            }
+        )
+        .parse(pair.into_inner());
+}
+
+fn parse_primary(pair: Pair<Rule>, pratt: &PrattParser<Rule>) -> Result<Vec<Term>, ExpressionParseError>
+{
+    let span = pair_span(&pair);
+    return match pair.as_rule()
+    {
+        Rule::number => Ok(vec![Term::Operand(Operand::Literal(Value::Scalar(parse_number(&pair)?)))]),
+        Rule::constant => 
+        {
+            let val = if pair.as_str() == "PI" { std::f32::consts::PI } else { std::f32::consts::E };
+            Ok(vec![Term::Operand(Operand::Literal(Value::Scalar(val)))])
+        },
+        Rule::boolean => Ok(vec![Term::Operand(Operand::Literal(Value::Bool(pair.as_str() == "true")))]),
+        Rule::ident => Ok(vec![Term::Operand(Operand::Variable(pair.as_str().to_string()))]),
+        Rule::expr => parse_infix_expr(pair, pratt),
+        Rule::typed_literal => Ok(vec![Term::Operand(Operand::Literal(parse_typed_literal(pair)?))]),
+        Rule::call => parse_call(pair, pratt),
+        _ => Err(ExpressionParseError::SyntaxError { span: span, message: format!("unexpected {:?}", pair.as_rule()) }),
+    };
+}
+
+fn parse_number(pair: &Pair<Rule>) -> Result<f32, ExpressionParseError>
+{
+    return pair.as_str().parse::<f32>().map_err(|_| ExpressionParseError::InvalidLiteral { span: pair_span(pair), message: format!("\"{}\" is not a number", pair.as_str()) });
+}
+
+fn parse_typed_literal(pair: Pair<Rule>) -> Result<Value, ExpressionParseError>
+{
+    let span = pair_span(&pair);
+    let mut inner = pair.into_inner();
+    let first = inner.next().unwrap();
+    if first.as_rule() == Rule::hex_color
+    {
+        let hex = &first.as_str()[1..];
+        let mut channels = [1.0; 4];
+        for i in 0..hex.len() / 2
+        {
+            channels[i] = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap() as f32 / 255.0;
+        }
+        return Ok(Value::Color(Vec4::from_array(channels)));
+    }
+    let nums = inner.map(|num| parse_number(&num)).collect::<Result<Vec<f32>, _>>()?;
+    let expected = match first.as_str()
+    {
+        "vec2" => 2,
+        "vec3" => 3,
+        "color" | "quat" | "vec4" => 4,
+        "mat3" => 9,
+        _ => 16,
+    };
+    if nums.len() != expected
+    {
+        return Err(ExpressionParseError::InvalidLiteral { span: span, message: format!("{} literals need {} components, found {}", first.as_str(), expected, nums.len()) });
+    }
+    return Ok
+    (
+        match first.as_str()
+        {
+            "vec2" => Value::Vector2(Vec2::from_slice(&nums)),
+            "vec3" => Value::Vector3(Vec3::from_slice(&nums)),
+            "color" => Value::Color(Vec4::from_slice(&nums)),
+            "vec4" => Value::Vector4(Vec4::from_slice(&nums)),
+            "quat" => Value::Quaternion(Quat::from_slice(&nums)),
+            "mat3" => Value::Matrix3(Mat3::from_cols_slice(&nums)),
+            _ => Value::Matrix4(Mat4::from_cols_slice(&nums)),
+        }
+    );
+}
+
+//arguments go on the stack in the order each operator pops them back off
+fn parse_call(pair: Pair<Rule>, pratt: &PrattParser<Rule>) -> Result<Vec<Term>, ExpressionParseError>
+{
+    let span = pair_span(&pair);
+    let mut inner = pair.into_inner();
+    let name = inner.next().unwrap().as_str().to_string();
+    let mut args = inner.map(|arg| parse_infix_expr(arg, pratt)).collect::<Result<Vec<Vec<Term>>, _>>()?;
+    let arg_count_error = |expected: &str, given: usize| ExpressionParseError::ArgumentCountError { span: span, name: name.clone(), expected: expected.to_string(), given: given };
+    let op = match name.as_str()
+    {
+        "vec2" => (2, Operator::CreateVector2),
+        "vec3" => (3, Operator::CreateVector3),
+        "color" => (4, Operator::CreateColor),
+        "vec4" => (4, Operator::CreateVector4),
+        //condition, value if true, value if false
+        "select" => (3, Operator::Select),
+        "int" => (1, Operator::UnaryOperator(UnaryOperator::ToInt)),
+        "float" => (1, Operator::UnaryOperator(UnaryOperator::ToScalar)),
+        //axis, angle
+        "quat" => (2, Operator::CreateQuaternion),
+        //translation, rotation, scale
+        "mat3" => (3, Operator::CreateMatrix3),
+        "mat4" => (3, Operator::CreateMatrix4),
+        //value, index
+        "row" => (2, Operator::Row),
+        "col" => (2, Operator::Column),
+        "entry" => (2, Operator::Entry),
+        "dot" => (2, Operator::BinaryOperator(BinaryOperator::Dot)),
+        "cross" => (2, Operator::BinaryOperator(BinaryOperator::Cross)),
+        "sin" => (1, Operator::UnaryOperator(UnaryOperator::Sine)),
+        "cos" => (1, Operator::UnaryOperator(UnaryOperator::Cosine)),
+        "tan" => (1, Operator::UnaryOperator(UnaryOperator::Tangent)),
+        "log" => (1, Operator::UnaryOperator(UnaryOperator::Log)),
+        "normalize" => (1, Operator::UnaryOperator(UnaryOperator::Normalize)),
+        "inverse" => (1, Operator::UnaryOperator(UnaryOperator::Inverse)),
+        "transpose" => (1, Operator::UnaryOperator(UnaryOperator::Transpose)),
+        //axis, angle. same as quat
+        "quat_axis_angle" => (2, Operator::CreateQuaternion),
+        "quat_euler" => (1, Operator::UnaryOperator(UnaryOperator::FromEuler)),
+        "euler" => (1, Operator::UnaryOperator(UnaryOperator::ToEuler)),
+        "translation_of" => (1, Operator::UnaryOperator(UnaryOperator::Translation)),
+        "rotation_of" => (1, Operator::UnaryOperator(UnaryOperator::Rotation)),
+        "scale_of" => (1, Operator::UnaryOperator(UnaryOperator::Scale)),
+        //translation, rotation, scale. unlike mat3 and mat4 the scale can be a single number
+        "trs" => (3, Operator::ComposeTransform),
+        //eye, target, up
+        "look_at" => (3, Operator::LookAt),
+        //from, to, t
+        "slerp" => (3, Operator::Slerp),
+        "nlerp" => (3, Operator::Nlerp),
+        //transform, point or direction
+        "transform_point" => (2, Operator::TransformPoint),
+        "transform_vector" => (2, Operator::TransformVector),
+        //value, then 2 to 4 component indices
+        "swizzle" => 
+        {
+            if args.len() < 3 || args.len() > 5
+            {
+                return Err(arg_count_error("3 to 5", args.len()));
+            }
+            let data = args.remove(0);
+            let size = args.len();
+            let mut terms = args.into_iter().flatten().collect::<Vec<_>>();
+            terms.push(Term::Operand(Operand::Literal(Value::Scalar(size as f32))));
+            terms.extend(data);
+            terms.push(Term::Operator(swizzle_operator(size)));
+            return Ok(terms);
+        },
+        _ => return Err(ExpressionParseError::UnknownFunction { span: span, name: name }),
+    };
+    if args.len() != op.0
+    {
+        return Err(arg_count_error(&op.0.to_string(), args.len()));
+    }
+    let mut terms = args.into_iter().flatten().collect::<Vec<_>>();
+    terms.push(Term::Operator(op.1));
+    return Ok(terms);
+}
+
+//.x becomes an entry lookup, anything longer is a swizzle
+fn parse_accessor(mut lhs: Vec<Term>, accessor: Pair<Rule>) -> Result<Vec<Term>, ExpressionParseError>
+{
+    let components = &accessor.as_str()[1..];
+    let invalid = || ExpressionParseError::InvalidAccessor { span: pair_span(&accessor), accessor: accessor.as_str().to_string() };
+    let indices = if components.chars().all(|c| "xyzw".contains(c)) 
+    {
+        components.chars().map(|c| "xyzw".find(c).unwrap()).collect::<Vec<_>>()
+    }
+    else if components.chars().all(|c| "rgba".contains(c))
+    {
+        components.chars().map(|c| "rgba".find(c).unwrap()).collect::<Vec<_>>()
+    }
+    else
+    {
+        return Err(invalid());
+    };
+    if indices.len() > 4
+    {
+        return Err(invalid());
+    }
+    if indices.len() == 1
+    {
+        lhs.push(Term::Operand(Operand::Literal(Value::Scalar(indices[0] as f32))));
+        lhs.push(Term::Operator(Operator::Entry));
+        return Ok(lhs);
+    }
+    let mut terms = indices.iter().map(|i| Term::Operand(Operand::Literal(Value::Scalar(*i as f32)))).collect::<Vec<_>>();
+    terms.push(Term::Operand(Operand::Literal(Value::Scalar(indices.len() as f32))));
+    terms.append(&mut lhs);
+    terms.push(Term::Operator(swizzle_operator(indices.len())));
+    return Ok(terms);
+}
+
+fn swizzle_operator(size: usize) -> Operator
+{
+    return match size
+    {
+        2 => Operator::Swizzle2,
+        3 => Operator::Swizzle3,
+        _ => Operator::Swizzle4,
+    };
+}
+
+fn pair_span(pair: &Pair<Rule>) -> Span
+{
+    return Span { start: pair.as_span().start(), end: pair.as_span().end() };
+}
+
 enum Term
 {
     Operand(Operand),
     Operator(Operator),
 }
 
-#[derive(Clone, Copy, Debug)]
+//coercions, applied by Operator::evaluate and nowhere else:
+//  Int becomes Scalar when the value it's combined with isn't also an Int, including in constructors
+//  Vector4 becomes Color when the value it's combined with is a Color
+//Bool never turns into a number. use int() or float() for that
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Value
 {
     Scalar(f32),
@@ -192,25 +972,67 @@ pub enum Value
     Matrix4(Mat4),
     Color(Vec4),
     Quaternion(Quat),
+    Bool(bool),
+    Int(i32),
+    Vector4(Vec4),
 }
 
 impl Value
 {
-    fn val_into_float_list(&self) -> Vec<f32>
+    //fills the front of a fixed buffer instead of returning a vec so operators don't allocate. returns the component count
+    fn val_into_float_list(&self, dest: &mut [f32; 16]) -> usize
     {
         match self 
         {
-            Value::Scalar(s_val) => return vec![s_val.clone()],
-            Value::Vector2(v2_val) => return vec![v2_val.x, v2_val.y],
-            Value::Vector3(v3_val) => return vec![v3_val.x, v3_val.y, v3_val.z],
-            Value::Color(v4_val) => return vec![v4_val.x, v4_val.y, v4_val.z, v4_val.z],
-            Value::Quaternion(q_val) => return vec![q_val.x, q_val.y, q_val.z, q_val.z],
-            Value::Matrix3(m3_val) => return m3_val.to_cols_array().to_vec(),
-            Value::Matrix4(m4_val) => return m4_val.to_cols_array().to_vec(),
+            Value::Scalar(s_val) => 
+            {
+                dest[0] = *s_val;
+                return 1;
+            },
+            Value::Vector2(v2_val) => 
+            {
+                dest[..2].copy_from_slice(&[v2_val.x, v2_val.y]);
+                return 2;
+            },
+            Value::Vector3(v3_val) => 
+            {
+                dest[..3].copy_from_slice(&[v3_val.x, v3_val.y, v3_val.z]);
+                return 3;
+            },
+            Value::Color(v4_val) | Value::Vector4(v4_val) => 
+            {
+                dest[..4].copy_from_slice(&v4_val.to_array());
+                return 4;
+            },
+            Value::Quaternion(q_val) => 
+            {
+                dest[..4].copy_from_slice(&q_val.to_array());
+                return 4;
+            },
+            Value::Bool(b_val) => 
+            {
+                dest[0] = if *b_val { 1.0 } else { 0.0 };
+                return 1;
+            },
+            Value::Int(i_val) => 
+            {
+                dest[0] = *i_val as f32;
+                return 1;
+            },
+            Value::Matrix3(m3_val) => 
+            {
+                dest[..9].copy_from_slice(&m3_val.to_cols_array());
+                return 9;
+            },
+            Value::Matrix4(m4_val) => 
+            {
+                *dest = m4_val.to_cols_array();
+                return 16;
+            },
         }
     }
 
-    fn type_name(&self) -> &str
+    pub fn type_name(&self) -> &str
     {
         return match self 
         {
@@ -221,15 +1043,103 @@ impl Value
             Value::Matrix4(_) => "Matrix4",
             Value::Color(_) => "Color",
             Value::Quaternion(_) => "Quaternion",
+            Value::Bool(_) => "Bool",
+            Value::Int(_) => "Int",
+            Value::Vector4(_) => "Vector4",
+        };
+    }
+
+    //(alignment, size) in bytes under std140
+    pub fn std140_layout(&self) -> (usize, usize)
+    {
+        return match self 
+        {
+            Value::Scalar(_) | Value::Bool(_) | Value::Int(_) => (4, 4),
+            Value::Vector2(_) => (8, 8),
+            Value::Vector3(_) => (16, 12),
+            Value::Color(_) | Value::Quaternion(_) | Value::Vector4(_) => (16, 16),
+            //columns are padded out to vec4s
+            Value::Matrix3(_) => (16, 48),
+            Value::Matrix4(_) => (16, 64),
         };
     }
 
+    //appends the value to a uniform buffer, padding the buffer to the value's alignment first. bools are written as 0 or 1 words
+    pub fn write_std140(&self, dest: &mut Vec<u8>)
+    {
+        let (alignment, _) = self.std140_layout();
+        dest.resize(dest.len().next_multiple_of(alignment), 0);
+        match self 
+        {
+            Value::Bool(b_val) => dest.extend_from_slice(&(*b_val as u32).to_ne_bytes()),
+            Value::Int(i_val) => dest.extend_from_slice(&i_val.to_ne_bytes()),
+            Value::Matrix3(m3_val) => 
+            {
+                for col in m3_val.to_cols_array_2d()
+                {
+                    for component in [col[0], col[1], col[2], 0.0]
+                    {
+                        dest.extend_from_slice(&component.to_ne_bytes());
+                    }
+                }
+            },
+            _ => 
+            {
+                let mut components = [0.0_f32; 16];
+                let num_components = self.val_into_float_list(&mut components);
+                for component in &components[..num_components]
+                {
+                    dest.extend_from_slice(&component.to_ne_bytes());
+                }
+            },
+        }
+    }
+
     fn matches_type(&self, other: &Value) -> bool
     {
         return std::mem::discriminant(self) == std::mem::discriminant(other);
     }
 }
 
+impl fmt::Display for Value
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result 
+    {
+        return match self 
+        {
+            Value::Scalar(s_val) => write!(f, "{}", s_val),
+            Value::Vector2(v2_val) => write!(f, "vec2({}, {})", v2_val.x, v2_val.y),
+            Value::Vector3(v3_val) => write!(f, "vec3({}, {}, {})", v3_val.x, v3_val.y, v3_val.z),
+            Value::Color(c_val) => write!(f, "color({}, {}, {}, {})", c_val.x, c_val.y, c_val.z, c_val.w),
+            Value::Quaternion(q_val) => write!(f, "quat({}, {}, {}, {})", q_val.x, q_val.y, q_val.z, q_val.w),
+            Value::Vector4(v4_val) => write!(f, "vec4({}, {}, {}, {})", v4_val.x, v4_val.y, v4_val.z, v4_val.w),
+            Value::Bool(b_val) => write!(f, "{}", b_val),
+            Value::Int(i_val) => write!(f, "{}", i_val),
+            //one row per line so it reads like it would on paper
+            Value::Matrix3(m3_val) => 
+            {
+                write!(f, "mat3")?;
+                for i in 0..3
+                {
+                    let row = m3_val.row(i);
+                    write!(f, "\n  [{}, {}, {}]", row.x, row.y, row.z)?;
+                }
+                Ok(())
+            },
+            Value::Matrix4(m4_val) => 
+            {
+                write!(f, "mat4")?;
+                for i in 0..4
+                {
+                    let row = m4_val.row(i);
+                    write!(f, "\n  [{}, {}, {}, {}]", row.x, row.y, row.z, row.w)?;
+                }
+                Ok(())
+            },
+        };
+    }
+}
+
 enum Operand
 {
     Literal(Value),
@@ -250,9 +1160,22 @@ enum Operator
     Entry,
     CreateQuaternion,
     CreateColor,
+    CreateVector4,
     Swizzle2,
     Swizzle3,
     Swizzle4,
+    //condition, value if true, value if false
+    Select,
+    //translation, rotation, scale
+    ComposeTransform,
+    //eye, target, up
+    LookAt,
+    //from, to, t
+    Slerp,
+    Nlerp,
+    //transform, then the point or direction it's applied to
+    TransformPoint,
+    TransformVector,
 }
 
 impl std::fmt::Display for Operator
@@ -273,6 +1196,14 @@ impl std::fmt::Display for Operator
                     BinaryOperator::Exponent => write!(f, "Exponent"),
                     BinaryOperator::Dot => write!(f, "Dot"),
                     BinaryOperator::Cross => write!(f, "Cross"),
+                    BinaryOperator::Equal => write!(f, "Equal"),
+                    BinaryOperator::NotEqual => write!(f, "NotEqual"),
+                    BinaryOperator::Less => write!(f, "Less"),
+                    BinaryOperator::LessEqual => write!(f, "LessEqual"),
+                    BinaryOperator::Greater => write!(f, "Greater"),
+                    BinaryOperator::GreaterEqual => write!(f, "GreaterEqual"),
+                    BinaryOperator::And => write!(f, "And"),
+                    BinaryOperator::Or => write!(f, "Or"),
                 }
             },
             Operator::UnaryOperator(uop) => 
@@ -287,6 +1218,14 @@ impl std::fmt::Display for Operator
                     UnaryOperator::Normalize => write!(f, "Normalize"),
                     UnaryOperator::Inverse => write!(f, "Inverse"),
                     UnaryOperator::Transpose => write!(f, "Transpose"),
+                    UnaryOperator::Not => write!(f, "Not"),
+                    UnaryOperator::ToInt => write!(f, "ToInt"),
+                    UnaryOperator::ToScalar => write!(f, "ToScalar"),
+                    UnaryOperator::Translation => write!(f, "Translation"),
+                    UnaryOperator::Rotation => write!(f, "Rotation"),
+                    UnaryOperator::Scale => write!(f, "Scale"),
+                    UnaryOperator::FromEuler => write!(f, "FromEuler"),
+                    UnaryOperator::ToEuler => write!(f, "ToEuler"),
                 }
             },
             Operator::CreateVector2 => write!(f, "CreateVector2"),
@@ -298,9 +1237,17 @@ impl std::fmt::Display for Operator
             Operator::Entry => write!(f, "Entry"),
             Operator::CreateQuaternion => write!(f, "CreateQuaternion"),
             Operator::CreateColor => write!(f, "CreateColor"),
+            Operator::CreateVector4 => write!(f, "CreateVector4"),
             Operator::Swizzle2 => write!(f, "Swizzle2"),
             Operator::Swizzle3 => write!(f, "Swizzle3"),
             Operator::Swizzle4 => write!(f, "Swizzle4"),
+            Operator::Select => write!(f, "Select"),
+            Operator::ComposeTransform => write!(f, "ComposeTransform"),
+            Operator::LookAt => write!(f, "LookAt"),
+            Operator::Slerp => write!(f, "Slerp"),
+            Operator::Nlerp => write!(f, "Nlerp"),
+            Operator::TransformPoint => write!(f, "TransformPoint"),
+            Operator::TransformVector => write!(f, "TransformVector"),
         }
     }
 }
@@ -316,6 +1263,17 @@ enum UnaryOperator
     Normalize,
     Inverse,
     Transpose,
+    Not,
+    //truncates toward zero
+    ToInt,
+    ToScalar,
+    //the parts of a mat3 or mat4 that trs builds
+    Translation,
+    Rotation,
+    Scale,
+    //euler angles are x, y and z in radians, turning about x first, then the new y, then the new z
+    FromEuler,
+    ToEuler,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -329,10 +1287,33 @@ enum BinaryOperator
     Exponent,
     Dot,
     Cross,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
 }
 
 impl Operator
 {
+    //values popped before the result is pushed. the constructors and swizzles can take more
+    fn min_operands(&self) -> usize
+    {
+        return match self 
+        {
+            Operator::UnaryOperator(_) => 1,
+            Operator::CreateVector2 | Operator::CreateVector3 | Operator::CreateColor | Operator::CreateVector4 => 1,
+            Operator::BinaryOperator(_) | Operator::Row | Operator::Column | Operator::Entry | Operator::CreateQuaternion => 2,
+            Operator::Swizzle2 | Operator::Swizzle3 | Operator::Swizzle4 => 2,
+            Operator::CreateMatrix3 | Operator::CreateMatrix4 | Operator::Select => 3,
+            Operator::TransformPoint | Operator::TransformVector => 2,
+            Operator::ComposeTransform | Operator::LookAt | Operator::Slerp | Operator::Nlerp => 3,
+        };
+    }
+
     //remember the value at the top of the stack is the one that was most recently evaluated, so arguments are in reverse order
     fn evaluate(&self, value_stack : &mut Vec<Value>) -> Result<Value, EvaluationError>
     {
@@ -346,8 +1327,23 @@ impl Operator
                 {
                     return Result::Err(EvaluationError::ValueUnderflowError{ op: Operator::BinaryOperator(*bop), });
                 }
-                let rhs_val = rhs.unwrap();
-                let lhs_val = lhs.unwrap();
+                let (lhs_val, rhs_val) = coerce_operands(lhs.unwrap(), rhs.unwrap());
+                match bop
+                {
+                    BinaryOperator::Equal | BinaryOperator::NotEqual | BinaryOperator::Less | BinaryOperator::LessEqual 
+                    | BinaryOperator::Greater | BinaryOperator::GreaterEqual | BinaryOperator::And | BinaryOperator::Or => 
+                    {
+                        return evaluate_comparison(*bop, lhs_val, rhs_val);
+                    },
+                    _ => (),
+                }
+                match (lhs_val, rhs_val)
+                {
+                    (Value::Int(i_lhs), Value::Int(i_rhs)) => return evaluate_int(*bop, i_lhs, i_rhs),
+                    (Value::Vector4(_), _) | (_, Value::Vector4(_)) => return evaluate_vector4(*bop, lhs_val, rhs_val),
+                    (Value::Bool(_), _) | (_, Value::Bool(_)) => return Result::Err(EvaluationError::TypeMismatchError{ op: Operator::BinaryOperator(*bop), }),
+                    _ => (),
+                }
                 match bop
                 {
                     BinaryOperator::Add => 
@@ -416,6 +1412,7 @@ impl Operator
                                     _ => return Result::Err(EvaluationError::TypeMismatchError{ op: Operator::BinaryOperator(*bop), }),
                                 }
                             },
+                            _ => return Result::Err(EvaluationError::TypeMismatchError{ op: Operator::BinaryOperator(*bop), }), //handled above
                         }
                     },
                     BinaryOperator::Subtract => 
@@ -481,6 +1478,7 @@ impl Operator
                                     _ => return Result::Err(EvaluationError::TypeMismatchError{ op: Operator::BinaryOperator(*bop), }),
                                 }
                             },
+                            _ => return Result::Err(EvaluationError::TypeMismatchError{ op: Operator::BinaryOperator(*bop), }), //handled above
                         }
                     },
                     BinaryOperator::Multiply => 
@@ -498,6 +1496,7 @@ impl Operator
                                     Value::Matrix4(m4_rhs)  => return Result::Ok(Value::Matrix4(s_lhs * m4_rhs)),
                                     Value::Color(c_rhs)  => return Result::Ok(Value::Color(s_lhs * c_rhs)),
                                     Value::Quaternion(q_rhs)  => return Result::Ok(Value::Quaternion(q_rhs * s_lhs)),
+                                    _ => return Result::Err(EvaluationError::TypeMismatchError{ op: Operator::BinaryOperator(*bop), }), //handled above
                                 }
                             },
                             Value::Vector2(v2_lhs) => 
@@ -555,6 +1554,7 @@ impl Operator
                                     _ => return Result::Err(EvaluationError::TypeMismatchError{ op: Operator::BinaryOperator(*bop), }),
                                 }
                             },
+                            _ => return Result::Err(EvaluationError::TypeMismatchError{ op: Operator::BinaryOperator(*bop), }), //handled above
                         }
                     },
                     BinaryOperator::Divide => 
@@ -715,6 +1715,7 @@ impl Operator
                             _ => return Result::Err(EvaluationError::TypeMismatchError { op: Operator::BinaryOperator(*bop), })
                         }
                     },
+                    _ => return Result::Err(EvaluationError::TypeMismatchError { op: Operator::BinaryOperator(*bop), }), //handled above
                 }
             },
             Operator::UnaryOperator(uop) =>
@@ -738,6 +1739,9 @@ impl Operator
                             Value::Matrix4(m4_val) => return Result::Ok(Value::Matrix4(-m4_val)),
                             Value::Color(c_val) => return Result::Ok(Value::Color(-c_val)),
                             Value::Quaternion(q_val) => return Result::Ok(Value::Quaternion(-q_val)),
+                            Value::Int(i_val) => return Result::Ok(Value::Int(i_val.wrapping_neg())),
+                            Value::Vector4(v4_val) => return Result::Ok(Value::Vector4(-v4_val)),
+                            Value::Bool(_) => return Result::Err(EvaluationError::TypeMismatchError { op: Operator::UnaryOperator(*uop), }),
                         }
                     },
                     UnaryOperator::Sine => 
@@ -778,6 +1782,7 @@ impl Operator
                         {
                             Value::Vector2(v2_val) => return Result::Ok(Value::Vector2(v2_val.normalize())),
                             Value::Vector3(v3_val) => return Result::Ok(Value::Vector3(v3_val.normalize())),
+                            Value::Vector4(v4_val) => return Result::Ok(Value::Vector4(v4_val.normalize())),
                             _ => Result::Err(EvaluationError::TypeMismatchError { op: Operator::UnaryOperator(*uop), }),
                         }
                     },
@@ -830,32 +1835,123 @@ impl Operator
                             _ => return Result::Err(EvaluationError::TypeMismatchError { op: Operator::UnaryOperator(*uop), }),
                         }
                     },
+                    UnaryOperator::Not => 
+                    {
+                        match val
+                        {
+                            Value::Bool(b_val) => return Result::Ok(Value::Bool(!b_val)),
+                            _ => return Result::Err(EvaluationError::TypeMismatchError { op: Operator::UnaryOperator(*uop), }),
+                        }
+                    },
+                    UnaryOperator::ToInt => 
+                    {
+                        match val
+                        {
+                            Value::Int(i_val) => return Result::Ok(Value::Int(i_val)),
+                            Value::Scalar(s_val) => return Result::Ok(Value::Int(s_val as i32)),
+                            Value::Bool(b_val) => return Result::Ok(Value::Int(b_val as i32)),
+                            _ => return Result::Err(EvaluationError::TypeMismatchError { op: Operator::UnaryOperator(*uop), }),
+                        }
+                    },
+                    UnaryOperator::ToScalar => 
+                    {
+                        match val
+                        {
+                            Value::Scalar(s_val) => return Result::Ok(Value::Scalar(s_val)),
+                            Value::Int(i_val) => return Result::Ok(Value::Scalar(i_val as f32)),
+                            Value::Bool(b_val) => return Result::Ok(Value::Scalar(if b_val { 1.0 } else { 0.0 })),
+                            _ => return Result::Err(EvaluationError::TypeMismatchError { op: Operator::UnaryOperator(*uop), }),
+                        }
+                    },
+                    UnaryOperator::Translation | UnaryOperator::Rotation | UnaryOperator::Scale => 
+                    {
+                        match val
+                        {
+                            Value::Matrix3(m3_val) => 
+                            {
+                                if m3_val.determinant() == 0.0
+                                {
+                                    return Result::Err(EvaluationError::DegenerateMatrixError);
+                                }
+                                let (scale, angle, loc) = Affine2::from_mat3(m3_val).to_scale_angle_translation();
+                                return match uop
+                                {
+                                    UnaryOperator::Translation => Result::Ok(Value::Vector2(loc)),
+                                    UnaryOperator::Rotation => Result::Ok(Value::Scalar(angle)),
+                                    _ => Result::Ok(Value::Vector2(scale)),
+                                };
+                            },
+                            Value::Matrix4(m4_val) => 
+                            {
+                                if m4_val.determinant() == 0.0
+                                {
+                                    return Result::Err(EvaluationError::DegenerateMatrixError);
+                                }
+                                let (scale, rot, loc) = m4_val.to_scale_rotation_translation();
+                                return match uop
+                                {
+                                    UnaryOperator::Translation => Result::Ok(Value::Vector3(loc)),
+                                    UnaryOperator::Rotation => Result::Ok(Value::Quaternion(rot)),
+                                    _ => Result::Ok(Value::Vector3(scale)),
+                                };
+                            },
+                            _ => return Result::Err(EvaluationError::TypeMismatchError { op: Operator::UnaryOperator(*uop), }),
+                        }
+                    },
+                    UnaryOperator::FromEuler => 
+                    {
+                        match val
+                        {
+                            Value::Vector3(v3_val) => return Result::Ok(Value::Quaternion(Quat::from_euler(EulerRot::XYZ, v3_val.x, v3_val.y, v3_val.z))),
+                            _ => return Result::Err(EvaluationError::TypeMismatchError { op: Operator::UnaryOperator(*uop), }),
+                        }
+                    },
+                    UnaryOperator::ToEuler => 
+                    {
+                        match val
+                        {
+                            Value::Quaternion(q_val) => 
+                            {
+                                let (x, y, z) = unit_quaternion(q_val, Operator::UnaryOperator(*uop))?.to_euler(EulerRot::XYZ);
+                                return Result::Ok(Value::Vector3(Vec3::new(x, y, z)));
+                            },
+                            _ => return Result::Err(EvaluationError::TypeMismatchError { op: Operator::UnaryOperator(*uop), }),
+                        }
+                    },
                 }
             },
-            Operator::CreateVector2 | Operator::CreateVector3 | Operator::CreateColor => 
+            Operator::CreateVector2 | Operator::CreateVector3 | Operator::CreateColor | Operator::CreateVector4 => 
             {
                 let output_len: usize = match self 
                 {
                     Operator::CreateVector2 => 2,
                     Operator::CreateVector3 => 3,
-                    Operator::CreateColor => 4,
+                    Operator::CreateColor | Operator::CreateVector4 => 4,
                     _ => 0, //unreachable
                 };
                 let scalars = 
                 {
-                    let mut ret : Vec<f32> = vec![];
-                    while ret.len() < output_len
+                    let mut ret = [0.0_f32; 4];
+                    let mut ret_len = 0;
+                    let mut components = [0.0_f32; 16];
+                    while ret_len < output_len
                     {
                         let val = value_stack.pop();
                         if val.is_none()
                         {
                             return Result::Err(EvaluationError::ValueUnderflowError { op: *self });
                         }
-                        ret.append(&mut val.unwrap().val_into_float_list());
-                        if ret.len() > output_len
+                        if matches!(val, Some(Value::Bool(_)))
+                        {
+                            return Result::Err(EvaluationError::TypeMismatchError { op: *self });
+                        }
+                        let num_components = val.unwrap().val_into_float_list(&mut components);
+                        if ret_len + num_components > output_len
                         {
                             return Result::Err(EvaluationError::TypeMismatchError { op: *self });
                         }
+                        ret[ret_len..ret_len + num_components].copy_from_slice(&components[..num_components]);
+                        ret_len += num_components;
                     }
                     ret
                 };
@@ -864,6 +1960,7 @@ impl Operator
                     Operator::CreateVector2 => return Result::Ok(Value::Vector2(Vec2::new(scalars[1], scalars[0]))),
                     Operator::CreateVector3 => return Result::Ok(Value::Vector3(Vec3::new(scalars[2], scalars[1], scalars[0]))),
                     Operator::CreateColor => return Result::Ok(Value::Color(Vec4::new(scalars[3], scalars[2], scalars[1], scalars[0]))),
+                    Operator::CreateVector4 => return Result::Ok(Value::Vector4(Vec4::new(scalars[3], scalars[2], scalars[1], scalars[0]))),
                     _ => return Result::Err(EvaluationError::TypeMismatchError { op: *self }), //unreachable
                 };
             },
@@ -988,7 +2085,7 @@ impl Operator
                             }
                             return Result::Ok(Value::Scalar(v3_data.to_array()[index_val]))
                         },
-                        Value::Color(c_data) => 
+                        Value::Color(c_data) | Value::Vector4(c_data) => 
                         {
                             if index_val > 3
                             {
@@ -1034,9 +2131,10 @@ impl Operator
                     Value::Scalar(s_angle) => s_angle,
                     _ => return Result::Err(EvaluationError::TypeMismatchError { op: *self }),    
                 };
+                //from_axis_angle expects a unit axis, and vec3(0, 2, 0) is easier to write than to normalize by hand
                 let axis_val = match axis.unwrap()
                 {
-                    Value::Vector3(v3_axis) => v3_axis,
+                    Value::Vector3(v3_axis) => v3_axis.try_normalize().ok_or(EvaluationError::DegenerateDirectionError { op: *self })?,
                     _ => return Result::Err(EvaluationError::TypeMismatchError { op: *self }),
                 };
                 return Result::Ok(Value::Quaternion(Quat::from_axis_angle(axis_val, angle_val)));
@@ -1050,9 +2148,10 @@ impl Operator
                 {
                     return Result::Err(EvaluationError::ValueUnderflowError { op: *self });
                 }
-                let data_val = match data.unwrap()
+                let mut data_val = [0.0_f32; 16];
+                let data_len = match data.unwrap()
                 {
-                    Value::Vector2(_) | Value::Vector3(_) | Value::Color(_) => data.unwrap().val_into_float_list(),
+                    Value::Vector2(_) | Value::Vector3(_) | Value::Color(_) | Value::Vector4(_) => data.unwrap().val_into_float_list(&mut data_val),
                     _ => return Result::Err(EvaluationError::TypeMismatchError { op: *self }),    
                 };
                 let size_val = match output_size.unwrap()
@@ -1070,7 +2169,7 @@ impl Operator
                 };
                 let output =
                 {
-                    let mut ret: Vec<f32> = vec![];
+                    let mut ret = [0.0_f32; 4];
                     for i in 0..size_val
                     {
                         let swizzdex = value_stack.pop();
@@ -1083,7 +2182,7 @@ impl Operator
                             Value::Scalar(s_size) => 
                             {
                                 let i_size = s_size.round();
-                                if i_size < 0.0 || i_size > (data_val.len() as f32)
+                                if i_size < 0.0 || i_size >= (data_len as f32)
                                 {
                                     return Result::Err(EvaluationError::InvalidOutputSize { op: *self, size: i_size });
                                 }
@@ -1091,7 +2190,7 @@ impl Operator
                             },
                             _ => return Result::Err(EvaluationError::TypeMismatchError { op: *self }),
                         };
-                        ret.push(data_val[swizz_val]);
+                        ret[i] = data_val[swizz_val];
                     }
                     ret
                 };
@@ -1100,11 +2199,241 @@ impl Operator
                     1 => return Result::Ok(Value::Scalar(output[0])),
                     2 => return Result::Ok(Value::Vector2(Vec2::new(output[1], output[0]))),
                     3 => return Result::Ok(Value::Vector3(Vec3::new(output[2], output[1], output[0]))),
-                    4 => return Result::Ok(Value::Color(Vec4::new(output[3], output[2], output[1], output[0]))),
+                    4 => 
+                    {
+                        let output_val = Vec4::new(output[3], output[2], output[1], output[0]);
+                        return Result::Ok(if matches!(data, Some(Value::Vector4(_))) { Value::Vector4(output_val) } else { Value::Color(output_val) });
+                    },
                     _ => return Result::Err(EvaluationError::TypeMismatchError { op: *self })
                 }
             },
+            Operator::Select => 
+            {
+                let if_false = value_stack.pop();
+                let if_true = value_stack.pop();
+                let condition = value_stack.pop();
+                if condition.is_none() || if_true.is_none() || if_false.is_none()
+                {
+                    return Result::Err(EvaluationError::ValueUnderflowError { op: *self });
+                }
+                //both branches get coerced so the result has the same type whichever way it goes
+                let (true_val, false_val) = coerce_operands(if_true.unwrap(), if_false.unwrap());
+                if !true_val.matches_type(&false_val)
+                {
+                    return Result::Err(EvaluationError::TypeMismatchError { op: *self });
+                }
+                return match condition.unwrap()
+                {
+                    Value::Bool(b_val) => Result::Ok(if b_val { true_val } else { false_val }),
+                    _ => Result::Err(EvaluationError::TypeMismatchError { op: *self }),
+                };
+            },
+            Operator::ComposeTransform | Operator::LookAt | Operator::Slerp | Operator::Nlerp | Operator::TransformPoint | Operator::TransformVector => 
+            {
+                return evaluate_transform(*self, value_stack);
+            },
         }
     }
     
-}
\ No newline at end of file
+}
+
+//trs, look_at, the quaternion blends and the point and vector transforms
+fn evaluate_transform(op: Operator, value_stack: &mut Vec<Value>) -> Result<Value, EvaluationError>
+{
+    let mismatch = EvaluationError::TypeMismatchError { op: op };
+    let num_args = op.min_operands();
+    if value_stack.len() < num_args
+    {
+        return Result::Err(EvaluationError::ValueUnderflowError { op: op });
+    }
+    //popped into an array so the compiled path still doesn't allocate
+    let mut args = [Value::Scalar(0.0); 3];
+    for i in (0..num_args).rev()
+    {
+        args[i] = value_stack.pop().unwrap();
+    }
+    return match op
+    {
+        Operator::ComposeTransform => match (args[0], args[1], int_to_scalar(args[2]))
+        {
+            (Value::Vector3(loc), Value::Quaternion(rot), Value::Vector3(scale)) => 
+                Result::Ok(Value::Matrix4(Mat4::from_scale_rotation_translation(scale, unit_quaternion(rot, op)?, loc))),
+            (Value::Vector3(loc), Value::Quaternion(rot), Value::Scalar(scale)) => 
+                Result::Ok(Value::Matrix4(Mat4::from_scale_rotation_translation(Vec3::splat(scale), unit_quaternion(rot, op)?, loc))),
+            (Value::Vector2(loc), angle, Value::Vector2(scale)) => match int_to_scalar(angle)
+            {
+                Value::Scalar(angle) => Result::Ok(Value::Matrix3(Mat3::from_scale_angle_translation(scale, angle, loc))),
+                _ => Result::Err(mismatch),
+            },
+            (Value::Vector2(loc), angle, Value::Scalar(scale)) => match int_to_scalar(angle)
+            {
+                Value::Scalar(angle) => Result::Ok(Value::Matrix3(Mat3::from_scale_angle_translation(Vec2::splat(scale), angle, loc))),
+                _ => Result::Err(mismatch),
+            },
+            _ => Result::Err(mismatch),
+        },
+        //a transform at eye with -z towards the target and y as close to up as it can get, the inverse of a view matrix
+        Operator::LookAt => match (args[0], args[1], args[2])
+        {
+            (Value::Vector3(eye), Value::Vector3(target), Value::Vector3(up)) => 
+            {
+                let dir = target - eye;
+                if dir.cross(up).length_squared() == 0.0
+                {
+                    return Result::Err(EvaluationError::DegenerateDirectionError { op: op });
+                }
+                Result::Ok(Value::Matrix4(Mat4::look_to_rh(eye, dir.normalize(), up.normalize()).inverse()))
+            },
+            _ => Result::Err(mismatch),
+        },
+        //both take the short way round
+        Operator::Slerp | Operator::Nlerp => match (args[0], args[1], int_to_scalar(args[2]))
+        {
+            (Value::Quaternion(from), Value::Quaternion(to), Value::Scalar(t)) => 
+            {
+                let (from, to) = (unit_quaternion(from, op)?, unit_quaternion(to, op)?);
+                Result::Ok(Value::Quaternion(if matches!(op, Operator::Slerp) { from.slerp(to, t) } else { from.lerp(to, t) }))
+            },
+            _ => Result::Err(mismatch),
+        },
+        //quaternions just rotate, so both transforms are the same for them
+        Operator::TransformPoint | Operator::TransformVector => 
+        {
+            let point = matches!(op, Operator::TransformPoint);
+            match (args[0], args[1])
+            {
+                (Value::Matrix4(m4_val), Value::Vector3(v3_val)) => Result::Ok(Value::Vector3(if point { m4_val.transform_point3(v3_val) } else { m4_val.transform_vector3(v3_val) })),
+                (Value::Matrix3(m3_val), Value::Vector2(v2_val)) => Result::Ok(Value::Vector2(if point { m3_val.transform_point2(v2_val) } else { m3_val.transform_vector2(v2_val) })),
+                (Value::Quaternion(q_val), Value::Vector3(v3_val)) => Result::Ok(Value::Vector3(unit_quaternion(q_val, op)? * v3_val)),
+                _ => Result::Err(mismatch),
+            }
+        },
+        _ => Result::Err(mismatch), //handled elsewhere
+    };
+}
+
+//quaternions built from literals or arithmetic don't have to be unit length, but glam's rotations expect them to be
+fn unit_quaternion(q_val: Quat, op: Operator) -> Result<Quat, EvaluationError>
+{
+    if q_val.length_squared() == 0.0
+    {
+        return Result::Err(EvaluationError::DegenerateDirectionError { op: op });
+    }
+    return Result::Ok(q_val.normalize());
+}
+
+fn int_to_scalar(val: Value) -> Value
+{
+    return match val
+    {
+        Value::Int(i_val) => Value::Scalar(i_val as f32),
+        _ => val,
+    };
+}
+
+//see the coercion rules on Value
+fn coerce_operands(lhs: Value, rhs: Value) -> (Value, Value)
+{
+    return match (lhs, rhs)
+    {
+        (Value::Int(_), Value::Int(_)) => (lhs, rhs),
+        (Value::Int(i_lhs), _) if !matches!(rhs, Value::Bool(_)) => (Value::Scalar(i_lhs as f32), rhs),
+        (_, Value::Int(i_rhs)) if !matches!(lhs, Value::Bool(_)) => (lhs, Value::Scalar(i_rhs as f32)),
+        (Value::Vector4(v4_lhs), Value::Color(_)) => (Value::Color(v4_lhs), rhs),
+        (Value::Color(_), Value::Vector4(v4_rhs)) => (lhs, Value::Color(v4_rhs)),
+        _ => (lhs, rhs),
+    };
+}
+
+//equality works on any two values of the same type, ordering only on numbers and and/or only on bools
+fn evaluate_comparison(bop: BinaryOperator, lhs: Value, rhs: Value) -> Result<Value, EvaluationError>
+{
+    let mismatch = EvaluationError::TypeMismatchError { op: Operator::BinaryOperator(bop) };
+    if !lhs.matches_type(&rhs)
+    {
+        return Result::Err(mismatch);
+    }
+    let ordering = match (lhs, rhs)
+    {
+        (Value::Scalar(s_lhs), Value::Scalar(s_rhs)) => s_lhs.partial_cmp(&s_rhs),
+        (Value::Int(i_lhs), Value::Int(i_rhs)) => Some(i_lhs.cmp(&i_rhs)),
+        _ => None,
+    };
+    return match bop
+    {
+        BinaryOperator::Equal => Result::Ok(Value::Bool(lhs == rhs)),
+        BinaryOperator::NotEqual => Result::Ok(Value::Bool(lhs != rhs)),
+        BinaryOperator::And | BinaryOperator::Or => 
+        {
+            match (lhs, rhs)
+            {
+                (Value::Bool(b_lhs), Value::Bool(b_rhs)) => Result::Ok(Value::Bool(if matches!(bop, BinaryOperator::And) { b_lhs && b_rhs } else { b_lhs || b_rhs })),
+                _ => Result::Err(mismatch),
+            }
+        },
+        _ => 
+        {
+            if !matches!(lhs, Value::Scalar(_) | Value::Int(_))
+            {
+                return Result::Err(mismatch);
+            }
+            //nan compares false to everything
+            let result = match ordering
+            {
+                Some(order) => match bop
+                {
+                    BinaryOperator::Less => order.is_lt(),
+                    BinaryOperator::LessEqual => order.is_le(),
+                    BinaryOperator::Greater => order.is_gt(),
+                    _ => order.is_ge(),
+                },
+                None => false,
+            };
+            Result::Ok(Value::Bool(result))
+        },
+    };
+}
+
+//integer math wraps instead of panicking on overflow. exponents are always done in floats
+fn evaluate_int(bop: BinaryOperator, lhs: i32, rhs: i32) -> Result<Value, EvaluationError>
+{
+    return match bop
+    {
+        BinaryOperator::Add => Result::Ok(Value::Int(lhs.wrapping_add(rhs))),
+        BinaryOperator::Subtract => Result::Ok(Value::Int(lhs.wrapping_sub(rhs))),
+        BinaryOperator::Multiply => Result::Ok(Value::Int(lhs.wrapping_mul(rhs))),
+        BinaryOperator::Divide | BinaryOperator::Modulo if rhs == 0 => Result::Err(EvaluationError::DivideByZeroError),
+        BinaryOperator::Divide => Result::Ok(Value::Int(lhs.wrapping_div(rhs))),
+        BinaryOperator::Modulo => Result::Ok(Value::Int(lhs.wrapping_rem(rhs))),
+        BinaryOperator::Exponent => Result::Ok(Value::Scalar((lhs as f32).powf(rhs as f32))),
+        _ => Result::Err(EvaluationError::TypeMismatchError { op: Operator::BinaryOperator(bop) }),
+    };
+}
+
+//a Vector4 mixes with another Vector4 or a scalar on either side
+fn evaluate_vector4(bop: BinaryOperator, lhs: Value, rhs: Value) -> Result<Value, EvaluationError>
+{
+    let mismatch = EvaluationError::TypeMismatchError { op: Operator::BinaryOperator(bop) };
+    let (v4_lhs, v4_rhs) = match (lhs, rhs)
+    {
+        (Value::Vector4(v4_lhs), Value::Vector4(v4_rhs)) => (v4_lhs, v4_rhs),
+        (Value::Vector4(v4_lhs), Value::Scalar(s_rhs)) => (v4_lhs, Vec4::splat(s_rhs)),
+        (Value::Scalar(s_lhs), Value::Vector4(v4_rhs)) if !matches!(bop, BinaryOperator::Exponent | BinaryOperator::Dot) => (Vec4::splat(s_lhs), v4_rhs),
+        _ => return Result::Err(mismatch),
+    };
+    if matches!(bop, BinaryOperator::Divide | BinaryOperator::Modulo) && v4_rhs.to_array().iter().any(|v| *v == 0.0)
+    {
+        return Result::Err(EvaluationError::DivideByZeroError);
+    }
+    return match bop
+    {
+        BinaryOperator::Add => Result::Ok(Value::Vector4(v4_lhs + v4_rhs)),
+        BinaryOperator::Subtract => Result::Ok(Value::Vector4(v4_lhs - v4_rhs)),
+        BinaryOperator::Multiply => Result::Ok(Value::Vector4(v4_lhs * v4_rhs)),
+        BinaryOperator::Divide => Result::Ok(Value::Vector4(v4_lhs / v4_rhs)),
+        BinaryOperator::Modulo => Result::Ok(Value::Vector4(v4_lhs % v4_rhs)),
+        BinaryOperator::Exponent if matches!(rhs, Value::Scalar(_)) => Result::Ok(Value::Vector4(v4_lhs.powf(v4_rhs.x))),
+        BinaryOperator::Dot if matches!(rhs, Value::Vector4(_)) => Result::Ok(Value::Scalar(v4_lhs.dot(v4_rhs))),
+        _ => Result::Err(mismatch),
+    };
+}
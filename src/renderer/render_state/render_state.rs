@@ -2,10 +2,11 @@ use std::collections::HashMap;
 use std::rc::Rc;
 
 use glam::Mat4;
-use super::animation::AnimationState;
+use super::animation::{AnimationState, Armature, AnimationClip};
 use super::common::{IdGenerator, Id};
 use super::light::{PointLight, SpotLight, SpotLightDescriptor, PointLightDescriptor};
 use super::model::{StaticModelInstance, AnimatedModelInstance, StaticModel, AnimatedModel};
+use super::texture::Shader;
 use super::view::{Camera, CameraDescriptor};
 
 pub struct RenderState
@@ -115,6 +116,49 @@ impl RenderState
             group.clear_dirty_state();
         }
     }
+
+    //hot reload hooks - these rebuild every live instance that was made from the old asset
+
+    pub fn reload_static_model(&mut self, model: &StaticModel)
+    {
+        for group in self.groups.values_mut()
+        {
+            group.get_static_models_mut().filter(|inst| *inst.model_id() == model.id).for_each(|inst| inst.rebuild(model));
+        }
+    }
+
+    pub fn reload_animated_model(&mut self, model: &AnimatedModel)
+    {
+        for group in self.groups.values_mut()
+        {
+            group.get_animated_models_mut().filter(|inst| *inst.model_id() == model.id).for_each(|inst| inst.rebuild(model));
+        }
+    }
+
+    pub fn replace_armature(&mut self, old: &Rc<Armature>, new: &Rc<Armature>)
+    {
+        for group in self.groups.values_mut()
+        {
+            group.get_animated_models_mut().filter(|inst| Rc::ptr_eq(&inst.anim_state().armature, old)).for_each(|inst| inst.set_armature(new.clone()));
+        }
+    }
+
+    pub fn replace_animation_clip(&mut self, old: &Rc<AnimationClip>, new: &Rc<AnimationClip>)
+    {
+        for group in self.groups.values_mut()
+        {
+            group.get_animated_models_mut().for_each(|inst| inst.anim_state_mut().replace_clip(old, new));
+        }
+    }
+
+    pub fn replace_shader(&mut self, old: &Rc<Shader>, new: &Rc<Shader>)
+    {
+        for group in self.groups.values_mut()
+        {
+            group.get_static_models_mut().for_each(|inst| inst.replace_shader(old, new));
+            group.get_animated_models_mut().for_each(|inst| inst.replace_shader(old, new));
+        }
+    }
 }
 
 //terminal struct for this aggregate
@@ -296,6 +340,10 @@ impl RenderGroup
 
     pub fn get_spot_light_mut(&mut self, key: Id) -> Option<&mut SpotLight> { return self.spot_lights.get_mut(&key); }
 
+    pub fn get_static_models_mut(&mut self) -> impl Iterator<Item=&mut StaticModelInstance> { return self.static_models.values_mut(); }
+
+    pub fn get_animated_models_mut(&mut self) -> impl Iterator<Item=&mut AnimatedModelInstance> { return self.animated_models.values_mut(); }
+
     fn remove_item(&mut self, id: Id)
     {
         if let Some(animated_model) = self.animated_models.remove(&id)
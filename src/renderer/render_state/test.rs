@@ -0,0 +1,872 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
+
+use super::animation::{AnimationClip, AnimationEvent, AnimationKey, AnimationParameter, AnimationState, AnimationStateBuilder, Armature, ArmatureMask, BlendSpaceMixer, BlendSpaceMixerBuilder, BlendSpacePoint, BlendSpaceWeighting, Bone, ChainMethod, ClipChannel, ClipCursor, IkConstraint, KeyInterpolation, RootMotion, SpringBone, SyncMarker, LayerBuilder, MixerBuilder, MixType, ParameterError, PlaybackType, SimpleMixerBuilder, StateMachineBuilder, StateTransition, TransitionCondition};
+use super::common::{Color, IdGenerator, NormalizedFloat};
+use super::diagnostics::{ErrorPolicy, EvaluationDiagnostic};
+use super::model::{ColorPalette, Model, ShaderSlot, StaticModel, StaticModelInstance};
+use super::overrides::{OverrideError, PropertyOverrides};
+use super::texture::{ExternalShaderValue, Placement, Projection, ProjectionSpace, Shader, ShaderInstance, ShaderLinkError, ShaderValueLink};
+use super::tween::{Easing, PropertyTweener, Tween, TweenEvent, interpolate_value};
+use super::properties::{EvalStack, EvaluationError, Expression, ExpressionParseError, PropertyGroup, PropertySchema, Span, Value};
+
+#[cfg(test)]
+
+fn eval(input: &str, props: &PropertyGroup) -> Value
+{
+    return Expression::parse(0, input).unwrap().evaluate(props).unwrap();
+}
+
+#[test]
+fn test_expression_parse_precedence()
+{
+    let props = PropertyGroup::new();
+    assert!(matches!(eval("1 + 2 * 3 ^ 2 ^ 0.5 - 4 / 2", &props), Value::Scalar(s) if (s - (1.0 + 2.0 * 3.0_f32.powf(2.0_f32.sqrt()) - 2.0)).abs() < 0.0001), "Operators should follow precedence and ^ should be right associative");
+    assert!(matches!(eval("-2 ^ 2", &props), Value::Scalar(s) if s == -4.0), "Unary minus should bind looser than ^");
+    assert!(matches!(eval("(1 + 2) * 3", &props), Value::Scalar(s) if s == 9.0), "Parentheses should group");
+}
+
+#[test]
+fn test_expression_parse_calls()
+{
+    let mut props = PropertyGroup::new();
+    props.add_property("hp", Value::Scalar(25.0));
+    props.add_property("maxhp", Value::Scalar(100.0));
+    assert!(matches!(eval("color(hp / maxhp, 0, 0, 1)", &props), Value::Color(c) if c == Vec4::new(0.25, 0.0, 0.0, 1.0)), "Color call should build a color from its arguments in order");
+    assert!(matches!(eval("cross(vec3(1, 0, 0), vec3[0, 1, 0])", &props), Value::Vector3(v) if v == Vec3::Z), "Calls and typed literals should mix");
+    assert!(matches!(eval("vec3(4, 5, 6).zx", &props), Value::Vector2(v) if v.x == 6.0 && v.y == 4.0), "Accessor should swizzle in the order written");
+    assert!(matches!(eval("swizzle(vec3(4, 5, 6), 1, 1, 0)", &props), Value::Vector3(v) if v == Vec3::new(5.0, 5.0, 4.0)), "Swizzle call should match the accessor");
+    assert!(matches!(eval("entry(mat4(vec3[1, 2, 3], quat(vec3[0, 0, 1], 0), vec3[1, 1, 1]), 13)", &props), Value::Scalar(s) if s == 2.0), "Matrix entries should be column major");
+    assert!(matches!(eval("#ff000080", &props), Value::Color(c) if c.x == 1.0 && c.y == 0.0 && (c.w - 128.0 / 255.0).abs() < 0.0001), "Hex colors should parse with alpha");
+}
+
+#[test]
+fn test_expression_parse_errors()
+{
+    assert!(matches!(Expression::parse(0, "1 + * 2"), Err(ExpressionParseError::SyntaxError { span: Span { start: 4, .. }, .. })), "Syntax errors should point at the bad token");
+    assert!(matches!(Expression::parse(0, "2 * vec3(1, 2)"), Err(ExpressionParseError::ArgumentCountError { span: Span { start: 4, end: 14 }, given: 2, .. })), "Wrong argument counts should span the call");
+    assert!(matches!(Expression::parse(0, "frob(1)"), Err(ExpressionParseError::UnknownFunction { .. })), "Unknown functions should be reported");
+    assert!(matches!(Expression::parse(0, "vec3[1, 2]"), Err(ExpressionParseError::InvalidLiteral { .. })), "Typed literals should check their size");
+    assert!(matches!(Expression::parse(0, "v.xq"), Err(ExpressionParseError::InvalidAccessor { span: Span { start: 1, end: 4 }, .. })), "Accessors should only take component names");
+}
+
+#[test]
+fn test_incremental_evaluation()
+{
+    let mut props = PropertyGroup::new();
+    props.add_property("hp", Value::Scalar(50.0));
+    props.add_property("maxhp", Value::Scalar(100.0));
+    props.add_property("tint", Value::Scalar(1.0));
+    let placements = [(0, "hp / maxhp"), (1, "color(tint, tint, tint, 1)")].map
+    (
+        |(id, text)| Placement::new(id as u32, Expression::parse(id, text).unwrap(), Projection::Planar, ProjectionSpace::Object)
+    );
+    let shader = Rc::new(Shader::new("health".to_string(), vec![], placements.into(), props));
+    let mut instance = ShaderInstance::new(shader.clone(), vec![]);
+    instance.clear_changed_expressions();
+
+    instance.set_property("hp", Value::Scalar(25.0)).unwrap();
+    instance.eval_expressions();
+    assert_eq!(instance.changed_expression_ids().collect::<Vec<_>>(), vec![0], "Only the expression reading hp should be recomputed");
+    assert_eq!(instance.expression_value(0), Some(&Value::Scalar(0.25)), "Recomputed value should be stored");
+
+    instance.clear_changed_expressions();
+    assert!(instance.set_property("hp", Value::Vector2(glam::Vec2::ZERO)).is_err(), "Assignments should keep the property's type");
+    instance.set_slot(shader.schema().slot("tint").unwrap(), Value::Scalar(0.5)).unwrap();
+    instance.eval_expressions();
+    assert_eq!(instance.changed_expression_ids().collect::<Vec<_>>(), vec![1], "Failed assignments should not mark anything, slot assignments should");
+    instance.eval_expressions();
+    assert_eq!(instance.expression_value(1), Some(&Value::Color(Vec4::new(0.5, 0.5, 0.5, 1.0))), "Evaluating with nothing assigned should leave the values alone");
+}
+
+#[test]
+fn test_compiled_evaluation()
+{
+    let mut props = PropertyGroup::new();
+    props.add_property("hp", Value::Scalar(25.0));
+    props.add_property("maxhp", Value::Scalar(100.0));
+    let schema = PropertySchema::new(&props);
+    let expr = Expression::parse(3, "color(hp / maxhp, 0, 0, 1) * (1 + 1)").unwrap();
+    let compiled = expr.compile(&schema).unwrap();
+    assert_eq!(compiled.get_id(), 3, "Compiling should keep the expression id");
+    assert!(compiled.stack_size() >= 4, "Stack size should cover the color constructor's arguments");
+
+    let mut stack = EvalStack::with_capacity(compiled.stack_size());
+    let capacity = stack.capacity();
+    let mut slots = schema.create_slots();
+    assert!(matches!(compiled.evaluate(&slots, &mut stack), Ok(Value::Color(c)) if c == Vec4::new(0.5, 0.0, 0.0, 2.0)), "Compiled expression should read defaults from the slots");
+    slots[schema.slot("hp").unwrap()] = Value::Scalar(50.0);
+    assert!(matches!(compiled.evaluate(&slots, &mut stack), Ok(Value::Color(c)) if c.x == 1.0), "Compiled expression should see slot writes");
+    assert_eq!(stack.capacity(), capacity, "A stack sized at compile time should never grow");
+    assert!(matches!(Expression::parse(0, "hp / armor").unwrap().compile(&schema), Err(EvaluationError::InvalidIdentifier { id }) if id == "armor"), "Unknown properties should fail to compile");
+}
+
+#[test]
+fn test_value_types()
+{
+    let mut props = PropertyGroup::new();
+    props.add_property("points", Value::Int(5));
+    props.add_property("enabled", Value::Bool(true));
+    props.add_property("tint", Value::Vector4(Vec4::new(0.5, 0.5, 0.5, 1.0)));
+    assert!(matches!(eval("points / 2 + 1", &props), Value::Scalar(s) if s == 3.5), "Ints mixed with scalars should become scalars");
+    assert!(matches!(eval("points % int(3.9)", &props), Value::Int(2)), "Int with int should stay integer math");
+    assert!(matches!(eval("points >= 5 && !(points == 6) || false", &props), Value::Bool(true)), "Comparisons should bind tighter than logical operators");
+    assert!(matches!(eval("select(enabled, points, 0.5)", &props), Value::Scalar(s) if s == 5.0), "Select should coerce both branches to one type");
+    assert!(matches!(eval("tint * #ff0000", &props), Value::Color(c) if c == Vec4::new(0.5, 0.0, 0.0, 1.0)), "Vector4 next to a color should become a color");
+    assert!(matches!(eval("tint * 2", &props), Value::Vector4(v) if v == Vec4::new(1.0, 1.0, 1.0, 2.0)), "Vector4 should keep its type with scalars");
+    assert!(matches!(Expression::parse(0, "enabled + 1").unwrap().evaluate(&props), Err(EvaluationError::TypeMismatchError { .. })), "Bools should not act as numbers");
+}
+
+#[test]
+fn test_std140_flattening()
+{
+    let mut buffer = vec![];
+    Value::Scalar(1.0).write_std140(&mut buffer);
+    Value::Color(Vec4::new(0.1, 0.2, 0.3, 0.4)).write_std140(&mut buffer);
+    assert_eq!(buffer.len(), 32, "Vec4 values should be padded to a 16 byte boundary");
+    let floats = buffer.chunks(4).map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap())).collect::<Vec<_>>();
+    assert_eq!(&floats[4..], &[0.1, 0.2, 0.3, 0.4], "Color alpha should be written as is, not a copy of blue");
+    Value::Bool(true).write_std140(&mut buffer);
+    Value::Matrix3(glam::Mat3::IDENTITY).write_std140(&mut buffer);
+    assert_eq!(buffer.len(), 96, "Mat3 columns should each take a vec4");
+    assert_eq!(u32::from_ne_bytes(buffer[32..36].try_into().unwrap()), 1, "Bools should be written as a whole word");
+}
+
+#[test]
+fn test_shader_links()
+{
+    let mut defaults = PropertyGroup::new();
+    defaults.add_property("eye_color", Value::Color(Vec4::ONE));
+    defaults.add_property("mouth_open", Value::Scalar(0.0));
+    let shader = Rc::new(Shader::new("face".to_string(), vec![], vec![], defaults));
+    let links = vec!
+    [
+        ShaderValueLink::new("eye_color", ExternalShaderValue::Color(1)),
+        ShaderValueLink::new("mouth_open", ExternalShaderValue::AnimationTrack("mouth".to_string())),
+    ];
+    let mut instance = ShaderInstance::new(shader.clone(), links);
+    let colors = vec![Color { data: Vec4::ZERO }, Color { data: Vec4::new(0.0, 0.0, 1.0, 1.0) }];
+    assert!(matches!(instance.apply_links(&colors, &[0.5]), Err(ShaderLinkError::NoSuchTrack { .. })), "Tracks should fail to resolve without an armature");
+    assert_eq!(instance.get_property("eye_color"), Some(&Value::Color(Vec4::new(0.0, 0.0, 1.0, 1.0))), "Color links should still apply when another link fails");
+
+    instance.bind_armature(&Armature::new("face".to_string(), vec![]).with_controls(vec!["blink".to_string(), "mouth".to_string()]));
+    assert!(instance.apply_links(&colors, &[1.0, 0.75]).is_ok(), "Links should apply once the track resolves");
+    assert_eq!(instance.get_property("mouth_open"), Some(&Value::Scalar(0.75)), "Track links should read their own control");
+    assert_eq!(instance.overridden_properties().count(), 0, "Linked values shouldn't count as overrides");
+    instance.set_link_toggle(1, false);
+    assert_eq!(instance.overridden_properties().map(|(name, _)| name.as_str()).collect::<Vec<&str>>(), vec!["mouth_open"], "Values left behind by a link toggled off should count as overrides");
+    assert!(matches!(instance.apply_links(&colors[..1], &[1.0, 0.75]), Err(ShaderLinkError::NoSuchColor { index: 1, num_colors: 1, .. })), "Missing colors should be reported");
+
+    let mut mismatched = ShaderInstance::new(shader, vec![ShaderValueLink::new("mouth_open", ExternalShaderValue::Color(0))]);
+    assert!(matches!(mismatched.apply_links(&colors, &[]), Err(ShaderLinkError::AssignmentError(_))), "Linking a color to a scalar property should be a type error");
+}
+
+#[test]
+fn test_shader_rebuild()
+{
+    let mut defaults = PropertyGroup::new();
+    defaults.add_property("eye_color", Value::Color(Vec4::ONE));
+    defaults.add_property("mouth_open", Value::Scalar(0.0));
+    let links = vec![ShaderValueLink::new("eye_color", ExternalShaderValue::Color(0))];
+    let mut instance = ShaderInstance::new(Rc::new(Shader::new("face".to_string(), vec![], vec![], defaults.clone())), links.clone());
+    instance.set_property("mouth_open", Value::Scalar(0.5)).unwrap();
+    instance.set_link_toggle(0, false);
+
+    defaults.add_property("blush", Value::Scalar(0.0));
+    instance.rebuild(Rc::new(Shader::new("face".to_string(), vec![], vec![], defaults)), links);
+    assert_eq!(instance.get_property("mouth_open"), Some(&Value::Scalar(0.5)), "Properties should survive a rebuild");
+    assert!(instance.apply_links(&[Color { data: Vec4::ZERO }], &[]).is_ok());
+    assert_eq!(instance.get_property("eye_color"), Some(&Value::Color(Vec4::ONE)), "Links toggled off should stay off after a rebuild");
+}
+
+#[test]
+fn test_property_tweens()
+{
+    let mut props = PropertyGroup::new();
+    props.add_property("flash", Value::Scalar(0.0));
+    props.add_property("tint", Value::Color(Vec4::ZERO));
+    let mut tweener = PropertyTweener::new();
+    let finished = Rc::new(Cell::new(false));
+    let finished_flag = finished.clone();
+    let id = tweener.start
+    (
+        Tween::new("flash", Value::Scalar(1.0), Duration::from_secs(1))
+            .then(Tween::new("flash", Value::Scalar(0.0), Duration::from_secs(1)).with_easing(Easing::Step).on_complete(move || finished_flag.set(true)))
+    );
+    tweener.update(0.5, &mut props).unwrap();
+    assert_eq!(props.get_property("flash"), Some(&Value::Scalar(0.5)), "Linear tween should be halfway at half its duration");
+    tweener.update(1.0, &mut props).unwrap();
+    assert_eq!(props.get_property("flash"), Some(&Value::Scalar(1.0)), "Step easing should hold until the end");
+    assert!(matches!(tweener.drain_events().next(), Some(TweenEvent::Completed { id: event_id, .. }) if event_id == id), "Finishing a step should report it");
+    tweener.update(0.5, &mut props).unwrap();
+    assert!(finished.get() && tweener.is_empty(), "Leftover time should carry into the chained step and finish it");
+
+    let tint = tweener.start(Tween::new("tint", Value::Color(Vec4::ONE), Duration::from_secs(2)));
+    tweener.update(1.0, &mut props).unwrap();
+    assert!(tweener.cancel(tint) && !tweener.is_active(tint), "Cancelling should stop the tween");
+    tweener.update(1.0, &mut props).unwrap();
+    assert_eq!(props.get_property("tint"), Some(&Value::Color(Vec4::splat(0.5))), "Cancelled tweens should leave the property where it was");
+
+    tweener.start(Tween::new("tint", Value::Scalar(1.0), Duration::from_secs(1)));
+    assert!(tweener.update(0.1, &mut props).is_err() && tweener.is_empty(), "Tweening to a different type should fail and drop the tween");
+
+    let half_turn = interpolate_value(&Value::Quaternion(Quat::IDENTITY), &Value::Quaternion(glam::Quat::from_rotation_z(std::f32::consts::FRAC_PI_2)), 0.5);
+    assert!(matches!(half_turn, Some(Value::Quaternion(q)) if q.angle_between(glam::Quat::from_rotation_z(std::f32::consts::FRAC_PI_4)) < 0.001), "Quaternions should slerp");
+}
+
+#[test]
+fn test_property_overrides()
+{
+    let mut defaults = PropertyGroup::new();
+    defaults.add_property("stripe_color", Value::Color(Vec4::ONE));
+    defaults.add_property("stripe_count", Value::Int(2));
+    defaults.add_property("stripe_tf", Value::Matrix3(glam::Mat3::IDENTITY));
+    let shader = Rc::new(Shader::new("stripes".to_string(), vec![], vec![], defaults));
+    let mut shader_slots = HashMap::new();
+    shader_slots.insert("hull".to_string(), ShaderSlot::new(vec![], shader, vec![]));
+    let model = StaticModel
+    {
+        id: "crate".to_string(),
+        vertices: vec![],
+        model_data: Model
+        {
+            polygons: vec![],
+            palettes: vec![ColorPalette { colors: vec![] }],
+            default_palette: 0,
+            shader_slots: shader_slots,
+            min_bound: Vec3::ZERO,
+            max_bound: Vec3::ONE,
+        },
+    };
+
+    let text = "# red variant\n[hull]\nstripe_color = color[1, 0, 0, 1]\nstripe_count = int(3)\n";
+    let overrides = PropertyOverrides::load(text, &model.model_data).unwrap();
+    let mut instance = StaticModelInstance::new(IdGenerator::new().get_id(), &model, glam::Mat4::IDENTITY);
+    instance.clear_dirty_state();
+    assert!(instance.apply_overrides(&overrides).is_empty(), "Valid overrides should apply cleanly");
+    assert!(instance.dirty(), "Applying overrides should mark the instance for upload");
+    assert_eq!(instance.overrides().get("hull", "stripe_count"), Some(&Value::Int(3)), "Overrides should set the instance's properties");
+
+    instance.set_shader_property("hull", "stripe_tf", Value::Matrix3(glam::Mat3::from_angle(0.5))).unwrap();
+    let saved = instance.overrides();
+    assert_eq!(saved.get("hull", "stripe_color"), Some(&Value::Color(Vec4::new(1.0, 0.0, 0.0, 1.0))), "Saving should keep overridden values");
+    assert_eq!(PropertyOverrides::parse(&saved.to_string()).unwrap().get("hull", "stripe_tf"), saved.get("hull", "stripe_tf"), "Written overrides should read back exactly");
+    instance.set_shader_property("hull", "stripe_count", Value::Int(2)).unwrap();
+    assert!(instance.overrides().get("hull", "stripe_count").is_none(), "Values back at their defaults shouldn't be saved");
+    instance.tween_shader_property("hull", Tween::new("stripe_count", Value::Int(6), Duration::from_secs(1))).unwrap();
+    instance.update(0.5).unwrap();
+    assert!(instance.overrides().get("hull", "stripe_count").is_none(), "Values partway through a tween shouldn't be saved");
+
+    let errors = PropertyOverrides::parse("stripe_count = 1\n[hull\n[hull]\nstripe_count = int(\nstripe_count = 1\nstripe_count = 2").unwrap_err();
+    assert!(matches!(errors[..], [OverrideError::SyntaxError { line: 1, .. }, OverrideError::SyntaxError { line: 2, .. }, OverrideError::InvalidValue { line: 4, .. }, OverrideError::SyntaxError { line: 6, .. }]), "Every bad line should be reported with its line number");
+    let errors = PropertyOverrides::load("[hull]\nstripe_count = 1\nstripes = int(1)\n[deck]\nstripe_count = int(1)", &model.model_data).unwrap_err();
+    assert_eq!(errors.len(), 3, "Validation should report unknown slots, unknown properties and type mismatches");
+}
+
+#[test]
+fn test_evaluation_diagnostics()
+{
+    let mut defaults = PropertyGroup::new();
+    defaults.add_property("scale", Value::Matrix4(glam::Mat4::IDENTITY));
+    let placement = Placement::new(0, Expression::parse(7, "inverse(scale)").unwrap(), Projection::Planar, ProjectionSpace::Object);
+    let shader = Rc::new(Shader::new("decal".to_string(), vec![], vec![placement], defaults));
+    let mut instance = ShaderInstance::new(shader, vec![]);
+    assert!(instance.diagnostics().pending().next().is_none(), "Defaults that evaluate shouldn't report anything");
+
+    instance.set_property("scale", Value::Matrix4(glam::Mat4::ZERO)).unwrap();
+    instance.update(0.0, &[], &[]).unwrap();
+    let diagnostics = instance.drain_diagnostics().collect::<Vec<_>>();
+    assert!(matches!(diagnostics[..], [EvaluationDiagnostic { expression_id: 7, error: EvaluationError::DegenerateMatrixError, frame: 1, .. }]), "Failures should be recorded with their expression and frame");
+    assert_eq!(diagnostics[0].inputs, vec![("scale".to_string(), Value::Matrix4(glam::Mat4::ZERO))], "Failures should record the inputs they were given");
+    assert!(!instance.is_errored(), "Keeping the last value shouldn't mark the instance");
+
+    instance.set_error_policy(ErrorPolicy::MarkErrored);
+    instance.set_property("scale", Value::Matrix4(glam::Mat4::from_scale(Vec3::new(0.0, 1.0, 1.0)))).unwrap();
+    instance.update(0.0, &[], &[]).unwrap();
+    assert!(instance.is_errored(), "A failing expression should mark the instance");
+    instance.set_property("scale", Value::Matrix4(glam::Mat4::from_scale(Vec3::splat(2.0)))).unwrap();
+    instance.update(0.0, &[], &[]).unwrap();
+    assert!(!instance.is_errored(), "The mark should clear once the expression evaluates again");
+
+    instance.set_error_policy(ErrorPolicy::SubstituteDefault);
+    instance.set_property("scale", Value::Matrix4(glam::Mat4::ZERO)).unwrap();
+    instance.update(0.0, &[], &[]).unwrap();
+    assert_eq!(instance.placement_transforms()[0], glam::Mat4::IDENTITY, "The default value should replace the failed one");
+}
+
+#[test]
+fn test_world_space_placement()
+{
+    let mut defaults = PropertyGroup::new();
+    defaults.add_property("offset", Value::Matrix4(glam::Mat4::from_translation(Vec3::X)));
+    let object = Placement::new(0, Expression::parse(0, "offset").unwrap(), Projection::Planar, ProjectionSpace::Object);
+    let world = Placement::new(0, Expression::parse(1, "offset").unwrap(), Projection::Spherical, ProjectionSpace::World);
+    let shader = Rc::new(Shader::new("decal".to_string(), vec![], vec![object, world], defaults));
+    let mut instance = ShaderInstance::new(shader, vec![]);
+    instance.set_object_transform(glam::Mat4::from_translation(Vec3::new(0.0, 2.0, 0.0)));
+    assert_eq!(instance.placement_transforms()[0].transform_point3(Vec3::ZERO), Vec3::new(-1.0, 0.0, 0.0), "Object space placements shouldn't see the object transform");
+    assert_eq!(instance.placement_transforms()[1].transform_point3(Vec3::ZERO), Vec3::new(-1.0, 2.0, 0.0), "World space placements should go through the object transform");
+}
+
+#[test]
+fn test_degenerate_placement()
+{
+    let mut defaults = PropertyGroup::new();
+    defaults.add_property("scale", Value::Matrix4(glam::Mat4::from_scale(Vec3::splat(2.0))));
+    let placement = Placement::new(0, Expression::parse(3, "scale").unwrap(), Projection::Planar, ProjectionSpace::Object);
+    let shader = Rc::new(Shader::new("decal".to_string(), vec![], vec![placement], defaults));
+    let mut instance = ShaderInstance::new(shader, vec![]);
+    instance.set_error_policy(ErrorPolicy::MarkErrored);
+    let placement_tf = instance.placement_transforms()[0];
+
+    instance.set_property("scale", Value::Matrix4(glam::Mat4::from_scale(Vec3::new(1.0, 0.0, 1.0)))).unwrap();
+    instance.update(0.0, &[], &[]).unwrap();
+    instance.update_placement_transforms(&[]);
+    assert_eq!(instance.placement_transforms()[0], placement_tf, "A placement that can't be inverted should keep its last transform");
+    let diagnostics = instance.drain_diagnostics().collect::<Vec<_>>();
+    assert!(matches!(diagnostics[..], [EvaluationDiagnostic { expression_id: 3, error: EvaluationError::DegenerateMatrixError, .. }]), "The placement should be reported once, against its transform");
+    assert!(instance.is_errored(), "A degenerate placement should mark the instance");
+
+    instance.set_property("scale", Value::Matrix4(glam::Mat4::from_scale(Vec3::splat(4.0)))).unwrap();
+    instance.update(0.0, &[], &[]).unwrap();
+    assert_eq!(instance.placement_transforms()[0], glam::Mat4::from_scale(Vec3::splat(0.25)), "The placement should pick up again once it can be inverted");
+    assert!(!instance.is_errored(), "The mark should clear with it");
+}
+
+#[test]
+fn test_uncompiled_expression()
+{
+    let mut defaults = PropertyGroup::new();
+    defaults.add_property("scale", Value::Matrix4(glam::Mat4::IDENTITY));
+    let good = Placement::new(0, Expression::parse(4, "scale").unwrap(), Projection::Planar, ProjectionSpace::Object);
+    let missing = Placement::new(0, Expression::parse(5, "offset").unwrap(), Projection::Planar, ProjectionSpace::Object);
+    let shader = Rc::new(Shader::new("decal".to_string(), vec![], vec![good, missing], defaults));
+    assert_eq!(shader.num_compiled_expressions(), 2, "Expressions that fail to compile should keep their index");
+    let mut instance = ShaderInstance::new(shader, vec![]);
+    instance.set_error_policy(ErrorPolicy::MarkErrored);
+    assert!(instance.diagnostics().is_failing(1), "The missing property should be reported at the expression's own index");
+    let diagnostics = instance.drain_diagnostics().collect::<Vec<_>>();
+    assert!(matches!(&diagnostics[..], [EvaluationDiagnostic { expression_id: 5, error: EvaluationError::InvalidIdentifier { id }, .. }] if id == "offset"), "The compile error should be recorded once");
+    instance.set_property("scale", Value::Matrix4(glam::Mat4::from_scale(Vec3::splat(2.0)))).unwrap();
+    instance.update(0.0, &[], &[]).unwrap();
+    assert!(instance.drain_diagnostics().next().is_none(), "Re-evaluating shouldn't report it again");
+    assert!(instance.is_errored(), "An expression that can never evaluate should keep the instance marked");
+}
+
+#[test]
+fn test_transform_operators()
+{
+    let props = PropertyGroup::new();
+    let near = |input: &str, expected: Vec3| matches!(eval(input, &props), Value::Vector3(v) if v.abs_diff_eq(expected, 0.0001));
+    let tf = "trs(vec3(1, 2, 3), quat_axis_angle(vec3(0, 2, 0), PI / 2), 2)";
+    assert!(near(&format!("transform_point({}, vec3(1, 0, 0))", tf), Vec3::new(1.0, 2.0, 1.0)), "trs should scale, then rotate, then translate");
+    assert!(near(&format!("transform_vector({}, vec3(1, 0, 0))", tf), Vec3::new(0.0, 0.0, -2.0)), "Vectors shouldn't pick up the translation");
+    assert!(near(&format!("translation_of({})", tf), Vec3::new(1.0, 2.0, 3.0)) && near(&format!("scale_of({})", tf), Vec3::splat(2.0)), "Decomposing should give back the parts");
+    assert!(matches!(eval("transform_vector(trs(vec2(1, 0), PI, vec2(1, 1)), vec2(1, 0))", &props), Value::Vector2(v) if v.abs_diff_eq(glam::Vec2::new(-1.0, 0.0), 0.0001)), "2d trs should build a mat3");
+
+    assert!(near("transform_point(look_at(vec3(5, 0, 0), vec3(0, 0, 0), vec3(0, 1, 0)), vec3(0, 0, -5))", Vec3::ZERO), "look_at should point -z at the target");
+    assert!(near("euler(quat_euler(vec3(0.1, 0.2, 0.3)))", Vec3::new(0.1, 0.2, 0.3)), "Euler angles should round trip");
+    assert!(near("transform_point(slerp(quat(vec3(0, 0, 1), 0), quat(vec3(0, 0, 1), PI / 2), 0.5), vec3(1, 0, 0))", Vec3::new(0.5_f32.sqrt(), 0.5_f32.sqrt(), 0.0)), "slerp should turn halfway");
+    assert!(near("transform_point(nlerp(quat(vec3(0, 0, 1), 0), quat(vec3(0, 0, 1), PI), 1), vec3(1, 0, 0))", Vec3::new(-1.0, 0.0, 0.0)), "nlerp should reach its target");
+
+    let error = |input: &str| Expression::parse(0, input).unwrap().evaluate(&props).unwrap_err();
+    assert!(matches!(error("look_at(vec3(1, 1, 1), vec3(1, 1, 1), vec3(0, 1, 0))"), EvaluationError::DegenerateDirectionError { .. }), "Looking at yourself should fail");
+    assert!(matches!(error("trs(vec3(0, 0, 0), 1, vec3(1, 1, 1))"), EvaluationError::TypeMismatchError { .. }), "3d trs needs a quaternion");
+    assert!(matches!(error("rotation_of(mat4[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1])"), EvaluationError::DegenerateMatrixError), "Flattened matrices can't be decomposed");
+}
+
+fn empty_clip(frames: u16) -> Rc<AnimationClip>
+{
+    return Rc::new(AnimationClip::new(0, frames, 24, HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), vec![]));
+}
+
+#[test]
+fn test_animation_state_machine()
+{
+    let mixers = vec!
+    [
+        MixerBuilder::Simple(SimpleMixerBuilder::new("idle", empty_clip(24)).playback_type(PlaybackType::Looping)),
+        MixerBuilder::Simple(SimpleMixerBuilder::new("walk", empty_clip(24)).playback_type(PlaybackType::Looping)),
+        MixerBuilder::Simple(SimpleMixerBuilder::new("jump", empty_clip(24))),
+    ];
+    let mut anim_state = AnimationStateBuilder::create_builder(Rc::new(Armature::new("rig".to_string(), vec![])), mixers)
+        .add_parameter("speed", AnimationParameter::Float(0.0))
+        .add_parameter("jump", AnimationParameter::Trigger(false))
+        .base_state_machine
+        (
+            StateMachineBuilder::new("idle")
+                .add_state("idle", "idle")
+                .add_state("walk", "walk")
+                .add_state("jump", "jump")
+                .add_state("swim", "swim")
+                .add_transition(StateTransition::new("idle", "walk").when(TransitionCondition::Greater("speed".to_string(), 0.5)).blend_duration(Duration::from_millis(200)))
+                .add_transition(StateTransition::new("walk", "idle").when(TransitionCondition::Less("speed".to_string(), 0.5)))
+                .add_transition(StateTransition::new("jump", "idle").exit_time(NormalizedFloat::one()))
+                .add_transition(StateTransition::from_any_state("jump").when(TransitionCondition::Triggered("jump".to_string())))
+                .add_transition(StateTransition::from_any_state("swim"))
+        )
+        .build();
+    anim_state.update(0.1);
+    assert_eq!(anim_state.base_layer.current_state(), Some("idle"), "Nothing should fire without its conditions, and states without a mixer should be dropped");
+    anim_state.set_float("speed", 1.0).unwrap();
+    anim_state.update(0.1);
+    assert_eq!(anim_state.base_layer.current_state(), Some("walk"), "Parameters should drive transitions");
+
+    anim_state.set_trigger("jump").unwrap();
+    anim_state.update(0.1);
+    assert_eq!(anim_state.base_layer.current_state(), Some("jump"), "Any state transitions should fire from whatever state is current");
+    assert_eq!(anim_state.parameter("jump"), Some(AnimationParameter::Trigger(false)), "Firing should consume the trigger");
+    anim_state.update(0.5);
+    anim_state.update(0.5);
+    assert_eq!(anim_state.base_layer.current_state(), Some("jump"), "Exit time should hold the state until its mixer finishes");
+    anim_state.update(0.0);
+    assert_eq!(anim_state.base_layer.current_state(), Some("idle"), "The state should leave once its exit time is reached");
+
+    assert!(matches!(anim_state.set_bool("speed", true), Err(ParameterError::TypeMismatch { .. })), "Parameters should keep their declared type");
+    assert!(matches!(anim_state.set_float("height", 1.0), Err(ParameterError::NoSuchParameter { .. })), "Undeclared parameters should be reported");
+}
+
+fn test_rig() -> Armature
+{
+    let bones = [("root", None), ("DEF-spine", Some(0)), ("DEF-chest", Some(1)), ("DEF-neck", Some(2)), ("DEF-upper_arm.l", Some(2)), ("DEF-hand.l", Some(4)), ("DEF-thigh.l", Some(0))];
+    return Armature::new("rig".to_string(), bones.iter().map(|(name, parent)| Bone::new(name.to_string(), *parent, Vec3::Y, Quat::IDENTITY)).collect());
+}
+
+#[test]
+fn test_armature_queries()
+{
+    let armature = test_rig();
+    assert_eq!(armature.id(), "rig");
+    assert_eq!(armature.bone_index("DEF-upper_arm.l"), Some(4), "Bones should be found by name");
+    assert_eq!(armature.bone_name(6), Some("DEF-thigh.l"));
+    assert!(armature.bone("DEF-tail").is_none(), "Missing names should give nothing");
+    assert_eq!(armature.children(2).collect::<Vec<_>>(), vec![3, 4], "Only direct children should be listed");
+    assert_eq!(armature.subtree(2), vec![2, 3, 4, 5], "Subtrees should include the bone and everything under it");
+    assert_eq!(armature.path_from_root(5), vec![0, 1, 2, 4, 5], "Paths should run from the root down to the bone");
+    assert!(armature.subtree(99).is_empty() && armature.path_from_root(99).is_empty(), "Out of range bones should give empty results");
+}
+
+//every bone of test_rig held at x
+fn offset_clip(x: f32) -> Rc<AnimationClip>
+{
+    let tracks = (0..7).map(|i| (i, ClipChannel::new(vec![AnimationKey { data: x, frame: 0 }]))).collect::<HashMap<_, _>>();
+    return Rc::new(AnimationClip::new(0, 24, 24, tracks, HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), vec![]));
+}
+
+#[test]
+fn test_masked_layers()
+{
+    let mut wave = LayerBuilder::create_builder("wave");
+    wave.add_mixer(MixerBuilder::Simple(SimpleMixerBuilder::new("wave", offset_clip(3.0))))
+        .starting_mixer("wave")
+        .mask(ArmatureMask::new().subtree("DEF-chest", NormalizedFloat::one()).subtree("DEF-neck", NormalizedFloat::clamped(0.5)).bones(&["DEF-tail"], NormalizedFloat::one()));
+    let mut lean = LayerBuilder::create_builder("lean");
+    lean.add_mixer(MixerBuilder::Simple(SimpleMixerBuilder::new("lean", offset_clip(1.0))))
+        .starting_mixer("lean")
+        .mix(MixType::Additive)
+        .init_weight(NormalizedFloat::zero())
+        .mask(ArmatureMask::new().bones(&["root"], NormalizedFloat::one()));
+    let mut anim_state = AnimationStateBuilder::create_builder(Rc::new(test_rig()), vec![MixerBuilder::Simple(SimpleMixerBuilder::new("walk", offset_clip(1.0)))])
+        .base_starting_animation("walk")
+        .add_layer(wave)
+        .add_layer(lean)
+        .build();
+    let x = |anim_state: &AnimationState, bone: usize| anim_state.pose().joint(bone).unwrap().loc_rot_scale().0.x;
+
+    anim_state.update(0.0);
+    assert_eq!(x(&anim_state, 0), 1.0, "Bones outside the mask should keep the base pose");
+    assert_eq!(x(&anim_state, 6), 1.0, "Bones outside the mask should keep the base pose");
+    assert_eq!(x(&anim_state, 2), 3.0, "Override layers should replace masked bones");
+    assert_eq!(x(&anim_state, 5), 3.0, "Subtree masks should reach the whole subtree");
+    assert_eq!(x(&anim_state, 3), 2.0, "Later mask entries should win over earlier ones");
+
+    anim_state.fade_layer_weight("wave", NormalizedFloat::zero(), Duration::from_secs(1));
+    anim_state.fade_layer_weight("lean", NormalizedFloat::one(), Duration::from_secs(1));
+    anim_state.update(0.5);
+    assert_eq!(anim_state.get_layer("wave").unwrap().weight().get_val(), 0.5, "Fades should move the weight over time");
+    assert_eq!(x(&anim_state, 2), 2.0, "Layer weight should scale the mask");
+    assert_eq!(x(&anim_state, 0), 1.5, "Additive layers should add on top of the layers under them");
+
+    anim_state.update(1.0);
+    assert_eq!(x(&anim_state, 2), 1.0, "Layers faded out should leave the base pose");
+    assert_eq!(x(&anim_state, 0), 2.0, "Fades should stop at their target");
+}
+
+#[test]
+fn test_blend_space()
+{
+    //each clip's offset is 2x + 3y of where it sits, so a correct blend inside a triangle lands on the same plane
+    let at = |x: f32, y: f32| offset_clip(2.0 * x + 3.0 * y);
+    let blend_space = BlendSpaceMixerBuilder::create("locomotion", at(0.0, 0.0), Vec2::ZERO)
+        .add_point(at(1.0, 0.0), Vec2::new(1.0, 0.0))
+        .add_point(at(0.0, 1.0), Vec2::new(0.0, 1.0))
+        .add_point(at(1.0, 1.0), Vec2::new(1.0, 1.0))
+        .blend(Vec2::new(0.25, 0.5))
+        .playback_type(PlaybackType::Looping);
+    let mut anim_state = AnimationStateBuilder::create_builder(Rc::new(test_rig()), vec![MixerBuilder::BlendSpace(blend_space)])
+        .base_starting_animation("locomotion")
+        .build();
+    let x = |anim_state: &AnimationState| anim_state.pose().joint(1).unwrap().loc_rot_scale().0.x;
+
+    anim_state.update(0.0);
+    assert!((x(&anim_state) - 2.0).abs() < 0.0001, "Blending inside a triangle should weight its corners by barycentric coordinates");
+    anim_state.base_layer.set_blend_position("locomotion", Vec2::new(2.0, 0.5));
+    anim_state.update(0.0);
+    assert!((x(&anim_state) - 3.5).abs() < 0.0001, "Positions outside the clips should use the nearest point on their outline");
+    assert_eq!(anim_state.base_layer.blend_position("locomotion"), Some(Vec2::new(2.0, 0.5)));
+
+    let compass = [Vec2::Y, Vec2::X, -Vec2::Y, -Vec2::X].map(|pos| BlendSpacePoint::new(at(0.0, 0.0), pos)).to_vec();
+    let mut strafe = BlendSpaceMixer::new(compass, BlendSpaceWeighting::GradientBand);
+    strafe.set_blend(Vec2::Y);
+    assert_eq!(strafe.weights(), &[1.0, 0.0, 0.0, 0.0], "Sitting on a clip should play only that clip");
+    strafe.set_blend(Vec2::new(0.5, 0.5));
+    assert!((strafe.weights()[0] - 0.5).abs() < 0.0001 && (strafe.weights()[1] - 0.5).abs() < 0.0001, "Between two directions should blend just those two evenly");
+    assert!((strafe.weights().iter().sum::<f32>() - 1.0).abs() < 0.0001, "Weights should sum to one");
+}
+
+#[test]
+fn test_synced_transition()
+{
+    let markers = |right: u16| vec![SyncMarker { name: "left_foot_down".to_string(), frame: 0 }, SyncMarker { name: "right_foot_down".to_string(), frame: right }];
+    //a second long step cycle with nothing animated, and a slower one where bone 1's x is the frame number
+    let short = Rc::new(AnimationClip::new(0, 10, 10, HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), vec![]).with_sync_markers(markers(5)));
+    let frames = HashMap::from([(1, ClipChannel::new(vec![AnimationKey { data: 0.0, frame: 0 }, AnimationKey { data: 20.0, frame: 20 }]))]);
+    let long = AnimationClip::new(0, 20, 10, frames, HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), vec![]).with_sync_markers(markers(10));
+    let mixers = vec!
+    [
+        MixerBuilder::Simple(SimpleMixerBuilder::new("walk", short.clone()).playback_type(PlaybackType::Looping)),
+        MixerBuilder::Simple(SimpleMixerBuilder::new("run", Rc::new(long)).playback_type(PlaybackType::Looping)),
+    ];
+    let mut anim_state = AnimationStateBuilder::create_builder(Rc::new(test_rig()), mixers).base_starting_animation("walk").build();
+    anim_state.update(0.3);
+    anim_state.base_layer.queue_synced_clip_mixer("run", Duration::from_secs(1));
+    anim_state.update(0.1);
+    //walk is at frame 4, most of the way to right_foot_down, so run should be at frame 8 and blended in by a tenth
+    let x = anim_state.pose().joint(1).unwrap().loc_rot_scale().0.x;
+    assert!((x - 0.8).abs() < 0.0001, "Synced transitions should follow the outgoing mixer's marker phase, got {}", x);
+
+    //the same again from a state machine, which queues the incoming mixer from its start before it's put on the phase
+    let long = AnimationClip::new(0, 20, 10, HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), vec![]).with_sync_markers(markers(10));
+    let mixers = vec!
+    [
+        MixerBuilder::Simple(SimpleMixerBuilder::new("walk", short).playback_type(PlaybackType::Looping)),
+        MixerBuilder::Simple
+        (
+            SimpleMixerBuilder::new("run", Rc::new(long)).playback_type(PlaybackType::Looping)
+                .add_event("skipped", NormalizedFloat::clamped(0.1))
+                .add_event("right", NormalizedFloat::clamped(0.5))
+        ),
+    ];
+    let mut anim_state = AnimationStateBuilder::create_builder(Rc::new(test_rig()), mixers)
+        .add_parameter("speed", AnimationParameter::Float(0.0))
+        .base_state_machine
+        (
+            StateMachineBuilder::new("walk")
+                .add_state("walk", "walk")
+                .add_state("run", "run")
+                .add_transition(StateTransition::new("walk", "run").when(TransitionCondition::Greater("speed".to_string(), 0.5)).blend_duration(Duration::from_secs(1)).sync_phase())
+        )
+        .build();
+    anim_state.update(0.3);
+    anim_state.set_float("speed", 1.0).unwrap();
+    anim_state.update(0.1);
+    assert_eq!(anim_state.drain_events().count(), 0, "Jumping onto the outgoing mixer's phase shouldn't fire the events jumped over");
+    anim_state.update(0.1);
+    assert_eq!(anim_state.drain_events().map(|event| event.name).collect::<Vec<String>>(), vec!["right".to_string()], "Synced mixers should fire the events they're carried past");
+}
+
+#[test]
+fn test_root_motion()
+{
+    let key = |frame: u16, data: f32| AnimationKey { data: data, frame: frame };
+    let channel = |keys: Vec<AnimationKey<f32>>| HashMap::from([(0, ClipChannel::new(keys))]);
+    //a second long stride that carries the root two units down -z while bobbing it up, and a quarter turn
+    let stride = AnimationClip::new(0, 10, 10, HashMap::new(), channel(vec![key(0, 0.5)]), channel(vec![key(0, 0.0), key(10, -2.0)]), HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), vec![]);
+    let turn_end = glam::Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+    let turn_orientation = HashMap::from
+    ([(
+        0, 
+        [(0.0, turn_end.x), (0.0, turn_end.y), (0.0, turn_end.z), (1.0, turn_end.w)].map(|(start, end)| ClipChannel::new(vec![key(0, start), key(10, end)]))
+    )]);
+    let turn = AnimationClip::new(0, 10, 10, HashMap::new(), HashMap::new(), HashMap::new(), turn_orientation, HashMap::new(), HashMap::new(), HashMap::new(), vec![]);
+    let mixers = vec!
+    [
+        MixerBuilder::Simple(SimpleMixerBuilder::new("stride", Rc::new(stride)).playback_type(PlaybackType::Looping).root_motion()),
+        MixerBuilder::Simple(SimpleMixerBuilder::new("turn", Rc::new(turn)).root_motion()),
+        MixerBuilder::Simple(SimpleMixerBuilder::new("still", empty_clip(24)).playback_type(PlaybackType::Looping).root_motion()),
+    ];
+    let mut anim_state = AnimationStateBuilder::create_builder(Rc::new(test_rig()), mixers).base_starting_animation("stride").build();
+    let close = |a: f32, b: f32| (a - b).abs() < 0.0001;
+
+    anim_state.update(0.75);
+    assert!(close(anim_state.root_motion().translation.z, -1.5) && close(anim_state.root_motion().yaw, 0.0), "Root motion should follow the root's movement");
+    let (root_loc, _, _) = anim_state.pose().joint(0).unwrap().loc_rot_scale();
+    assert!(close(root_loc.z, 0.0) && close(root_loc.y, 0.5), "Horizontal movement should be taken out of the pose but not vertical");
+    anim_state.update(0.5);
+    assert!(close(anim_state.root_motion().translation.z, -1.0), "Looping back to the start shouldn't jump the root back, got {:?}", anim_state.root_motion());
+    anim_state.update(2.5);
+    assert!(close(anim_state.root_motion().translation.z, -5.0), "Coming round more than once should count every stride, got {:?}", anim_state.root_motion());
+
+    anim_state.base_layer.queue_clip_mixer("still", Duration::from_secs(1));
+    anim_state.update(0.5);
+    anim_state.update(0.25);
+    assert!(close(anim_state.root_motion().translation.z, -0.25), "Blending out should scale the motion by the mixer's share, got {:?}", anim_state.root_motion());
+
+    anim_state.base_layer.queue_clip_mixer("turn", Duration::ZERO);
+    anim_state.update(0.0);
+    anim_state.update(0.5);
+    assert!(close(anim_state.root_motion().yaw, std::f32::consts::FRAC_PI_4), "Yaw should be the root's turn about y, got {:?}", anim_state.root_motion());
+    let (_, root_rot, _) = anim_state.pose().joint(0).unwrap().loc_rot_scale();
+    assert!(root_rot.angle_between(Quat::IDENTITY) < 0.0001, "Yaw should be taken out of the pose");
+
+    let moved = RootMotion { translation: Vec3::Z, yaw: std::f32::consts::FRAC_PI_2 }.then(RootMotion { translation: Vec3::Z, yaw: 0.0 });
+    assert!(moved.translation.abs_diff_eq(Vec3::new(1.0, 0.0, 1.0), 0.0001), "Later motion should go the way the first left the character facing");
+}
+
+fn model_heads(anim_state: &AnimationState) -> Vec<Vec3>
+{
+    let mut skin = vec![Mat4::IDENTITY; anim_state.armature.num_bones()];
+    let mut rest = vec![Mat4::IDENTITY; anim_state.armature.num_bones()];
+    anim_state.write_current_pose_transforms(&mut skin, &mut rest);
+    return skin.iter().zip(rest).map(|(skin, rest)| (*skin * rest).w_axis.truncate()).collect();
+}
+
+#[test]
+fn test_ik_constraints()
+{
+    let build = |constraint: IkConstraint| 
+    {
+        let mut anim_state = AnimationStateBuilder::create_builder(Rc::new(test_rig()), vec![MixerBuilder::Simple(SimpleMixerBuilder::new("idle", empty_clip(10)))])
+            .base_starting_animation("idle")
+            .add_ik(constraint)
+            .build();
+        anim_state.update(0.0);
+        return anim_state;
+    };
+    let close = |a: Vec3, b: Vec3| a.distance(b) < 0.001;
+
+    let mut leg = build(IkConstraint::two_bone("leg", "DEF-spine", "DEF-chest", "DEF-neck").target(Vec3::new(1.0, 3.0, 0.0)).pole(Vec3::new(0.5, 2.5, -5.0)));
+    let heads = model_heads(&leg);
+    assert!(close(heads[3], Vec3::new(1.0, 3.0, 0.0)), "Two bone constraints should put the end on the target, got {}", heads[3]);
+    assert!(close(heads[2], Vec3::new(0.5, 2.5, -0.5f32.sqrt())), "Two bone constraints should bend towards the pole, got {}", heads[2]);
+    assert!(close(heads[5], heads[4] + (heads[4] - heads[2])), "Bones under the chain should follow it");
+    leg.set_ik_target("leg", Vec3::new(0.0, 10.0, 0.0));
+    leg.update(0.0);
+    assert!(close(model_heads(&leg)[3], Vec3::new(0.0, 4.0, 0.0)), "Out of reach targets should leave the limb pointing at them");
+    leg.set_ik_weight("leg", NormalizedFloat::zero());
+    leg.set_ik_target("leg", Vec3::new(1.0, 3.0, 0.0));
+    leg.update(0.0);
+    assert!(close(model_heads(&leg)[3], Vec3::new(0.0, 4.0, 0.0)), "Constraints with no weight should leave the pose alone");
+
+    for method in [ChainMethod::Fabrik, ChainMethod::Ccd]
+    {
+        let chain = build(IkConstraint::chain("reach", "root", "DEF-neck", method).iterations(50, 0.0001).target(Vec3::new(1.5, 2.5, 0.5)));
+        let heads = model_heads(&chain);
+        assert!(heads[3].distance(Vec3::new(1.5, 2.5, 0.5)) < 0.01, "{:?} chains should reach the target, got {}", method, heads[3]);
+        assert!(close(heads[0], Vec3::Y), "Chains should keep their root in place");
+    }
+    let unresolved = build(IkConstraint::chain("reach", "DEF-neck", "DEF-spine", ChainMethod::Fabrik).target(Vec3::X));
+    assert!(close(model_heads(&unresolved)[3], Vec3::new(0.0, 4.0, 0.0)), "Chains whose root isn't above the tip should do nothing");
+
+    let look = build(IkConstraint::aim("look", "DEF-neck", Vec3::Y).target(Vec3::new(0.0, 4.0, 5.0)));
+    let facing = model_heads(&look)[3] - Vec3::new(0.0, 4.0, 0.0);
+    assert!(close(look.pose().joint(3).unwrap().loc_rot_scale().1 * Vec3::Y, Vec3::Z), "Aim constraints should point the bone at the target");
+    assert!(close(facing, Vec3::ZERO), "Aim constraints should turn the bone in place");
+    let limited = build(IkConstraint::aim("look", "DEF-neck", Vec3::Y).target(Vec3::new(0.0, 4.0, 5.0)).limit("DEF-neck", std::f32::consts::FRAC_PI_4));
+    let (axis, angle) = limited.pose().joint(3).unwrap().loc_rot_scale().1.to_axis_angle();
+    assert!((angle - std::f32::consts::FRAC_PI_4).abs() < 0.001 && close(axis, Vec3::X), "Joint limits should stop the bone short, got {} about {}", angle, axis);
+}
+
+#[test]
+fn test_spring_bones()
+{
+    let build = |spring: SpringBone| 
+    {
+        return AnimationStateBuilder::create_builder(Rc::new(test_rig()), vec![MixerBuilder::Simple(SimpleMixerBuilder::new("idle", empty_clip(10)))])
+            .base_starting_animation("idle")
+            .add_spring(spring)
+            .spring_step(Duration::from_micros(15625))
+            .build();
+    };
+    let orientation = |anim_state: &AnimationState, bone: usize| anim_state.pose().joint(bone).unwrap().loc_rot_scale().1;
+
+    let mut still = build(SpringBone::new("DEF-neck").length(1.0));
+    still.update(1.0);
+    assert!(orientation(&still, 3).angle_between(Quat::IDENTITY) < 0.0001, "Springs with nothing pulling on them should keep the animated pose");
+
+    let sideways = SpringBone::new("DEF-neck").length(1.0).gravity(Vec3::X * 50.0).max_angle(std::f32::consts::FRAC_PI_8);
+    let mut short_steps = build(sideways.clone());
+    let mut long_steps = build(sideways.clone());
+    for _ in 0..64
+    {
+        short_steps.update(0.03125);
+    }
+    for _ in 0..32
+    {
+        long_steps.update(0.0625);
+    }
+    assert_eq!(orientation(&short_steps, 3), orientation(&long_steps, 3), "Springs should come out the same however the time is split up, as long as no update goes past the step limit");
+    let (axis, angle) = orientation(&short_steps, 3).to_axis_angle();
+    assert!((angle - std::f32::consts::FRAC_PI_8).abs() < 0.001 && axis.distance(-Vec3::Z) < 0.001, "Gravity should swing the bone until the angle limit, got {} about {}", angle, axis);
+    let mut limit_steps = build(sideways.clone());
+    let mut hitch = build(sideways);
+    limit_steps.update(0.125);
+    hitch.update(1.0);
+    assert_eq!(orientation(&limit_steps, 3), orientation(&hitch, 3), "Time past the step limit should be dropped rather than simulated");
+    short_steps.reset_springs();
+    short_steps.update(0.0);
+    assert!(orientation(&short_steps, 3).angle_between(Quat::IDENTITY) < 0.0001, "Reset springs should start again from the animated pose");
+
+    let mut arm = build(SpringBone::subtree("DEF-upper_arm.l").gravity(Vec3::X * 50.0).max_angle(std::f32::consts::FRAC_PI_8));
+    arm.update(2.0);
+    for bone in [4, 5]
+    {
+        let angle = orientation(&arm, bone).angle_between(Quat::IDENTITY);
+        assert!(angle > 0.01 && angle < std::f32::consts::FRAC_PI_8 + 0.001, "Every bone in a spring subtree should swing within its limit, got {} for bone {}", angle, bone);
+    }
+}
+
+#[test]
+fn test_animation_events()
+{
+    let mut anim_state = AnimationStateBuilder::create_builder
+    (
+        Rc::new(test_rig()), 
+        vec!
+        [
+            MixerBuilder::Simple
+            (
+                SimpleMixerBuilder::new("walk", empty_clip(24)).playback_type(PlaybackType::Looping)
+                    .add_event("left", NormalizedFloat::clamped(0.25))
+                    .add_event("right", NormalizedFloat::clamped(0.75))
+                    .add_event("loop", NormalizedFloat::zero())
+            ),
+            MixerBuilder::Simple(SimpleMixerBuilder::new("swing", empty_clip(24)).add_event("hit", NormalizedFloat::clamped(0.5)).add_event("wind_up", NormalizedFloat::zero())),
+        ]
+    )
+    .base_starting_animation("walk")
+    .build();
+    let names = |events: &Vec<AnimationEvent>| events.iter().map(|event| event.name.as_str()).collect::<Vec<&str>>().join(" ");
+
+    anim_state.update(0.5);
+    let events = anim_state.drain_events().collect::<Vec<AnimationEvent>>();
+    let event = |name: &str, time: f32| AnimationEvent { name: name.to_string(), layer: "base".to_string(), mixer: "walk".to_string(), time: NormalizedFloat::clamped(time), weight: 1.0 };
+    assert_eq!(events, vec![event("loop", 0.0), event("left", 0.25)], "The working mixer's events should fire, including one where it starts");
+    assert_eq!(anim_state.drain_events().count(), 0, "Drained events shouldn't come back");
+
+    anim_state.update(2.0);
+    let events = anim_state.drain_events().collect::<Vec<AnimationEvent>>();
+    assert_eq!(names(&events), "right loop left right loop left", "Long steps should fire every event passed, once per time round the loop");
+
+    anim_state.base_layer.queue_clip_mixer("swing", Duration::from_secs(1));
+    anim_state.update(0.25);
+    anim_state.update(0.5);
+    let events = anim_state.drain_events().collect::<Vec<AnimationEvent>>();
+    assert_eq!(names(&events), "right wind_up loop left hit", "Events should fire for transitioning mixers as well as the working one, from where they start");
+    assert!((events[4].weight - 0.75).abs() < 0.0001, "Events should carry their mixer's share of the layer, got {}", events[4].weight);
+    assert!((events[0].weight - 0.75).abs() < 0.0001 && (events[3].weight - 0.25).abs() < 0.0001, "Mixers blending out should report their remaining share");
+
+    anim_state.update(1.0);
+    assert_eq!(names(&anim_state.drain_events().collect()), "right loop left", "Mixers should fire events on the update their transition finishes");
+    anim_state.update(1.0);
+    assert_eq!(names(&anim_state.drain_events().collect()), "", "Sequential mixers shouldn't fire events again once they've stopped");
+}
+
+#[test]
+fn test_keyframe_interpolation()
+{
+    let key = |frame: u16, data: f32| AnimationKey { data: data, frame: frame };
+    let rise = |modes: Vec<KeyInterpolation>| ClipChannel::new(vec![key(0, 0.0), key(10, 1.0)]).with_interpolation(modes);
+    let eased = KeyInterpolation::Bezier { in_handle: Vec2::new(-10.0 / 3.0, 0.0), out_handle: Vec2::new(10.0 / 3.0, 0.0) };
+    let x_tracks = HashMap::from
+    ([
+        (0, rise(vec![])),
+        (1, rise(vec![KeyInterpolation::Constant])),
+        (2, rise(vec![eased, eased])),
+        (3, rise(vec![KeyInterpolation::Hermite { in_slope: 0.0, out_slope: 0.2 }, KeyInterpolation::Hermite { in_slope: 0.0, out_slope: 0.0 }])),
+        (4, ClipChannel::new(vec![key(0, 0.0), key(5, 1.0), key(10, 0.0)]).with_interpolation(vec![KeyInterpolation::CatmullRom; 3])),
+        //handles reaching past the segment are pulled back in, which makes this one linear in frames
+        (5, rise(vec![KeyInterpolation::Bezier { in_handle: Vec2::ZERO, out_handle: Vec2::new(100.0, 0.0) }])),
+    ]);
+    let turn_end = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+    let orientation = HashMap::from([(6, [(0.0, turn_end.x), (0.0, turn_end.y), (0.0, turn_end.z), (1.0, turn_end.w)].map(|(start, end)| ClipChannel::new(vec![key(0, start), key(10, end)])))]);
+    let clip = AnimationClip::new(0, 10, 10, x_tracks, HashMap::new(), HashMap::new(), orientation, HashMap::new(), HashMap::new(), HashMap::new(), vec![]);
+    let mut anim_state = AnimationStateBuilder::create_builder(Rc::new(test_rig()), vec![MixerBuilder::Simple(SimpleMixerBuilder::new("curves", Rc::new(clip)))])
+        .base_starting_animation("curves")
+        .build();
+    let x = |anim_state: &AnimationState, bone: usize| anim_state.pose().joint(bone).unwrap().loc_rot_scale().0.x;
+    let close = |a: f32, b: f32| (a - b).abs() < 0.0001;
+
+    anim_state.update(0.25);
+    assert!(close(x(&anim_state, 0), 0.25), "Keys should be linear unless told otherwise");
+    assert!(close(x(&anim_state, 1), 0.0), "Constant keys should hold until the next key");
+    assert!(close(x(&anim_state, 2), 0.15625), "Flat bezier handles a third along should ease like smoothstep, got {}", x(&anim_state, 2));
+    assert!(close(x(&anim_state, 3), 0.4375), "Hermite keys should follow their slopes, got {}", x(&anim_state, 3));
+    assert!(close(x(&anim_state, 4), 0.625), "Catmull-rom keys should take their slopes from their neighbours, got {}", x(&anim_state, 4));
+    let (_, rotation, _) = anim_state.pose().joint(6).unwrap().loc_rot_scale();
+    assert!(close(rotation.length(), 1.0), "Orientations should come out normalized, got a length of {}", rotation.length());
+    //a quarter of the way round the arc turns a quarter of the angle
+    let turned = rotation.angle_between(Quat::IDENTITY);
+    assert!(close(turned, 0.25 * std::f32::consts::FRAC_PI_2), "Orientations should turn at a steady rate between keys, got {}", turned);
+
+    anim_state.update(0.5);
+    assert!(close(x(&anim_state, 1), 0.0) && close(x(&anim_state, 2), 0.84375), "Curves should hold their shape all the way along");
+    assert!(x(&anim_state, 5) > 0.0 && x(&anim_state, 5) < 1.0, "Handles past the end of the segment shouldn't overshoot it, got {}", x(&anim_state, 5));
+}
+
+#[test]
+fn test_orientation_shortest_arc()
+{
+    let key = |frame: u16, data: f32| AnimationKey { data: data, frame: frame };
+    //the same quarter turn as above, but with the end key on the other side of the hypersphere
+    let turn_end = -Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+    assert!(Quat::IDENTITY.dot(turn_end) < 0.0);
+    let orientation = HashMap::from([(6, [(0.0, turn_end.x), (0.0, turn_end.y), (0.0, turn_end.z), (1.0, turn_end.w)].map(|(start, end)| ClipChannel::new(vec![key(0, start), key(10, end)])))]);
+    let clip = AnimationClip::new(0, 10, 10, HashMap::new(), HashMap::new(), HashMap::new(), orientation, HashMap::new(), HashMap::new(), HashMap::new(), vec![]);
+    let mut anim_state = AnimationStateBuilder::create_builder(Rc::new(test_rig()), vec![MixerBuilder::Simple(SimpleMixerBuilder::new("turn", Rc::new(clip)))])
+        .base_starting_animation("turn")
+        .build();
+    let close = |a: f32, b: f32| (a - b).abs() < 0.0001;
+
+    anim_state.update(0.5);
+    let (_, rotation, _) = anim_state.pose().joint(6).unwrap().loc_rot_scale();
+    assert!(close(rotation.length(), 1.0), "Orientations should come out normalized, got a length of {}", rotation.length());
+    let off = rotation.angle_between(Quat::from_rotation_y(std::f32::consts::FRAC_PI_4));
+    assert!(close(off, 0.0), "Halfway should be an eighth of a turn the short way round, got {} away from it", off);
+}
+
+#[test]
+fn test_clip_cursor()
+{
+    let key = |frame: u16, data: f32| AnimationKey { data: data, frame: frame };
+    //keys that don't start on the clip's first frame or finish on its last, so both ends have to fall back
+    let channel = ClipChannel::new(vec![key(2, 1.0), key(3, -2.0), key(7, 4.0), key(8, 0.5), key(13, 3.0), key(14, 3.0), key(18, -1.0)]);
+    assert_eq!(channel.frames(), &[2, 3, 7, 8, 13, 14, 18]);
+    assert_eq!(channel.values(), &[1.0, -2.0, 4.0, 0.5, 3.0, 3.0, -1.0]);
+
+    //forwards a step at a time, backwards, then jumping about, which is everything a playhead does
+    let forwards = (0..=80).map(|i| i as f32 / 80.0);
+    let backwards = (0..=80).rev().map(|i| i as f32 / 80.0);
+    let jumps = (0..80).map(|i| ((i * 37) % 80) as f32 / 80.0);
+    let times = forwards.chain(backwards).chain(jumps).collect::<Vec<_>>();
+    for playback_type in [PlaybackType::Sequential, PlaybackType::Looping]
+    {
+        let cursor = ClipCursor::new();
+        for t in &times
+        {
+            let time = NormalizedFloat::clamped(*t);
+            let searched = channel.sample(0, 20, time, playback_type, None);
+            let cached = channel.sample(0, 20, time, playback_type, Some(&cursor));
+            assert_eq!(searched.to_bits(), cached.to_bits(), "Sampling with a cursor should match searching from scratch at {}", t);
+        }
+    }
+    let between = channel.sample(0, 20, NormalizedFloat::clamped(0.5), PlaybackType::Sequential, None);
+    assert!((between - 1.5).abs() < 0.0001, "Frames between keys should blend the keys either side, got {}", between);
+    assert_eq!(channel.sample(0, 20, NormalizedFloat::clamped(0.0), PlaybackType::Sequential, None), 1.0, "Sequential channels should hold their first key before it");
+    assert_eq!(channel.sample(0, 20, NormalizedFloat::clamped(1.0), PlaybackType::Sequential, None), -1.0, "Sequential channels should hold their last key after it");
+    assert_eq!(channel.sample(0, 20, NormalizedFloat::clamped(1.0), PlaybackType::Looping, None), 0.0, "Looping channels should head back round to their first key");
+}
@@ -1,45 +1,194 @@
+use std::fmt;
 use std::rc::Rc;
 
-use super::properties::{Expression, PropertyGroup, EvalTable, Value, AssignmentError};
+use glam::Mat4;
+
+use super::animation::Armature;
+use super::common::Color;
+use super::diagnostics::{ErrorPolicy, EvaluationDiagnostic, ShaderDiagnostics};
+use super::tween::{PropertyTweener, Tween, TweenEvent, TweenId};
+use super::properties::{CompiledExpression, EvalStack, EvaluationError, Expression, PropertyGroup, PropertySchema, PropertySlots, PropertyStore, EvalTable, Value, AssignmentError};
 
 pub struct Shader
 {
     pub id: String,
     pub color_maps : Vec<ColorMap>,
     pub placements : Vec<Placement>,
-    pub default_args : PropertyGroup
+    pub default_args : PropertyGroup,
+    //built once in new, so editing the maps or placements afterwards leaves these stale
+    schema: Rc<PropertySchema>,
+    //in visit order. expressions that read a property the defaults don't have keep their compile error here,
+    //and every instance reports it in its diagnostics at the same index
+    compiled: Vec<Result<CompiledExpression, FailedExpression>>,
+    //indices into compiled for each slot
+    slot_dependents: Vec<Vec<usize>>,
+    stack_size: usize,
+    //what each compiled expression gives with default_args, for ErrorPolicy::SubstituteDefault
+    default_values: EvalTable,
+}
+
+struct FailedExpression
+{
+    id: u16,
+    error: EvaluationError,
 }
 
 impl Shader
 {
-    pub fn create_properties_instance(&self) -> PropertyGroup
+    pub fn new(id: String, color_maps: Vec<ColorMap>, placements: Vec<Placement>, default_args: PropertyGroup) -> Self
     {
-        return self.default_args.clone();
+        let schema = Rc::new(PropertySchema::new(&default_args));
+        let mut shader = Shader 
+        { 
+            id: id, 
+            color_maps: color_maps, 
+            placements: placements, 
+            default_args: default_args, 
+            schema: schema,
+            compiled: vec![],
+            slot_dependents: vec![],
+            stack_size: 0,
+            default_values: EvalTable::new(),
+        };
+        let mut compiled = vec![];
+        shader.visit_expressions
+        (
+            &mut |expr| 
+            {
+                compiled.push(expr.compile(&shader.schema).map_err(|err| return FailedExpression { id: expr.get_id(), error: err }));
+            }
+        );
+        let mut slot_dependents = vec![vec![]; shader.schema.len()];
+        for (i, expr) in compiled.iter().enumerate().filter_map(|(i, expr)| return Some((i, expr.as_ref().ok()?)))
+        {
+            for slot in expr.slots()
+            {
+                slot_dependents[*slot].push(i);
+            }
+        }
+        shader.stack_size = compiled.iter().filter_map(|expr| return expr.as_ref().ok()).map(|expr| expr.stack_size()).max().unwrap_or(0);
+        shader.compiled = compiled;
+        shader.slot_dependents = slot_dependents;
+        let mut default_values = EvalTable::new();
+        shader.eval_slots(&shader.schema.create_slots(), &mut shader.create_eval_stack(), &mut default_values, &mut ShaderDiagnostics::new(shader.compiled.len()));
+        default_values.clear_changed();
+        shader.default_values = default_values;
+        return shader;
+    }
+
+    pub fn schema(&self) -> &PropertySchema
+    {
+        return &self.schema;
+    }
+
+    //big enough for every compiled expression in the shader
+    pub fn create_eval_stack(&self) -> EvalStack
+    {
+        return EvalStack::with_capacity(self.stack_size);
+    }
+
+    //counts the ones that failed to compile too, so diagnostic indices line up with visit order
+    pub fn num_compiled_expressions(&self) -> usize
+    {
+        return self.compiled.len();
+    }
+
+    pub fn eval_slots(&self, slots: &[Value], stack: &mut EvalStack, dest: &mut EvalTable, diagnostics: &mut ShaderDiagnostics)
+    {
+        for i in 0..self.compiled.len()
+        {
+            self.eval_compiled(i, slots, stack, dest, diagnostics);
+        }
+    }
+
+    //flags every compiled expression that reads the slot, for eval_dirty
+    pub fn mark_dependents(&self, slot: usize, dirty: &mut [bool])
+    {
+        if let Some(dependents) = self.slot_dependents.get(slot)
+        {
+            for i in dependents
+            {
+                dirty[*i] = true;
+            }
+        }
     }
 
-    pub fn create_value_table_instance(&self) -> EvalTable
+    //re-runs the flagged expressions and clears their flags. dirty needs one entry per compiled expression
+    pub fn eval_dirty(&self, slots: &[Value], dirty: &mut [bool], stack: &mut EvalStack, dest: &mut EvalTable, diagnostics: &mut ShaderDiagnostics)
     {
-        let mut table = EvalTable::new();
-        self.eval(&self.default_args, &mut table);
-        return table;
+        for (i, flag) in dirty.iter_mut().enumerate().take(self.compiled.len())
+        {
+            if *flag
+            {
+                self.eval_compiled(i, slots, stack, dest, diagnostics);
+                *flag = false;
+            }
+        }
     }
 
-    pub fn eval(&self, args: &PropertyGroup, dest: &mut EvalTable)
+    //failures are recorded and handled by the diagnostics' policy. only failures pay for naming the inputs
+    fn eval_compiled(&self, index: usize, slots: &[Value], stack: &mut EvalStack, dest: &mut EvalTable, diagnostics: &mut ShaderDiagnostics)
+    {
+        let expr = match &self.compiled[index]
+        {
+            Ok(expr) => expr,
+            //it can never succeed, so it only gets reported the first time
+            Err(failed) => 
+            {
+                if !diagnostics.is_failing(index)
+                {
+                    diagnostics.record(index, failed.id, failed.error.clone(), vec![]);
+                }
+                return;
+            },
+        };
+        let err = match dest.update_compiled(expr, slots, stack)
+        {
+            Ok(()) => 
+            {
+                diagnostics.resolve(index);
+                return;
+            },
+            Err(err) => err,
+        };
+        if diagnostics.policy() == ErrorPolicy::SubstituteDefault
+        {
+            if let Some(val) = self.default_values.get_value(expr.get_id())
+            {
+                dest.set_value(expr.get_id(), *val);
+            }
+        }
+        let inputs = expr.slots().iter().map(|slot| (self.schema.names()[*slot].clone(), slots[*slot])).collect();
+        diagnostics.record(index, expr.get_id(), err, inputs);
+    }
+
+    pub fn create_properties_instance(&self) -> PropertyGroup
+    {
+        return self.default_args.clone();
+    }
+
+    fn visit_expressions(&self, visit: &mut impl FnMut(&Expression))
     {
         for placement in &self.placements
         {
-            dest.update(&placement.tf, args);
+            visit(&placement.tf);
             match &placement.variant
             {
                 PlacementVariant::Singular() => (),
-                PlacementVariant::TilePattern(expr) => dest.update(&expr, args),
+                PlacementVariant::TilePattern(expr) => visit(&expr),
+            }
+            match &placement.projection
+            {
+                Projection::Triplanar { sharpness } => visit(sharpness),
+                Projection::Cylindrical { axis } => visit(axis),
+                Projection::Planar | Projection::Spherical => (),
             }
         }
         for map in &self.color_maps
         {
             for term in &map.sdf_stack
             {
-                term.eval(args, dest);
+                term.visit_expressions(visit);
             }
             match &map.variant
             {
@@ -47,21 +196,21 @@ impl Shader
                 {
                     for color_point in &grad.inner_grad.color_points
                     {
-                        dest.update(&color_point.color, args);
-                        dest.update(&color_point.val, args);
+                        visit(&color_point.color);
+                        visit(&color_point.val);
                     }
                     if let Some(outer_grad) = &grad.outer_grad
                     {
                         for color_point in &outer_grad.color_points
                         {
-                            dest.update(&color_point.color, args);
-                            dest.update(&color_point.val, args);
+                            visit(&color_point.color);
+                            visit(&color_point.val);
                         }
                     }
                 },
                 ColorMapVariant::Binary(binary) => 
                 {
-                    dest.update(&binary.color, args);
+                    visit(&binary.color);
                 },
             }
         }
@@ -74,13 +223,26 @@ pub struct ColorMap
     pub sdf_stack : Vec<SDFTerm>
 }
 
+impl ColorMap
+{
+    pub fn binary(color: Expression, sdf_stack: Vec<SDFTerm>) -> Self
+    {
+        return ColorMap { variant: ColorMapVariant::Binary(BinaryColorMap { color: color }), sdf_stack: sdf_stack };
+    }
+
+    pub fn gradient(inner_grad: ColorGradient, outer_grad: Option<ColorGradient>, sdf_stack: Vec<SDFTerm>) -> Self
+    {
+        return ColorMap { variant: ColorMapVariant::Gradient(GradientColorMap { inner_grad: inner_grad, outer_grad: outer_grad }), sdf_stack: sdf_stack };
+    }
+}
+
 pub enum ColorMapVariant
 {
     Gradient(GradientColorMap),
     Binary(BinaryColorMap),
 }
 
-struct BinaryColorMap
+pub struct BinaryColorMap
 {
     color : Expression,
 }
@@ -97,11 +259,67 @@ pub struct ColorGradient
     color_points : Vec<ColorPoint>,
     max_distance : Expression
 }
+
+impl ColorGradient
+{
+    pub fn new(max_distance: Expression, extrapolation: GradientExtrapolation) -> Self
+    {
+        return ColorGradient { extrapolation: extrapolation, color_points: vec![], max_distance: max_distance };
+    }
+
+    pub fn with_point(mut self, color: Expression, val: Expression, interpolation_mode: GradientInterpolation) -> Self
+    {
+        self.color_points.push(ColorPoint { val: val, color: color, interpolation_mode: interpolation_mode });
+        return self;
+    }
+}
+
 pub struct Placement
 {
     index : u32,
     tf : Expression,
     variant : PlacementVariant,
+    projection : Projection,
+    space : ProjectionSpace,
+    //placements following a bone get carried along by the current pose
+    bone : Option<String>,
+}
+
+impl Placement
+{
+    pub fn new(index: u32, tf: Expression, projection: Projection, space: ProjectionSpace) -> Self
+    {
+        return Placement { index: index, tf: tf, variant: PlacementVariant::Singular(), projection: projection, space: space, bone: None };
+    }
+
+    pub fn with_bone(mut self, bone: &str) -> Self
+    {
+        self.bone = Some(bone.to_string());
+        return self;
+    }
+
+    //repeats the placement every offset
+    pub fn with_tile(mut self, offset: Expression) -> Self
+    {
+        self.variant = PlacementVariant::TilePattern(offset);
+        return self;
+    }
+
+    //which of the shader's color maps gets drawn here
+    pub fn map_index(&self) -> u32
+    {
+        return self.index;
+    }
+
+    pub fn projection(&self) -> &Projection
+    {
+        return &self.projection;
+    }
+
+    pub fn space(&self) -> &ProjectionSpace
+    {
+        return &self.space;
+    }
 }
 
 enum PlacementVariant
@@ -110,6 +328,21 @@ enum PlacementVariant
     TilePattern(Expression),
 }
 
+//how surface points get mapped into a placement's space before the sdfs are evaluated
+pub enum Projection
+{
+    Planar,
+    Triplanar{sharpness: Expression},
+    Spherical,
+    Cylindrical{axis: Expression},
+}
+
+pub enum ProjectionSpace
+{
+    Object,
+    World,
+}
+
 struct ColorPoint
 {
     val : Expression,
@@ -117,13 +350,13 @@ struct ColorPoint
     interpolation_mode : GradientInterpolation
 }
 
-enum GradientInterpolation
+pub enum GradientInterpolation
 {
     Linear,
     Step,
 }
 
-enum GradientExtrapolation
+pub enum GradientExtrapolation
 {
     LastColor,
     Repeat,
@@ -138,20 +371,21 @@ pub enum SDFTerm
 
 impl SDFTerm
 {
-    fn eval(&self, args: &PropertyGroup, dest: &mut EvalTable)
+    fn visit_expressions(&self, visit: &mut impl FnMut(&Expression))
     {
         match self 
         {
             SDFTerm::Operator(_) => (),
-            SDFTerm::Operand(operand) => operand.eval(args, dest),
+            SDFTerm::Operand(operand) => operand.visit_expressions(visit),
         }
     }
 }
 
-enum SDFOperator
+//minimum and average take the number of sdfs under them on the stack
+pub enum SDFOperator
 {
-    Minimum,
-    Average,
+    Minimum(usize),
+    Average(usize),
     Mask,
     Round,
     WaveSheet,
@@ -171,59 +405,62 @@ pub enum SDFOperand
 
 impl SDFOperand
 {
-    fn eval(&self, args: &PropertyGroup, dest: &mut EvalTable)
+    fn visit_expressions(&self, visit: &mut impl FnMut(&Expression))
     {
         match self 
         {
             SDFOperand::Circle { tf, radius } => 
             {
-                dest.update(tf, args);
-                dest.update(radius, args);
+                visit(tf);
+                visit(radius);
             },
             SDFOperand::Rectangle { tf, width, height } => 
             {
-                dest.update(tf, args);
-                dest.update(width, args);
-                dest.update(height, args);
+                visit(tf);
+                visit(width);
+                visit(height);
             },
             SDFOperand::Sphere { tf, radius } => 
             {
-                dest.update(tf, args);
-                dest.update(radius, args);
+                visit(tf);
+                visit(radius);
             },
             SDFOperand::Plane { tf } => 
             {
-                dest.update(tf, args);
+                visit(tf);
             },
             SDFOperand::Polygon { tf, points } => 
             {
-                dest.update(tf, args);
+                visit(tf);
                 for p in points
                 {
-                    dest.update(p, args);
+                    visit(p);
                 }
             },
             SDFOperand::RegularPolygon { tf, num_points, radius } => 
             {
-                dest.update(tf, args);
-                dest.update(num_points, args);
-                dest.update(radius, args);
+                visit(tf);
+                visit(num_points);
+                visit(radius);
             },
             SDFOperand::PolyStar { tf, numpoints, inner_radius, outer_radius } => 
             {
-                dest.update(tf, args);
-                dest.update(numpoints, args);
-                dest.update(inner_radius, args);
-                dest.update(outer_radius, args);
+                visit(tf);
+                visit(numpoints);
+                visit(inner_radius);
+                visit(outer_radius);
             },
         }
     }
 }
 
+//where a linked property gets its value from each frame
 #[derive(Clone)]
-enum ExternalShaderValue
+pub enum ExternalShaderValue
 {
+    //index into the model instance's colors, feeds a Color property
     Color(usize),
+    //named armature control, feeds a Scalar property
     AnimationTrack(String)
 }
 
@@ -235,27 +472,245 @@ pub struct ShaderValueLink
     value: ExternalShaderValue,
 }
 
+impl ShaderValueLink
+{
+    pub fn new(property_name: &str, value: ExternalShaderValue) -> Self
+    {
+        return ShaderValueLink { active: true, property_name: property_name.to_string(), value: value };
+    }
+}
+
+#[derive(Debug)]
+pub enum ShaderLinkError
+{
+    NoSuchColor{ property_name: String, index: usize, num_colors: usize },
+    NoSuchTrack{ property_name: String, track: String },
+    AssignmentError(AssignmentError),
+}
+
+impl std::error::Error for ShaderLinkError {}
+
+#[derive(Debug)]
+pub enum ShaderUpdateError
+{
+    LinkError(ShaderLinkError),
+    TweenError(AssignmentError),
+}
+
+impl std::error::Error for ShaderUpdateError {}
+
+impl fmt::Display for ShaderUpdateError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result 
+    {
+        return match self 
+        {
+            ShaderUpdateError::LinkError(err) => write!(f, "{}", err),
+            ShaderUpdateError::TweenError(err) => write!(f, "Could not apply shader tween. {}", err),
+        };
+    }
+}
+
+impl fmt::Display for ShaderLinkError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result 
+    {
+        return match self 
+        {
+            ShaderLinkError::NoSuchColor { property_name, index, num_colors } => 
+            {
+                write!(f, "Property \"{}\" is linked to color {}, but the model only has {} colors.", property_name, index, num_colors)
+            },
+            ShaderLinkError::NoSuchTrack { property_name, track } => 
+            {
+                write!(f, "Property \"{}\" is linked to animation track \"{}\", which the armature doesn't have.", property_name, track)
+            },
+            ShaderLinkError::AssignmentError(err) => write!(f, "Could not apply shader link. {}", err),
+        };
+    }
+}
+
 pub struct ShaderInstance
 {
     shader : Rc<Shader>,
-    properties : PropertyGroup,
+    //laid out by the shader's schema, what the compiled expressions actually read
+    properties : PropertySlots,
+    eval_stack: EvalStack,
+    dirty_expressions: Vec<bool>,
     links: Vec<ShaderValueLink>,
+    //one per link, the slot of the property it feeds
+    link_slots: Vec<Option<usize>>,
+    //one per link, the control index of AnimationTrack links once an armature is bound
+    link_tracks: Vec<Option<usize>>,
+    tweens: PropertyTweener,
     expression_cache: EvalTable,
+    diagnostics: ShaderDiagnostics,
+    //one per placement
+    placement_bones: Vec<Option<usize>>,
+    bone_transforms: Vec<Mat4>,
+    placement_transforms: Vec<Mat4>,
+    //object to world, used by world space placements
+    object_tf: Mat4,
 }
 
 impl ShaderInstance
 {
     pub fn new(shader: Rc<Shader>, links: Vec<ShaderValueLink>) -> Self
     {
-        let eval_table = shader.create_value_table_instance();
-        let properties = shader.create_properties_instance();
-        return ShaderInstance 
+        let num_placements = shader.placements.len();
+        let mut instance = ShaderInstance 
         {
+            properties: PropertySlots::new(shader.schema.clone()),
+            link_slots: links.iter().map(|link| shader.schema.slot(&link.property_name)).collect(),
+            eval_stack: shader.create_eval_stack(),
+            dirty_expressions: vec![false; shader.num_compiled_expressions()],
+            //placements that can't be inverted get tracked after the expressions
+            diagnostics: ShaderDiagnostics::new(shader.num_compiled_expressions() + num_placements),
             shader: shader,
-            properties: properties,
+            link_tracks: vec![None; links.len()],
             links: links,
-            expression_cache: eval_table,
+            tweens: PropertyTweener::new(),
+            expression_cache: EvalTable::new(),
+            placement_bones: vec![None; num_placements],
+            bone_transforms: vec![Mat4::IDENTITY; num_placements],
+            placement_transforms: vec![Mat4::IDENTITY; num_placements],
+            object_tf: Mat4::IDENTITY,
         };
+        //evaluated per instance rather than copied from the shader so failures with the defaults show up in its diagnostics
+        instance.shader.eval_slots(instance.properties.values(), &mut instance.eval_stack, &mut instance.expression_cache, &mut instance.diagnostics);
+        instance.calculate_placement_transforms();
+        return instance;
+    }
+
+    //swaps in a reloaded shader. properties that still exist with the same type keep their values,
+    //and links feeding the same property keep whether they were toggled on
+    pub fn rebuild(&mut self, shader: Rc<Shader>, mut links: Vec<ShaderValueLink>)
+    {
+        for link in links.iter_mut()
+        {
+            if let Some(old_link) = self.links.iter().find(|old_link| old_link.property_name == link.property_name)
+            {
+                link.active = old_link.active;
+            }
+        }
+        let mut properties = PropertySlots::new(shader.schema.clone());
+        for (name, val) in self.properties.get_properties()
+        {
+            let _ = properties.set_property(name, *val);
+        }
+        let num_placements = shader.placements.len();
+        properties.clear_changed();
+        self.eval_stack = shader.create_eval_stack();
+        self.dirty_expressions = vec![false; shader.num_compiled_expressions()];
+        self.expression_cache = EvalTable::new();
+        self.diagnostics.reset(shader.num_compiled_expressions() + num_placements);
+        shader.eval_slots(properties.values(), &mut self.eval_stack, &mut self.expression_cache, &mut self.diagnostics);
+        self.link_slots = links.iter().map(|link| shader.schema.slot(&link.property_name)).collect();
+        self.shader = shader;
+        self.properties = properties;
+        self.link_tracks = vec![None; links.len()];
+        self.links = links;
+        self.placement_bones = vec![None; num_placements];
+        self.bone_transforms = vec![Mat4::IDENTITY; num_placements];
+        self.placement_transforms = vec![Mat4::IDENTITY; num_placements];
+        self.calculate_placement_transforms();
+    }
+
+    //rebuilds onto new if the instance was made from old, keeping its own links. returns whether it did
+    pub fn replace_shader(&mut self, old: &Rc<Shader>, new: &Rc<Shader>) -> bool
+    {
+        if !Rc::ptr_eq(&self.shader, old)
+        {
+            return false;
+        }
+        let links = self.links.clone();
+        self.rebuild(new.clone(), links);
+        return true;
+    }
+
+    //placements naming a bone that isn't in the armature just stay put in the bind pose.
+    //track links naming a control it doesn't have fail in apply_links
+    pub fn bind_armature(&mut self, armature: &Armature)
+    {
+        self.placement_bones = self.shader.placements.iter().map
+        (
+            |placement| match &placement.bone
+            {
+                Some(name) => armature.bone_index(name),
+                None => None,
+            }
+        ).collect();
+        self.link_tracks = self.links.iter().map
+        (
+            |link| match &link.value
+            {
+                ExternalShaderValue::AnimationTrack(track) => armature.control_index(track),
+                ExternalShaderValue::Color(_) => None,
+            }
+        ).collect();
+    }
+
+    pub fn shader(&self) -> &Shader
+    {
+        return &self.shader;
+    }
+
+    //pose_transforms are the skinning transforms from AnimationState::write_current_pose_transforms
+    pub fn update_placement_transforms(&mut self, pose_transforms: &[Mat4])
+    {
+        for i in 0..self.placement_bones.len()
+        {
+            self.bone_transforms[i] = match self.placement_bones[i].and_then(|b| pose_transforms.get(b))
+            {
+                Some(pose_tf) => *pose_tf,
+                None => Mat4::IDENTITY,
+            };
+        }
+        self.calculate_placement_transforms();
+    }
+
+    //only matters for world space placements
+    pub fn set_object_transform(&mut self, tf: Mat4)
+    {
+        self.object_tf = tf;
+        self.calculate_placement_transforms();
+    }
+
+    //a placement that flattens to nothing keeps its last transform until it can be inverted again
+    fn calculate_placement_transforms(&mut self)
+    {
+        let num_expressions = self.shader.num_compiled_expressions();
+        for i in 0..self.shader.placements.len()
+        {
+            let expression_id = self.shader.placements[i].tf.get_id();
+            let tf = match self.expression_cache.get_value(expression_id)
+            {
+                Some(Value::Matrix4(mat)) => *mat,
+                _ => Mat4::IDENTITY,
+            };
+            let placement_tf = self.bone_transforms[i] * tf;
+            if placement_tf.determinant() == 0.0
+            {
+                //reported once when it goes degenerate rather than every frame it stays that way
+                if !self.diagnostics.is_failing(num_expressions + i)
+                {
+                    self.diagnostics.record(num_expressions + i, expression_id, EvaluationError::DegenerateMatrixError, vec![]);
+                }
+                continue;
+            }
+            self.diagnostics.resolve(num_expressions + i);
+            self.placement_transforms[i] = match self.shader.placements[i].space
+            {
+                ProjectionSpace::Object => placement_tf.inverse(),
+                ProjectionSpace::World => placement_tf.inverse() * self.object_tf,
+            };
+        }
+    }
+
+    //object space to placement space, one per placement. world space placements go through the object transform first
+    pub fn placement_transforms(&self) -> &[Mat4]
+    {
+        return &self.placement_transforms;
     }
 
     pub fn set_property(&mut self, name: &str, val: Value) -> Result<(), AssignmentError>
@@ -263,6 +718,96 @@ impl ShaderInstance
         return self.properties.set_property(name, val);
     }
 
+    //for callers that set the same property every frame, the slot comes from the shader's schema
+    pub fn set_slot(&mut self, slot: usize, val: Value) -> Result<(), AssignmentError>
+    {
+        return self.properties.set_slot(slot, val);
+    }
+
+    pub fn get_property(&self, name: &str) -> Option<&Value>
+    {
+        return self.properties.get_property(name);
+    }
+
+    //properties whose values differ from the shader's defaults. ones an active link or a running tween is driving are left out,
+    //their current values are only passing through
+    pub fn overridden_properties(&self) -> impl Iterator<Item=(&String, &Value)> + '_
+    {
+        let defaults = self.shader.schema.defaults();
+        return self.properties.get_properties().enumerate()
+            .filter(move |(slot, (_, val))| defaults[*slot] != **val)
+            .map(|(_, property)| property)
+            .filter(move |(name, _)| !self.links.iter().any(|link| link.active && &link.property_name == *name) && !self.tweens.is_tweening(name));
+    }
+
+    //tweens run first so a linked property always ends up following its link
+    pub fn update(&mut self, dt: f32, colors: &[Color], control_values: &[f32]) -> Result<(), ShaderUpdateError>
+    {
+        self.diagnostics.advance_frame();
+        let tweened = self.tweens.update(dt, &mut self.properties).map_err(ShaderUpdateError::TweenError);
+        let linked = self.apply_links(colors, control_values).map_err(ShaderUpdateError::LinkError);
+        return tweened.and(linked);
+    }
+
+    //starting a tween on a property that's already tweening cancels the old one
+    pub fn tween_property(&mut self, tween: Tween) -> TweenId
+    {
+        return self.tweens.start(tween);
+    }
+
+    pub fn cancel_tween(&mut self, id: TweenId) -> bool
+    {
+        return self.tweens.cancel(id);
+    }
+
+    pub fn drain_tween_events(&mut self) -> impl Iterator<Item=TweenEvent> + '_
+    {
+        return self.tweens.drain_events();
+    }
+
+    //pushes every active link's current value into the properties and re-evaluates what changed.
+    //a broken link doesn't stop the others from applying, only the first error is returned
+    pub fn apply_links(&mut self, colors: &[Color], control_values: &[f32]) -> Result<(), ShaderLinkError>
+    {
+        let mut result = Ok(());
+        for ((link, track), slot) in self.links.iter().zip(&self.link_tracks).zip(&self.link_slots)
+        {
+            if !link.active
+            {
+                continue;
+            }
+            let val = match &link.value
+            {
+                ExternalShaderValue::Color(index) => match colors.get(*index)
+                {
+                    Some(color) => Ok(Value::Color(color.data)),
+                    None => Err(ShaderLinkError::NoSuchColor { property_name: link.property_name.clone(), index: *index, num_colors: colors.len() }),
+                },
+                ExternalShaderValue::AnimationTrack(name) => match track.and_then(|t| control_values.get(t))
+                {
+                    Some(control) => Ok(Value::Scalar(*control)),
+                    None => Err(ShaderLinkError::NoSuchTrack { property_name: link.property_name.clone(), track: name.clone() }),
+                },
+            };
+            //only assign on change so unchanged links don't re-run their dependents every frame
+            let applied = val.and_then
+            (
+                |val| match slot
+                {
+                    Some(slot) if self.properties.get_slot(*slot) == Some(&val) => Ok(()),
+                    Some(slot) => self.properties.set_slot(*slot, val).map_err(ShaderLinkError::AssignmentError),
+                    None => Err(ShaderLinkError::AssignmentError(AssignmentError::NoSuchPropertyError { property_name: link.property_name.clone() })),
+                }
+            );
+            if result.is_ok()
+            {
+                result = applied;
+            }
+        }
+        self.eval_expressions();
+        return result;
+    }
+
     pub fn set_link_toggle(&mut self, id: usize, active: bool)
     {
         if let Some(link) = self.links.get_mut(id)
@@ -271,8 +816,59 @@ impl ShaderInstance
         }
     }
 
+    //re-runs the expressions that read a property assigned since the last call
     pub fn eval_expressions(&mut self)
     {
-        self.shader.eval(&self.properties, &mut self.expression_cache);
+        let mut changed = false;
+        for slot in self.properties.changed_slots()
+        {
+            self.shader.mark_dependents(slot, &mut self.dirty_expressions);
+            changed = true;
+        }
+        if !changed
+        {
+            return;
+        }
+        self.properties.clear_changed();
+        self.shader.eval_dirty(self.properties.values(), &mut self.dirty_expressions, &mut self.eval_stack, &mut self.expression_cache, &mut self.diagnostics);
+        self.calculate_placement_transforms();
+    }
+
+    pub fn expression_value(&self, id: u16) -> Option<&Value>
+    {
+        return self.expression_cache.get_value(id);
+    }
+
+    //expression ids whose values changed since clear_changed_expressions, for partial uploads
+    pub fn changed_expression_ids(&self) -> impl Iterator<Item=u16> + '_
+    {
+        return self.expression_cache.changed_ids();
+    }
+
+    pub fn clear_changed_expressions(&mut self)
+    {
+        self.expression_cache.clear_changed();
+    }
+
+    //only affects failures from here on
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy)
+    {
+        self.diagnostics.set_policy(policy);
+    }
+
+    pub fn diagnostics(&self) -> &ShaderDiagnostics
+    {
+        return &self.diagnostics;
+    }
+
+    pub fn drain_diagnostics(&mut self) -> impl Iterator<Item=EvaluationDiagnostic> + '_
+    {
+        return self.diagnostics.drain();
+    }
+
+    //under ErrorPolicy::MarkErrored, whether any expression is still failing. uploaded with the model's placements
+    pub fn is_errored(&self) -> bool
+    {
+        return self.diagnostics.is_errored();
     }
 }
\ No newline at end of file
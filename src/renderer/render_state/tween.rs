@@ -0,0 +1,321 @@
+use std::time::Duration;
+
+use glam::{Affine2, Mat3, Mat4};
+
+use super::animation::Interpolate;
+use super::properties::{AssignmentError, PropertyStore, Value};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Easing
+{
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    //holds the start value until the end
+    Step,
+    //goes out to the target and back again, for flashes and highlights
+    Pulse,
+}
+
+impl Easing
+{
+    pub fn apply(&self, t: f32) -> f32
+    {
+        let t = t.clamp(0.0, 1.0);
+        return match self
+        {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => if t < 0.5 { 4.0 * t * t * t } else { 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0 },
+            Easing::Step => if t >= 1.0 { 1.0 } else { 0.0 },
+            Easing::Pulse => 1.0 - (2.0 * t - 1.0).abs(),
+        };
+    }
+}
+
+//None if the values aren't the same type. quaternions slerp, matrices are split into scale, rotation and translation first,
+//ints round and bools switch over at the halfway point
+pub fn interpolate_value(from: &Value, to: &Value, t: f32) -> Option<Value>
+{
+    return match (from, to)
+    {
+        (Value::Scalar(a), Value::Scalar(b)) => Some(Value::Scalar(a.interpolate(b, t))),
+        (Value::Vector2(a), Value::Vector2(b)) => Some(Value::Vector2(a.lerp(*b, t))),
+        (Value::Vector3(a), Value::Vector3(b)) => Some(Value::Vector3(a.lerp(*b, t))),
+        (Value::Vector4(a), Value::Vector4(b)) => Some(Value::Vector4(a.lerp(*b, t))),
+        (Value::Color(a), Value::Color(b)) => Some(Value::Color(a.lerp(*b, t))),
+        (Value::Quaternion(a), Value::Quaternion(b)) => Some(Value::Quaternion(a.interpolate(b, t))),
+        (Value::Int(a), Value::Int(b)) => Some(Value::Int((*a as f32).interpolate(&(*b as f32), t).round() as i32)),
+        (Value::Bool(a), Value::Bool(b)) => Some(Value::Bool(if t < 0.5 { *a } else { *b })),
+        (Value::Matrix3(a), Value::Matrix3(b)) =>
+        {
+            let (a_scale, a_angle, a_loc) = Affine2::from_mat3(*a).to_scale_angle_translation();
+            let (b_scale, b_angle, b_loc) = Affine2::from_mat3(*b).to_scale_angle_translation();
+            //the short way round
+            let mut angle_diff = (b_angle - a_angle) % std::f32::consts::TAU;
+            if angle_diff > std::f32::consts::PI
+            {
+                angle_diff -= std::f32::consts::TAU;
+            }
+            else if angle_diff < -std::f32::consts::PI
+            {
+                angle_diff += std::f32::consts::TAU;
+            }
+            Some(Value::Matrix3(Mat3::from_scale_angle_translation(a_scale.lerp(b_scale, t), a_angle + angle_diff * t, a_loc.lerp(b_loc, t))))
+        },
+        (Value::Matrix4(a), Value::Matrix4(b)) =>
+        {
+            let (a_scale, a_rot, a_loc) = a.to_scale_rotation_translation();
+            let (b_scale, b_rot, b_loc) = b.to_scale_rotation_translation();
+            Some(Value::Matrix4(Mat4::from_scale_rotation_translation(a_scale.lerp(b_scale, t), a_rot.interpolate(&b_rot, t), a_loc.lerp(b_loc, t))))
+        },
+        _ => None,
+    };
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TweenId(u32);
+
+#[derive(Debug)]
+pub enum TweenEvent
+{
+    //one per step of a chain
+    Completed{ id: TweenId, property_name: String },
+    Cancelled{ id: TweenId, property_name: String },
+}
+
+pub struct Tween
+{
+    property_name: String,
+    target: Value,
+    duration: f32,
+    easing: Easing,
+    on_complete: Vec<Box<dyn Fn()>>,
+    next: Option<Box<Tween>>,
+}
+
+impl Tween
+{
+    pub fn new(property_name: &str, target: Value, duration: Duration) -> Self
+    {
+        return Tween
+        {
+            property_name: property_name.to_string(),
+            target: target,
+            duration: duration.as_secs_f32(),
+            easing: Easing::Linear,
+            on_complete: vec![],
+            next: None,
+        };
+    }
+
+    pub fn with_easing(mut self, easing: Easing) -> Self
+    {
+        self.easing = easing;
+        return self;
+    }
+
+    pub fn on_complete(mut self, callback: impl Fn() + 'static) -> Self
+    {
+        self.on_complete.push(Box::new(callback));
+        return self;
+    }
+
+    //starts once this one and anything already chained after it finish. it can target a different property
+    pub fn then(mut self, next: Tween) -> Self
+    {
+        match self.next
+        {
+            Some(chained) => self.next = Some(Box::new(chained.then(next))),
+            None => self.next = Some(Box::new(next)),
+        }
+        return self;
+    }
+}
+
+struct ActiveTween
+{
+    id: TweenId,
+    tween: Tween,
+    //captured from the property on the first update
+    start: Option<Value>,
+    elapsed: f32,
+}
+
+//runs tweens against a PropertyGroup or a shader instance. one tween per property, starting another on the same property cancels the first
+pub struct PropertyTweener
+{
+    active: Vec<ActiveTween>,
+    events: Vec<TweenEvent>,
+    next_id: u32,
+}
+
+impl PropertyTweener
+{
+    pub fn new() -> Self
+    {
+        return PropertyTweener { active: vec![], events: vec![], next_id: 0 };
+    }
+
+    pub fn start(&mut self, tween: Tween) -> TweenId
+    {
+        let id = TweenId(self.next_id);
+        self.next_id = self.next_id.wrapping_add(1);
+        self.cancel_property(&tween.property_name);
+        self.active.push(ActiveTween { id: id, tween: tween, start: None, elapsed: 0.0 });
+        return id;
+    }
+
+    //drops the rest of the chain too
+    pub fn cancel(&mut self, id: TweenId) -> bool
+    {
+        return self.cancel_where(|active| active.id == id);
+    }
+
+    pub fn cancel_property(&mut self, property_name: &str) -> bool
+    {
+        return self.cancel_where(|active| active.tween.property_name == property_name);
+    }
+
+    fn cancel_where(&mut self, predicate: impl Fn(&ActiveTween) -> bool) -> bool
+    {
+        let mut cancelled = false;
+        let events = &mut self.events;
+        self.active.retain
+        (
+            |active|
+            {
+                if predicate(active)
+                {
+                    events.push(TweenEvent::Cancelled { id: active.id, property_name: active.tween.property_name.clone() });
+                    cancelled = true;
+                    return false;
+                }
+                return true;
+            }
+        );
+        return cancelled;
+    }
+
+    pub fn is_active(&self, id: TweenId) -> bool
+    {
+        return self.active.iter().any(|active| active.id == id);
+    }
+
+    pub fn is_tweening(&self, property_name: &str) -> bool
+    {
+        return self.active.iter().any(|active| active.tween.property_name == property_name);
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        return self.active.is_empty();
+    }
+
+    //completion and cancellation events since the last drain
+    pub fn drain_events(&mut self) -> impl Iterator<Item=TweenEvent> + '_
+    {
+        return self.events.drain(..);
+    }
+
+    //tweens whose property is missing or a different type from their target get dropped. only the first error is returned
+    pub fn update(&mut self, dt: f32, properties: &mut impl PropertyStore) -> Result<(), AssignmentError>
+    {
+        let mut result = Ok(());
+        //chained steps start after everything else has moved, with whatever time their predecessor had left over
+        let mut chained = vec![];
+        let mut i = 0;
+        while i < self.active.len()
+        {
+            match self.advance(i, dt, properties)
+            {
+                Ok(None) => i += 1,
+                Ok(Some(leftover)) => 
+                {
+                    let finished = self.active.remove(i);
+                    if let Some(next) = finished.tween.next
+                    {
+                        chained.push((finished.id, *next, leftover));
+                    }
+                },
+                Err(err) => 
+                {
+                    self.active.remove(i);
+                    if result.is_ok()
+                    {
+                        result = Err(err);
+                    }
+                },
+            }
+        }
+        while !chained.is_empty()
+        {
+            let (id, next, leftover) = chained.remove(0);
+            //a step on another property takes it over from whatever was tweening it
+            self.cancel_property(&next.property_name);
+            self.active.push(ActiveTween { id: id, tween: next, start: None, elapsed: 0.0 });
+            let index = self.active.len() - 1;
+            match self.advance(index, leftover, properties)
+            {
+                Ok(None) => (),
+                Ok(Some(leftover)) => 
+                {
+                    let finished = self.active.remove(index);
+                    if let Some(next) = finished.tween.next
+                    {
+                        chained.push((finished.id, *next, leftover));
+                    }
+                },
+                Err(err) => 
+                {
+                    self.active.remove(index);
+                    if result.is_ok()
+                    {
+                        result = Err(err);
+                    }
+                },
+            }
+        }
+        return result;
+    }
+
+    //writes the tween's current value. once it reaches the end, fires its callbacks and returns the time left over
+    fn advance(&mut self, index: usize, dt: f32, properties: &mut impl PropertyStore) -> Result<Option<f32>, AssignmentError>
+    {
+        let active = &mut self.active[index];
+        let property_name = &active.tween.property_name;
+        let start = match active.start
+        {
+            Some(start) => start,
+            None => 
+            {
+                let current = *properties.get_property(property_name).ok_or_else(|| AssignmentError::NoSuchPropertyError { property_name: property_name.clone() })?;
+                active.start = Some(current);
+                current
+            },
+        };
+        active.elapsed += dt;
+        let progress = if active.tween.duration > 0.0 { active.elapsed / active.tween.duration } else { 1.0 };
+        let eased = active.tween.easing.apply(progress);
+        let val = interpolate_value(&start, &active.tween.target, eased).ok_or_else
+        (
+            || AssignmentError::TypeMismatchError { property_name: property_name.clone(), expected: start, given: active.tween.target }
+        )?;
+        if properties.get_property(property_name) != Some(&val)
+        {
+            properties.set_property(property_name, val)?;
+        }
+        if progress < 1.0
+        {
+            return Ok(None);
+        }
+        for callback in &active.tween.on_complete
+        {
+            callback();
+        }
+        self.events.push(TweenEvent::Completed { id: active.id, property_name: property_name.clone() });
+        return Ok(Some((active.elapsed - active.tween.duration).max(0.0)));
+    }
+}
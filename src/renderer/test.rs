@@ -118,14 +118,14 @@ fn test_setup()
     let armature = repo.get_armature(&"rho_armature".to_string()).unwrap();
     {
         let mut arma_tfs = vec![];
-        for i in 0..armature.bones.len()
+        for i in 0..armature.bones().len()
         {
-            let arma_parent = match armature.bones[i].parent 
+            let arma_parent = match armature.bones()[i].parent 
             {
                 Some(parent) => arma_tfs[parent],
                 None => Mat4::IDENTITY,
             };
-            arma_tfs.push(arma_parent * armature.bones[i].local_tf);
+            arma_tfs.push(arma_parent * armature.bones()[i].local_tf);
             arma_ids.push(state.add_static_model(group_id, model, arma_tfs[i] * Mat4::from_scale(Vec3::ONE * 0.2)).unwrap());
         }
     }*/
@@ -236,7 +236,7 @@ fn test_setup()
         
         test_tf = test_tf * Mat4::from_quat(Quat::from_axis_angle(Vec3::Y, 0.05));
         //state.get_group_mut(group_id).unwrap().get_static_model_mut(stat_mod).unwrap().set_transform(test_tf);
-        state.get_group_mut(group_id).unwrap().get_animated_model_mut(anim_mod).unwrap().update(1.0/60.0);
+        state.get_group_mut(group_id).unwrap().get_animated_model_mut(anim_mod).unwrap().update(1.0/60.0).unwrap();
         renderer.push_buffer_updates(&state, &device, &queue);
         state.clear_dirty_state();
         renderer.render(&state, &gpu_store, &device, &queue);